@@ -1,16 +1,48 @@
 use {
-    borsh::de::BorshDeserialize,
     mpl_token_metadata::{
         instruction::MetadataInstruction,
-        pda::find_metadata_account,
+        pda::{find_master_edition_account, find_metadata_account},
         state::Creator as MplCreator,
         state::Collection as MplCollection,
+        state::Data as MplData,
+        state::DataV2 as MplDataV2,
+        state::UseMethod as MplUseMethod,
+        state::Uses as MplUses,
     },
     solana_sdk::{
         pubkey::Pubkey,
         instruction::CompiledInstruction,
     },
     spl_token::instruction::{AuthorityType, TokenInstruction},
+    std::fmt,
+    crate::{
+        auction_house::{AuctionHouseInstruction, BuyArgs, AUCTIONEER_LISTING_PRICE},
+        bubblegum::{
+            find_asset_id,
+            unpack_leaf_schema,
+            BubblegumInstruction,
+            LeafSchema,
+            MetadataArgs,
+            UpdateArgs as BubblegumUpdateArgs,
+        },
+        candy_machine::{CandyGuardInstruction, CandyMachineInstruction},
+        layouts::{AccountRole, Decoding, LayoutRegistry},
+        metadata::{
+            self,
+            CreateArgs,
+            DelegateArgs,
+            PrintArgs,
+            RevokeArgs,
+            TokenMetadataInstruction,
+            UpdateArgs,
+            VerificationArgs,
+        },
+        partition::TransactionTokenMeta,
+        programs,
+        registry::TokenAccountRegistry,
+        storefront::{FixedPriceSaleInstruction, MetaplexInstruction, VaultInstruction},
+        token::SplTokenInstruction,
+    },
 };
 
 #[derive(Clone, Debug, PartialEq)]
@@ -32,6 +64,142 @@ impl Default for EditionStatus {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum SupplyStatus {
+    // we never saw who holds the mint authority
+    Unknown,
+
+    // mint authority was revoked or is held by the edition PDA (which only mints the 1 token)
+    Fixed,
+
+    // someone can still mint more
+    Mintable,
+}
+
+// mirrors the metaplex TokenStandard classification
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenStandard {
+    // master edition, or decimals 0 with no more than 1 token and no edition seen
+    NonFungible,
+
+    // decimals 0 without an edition and more than 1 token at some point (SFTs)
+    FungibleAsset,
+
+    // decimals > 0. we only see these if something else pulled in the mint
+    Fungible,
+
+    // limited edition print
+    Edition,
+
+    // master edition whose transfers, delegates, etc go through token metadata
+    ProgrammableNonFungible,
+
+    // limited edition print of a programmable master edition
+    ProgrammableEdition,
+}
+
+impl From<metadata::TokenStandard> for TokenStandard {
+    fn from(token_standard: metadata::TokenStandard) -> Self {
+        match token_standard {
+            metadata::TokenStandard::NonFungible => Self::NonFungible,
+            metadata::TokenStandard::FungibleAsset => Self::FungibleAsset,
+            metadata::TokenStandard::Fungible => Self::Fungible,
+            metadata::TokenStandard::NonFungibleEdition => Self::Edition,
+            metadata::TokenStandard::ProgrammableNonFungible => Self::ProgrammableNonFungible,
+            metadata::TokenStandard::ProgrammableNonFungibleEdition => Self::ProgrammableEdition,
+        }
+    }
+}
+
+// token record state of a programmable asset's token account
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenState {
+    Unlocked,
+
+    // frozen by a delegate or the freeze authority
+    Locked,
+
+    // has a sale delegate
+    Listed,
+}
+
+impl Default for TokenState {
+    fn default() -> Self {
+        Self::Unlocked
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenDelegateRole {
+    Sale,
+
+    Transfer,
+
+    Utility,
+
+    Staking,
+
+    Standard,
+
+    LockedTransfer,
+
+    Migration,
+}
+
+impl TokenDelegateRole {
+    // None for the metadata delegates, which don't touch the token record
+    fn from_delegate_args(args: &DelegateArgs) -> Option<Self> {
+        match args {
+            DelegateArgs::SaleV1 { .. } => Some(Self::Sale),
+            DelegateArgs::TransferV1 { .. } => Some(Self::Transfer),
+            DelegateArgs::UtilityV1 { .. } => Some(Self::Utility),
+            DelegateArgs::StakingV1 { .. } => Some(Self::Staking),
+            DelegateArgs::StandardV1 { .. } => Some(Self::Standard),
+            DelegateArgs::LockedTransferV1 { .. } => Some(Self::LockedTransfer),
+            _ => None,
+        }
+    }
+
+    fn from_revoke_args(args: RevokeArgs) -> Option<Self> {
+        match args {
+            RevokeArgs::SaleV1 => Some(Self::Sale),
+            RevokeArgs::TransferV1 => Some(Self::Transfer),
+            RevokeArgs::UtilityV1 => Some(Self::Utility),
+            RevokeArgs::StakingV1 => Some(Self::Staking),
+            RevokeArgs::StandardV1 => Some(Self::Standard),
+            RevokeArgs::LockedTransferV1 => Some(Self::LockedTransfer),
+            RevokeArgs::MigrationV1 => Some(Self::Migration),
+            _ => None,
+        }
+    }
+}
+
+// The token record PDA of the current token account. Only programmable assets have one; the
+// program resets it whenever the token moves to another account
+#[derive(Default, Debug, Clone)]
+pub struct TokenRecord {
+    pub state: TokenState,
+
+    pub delegate: Option<Pubkey>,
+
+    pub delegate_role: Option<TokenDelegateRole>,
+
+    pub instruction_index: InstructionIndex,
+}
+
+// Where a compressed NFT lives. Cleared when it's decompressed into a regular mint
+#[derive(Debug, Clone)]
+pub struct CompressedLeaf {
+    pub tree: Pubkey,
+
+    // index of the leaf in the tree. not known for a mint until its leaf schema is logged
+    pub nonce: u64,
+
+    // redeemed leaves are removed from the tree until they're decompressed or the redeem is
+    // cancelled
+    pub redeemed: bool,
+}
+
 #[derive(Debug)]
 pub struct LimitedEdition {
     pub master_key: Pubkey,
@@ -82,7 +250,48 @@ impl From<MplCollection> for Collection {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum UseMethod {
+    Burn,
+
+    Multiple,
+
+    Single,
+}
+
+impl From<MplUseMethod> for UseMethod {
+    fn from(use_method: MplUseMethod) -> Self {
+        match use_method {
+            MplUseMethod::Burn => Self::Burn,
+            MplUseMethod::Multiple => Self::Multiple,
+            MplUseMethod::Single => Self::Single,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Uses {
+    pub use_method: UseMethod,
+
+    pub remaining: i64,
+
+    pub total: i64,
+}
+
+impl From<MplUses> for Uses {
+    fn from(uses: MplUses) -> Self {
+        Self {
+            use_method: uses.use_method.into(),
+            remaining: uses.remaining as i64,
+            total: uses.total as i64,
+        }
+    }
+}
+
+// Orders instructions as the runtime executed them. An outer instruction (no inner_index) runs
+// before the inner instructions it invokes, which are recorded in invocation order regardless of
+// how deeply nested they are
+#[derive(Default, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct InstructionIndex {
     pub slot: i64,
 
@@ -95,313 +304,1662 @@ pub struct InstructionIndex {
 
 #[derive(Default, Debug, Clone)]
 pub struct Glazing {
+    pub name: Vec<u8>,
+
+    pub symbol: Vec<u8>,
+
     pub uri: Vec<u8>,
 
+    pub seller_fee_basis_points: u16,
+
     pub creators: Vec<Creator>,
 
     pub collection: Option<Collection>,
 
-    pub instruction_index: InstructionIndex,
-}
+    pub uses: Option<Uses>,
 
-#[derive(Default, Debug)]
-pub struct Bonbon {
-    pub mint_key: Pubkey, // could be pubkey::default
+    pub primary_sale_happened: bool,
 
-    pub metadata_key: Pubkey, // could be pubkey::default
+    pub is_mutable: bool,
 
-    // TODO: track transient token_metas so we can have stronger assertions?
-    pub current_owner: Option<Pubkey>,
+    // None when we haven't seen the metadata created (e.g a verification came first)
+    pub update_authority: Option<Pubkey>,
 
-    pub current_account: Option<Pubkey>,
+    pub instruction_index: InstructionIndex,
+}
 
-    pub edition_status: EditionStatus,
+impl Glazing {
+    // v1 data doesn't include collection or uses so those are left as-is, same as the program
+    fn apply_data(&mut self, data: MplData) {
+        self.name = data.name.into_bytes();
+        self.symbol = data.symbol.into_bytes();
+        self.uri = data.uri.into_bytes();
+        self.seller_fee_basis_points = data.seller_fee_basis_points;
+        self.creators = from_creators(data.creators);
+    }
 
-    pub limited_edition: Option<LimitedEdition>,
+    fn apply_data_v2(&mut self, data: MplDataV2) {
+        self.name = data.name.into_bytes();
+        self.symbol = data.symbol.into_bytes();
+        self.uri = data.uri.into_bytes();
+        self.seller_fee_basis_points = data.seller_fee_basis_points;
+        self.creators = from_creators(data.creators);
+        self.collection = data.collection.map(Collection::from);
+        self.uses = data.uses.map(Uses::from);
+    }
 
-    // we add a record of updates so that we can join up values at the end by slot/block/indexes.
-    // track creator / collection verification and override those with the new values for the
-    // limited edition
-    pub glazings: Vec<Glazing>,
-}
+    fn apply_metadata_args(&mut self, args: MetadataArgs) {
+        self.name = args.name.into_bytes();
+        self.symbol = args.symbol.into_bytes();
+        self.uri = args.uri.into_bytes();
+        self.seller_fee_basis_points = args.seller_fee_basis_points;
+        self.creators = from_creators(Some(args.creators));
+        self.collection = args.collection.map(Collection::from);
+        self.uses = args.uses.map(Uses::from);
+        self.primary_sale_happened = args.primary_sale_happened;
+        self.is_mutable = args.is_mutable;
+    }
 
-impl Bonbon {
-    pub fn apply_creator_verification(
-        &mut self, creator_key: &Pubkey, verified: bool,
-        instruction_index: InstructionIndex,
-    ) {
-        if let Some(last) = self.glazings.last() {
-            let mut next: Glazing = last.clone();
-            for creator in &mut next.creators {
-                if creator.address == *creator_key {
-                    creator.verified = verified;
-                    break;
-                }
-            }
-            next.instruction_index = instruction_index;
-            self.glazings.push(next);
-        } else {
-            self.glazings.push(Glazing {
-                creators: vec![Creator { address: *creator_key, verified, share: 0 }],
-                instruction_index,
-                ..Glazing::default()
-            });
+    fn apply_update_args(&mut self, args: BubblegumUpdateArgs) {
+        if let Some(name) = args.name {
+            self.name = name.into_bytes();
+        }
+        if let Some(symbol) = args.symbol {
+            self.symbol = symbol.into_bytes();
+        }
+        if let Some(uri) = args.uri {
+            self.uri = uri.into_bytes();
+        }
+        if let Some(creators) = args.creators {
+            self.creators = from_creators(Some(creators));
+        }
+        if let Some(seller_fee_basis_points) = args.seller_fee_basis_points {
+            self.seller_fee_basis_points = seller_fee_basis_points;
+        }
+        if let Some(primary_sale_happened) = args.primary_sale_happened {
+            self.primary_sale_happened = primary_sale_happened;
+        }
+        if let Some(is_mutable) = args.is_mutable {
+            self.is_mutable = is_mutable;
         }
     }
+}
 
-    pub fn apply_collection_verification(
-        &mut self, collection_key: &Pubkey, verified: bool,
-        instruction_index: InstructionIndex,
-    ) {
-        let prev = self.glazings.last()
-            .map(|v| v.clone()).unwrap_or(Glazing::default());
-        self.glazings.push(Glazing {
-            collection: Some(Collection {
-                address: *collection_key,
-                verified,
-            }),
-            instruction_index,
-            ..prev
-        })
-    }
+#[derive(Debug, Clone)]
+pub struct Holding {
+    pub account: Pubkey,
+
+    // None if we couldn't find the owner of the token account
+    pub owner: Option<Pubkey>,
+
+    pub amount: u64,
+
+    // pNFT token accounts stay frozen between token metadata instructions
+    pub frozen: bool,
+
+    // last instruction that touched this account's balance
+    pub instruction_index: InstructionIndex,
 }
 
-#[derive(Debug)]
-pub enum ErrorCode {
-    BadAccountKeyIndex,
+#[derive(Debug, Clone)]
+pub struct AuthorityChange {
+    // None when the authority was revoked
+    pub authority: Option<Pubkey>,
 
-    FailedInstructionDeserialization,
+    pub instruction_index: InstructionIndex,
+}
 
-    InvalidMetadataCreate,
+// A collection authority record approved (or revoked) on a collection NFT. Newer collection
+// delegates are recorded the same way
+#[derive(Debug, Clone)]
+pub struct CollectionAuthorityChange {
+    pub authority: Pubkey,
 
-    InvalidMetadataUpdate,
+    // the collection authority record, or the metadata delegate record
+    pub record: Pubkey,
 
-    InvalidMasterEditionCreate,
+    // false when revoked
+    pub approved: bool,
 
-    // includes unverify creator/collection
-    InvalidMetadataVerifyOperation,
+    pub instruction_index: InstructionIndex,
 }
 
-pub struct TransactionTokenOwnerMeta {
-    pub account_index: u8,
+// The bonbon joining (verified) or leaving (unverified) a collection, see
+// `Bonbon::collection_memberships`
+#[derive(Debug, Clone)]
+pub struct CollectionMembership {
+    pub collection: Pubkey,
 
-    pub owner_key: Pubkey,
+    pub verified: bool,
+
+    pub instruction_index: InstructionIndex,
 }
 
-pub struct InstructionContext<'a> {
-    pub instruction: &'a CompiledInstruction,
+// A sale of the bonbon through a marketplace program. Each marketplace's updater fills one in
+// from its own instruction
+#[derive(Debug, Clone)]
+pub struct Sale {
+    pub program_key: Pubkey,
 
-    pub account_keys: &'a [Pubkey],
+    // the program's account for the marketplace running the sale (e.g the auction house), if
+    // it has one
+    pub marketplace: Option<Pubkey>,
 
-    pub owners: &'a [TransactionTokenOwnerMeta],
+    pub buyer: Pubkey,
+
+    pub seller: Pubkey,
+
+    // total paid for `amount` tokens, in the smallest unit of `currency_mint`
+    pub price: u64,
+
+    // the native mint for SOL
+    pub currency_mint: Pubkey,
+
+    pub amount: u64,
+
+    // one per verified creator of the glazing in effect at the sale
+    pub royalties: Vec<RoyaltyPayment>,
 
     pub instruction_index: InstructionIndex,
 }
 
-pub fn update_metadata_instruction(
-    bonbon: &mut Bonbon,
-    InstructionContext {
-        instruction, account_keys, owners: _,
-        instruction_index,
-    }: InstructionContext,
-) -> Result<(), ErrorCode> {
-    let get_account_key = |index: usize| account_keys.get(
-        usize::from(instruction.accounts[index])
-    ).ok_or(ErrorCode::BadAccountKeyIndex);
+// What a verified creator was owed on a sale against what they got
+#[derive(Debug, Clone)]
+pub struct RoyaltyPayment {
+    pub creator: Pubkey,
 
-    let metadata_instruction = MetadataInstruction::try_from_slice(&instruction.data)
-        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    // as of the sale
+    pub share: i16,
 
+    pub seller_fee_basis_points: u16,
 
-    match metadata_instruction {
-        MetadataInstruction::CreateMetadataAccount(args) => {
-            // OG create metadata
-            let metadata_key = get_account_key(0)?;
-            if find_metadata_account(&bonbon.mint_key).0 != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataCreate);
-            }
+    // the creator's share of `seller_fee_basis_points` of the price, rounded down like the
+    // marketplace programs do. In the sale's currency
+    pub expected: u64,
 
-            bonbon.metadata_key = *metadata_key;
-            bonbon.glazings.push(Glazing {
-                uri: args.data.uri.into_bytes(),
-                creators: from_creators(args.data.creators),
-                collection: None,
-                instruction_index,
-            });
-        },
-        MetadataInstruction::CreateMetadataAccountV2(args) => {
-            // create metadata with datav2 (adds collection info, etc)
-            let metadata_key = get_account_key(0)?;
-            if find_metadata_account(&bonbon.mint_key).0 != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataCreate);
-            }
+    // the creator's balance change in the sale's currency over the whole transaction. None if
+    // the transaction has no status meta, the creator's balance isn't in it or it's `ambiguous`
+    pub paid: Option<i64>,
 
-            bonbon.metadata_key = *metadata_key;
-            bonbon.glazings.push(Glazing {
-                uri: args.data.uri.into_bytes(),
-                creators: from_creators(args.data.creators),
-                collection: args.data.collection.map(Collection::from),
-                instruction_index,
-            });
-        },
-        MetadataInstruction::UpdateMetadataAccount(args) => {
-            let metadata_key = get_account_key(0)?;
-            if bonbon.metadata_key != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataUpdate);
-            }
+    // how much of `expected` the creator didn't get, 0 if they got all of it. None when `paid` is
+    pub shortfall: Option<u64>,
 
-            if let Some(data) = args.data {
-                bonbon.glazings.push(Glazing {
-                    uri: data.uri.into_bytes(),
-                    creators: from_creators(data.creators),
-                    collection: None,
-                    instruction_index,
-                });
-            }
-        },
-        MetadataInstruction::UpdateMetadataAccountV2(args) => {
-            let metadata_key = get_account_key(0)?;
-            if bonbon.metadata_key != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataUpdate);
-            }
+    // the creator's balance change would count more than this royalty: they were the buyer or
+    // the seller, or the transaction has more than one sale
+    pub ambiguous: bool,
+}
 
-            if let Some(data) = args.data {
-                bonbon.glazings.push(Glazing {
-                    uri: data.uri.into_bytes(),
-                    creators: from_creators(data.creators),
-                    collection: data.collection.map(Collection::from),
-                    instruction_index,
-                });
-            }
-        },
-        MetadataInstruction::DeprecatedCreateMasterEdition(_) => {
-            // master edition with printing tokens (and reservation list?)
-            let metadata_key = get_account_key(7)?;
-            if bonbon.metadata_key != *metadata_key
-                    || bonbon.edition_status != EditionStatus::None {
-                return Err(ErrorCode::InvalidMasterEditionCreate);
+impl RoyaltyPayment {
+    // royalties owed on `sale`, checked against the transaction's SOL balances for the native
+    // mint and its token balances otherwise
+    pub fn for_sale(
+        glazing: Option<&Glazing>, sale: &Sale,
+        account_keys: &[Pubkey], owners: &[TransactionTokenOwnerMeta],
+        pre_balances: &[u64], post_balances: &[u64],
+    ) -> Vec<Self> {
+        let glazing = match glazing {
+            Some(glazing) => glazing,
+            None => return vec![],
+        };
+        let Sale { price, currency_mint, buyer, seller, .. } = sale;
+        let total_fee = u128::from(*price) * u128::from(glazing.seller_fee_basis_points) / 10000;
+        let has_balances = !pre_balances.is_empty();
+
+        // a sale moves the item into one account. more token balances going up (other than in
+        // the currency) means other sales, e.g a sweep
+        let several_sales = owners.iter()
+            .filter(|m| m.mint_key != *currency_mint)
+            .filter(|m| m.post_amount.unwrap_or(0) > m.pre_amount.unwrap_or(0))
+            .count() > 1;
+
+        let paid_to = |creator: &Pubkey| -> Option<i64> {
+            if *currency_mint == spl_token::native_mint::id() {
+                let index = account_keys.iter().position(|k| k == creator)?;
+                Some(*post_balances.get(index)? as i64 - *pre_balances.get(index)? as i64)
+            } else {
+                owners.iter()
+                    .filter(|m| m.mint_key == *currency_mint && m.owner_key == *creator)
+                    .map(|m| m.post_amount.unwrap_or(0) as i64 - m.pre_amount.unwrap_or(0) as i64)
+                    .reduce(|total, delta| total + delta)
             }
+        };
+
+        glazing.creators.iter()
+            .filter(|creator| creator.verified)
+            .map(|creator| {
+                let expected =
+                    (total_fee * u128::try_from(creator.share).unwrap_or(0) / 100) as u64;
+                let ambiguous = creator.address == *buyer || creator.address == *seller
+                    || several_sales;
+                let paid = (has_balances && !ambiguous)
+                    .then(|| paid_to(&creator.address))
+                    .flatten();
+                Self {
+                    creator: creator.address,
+                    share: creator.share,
+                    seller_fee_basis_points: glazing.seller_fee_basis_points,
+                    expected,
+                    paid,
+                    shortfall: paid.map(|paid| expected.saturating_sub(paid.max(0) as u64)),
+                    ambiguous,
+                }
+            })
+            .collect()
+    }
+}
 
-            bonbon.edition_status = EditionStatus::Master;
-        }
-        MetadataInstruction::CreateMasterEdition(_) => {
-            // edition v2 w/ bitvec directly
-            let metadata_key = get_account_key(5)?;
-            if bonbon.metadata_key != *metadata_key
-                    || bonbon.edition_status != EditionStatus::None {
-                return Err(ErrorCode::InvalidMasterEditionCreate);
-            }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderSide {
+    Listing,
 
-            bonbon.edition_status = EditionStatus::Master;
-        }
-        MetadataInstruction::CreateMasterEditionV3(_) => {
-            // not sure why this exists
-            let metadata_key = get_account_key(5)?;
-            if bonbon.metadata_key != *metadata_key
-                    || bonbon.edition_status != EditionStatus::None {
-                return Err(ErrorCode::InvalidMasterEditionCreate);
-            }
+    Bid,
+}
 
-            bonbon.edition_status = EditionStatus::Master;
-        }
-        MetadataInstruction::DeprecatedMintNewEditionFromMasterEditionViaPrintingToken => {
-            // TODO: link with master edition for uri, creators, collection
-            let metadata_key = get_account_key(0)?;
-            if find_metadata_account(&bonbon.mint_key).0 != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataCreate);
-            }
+// A listing or bid that hasn't been cancelled or filled yet
+#[derive(Debug, Clone)]
+pub struct OpenOrder {
+    pub side: OrderSide,
 
-            bonbon.metadata_key = *metadata_key;
-            bonbon.edition_status = EditionStatus::Limited;
-            bonbon.limited_edition = None;
+    // the marketplace program, or the token program for listings we only know by their delegate
+    pub program_key: Pubkey,
+
+    // as in `Sale`
+    pub marketplace: Option<Pubkey>,
+
+    // the seller for listings, the buyer for bids
+    pub wallet: Pubkey,
+
+    // the token account listed, or the one bid on
+    pub token_account: Option<Pubkey>,
+
+    // set for escrowless listings
+    pub delegate: Option<Pubkey>,
+
+    // total asked or offered for `amount` tokens. None if the instruction doesn't say
+    pub price: Option<u64>,
+
+    pub currency_mint: Option<Pubkey>,
+
+    pub amount: u64,
+
+    pub instruction_index: InstructionIndex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorefrontSaleKind {
+    // the item the winner bid on
+    Auction,
+
+    // the item along with its metadata's update authority
+    FullRightsTransfer,
+
+    // a print of a master edition put up for auction
+    AuctionPrint,
+
+    // a print for taking part in the auction
+    Participation,
+
+    // a print bought at a fixed price
+    FixedPrice,
+}
+
+// How the bonbon left a legacy Metaplex storefront, see `storefront`
+#[derive(Debug, Clone)]
+pub struct StorefrontSale {
+    pub kind: StorefrontSaleKind,
+
+    pub program_key: Pubkey,
+
+    // the auction manager, or the fixed price sale's market
+    pub manager: Pubkey,
+
+    // None for fixed price sales
+    pub auction: Option<Pubkey>,
+
+    // the token vault the item was auctioned out of. None for fixed price sales
+    pub vault: Option<Pubkey>,
+
+    // the winning bidder, or the buyer
+    pub buyer: Pubkey,
+
+    // The winning bid is kept in the auction's bidder metadata account rather than passed to the
+    // redemption, so auctions keep the account to look it up instead
+    pub bidder_metadata: Option<Pubkey>,
+
+    // fixed price sales only, in the smallest unit of `currency_mint`
+    pub price: Option<u64>,
+
+    pub currency_mint: Option<Pubkey>,
+
+    pub instruction_index: InstructionIndex,
+}
+
+// A deposit into, or withdrawal from, a token vault's safety deposit box
+#[derive(Debug, Clone)]
+pub struct VaultCustody {
+    pub vault: Pubkey,
+
+    pub safety_deposit_box: Pubkey,
+
+    // false for withdrawals
+    pub deposit: bool,
+
+    pub amount: u64,
+
+    pub instruction_index: InstructionIndex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandyMachineVersion {
+    V1,
+
+    V2,
+
+    // a candy guard in front of a v3 candy machine
+    Guard,
+}
+
+// The candy machine that minted the NFT
+#[derive(Debug, Clone)]
+pub struct CandyMachineMint {
+    pub version: CandyMachineVersion,
+
+    pub candy_machine: Pubkey,
+
+    // v1's config account (v2 keeps its config on the candy machine) or the candy guard
+    pub config: Option<Pubkey>,
+
+    pub payer: Pubkey,
+
+    // lamports the candy machine's wallet received, so 0 for mints paid in tokens. None for
+    // candy guard since the payment guards name their own destination
+    pub price: Option<u64>,
+
+    pub instruction_index: InstructionIndex,
+}
+
+// owner and delegate of a compressed leaf, which has no token account to hold it
+#[derive(Debug, Clone)]
+pub struct LeafOwnerChange {
+    pub owner: Pubkey,
+
+    // the owner when there's no delegate
+    pub delegate: Pubkey,
+
+    pub instruction_index: InstructionIndex,
+}
+
+// an instruction that failed to apply and was skipped by `Bonbon::update_lenient`
+#[derive(Debug, Clone)]
+pub struct Anomaly {
+    pub instruction_index: InstructionIndex,
+
+    pub error_kind: ErrorCode,
+
+    // `Error` display with the signature, keys, etc
+    pub details: String,
+}
+
+// Compressed NFTs have neither a mint nor a metadata account, so both keys are the asset id
+#[derive(Default, Debug)]
+pub struct Bonbon {
+    pub mint_key: Pubkey, // could be pubkey::default
+
+    pub metadata_key: Pubkey, // could be pubkey::default
+
+    // TODO: track transient token_metas so we can have stronger assertions?
+    pub current_owner: Option<Pubkey>,
+
+    pub current_account: Option<Pubkey>,
+
+    pub edition_status: EditionStatus,
+
+    pub limited_edition: Option<LimitedEdition>,
+
+    // we add a record of updates so that we can join up values at the end by slot/block/indexes.
+    // track creator / collection verification and override those with the new values for the
+    // limited edition
+    pub glazings: Vec<Glazing>,
+
+    // every point where the update authority was set or observed to be different from what we
+    // had. the last entry is the current update authority
+    pub update_authorities: Vec<AuthorityChange>,
+
+    pub mint_authorities: Vec<AuthorityChange>,
+
+    pub freeze_authorities: Vec<AuthorityChange>,
+
+    // None until we see the mint initialized
+    pub decimals: Option<u8>,
+
+    // set by the newer instructions that store the standard on the metadata account. None means
+    // we derive it, see `token_standard`
+    pub recorded_token_standard: Option<TokenStandard>,
+
+    // authorization rules checked on transfers and delegates of a programmable asset
+    pub rule_set: Option<Pubkey>,
+
+    // None until a programmable instruction touches the current token account's record
+    pub token_record: Option<TokenRecord>,
+
+    // running total of mints less burns. could be under if we missed instructions
+    pub supply: u64,
+
+    // the most `supply` has been. above 1 means semi-fungible, even if tokens were burned since
+    pub peak_supply: u64,
+
+    // current non-zero token accounts for the mint. for NFTs this is just current_account but
+    // SFTs can have any number of holders
+    pub holdings: Vec<Holding>,
+
+    // Collection NFTs only. The size as created or last set, None for unsized collections.
+    // Verifying a sized collection's items changes the on-chain count through the items' own
+    // instructions, which we don't see here, so count `collection_memberships` instead
+    pub collection_size: Option<u64>,
+
+    // collection NFTs only, in order
+    pub collection_authorities: Vec<CollectionAuthorityChange>,
+
+    // None unless the asset is (still) a compressed NFT
+    pub compressed_leaf: Option<CompressedLeaf>,
+
+    // every change of a compressed leaf's owner or delegate. the last entry is the current one
+    pub leaf_owners: Vec<LeafOwnerChange>,
+
+    // None unless minted through a candy machine
+    pub candy_machine_mint: Option<CandyMachineMint>,
+
+    // marketplace sales in order
+    pub sales: Vec<Sale>,
+
+    // listings and bids that are still open
+    pub open_orders: Vec<OpenOrder>,
+
+    // legacy storefront redemptions and purchases in order
+    pub storefront_sales: Vec<StorefrontSale>,
+
+    // token vault deposits and withdrawals in order
+    pub vault_custody: Vec<VaultCustody>,
+
+    // empty unless assembled leniently
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl Bonbon {
+    // unsized collections that never delegated an authority look like any other bonbon
+    pub fn is_collection(&self) -> bool {
+        self.collection_size.is_some() || !self.collection_authorities.is_empty()
+    }
+
+    // Every change to the collection the bonbon is a verified member of, from its glazings. A
+    // move between collections leaves the old one before joining the new one. Unverified
+    // collections don't count as membership
+    pub fn collection_memberships(&self) -> Vec<CollectionMembership> {
+        let mut memberships = vec![];
+        let mut current: Option<Pubkey> = None;
+        for glazing in &self.glazings {
+            let verified_in = glazing.collection.as_ref()
+                .filter(|c| c.verified)
+                .map(|c| c.address);
+            if verified_in == current {
+                continue;
+            }
+            if let Some(collection) = current {
+                memberships.push(CollectionMembership {
+                    collection,
+                    verified: false,
+                    instruction_index: glazing.instruction_index.clone(),
+                });
+            }
+            if let Some(collection) = verified_in {
+                memberships.push(CollectionMembership {
+                    collection,
+                    verified: true,
+                    instruction_index: glazing.instruction_index.clone(),
+                });
+            }
+            current = verified_in;
+        }
+        memberships
+    }
+
+    pub fn update_authority(&self) -> Option<Pubkey> {
+        self.update_authorities.last().and_then(|c| c.authority)
+    }
+
+    // i.e did the update authority ever change hands after the metadata was created
+    pub fn update_authority_changed(&self) -> bool {
+        self.update_authorities.len() > 1
+    }
+
+    pub fn apply_update_authority(
+        &mut self, update_authority: &Pubkey,
+        instruction_index: InstructionIndex,
+    ) {
+        if self.update_authority() == Some(*update_authority) {
+            return;
+        }
+        self.update_authorities.push(AuthorityChange {
+            authority: Some(*update_authority),
+            instruction_index,
+        });
+    }
+
+    pub fn mint_authority(&self) -> Option<Pubkey> {
+        self.mint_authorities.last().and_then(|c| c.authority)
+    }
+
+    pub fn freeze_authority(&self) -> Option<Pubkey> {
+        self.freeze_authorities.last().and_then(|c| c.authority)
+    }
+
+    pub fn supply_status(&self) -> SupplyStatus {
+        if self.mint_authorities.is_empty() {
+            return SupplyStatus::Unknown;
+        }
+        match self.mint_authority() {
+            None => SupplyStatus::Fixed,
+            // master and limited editions both live at this PDA for their own mint
+            Some(authority) if authority == find_master_edition_account(&self.mint_key).0 => {
+                SupplyStatus::Fixed
+            }
+            Some(_) => SupplyStatus::Mintable,
+        }
+    }
+
+    fn apply_mint(&mut self, amount: u64) {
+        self.supply = self.supply.saturating_add(amount);
+        self.peak_supply = self.peak_supply.max(self.supply);
+    }
+
+    pub fn token_standard(&self) -> TokenStandard {
+        if let Some(token_standard) = &self.recorded_token_standard {
+            return token_standard.clone();
+        }
+        match self.edition_status {
+            EditionStatus::Master => TokenStandard::NonFungible,
+            EditionStatus::Limited => TokenStandard::Edition,
+            EditionStatus::None => match self.decimals {
+                Some(decimals) if decimals != 0 => TokenStandard::Fungible,
+                // the edition could just be one we haven't seen, so it's only an SFT once there
+                // has been more than one token
+                _ if self.peak_supply > 1 => TokenStandard::FungibleAsset,
+                _ => TokenStandard::NonFungible,
+            },
+        }
+    }
+
+    pub fn credit_holding(
+        &mut self, account: &Pubkey, owner: Option<Pubkey>, amount: u64,
+        instruction_index: &InstructionIndex,
+    ) {
+        if let Some(holding) = self.holdings.iter_mut().find(|h| h.account == *account) {
+            holding.amount = holding.amount.saturating_add(amount);
+            if owner.is_some() {
+                holding.owner = owner;
+            }
+            holding.instruction_index = instruction_index.clone();
+        } else {
+            self.holdings.push(Holding {
+                account: *account,
+                owner,
+                amount,
+                frozen: false,
+                instruction_index: instruction_index.clone(),
+            });
+        }
+    }
+
+    pub fn debit_holding(
+        &mut self, account: &Pubkey, amount: u64,
+        instruction_index: &InstructionIndex,
+    ) {
+        if let Some(index) = self.holdings.iter().position(|h| h.account == *account) {
+            let holding = &mut self.holdings[index];
+            holding.amount = holding.amount.saturating_sub(amount);
+            holding.instruction_index = instruction_index.clone();
+            if holding.amount == 0 {
+                self.holdings.swap_remove(index);
+                self.close_listings_for(account);
+            }
+        }
+    }
+
+    // The transaction's post balances are authoritative for every account of this mint that
+    // shows up in them, so overwrite whatever we've tracked from instruction amounts. Applied
+    // after each instruction so the holdings are right once the transaction's last instruction
+    // has been applied
+    pub fn apply_token_balances(
+        &mut self, account_keys: &[Pubkey], owners: &[TransactionTokenOwnerMeta],
+        instruction_index: &InstructionIndex,
+    ) {
+        if self.mint_key == Pubkey::default() {
+            return;
+        }
+        for meta in owners.iter().filter(|m| m.mint_key == self.mint_key) {
+            let (account, post_amount) = match (
+                account_keys.get(usize::from(meta.account_index)), meta.post_amount) {
+                (Some(account), Some(post_amount)) => (account, post_amount),
+                _ => continue,
+            };
+            let index = self.holdings.iter().position(|h| h.account == *account);
+            match (index, post_amount) {
+                (Some(index), 0) => {
+                    self.holdings.swap_remove(index);
+                    self.close_listings_for(account);
+                }
+                (Some(index), _) => {
+                    let holding = &mut self.holdings[index];
+                    if holding.amount != post_amount || holding.owner != Some(meta.owner_key) {
+                        holding.amount = post_amount;
+                        holding.owner = Some(meta.owner_key);
+                        holding.instruction_index = instruction_index.clone();
+                    }
+                }
+                (None, 0) => {}
+                (None, _) => {
+                    self.holdings.push(Holding {
+                        account: *account,
+                        owner: Some(meta.owner_key),
+                        amount: post_amount,
+                        frozen: false,
+                        instruction_index: instruction_index.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    // a token metadata transfer already set the destination owner before its token CPI, which
+    // might not find the owner of an account created in the same transaction
+    pub fn apply_transfer_destination(&mut self, account: &Pubkey, owner: Option<Pubkey>) {
+        if owner.is_some() || self.current_account != Some(*account) {
+            self.current_owner = owner;
+        }
+        self.current_account = Some(*account);
+    }
+
+    pub fn apply_frozen(&mut self, account: &Pubkey, frozen: bool) {
+        if let Some(holding) = self.holdings.iter_mut().find(|h| h.account == *account) {
+            holding.frozen = frozen;
+        }
+    }
+
+    // token record of the current account, unlocked without a delegate if we haven't seen it
+    pub fn token_record_at(&mut self, instruction_index: InstructionIndex) -> &mut TokenRecord {
+        let token_record = self.token_record.get_or_insert_with(TokenRecord::default);
+        token_record.instruction_index = instruction_index;
+        token_record
+    }
+
+    // a new token record, or one the program reset (e.g after a revoke)
+    pub fn reset_token_record(&mut self, instruction_index: InstructionIndex) {
+        self.token_record = Some(TokenRecord {
+            instruction_index,
+            ..TokenRecord::default()
+        });
+    }
+
+    pub fn apply_leaf_owner(
+        &mut self, owner: &Pubkey, delegate: &Pubkey,
+        instruction_index: InstructionIndex,
+    ) {
+        self.current_owner = Some(*owner);
+        if let Some(last) = self.leaf_owners.last() {
+            if last.owner == *owner && last.delegate == *delegate {
+                return;
+            }
+        }
+        self.leaf_owners.push(LeafOwnerChange {
+            owner: *owner,
+            delegate: *delegate,
+            instruction_index,
+        });
+    }
+
+    // replaces the wallet's earlier order for the same token account on the same side of the
+    // marketplace (a relist or a new bid)
+    pub fn apply_open_order(&mut self, order: OpenOrder) {
+        self.open_orders.retain(|o| {
+            o.side != order.side
+                || o.program_key != order.program_key
+                || o.marketplace != order.marketplace
+                || o.wallet != order.wallet
+                || o.token_account != order.token_account
+        });
+        self.open_orders.push(order);
+    }
+
+    // Escrowless marketplaces list by approving a delegate on the seller's token account. We
+    // can't tell these apart from other delegations (staking, etc) so they're all listings
+    // without a price. Marketplaces we decode approve a delegate for listings we already have
+    pub fn apply_delegate_listing(
+        &mut self, account: &Pubkey, delegate: &Pubkey, owner: &Pubkey, amount: u64,
+        instruction_index: InstructionIndex,
+    ) {
+        let listed_by_marketplace = self.open_orders.iter().any(|o| {
+            o.side == OrderSide::Listing
+                && o.program_key != spl_token::id()
+                && o.token_account == Some(*account)
+                && o.instruction_index.slot == instruction_index.slot
+                && o.instruction_index.block_index == instruction_index.block_index
+        });
+        if listed_by_marketplace {
+            return;
+        }
+        // a token account has one delegate at a time
+        self.revoke_delegate_listing(account);
+        self.open_orders.push(OpenOrder {
+            side: OrderSide::Listing,
+            program_key: spl_token::id(),
+            marketplace: None,
+            wallet: *owner,
+            token_account: Some(*account),
+            delegate: Some(*delegate),
+            price: None,
+            currency_mint: None,
+            amount,
+            instruction_index,
+        });
+    }
+
+    pub fn revoke_delegate_listing(&mut self, account: &Pubkey) {
+        self.open_orders.retain(|o| {
+            o.program_key != spl_token::id() || o.token_account != Some(*account)
+        });
+    }
+
+    // cancels identify the order by its side, wallet and price. None for orders placed without
+    // one
+    pub fn cancel_orders(
+        &mut self, program_key: &Pubkey, side: OrderSide, marketplace: Option<Pubkey>,
+        wallet: &Pubkey, price: Option<u64>,
+    ) {
+        self.open_orders.retain(|o| {
+            o.side != side
+                || o.program_key != *program_key
+                || o.marketplace != marketplace
+                || o.wallet != *wallet
+                || o.price != price
+        });
+    }
+
+    // a sale fills (some of) the seller's listing and the buyer's bid on the same marketplace
+    pub fn fill_orders(&mut self, sale: &Sale) {
+        let on_marketplace = |o: &OpenOrder| {
+            o.program_key == sale.program_key && o.marketplace == sale.marketplace
+        };
+        for listing in self.open_orders.iter_mut() {
+            if listing.side == OrderSide::Listing && on_marketplace(listing)
+                    && listing.wallet == sale.seller {
+                listing.amount = listing.amount.saturating_sub(sale.amount);
+            }
+        }
+        self.open_orders.retain(|o| match o.side {
+            OrderSide::Listing => !on_marketplace(o) || o.wallet != sale.seller || o.amount > 0,
+            OrderSide::Bid => !on_marketplace(o) || o.wallet != sale.buyer,
+        });
+    }
+
+    // listings on a token account that no longer holds the tokens can't be filled
+    fn close_listings_for(&mut self, account: &Pubkey) {
+        self.open_orders.retain(|o| {
+            o.side != OrderSide::Listing || o.token_account != Some(*account)
+        });
+    }
+
+    pub fn apply_creator_verification(
+        &mut self, creator_key: &Pubkey, verified: bool,
+        instruction_index: InstructionIndex,
+    ) {
+        if let Some(last) = self.glazings.last() {
+            let mut next: Glazing = last.clone();
+            for creator in &mut next.creators {
+                if creator.address == *creator_key {
+                    creator.verified = verified;
+                    break;
+                }
+            }
+            next.instruction_index = instruction_index;
+            self.glazings.push(next);
+        } else {
+            self.glazings.push(Glazing {
+                creators: vec![Creator { address: *creator_key, verified, share: 0 }],
+                instruction_index,
+                ..Glazing::default()
+            });
+        }
+    }
+
+    pub fn apply_collection_verification(
+        &mut self, collection_key: &Pubkey, verified: bool,
+        instruction_index: InstructionIndex,
+    ) {
+        let prev = self.glazings.last()
+            .map(|v| v.clone()).unwrap_or(Glazing::default());
+        self.glazings.push(Glazing {
+            collection: Some(Collection {
+                address: *collection_key,
+                verified,
+            }),
+            instruction_index,
+            ..prev
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadAccountKeyIndex,
+
+    FailedInstructionDeserialization,
+
+    InvalidMetadataCreate,
+
+    InvalidMetadataUpdate,
+
+    InvalidMasterEditionCreate,
+
+    // includes unverify creator/collection
+    InvalidMetadataVerifyOperation,
+
+    // the instruction needs a layout (for an account that moved) and none was registered
+    MissingInstructionLayout,
+
+    // the tree and nonce (or logged leaf) are for a different asset id
+    InvalidCompressedLeafOperation,
+}
+
+// An `ErrorCode` plus whatever context we had when it happened. Updaters only know the keys
+// involved; `Bonbon::update` fills in which instruction it was
+#[derive(Debug)]
+pub struct Error {
+    pub code: ErrorCode,
+
+    pub signature: Option<Vec<u8>>,
+
+    pub program_key: Option<Pubkey>,
+
+    pub instruction_index: Option<InstructionIndex>,
+
+    // instruction variant, e.g `UpdateMetadataAccountV2`
+    pub instruction_name: Option<String>,
+
+    pub expected_key: Option<Pubkey>,
+
+    pub actual_key: Option<Pubkey>,
+}
+
+impl From<ErrorCode> for Error {
+    fn from(code: ErrorCode) -> Self {
+        Self {
+            code,
+            signature: None,
+            program_key: None,
+            instruction_index: None,
+            instruction_name: None,
+            expected_key: None,
+            actual_key: None,
+        }
+    }
+}
+
+impl Error {
+    pub fn with_keys(mut self, expected_key: &Pubkey, actual_key: &Pubkey) -> Self {
+        self.expected_key = Some(*expected_key);
+        self.actual_key = Some(*actual_key);
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.code)?;
+        if let Some(instruction_name) = &self.instruction_name {
+            write!(f, " in {}", instruction_name)?;
+        }
+        if let Some(program_key) = &self.program_key {
+            write!(f, " of {}", program_key)?;
+        }
+        if let Some(InstructionIndex { slot, block_index, outer_index, inner_index })
+                = &self.instruction_index {
+            write!(f, " at {}.{:04x} instruction {}", slot, block_index, outer_index)?;
+            if let Some(inner_index) = inner_index {
+                write!(f, ".{}", inner_index)?;
+            }
+        }
+        if let Some(signature) = &self.signature {
+            write!(f, " [{}]", bs58::encode(signature).into_string())?;
+        }
+        if let Some(expected_key) = &self.expected_key {
+            write!(f, ", expected {}", expected_key)?;
+        }
+        if let Some(actual_key) = &self.actual_key {
+            write!(f, ", got {}", actual_key)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct TransactionTokenOwnerMeta {
+    pub account_index: u8,
+
+    pub mint_key: Pubkey,
+
+    pub owner_key: Pubkey,
+
+    // token account balances before and after the whole transaction
+    pub pre_amount: Option<u64>,
+
+    pub post_amount: Option<u64>,
+}
+
+impl From<&TransactionTokenMeta> for TransactionTokenOwnerMeta {
+    fn from(meta: &TransactionTokenMeta) -> Self {
+        Self {
+            account_index: meta.account_index,
+            mint_key: meta.mint_key,
+            owner_key: meta.owner_key,
+            pre_amount: meta.pre_amount.as_ref().and_then(|a| a.parse().ok()),
+            post_amount: meta.post_amount.as_ref().and_then(|a| a.parse().ok()),
+        }
+    }
+}
+
+pub struct InstructionContext<'a> {
+    pub signature: &'a [u8],
+
+    pub instruction: &'a CompiledInstruction,
+
+    pub account_keys: &'a [Pubkey],
+
+    pub owners: &'a [TransactionTokenOwnerMeta],
+
+    // lamports per account key before and after the whole transaction. empty if the
+    // transaction had no status meta
+    pub pre_balances: &'a [u64],
+
+    pub post_balances: &'a [u64],
+
+    // fallback for token accounts missing from `owners`
+    pub token_accounts: &'a TokenAccountRegistry,
+
+    // instruction layouts by slot for accounts and arguments that changed between upgrades
+    pub layouts: &'a LayoutRegistry,
+
+    pub instruction_index: InstructionIndex,
+}
+
+pub fn update_metadata_instruction(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction, account_keys, layouts,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    // too few accounts is as malformed as bad instruction data
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let slot_layout = layouts.layout_for(
+        &mpl_token_metadata::id(), &instruction.data, instruction_index.slot);
+    if slot_layout.map(|l| l.decoding) == Some(Decoding::Ignore) {
+        return Ok(());
+    }
+    // a bad index is left to `get_account_key` to report. it just fits no layout here
+    let accounts = instruction.accounts.iter()
+        .map(|i| account_keys.get(usize::from(*i)).copied().unwrap_or_default())
+        .collect::<Vec<_>>();
+    let (layout, metadata_instruction) = TokenMetadataInstruction::unpack_with(
+        &instruction.data, &accounts, instruction_index.slot, layouts,
+    ).map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    let get_role_key = |role: AccountRole| layout
+        .and_then(|l| l.account_index(role))
+        .ok_or(ErrorCode::MissingInstructionLayout)
+        .and_then(|index| get_account_key(index));
+
+    let metadata_instruction = match metadata_instruction {
+        TokenMetadataInstruction::Legacy(metadata_instruction) => metadata_instruction,
+        newer => {
+            return update_newer_metadata_instruction(
+                bonbon, newer, instruction, account_keys, instruction_index);
+        }
+    };
+
+    match metadata_instruction {
+        MetadataInstruction::CreateMetadataAccount(args) => {
+            // OG create metadata
+            let metadata_key = get_account_key(0)?;
+            let expected_key = find_metadata_account(&bonbon.mint_key).0;
+            if expected_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataCreate)
+                    .with_keys(&expected_key, metadata_key));
+            }
+
+            let update_authority = get_account_key(4)?;
+            bonbon.metadata_key = *metadata_key;
+            bonbon.apply_update_authority(update_authority, instruction_index.clone());
+            let mut glazing = Glazing {
+                is_mutable: args.is_mutable,
+                update_authority: Some(*update_authority),
+                instruction_index,
+                ..Glazing::default()
+            };
+            glazing.apply_data(args.data);
+            bonbon.glazings.push(glazing);
+        },
+        MetadataInstruction::CreateMetadataAccountV2(args) => {
+            // create metadata with datav2 (adds collection info, etc)
+            let metadata_key = get_account_key(0)?;
+            let expected_key = find_metadata_account(&bonbon.mint_key).0;
+            if expected_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataCreate)
+                    .with_keys(&expected_key, metadata_key));
+            }
+
+            let update_authority = get_account_key(4)?;
+            bonbon.metadata_key = *metadata_key;
+            bonbon.apply_update_authority(update_authority, instruction_index.clone());
+            let mut glazing = Glazing {
+                is_mutable: args.is_mutable,
+                update_authority: Some(*update_authority),
+                instruction_index,
+                ..Glazing::default()
+            };
+            glazing.apply_data_v2(args.data);
+            bonbon.glazings.push(glazing);
+        },
+        MetadataInstruction::UpdateMetadataAccount(args) => {
+            let metadata_key = get_account_key(0)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataUpdate)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            // the signing authority is the live update authority regardless of what we've seen
+            bonbon.apply_update_authority(get_account_key(1)?, instruction_index.clone());
+            if let Some(update_authority) = &args.update_authority {
+                bonbon.apply_update_authority(update_authority, instruction_index.clone());
+            }
+
+            if args.data.is_none()
+                    && args.update_authority.is_none()
+                    && args.primary_sale_happened.is_none() {
+                return Ok(());
+            }
+
+            let mut next = bonbon.glazings.last().cloned().unwrap_or_default();
+            if let Some(data) = args.data {
+                next.apply_data(data);
+            }
+            if let Some(update_authority) = args.update_authority {
+                next.update_authority = Some(update_authority);
+            }
+            if let Some(primary_sale_happened) = args.primary_sale_happened {
+                next.primary_sale_happened = primary_sale_happened;
+            }
+            next.instruction_index = instruction_index;
+            bonbon.glazings.push(next);
+        },
+        MetadataInstruction::UpdateMetadataAccountV2(args) => {
+            let metadata_key = get_account_key(0)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataUpdate)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            // the signing authority is the live update authority regardless of what we've seen
+            bonbon.apply_update_authority(get_account_key(1)?, instruction_index.clone());
+            if let Some(update_authority) = &args.update_authority {
+                bonbon.apply_update_authority(update_authority, instruction_index.clone());
+            }
+
+            if args.data.is_none()
+                    && args.update_authority.is_none()
+                    && args.primary_sale_happened.is_none()
+                    && args.is_mutable.is_none() {
+                return Ok(());
+            }
+
+            let mut next = bonbon.glazings.last().cloned().unwrap_or_default();
+            if let Some(data) = args.data {
+                next.apply_data_v2(data);
+            }
+            if let Some(update_authority) = args.update_authority {
+                next.update_authority = Some(update_authority);
+            }
+            if let Some(primary_sale_happened) = args.primary_sale_happened {
+                next.primary_sale_happened = primary_sale_happened;
+            }
+            if let Some(is_mutable) = args.is_mutable {
+                next.is_mutable = is_mutable;
+            }
+            next.instruction_index = instruction_index;
+            bonbon.glazings.push(next);
+        },
+        MetadataInstruction::DeprecatedCreateMasterEdition(_) => {
+            // master edition with printing tokens (and reservation list?)
+            let metadata_key = get_account_key(7)?;
+            if bonbon.metadata_key != *metadata_key
+                    || bonbon.edition_status != EditionStatus::None {
+                return Err(Error::from(ErrorCode::InvalidMasterEditionCreate)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            bonbon.edition_status = EditionStatus::Master;
+        }
+        MetadataInstruction::CreateMasterEdition(_) => {
+            // edition v2 w/ bitvec directly
+            let metadata_key = get_account_key(5)?;
+            if bonbon.metadata_key != *metadata_key
+                    || bonbon.edition_status != EditionStatus::None {
+                return Err(Error::from(ErrorCode::InvalidMasterEditionCreate)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            bonbon.edition_status = EditionStatus::Master;
+        }
+        MetadataInstruction::CreateMasterEditionV3(_) => {
+            // not sure why this exists
+            let metadata_key = get_account_key(5)?;
+            if bonbon.metadata_key != *metadata_key
+                    || bonbon.edition_status != EditionStatus::None {
+                return Err(Error::from(ErrorCode::InvalidMasterEditionCreate)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            bonbon.edition_status = EditionStatus::Master;
+        }
+        MetadataInstruction::DeprecatedMintNewEditionFromMasterEditionViaPrintingToken => {
+            // TODO: link with master edition for uri, creators, collection
+            let metadata_key = get_account_key(0)?;
+            let expected_key = find_metadata_account(&bonbon.mint_key).0;
+            if expected_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataCreate)
+                    .with_keys(&expected_key, metadata_key));
+            }
+
+            bonbon.metadata_key = *metadata_key;
+            bonbon.edition_status = EditionStatus::Limited;
+            // printing tokens don't carry an edition number
+            bonbon.limited_edition = Some(LimitedEdition {
+                master_key: *get_role_key(AccountRole::MasterMetadata)?,
+                edition_num: None,
+            });
         }
         MetadataInstruction::MintNewEditionFromMasterEditionViaToken(args) => {
             // TODO: link with master edition for uri, creators, collection
             let metadata_key = get_account_key(0)?;
-            if find_metadata_account(&bonbon.mint_key).0 != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataCreate);
+            let expected_key = find_metadata_account(&bonbon.mint_key).0;
+            if expected_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataCreate)
+                    .with_keys(&expected_key, metadata_key));
+            }
+
+            bonbon.metadata_key = *metadata_key;
+            bonbon.edition_status = EditionStatus::Limited;
+            bonbon.limited_edition = Some(LimitedEdition {
+                master_key: *get_account_key(10)?,
+                edition_num: Some(args.edition as i64),
+            });
+        }
+        MetadataInstruction::MintNewEditionFromMasterEditionViaVaultProxy(args) => {
+            // TODO: link with master edition for uri, creators, collection
+            let metadata_key = get_account_key(0)?;
+            let expected_key = find_metadata_account(&bonbon.mint_key).0;
+            if expected_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataCreate)
+                    .with_keys(&expected_key, metadata_key));
+            }
+
+            bonbon.metadata_key = *metadata_key;
+            bonbon.edition_status = EditionStatus::Limited;
+            bonbon.limited_edition = Some(LimitedEdition {
+                master_key: *get_account_key(12)?,
+                edition_num: Some(args.edition as i64),
+            });
+        }
+        MetadataInstruction::SignMetadata => {
+            let metadata_key = get_account_key(0)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataVerifyOperation)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            let creator_key = get_account_key(1)?;
+            bonbon.apply_creator_verification(creator_key, true, instruction_index);
+        }
+        MetadataInstruction::RemoveCreatorVerification => {
+            let metadata_key = get_account_key(0)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataVerifyOperation)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            let creator_key = get_account_key(1)?;
+            bonbon.apply_creator_verification(creator_key, false, instruction_index);
+        }
+        MetadataInstruction::VerifyCollection => {
+            let metadata_key = get_account_key(0)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataVerifyOperation)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            let collection_key = get_account_key(3)?;
+            bonbon.apply_collection_verification(collection_key, true, instruction_index);
+        }
+        MetadataInstruction::SetAndVerifyCollection => {
+            let metadata_key = get_account_key(0)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataVerifyOperation)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            let collection_key = get_account_key(4)?;
+            bonbon.apply_collection_verification(collection_key, true, instruction_index);
+        }
+        MetadataInstruction::UnverifyCollection => {
+            let metadata_key = get_account_key(0)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataVerifyOperation)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            // no payer here, so the collection mint comes one account earlier than in
+            // `VerifyCollection`
+            let collection_key = get_account_key(2)?;
+            bonbon.apply_collection_verification(collection_key, false, instruction_index);
+        }
+        MetadataInstruction::UpdatePrimarySaleHappenedViaToken => {
+            let metadata_key = get_account_key(0)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataUpdate)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            let mut next = bonbon.glazings.last().cloned().unwrap_or_default();
+            if !next.primary_sale_happened {
+                next.primary_sale_happened = true;
+                next.instruction_index = instruction_index;
+                bonbon.glazings.push(next);
+            }
+        }
+        MetadataInstruction::DeprecatedSetReservationList(_) => { }
+        MetadataInstruction::DeprecatedCreateReservationList => { }
+        MetadataInstruction::DeprecatedMintPrintingTokensViaToken(_) => { }
+        MetadataInstruction::DeprecatedMintPrintingTokens(_) => { }
+        MetadataInstruction::ConvertMasterEditionV1ToV2 => { }
+        MetadataInstruction::PuffMetadata => { }
+        MetadataInstruction::Utilize(_) => { }
+        MetadataInstruction::ApproveUseAuthority(_) => { }
+        MetadataInstruction::RevokeUseAuthority => { }
+        MetadataInstruction::ApproveCollectionAuthority => {
+            let metadata_key = get_account_key(4)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataUpdate)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            bonbon.collection_authorities.push(CollectionAuthorityChange {
+                authority: *get_account_key(1)?,
+                record: *get_account_key(0)?,
+                approved: true,
+                instruction_index,
+            });
+        }
+        MetadataInstruction::RevokeCollectionAuthority => {
+            let metadata_key = get_account_key(3)?;
+            if bonbon.metadata_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataUpdate)
+                    .with_keys(&bonbon.metadata_key, metadata_key));
+            }
+
+            bonbon.collection_authorities.push(CollectionAuthorityChange {
+                authority: *get_account_key(1)?,
+                record: *get_account_key(0)?,
+                approved: false,
+                instruction_index,
+            });
+        }
+        MetadataInstruction::FreezeDelegatedAccount => { }
+        MetadataInstruction::ThawDelegatedAccount => { }
+    }
+
+    Ok(())
+}
+
+// Instructions added after the token metadata version we build against. Optional accounts of the
+// newer API are passed as the program id when they aren't used
+fn update_newer_metadata_instruction(
+    bonbon: &mut Bonbon,
+    metadata_instruction: TokenMetadataInstruction,
+    instruction: &CompiledInstruction,
+    account_keys: &[Pubkey],
+    instruction_index: InstructionIndex,
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    // optional accounts can also be left off the end of the list
+    let get_optional_account_key = |index: usize| -> Result<Option<&Pubkey>, ErrorCode> {
+        match instruction.accounts.get(index) {
+            Some(account_index) => account_keys.get(usize::from(*account_index))
+                .ok_or(ErrorCode::BadAccountKeyIndex)
+                .map(|key| (*key != mpl_token_metadata::id()).then(|| key)),
+            None => Ok(None),
+        }
+    };
+
+    let check_metadata_key = |index: usize, code: ErrorCode| -> Result<(), Error> {
+        let metadata_key = get_account_key(index)?;
+        if bonbon.metadata_key != *metadata_key {
+            return Err(Error::from(code).with_keys(&bonbon.metadata_key, metadata_key));
+        }
+        Ok(())
+    };
+
+    match metadata_instruction {
+        TokenMetadataInstruction::Legacy(_) => {
+            // handled by `update_metadata_instruction`
+        }
+        TokenMetadataInstruction::CreateMetadataAccountV3(args) => {
+            // same as v2 plus collection details for collection NFTs
+            let metadata_key = get_account_key(0)?;
+            let expected_key = find_metadata_account(&bonbon.mint_key).0;
+            if expected_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataCreate)
+                    .with_keys(&expected_key, metadata_key));
+            }
+
+            let update_authority = get_account_key(4)?;
+            bonbon.metadata_key = *metadata_key;
+            bonbon.apply_update_authority(update_authority, instruction_index.clone());
+            let mut glazing = Glazing {
+                is_mutable: args.is_mutable,
+                update_authority: Some(*update_authority),
+                instruction_index,
+                ..Glazing::default()
+            };
+            glazing.apply_data_v2(args.data);
+            bonbon.glazings.push(glazing);
+            bonbon.collection_size = args.collection_details.map(u64::from);
+        }
+        TokenMetadataInstruction::Create(CreateArgs::V1 { asset_data, decimals, .. }) => {
+            // the mint can be initialized by this instruction (in a CPI we haven't seen yet).
+            // every key is checked before anything is set
+            let mint_key = if bonbon.mint_key == Pubkey::default() {
+                *get_account_key(2)?
+            } else {
+                bonbon.mint_key
+            };
+            let metadata_key = get_account_key(0)?;
+            let expected_key = find_metadata_account(&mint_key).0;
+            if expected_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataCreate)
+                    .with_keys(&expected_key, metadata_key));
+            }
+            let update_authority = get_account_key(5)?;
+            let master_edition = get_optional_account_key(1)?;
+
+            bonbon.mint_key = mint_key;
+            if bonbon.decimals.is_none() {
+                bonbon.decimals = decimals;
+            }
+            bonbon.metadata_key = *metadata_key;
+            bonbon.apply_update_authority(update_authority, instruction_index.clone());
+            bonbon.recorded_token_standard = Some(asset_data.token_standard.into());
+            bonbon.rule_set = asset_data.rule_set;
+            bonbon.collection_size = asset_data.collection_details.map(u64::from);
+            if master_edition.is_some() {
+                bonbon.edition_status = EditionStatus::Master;
+            }
+
+            bonbon.glazings.push(Glazing {
+                name: asset_data.name.into_bytes(),
+                symbol: asset_data.symbol.into_bytes(),
+                uri: asset_data.uri.into_bytes(),
+                seller_fee_basis_points: asset_data.seller_fee_basis_points,
+                creators: from_creators(asset_data.creators),
+                collection: asset_data.collection.map(Collection::from),
+                uses: asset_data.uses.map(Uses::from),
+                primary_sale_happened: asset_data.primary_sale_happened,
+                is_mutable: asset_data.is_mutable,
+                update_authority: Some(*update_authority),
+                instruction_index,
+            });
+        }
+        TokenMetadataInstruction::Update(args) => {
+            check_metadata_key(4, ErrorCode::InvalidMetadataUpdate)?;
+
+            let mut next = bonbon.glazings.last().cloned().unwrap_or_default();
+            let mut new_update_authority = None;
+            let token_standard = match &args {
+                UpdateArgs::AsUpdateAuthorityV2 { token_standard, .. }
+                        | UpdateArgs::AsAuthorityItemDelegateV2 { token_standard, .. } => {
+                    *token_standard
+                }
+                _ => None,
+            };
+            match args {
+                UpdateArgs::V1 {
+                    new_update_authority: authority, data, primary_sale_happened, is_mutable,
+                    collection, collection_details, uses, rule_set,
+                } | UpdateArgs::AsUpdateAuthorityV2 {
+                    new_update_authority: authority, data, primary_sale_happened, is_mutable,
+                    collection, collection_details, uses, rule_set, ..
+                } => {
+                    // signed by the live update authority
+                    bonbon.apply_update_authority(
+                        get_account_key(0)?, instruction_index.clone());
+                    new_update_authority = authority;
+                    if let Some(data) = data {
+                        next.apply_data(data);
+                    }
+                    if let Some(primary_sale_happened) = primary_sale_happened {
+                        next.primary_sale_happened = primary_sale_happened;
+                    }
+                    if let Some(is_mutable) = is_mutable {
+                        next.is_mutable = is_mutable;
+                    }
+                    collection.apply(&mut next.collection);
+                    collection_details.apply(&mut bonbon.collection_size);
+                    uses.apply(&mut next.uses);
+                    rule_set.apply(&mut bonbon.rule_set);
+                }
+                UpdateArgs::AsAuthorityItemDelegateV2 {
+                    new_update_authority: authority, primary_sale_happened, is_mutable, ..
+                } => {
+                    new_update_authority = authority;
+                    if let Some(primary_sale_happened) = primary_sale_happened {
+                        next.primary_sale_happened = primary_sale_happened;
+                    }
+                    if let Some(is_mutable) = is_mutable {
+                        next.is_mutable = is_mutable;
+                    }
+                }
+                UpdateArgs::AsCollectionDelegateV2 { collection }
+                        | UpdateArgs::AsCollectionItemDelegateV2 { collection } => {
+                    collection.apply(&mut next.collection);
+                }
+                UpdateArgs::AsDataDelegateV2 { data }
+                        | UpdateArgs::AsDataItemDelegateV2 { data } => {
+                    if let Some(data) = data {
+                        next.apply_data(data);
+                    }
+                }
+                UpdateArgs::AsProgrammableConfigDelegateV2 { rule_set }
+                        | UpdateArgs::AsProgrammableConfigItemDelegateV2 { rule_set } => {
+                    // nothing on the metadata account's data changes
+                    rule_set.apply(&mut bonbon.rule_set);
+                    return Ok(());
+                }
             }
 
-            bonbon.metadata_key = *metadata_key;
-            bonbon.edition_status = EditionStatus::Limited;
-            bonbon.limited_edition = Some(LimitedEdition {
-                master_key: *get_account_key(10)?,
-                edition_num: Some(args.edition as i64),
-            });
+            if let Some(update_authority) = new_update_authority {
+                bonbon.apply_update_authority(&update_authority, instruction_index.clone());
+                next.update_authority = Some(update_authority);
+            }
+            if let Some(token_standard) = token_standard {
+                bonbon.recorded_token_standard = Some(token_standard.into());
+            }
+            next.instruction_index = instruction_index;
+            bonbon.glazings.push(next);
         }
-        MetadataInstruction::MintNewEditionFromMasterEditionViaVaultProxy(args) => {
+        TokenMetadataInstruction::Print(PrintArgs::V1 { edition }) => {
             // TODO: link with master edition for uri, creators, collection
+            let mint_key = if bonbon.mint_key == Pubkey::default() {
+                *get_account_key(2)?
+            } else {
+                bonbon.mint_key
+            };
             let metadata_key = get_account_key(0)?;
-            if find_metadata_account(&bonbon.mint_key).0 != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataCreate);
+            let expected_key = find_metadata_account(&mint_key).0;
+            if expected_key != *metadata_key {
+                return Err(Error::from(ErrorCode::InvalidMetadataCreate)
+                    .with_keys(&expected_key, metadata_key));
             }
+            let master_key = get_account_key(12)?;
 
+            bonbon.mint_key = mint_key;
             bonbon.metadata_key = *metadata_key;
             bonbon.edition_status = EditionStatus::Limited;
             bonbon.limited_edition = Some(LimitedEdition {
-                master_key: *get_account_key(12)?,
-                edition_num: Some(args.edition as i64),
+                master_key: *master_key,
+                edition_num: Some(edition as i64),
             });
         }
-        MetadataInstruction::SignMetadata => {
-            let metadata_key = get_account_key(0)?;
-            if bonbon.metadata_key != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataVerifyOperation);
+        TokenMetadataInstruction::VerifySizedCollectionItem => {
+            check_metadata_key(0, ErrorCode::InvalidMetadataVerifyOperation)?;
+            let collection_key = get_account_key(3)?;
+            bonbon.apply_collection_verification(collection_key, true, instruction_index);
+        }
+        TokenMetadataInstruction::SetAndVerifySizedCollectionItem => {
+            check_metadata_key(0, ErrorCode::InvalidMetadataVerifyOperation)?;
+            let collection_key = get_account_key(4)?;
+            bonbon.apply_collection_verification(collection_key, true, instruction_index);
+        }
+        TokenMetadataInstruction::UnverifySizedCollectionItem => {
+            check_metadata_key(0, ErrorCode::InvalidMetadataVerifyOperation)?;
+            let collection_key = get_account_key(3)?;
+            bonbon.apply_collection_verification(collection_key, false, instruction_index);
+        }
+        TokenMetadataInstruction::Verify(args) | TokenMetadataInstruction::Unverify(args) => {
+            check_metadata_key(2, ErrorCode::InvalidMetadataVerifyOperation)?;
+            let verified = matches!(metadata_instruction, TokenMetadataInstruction::Verify(_));
+            match args {
+                VerificationArgs::CreatorV1 => {
+                    let creator_key = get_account_key(0)?;
+                    bonbon.apply_creator_verification(creator_key, verified, instruction_index);
+                }
+                VerificationArgs::CollectionV1 => {
+                    let collection_key = get_account_key(3)?;
+                    bonbon.apply_collection_verification(
+                        collection_key, verified, instruction_index);
+                }
             }
-
-            let creator_key = get_account_key(1)?;
-            bonbon.apply_creator_verification(creator_key, true, instruction_index);
         }
-        MetadataInstruction::RemoveCreatorVerification => {
-            let metadata_key = get_account_key(0)?;
-            if bonbon.metadata_key != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataVerifyOperation);
+        TokenMetadataInstruction::Migrate => {
+            // only ever from a non-fungible to a programmable non-fungible
+            check_metadata_key(0, ErrorCode::InvalidMetadataUpdate)?;
+            bonbon.recorded_token_standard = Some(TokenStandard::ProgrammableNonFungible);
+            bonbon.rule_set = get_optional_account_key(14)?.copied();
+            bonbon.reset_token_record(instruction_index);
+        }
+        TokenMetadataInstruction::Mint(_) => {
+            // the token CPIs pick up the supply and holdings. programmable assets also get a
+            // token record for the destination account
+            check_metadata_key(2, ErrorCode::InvalidMetadataUpdate)?;
+            if get_optional_account_key(4)?.is_some() {
+                bonbon.reset_token_record(instruction_index);
             }
-
-            let creator_key = get_account_key(1)?;
-            bonbon.apply_creator_verification(creator_key, false, instruction_index);
         }
-        MetadataInstruction::VerifyCollection => {
-            let metadata_key = get_account_key(0)?;
-            if bonbon.metadata_key != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataVerifyOperation);
+        TokenMetadataInstruction::Transfer(_) => {
+            // the token CPI moves the balance (thawing and freezing around it for programmable
+            // assets) but the destination owner is right here even if the destination account
+            // only shows up as transient
+            check_metadata_key(5, ErrorCode::InvalidMetadataUpdate)?;
+            bonbon.current_owner = Some(*get_account_key(3)?);
+            bonbon.current_account = Some(*get_account_key(2)?);
+            if get_optional_account_key(8)?.is_some() {
+                bonbon.reset_token_record(instruction_index);
             }
-
-            let collection_key = get_account_key(3)?;
-            bonbon.apply_collection_verification(collection_key, true, instruction_index);
         }
-        MetadataInstruction::SetAndVerifyCollection => {
-            let metadata_key = get_account_key(0)?;
-            if bonbon.metadata_key != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataVerifyOperation);
+        TokenMetadataInstruction::Delegate(DelegateArgs::CollectionV1) => {
+            check_metadata_key(2, ErrorCode::InvalidMetadataUpdate)?;
+            bonbon.collection_authorities.push(CollectionAuthorityChange {
+                authority: *get_account_key(1)?,
+                record: *get_account_key(0)?,
+                approved: true,
+                instruction_index,
+            });
+        }
+        TokenMetadataInstruction::Delegate(args) => {
+            check_metadata_key(2, ErrorCode::InvalidMetadataUpdate)?;
+            // metadata delegates live in their own records and token delegates of
+            // non-programmable assets are a plain approve
+            let role = match TokenDelegateRole::from_delegate_args(&args) {
+                Some(role) if get_optional_account_key(4)?.is_some() => role,
+                _ => return Ok(()),
+            };
+            let delegate = *get_account_key(1)?;
+            let token_record = bonbon.token_record_at(instruction_index);
+            token_record.state = if role == TokenDelegateRole::Sale {
+                TokenState::Listed
+            } else {
+                TokenState::Unlocked
+            };
+            token_record.delegate = Some(delegate);
+            token_record.delegate_role = Some(role);
+        }
+        TokenMetadataInstruction::Revoke(RevokeArgs::CollectionV1) => {
+            check_metadata_key(2, ErrorCode::InvalidMetadataUpdate)?;
+            bonbon.collection_authorities.push(CollectionAuthorityChange {
+                authority: *get_account_key(1)?,
+                record: *get_account_key(0)?,
+                approved: false,
+                instruction_index,
+            });
+        }
+        TokenMetadataInstruction::Revoke(args) => {
+            check_metadata_key(2, ErrorCode::InvalidMetadataUpdate)?;
+            if TokenDelegateRole::from_revoke_args(args).is_some()
+                    && get_optional_account_key(4)?.is_some() {
+                bonbon.reset_token_record(instruction_index);
             }
-
-            let collection_key = get_account_key(4)?;
-            bonbon.apply_collection_verification(collection_key, true, instruction_index);
         }
-        MetadataInstruction::UnverifyCollection => {
-            let metadata_key = get_account_key(0)?;
-            if bonbon.metadata_key != *metadata_key {
-                return Err(ErrorCode::InvalidMetadataVerifyOperation);
+        TokenMetadataInstruction::Lock | TokenMetadataInstruction::Unlock => {
+            // non-programmable assets are frozen / thawed instead, see `update_token_instruction`
+            check_metadata_key(4, ErrorCode::InvalidMetadataUpdate)?;
+            if get_optional_account_key(6)?.is_some() {
+                let locked = matches!(metadata_instruction, TokenMetadataInstruction::Lock);
+                bonbon.token_record_at(instruction_index).state = if locked {
+                    TokenState::Locked
+                } else {
+                    TokenState::Unlocked
+                };
             }
-
-            let collection_key = get_account_key(3)?;
-            bonbon.apply_collection_verification(collection_key, true, instruction_index);
         }
-        MetadataInstruction::UpdatePrimarySaleHappenedViaToken => { }
-        MetadataInstruction::DeprecatedSetReservationList(_) => { }
-        MetadataInstruction::DeprecatedCreateReservationList => { }
-        MetadataInstruction::DeprecatedMintPrintingTokensViaToken(_) => { }
-        MetadataInstruction::DeprecatedMintPrintingTokens(_) => { }
-        MetadataInstruction::ConvertMasterEditionV1ToV2 => { }
-        MetadataInstruction::PuffMetadata => { }
-        MetadataInstruction::Utilize(_) => { }
-        MetadataInstruction::ApproveUseAuthority(_) => { }
-        MetadataInstruction::RevokeUseAuthority => { }
-        MetadataInstruction::ApproveCollectionAuthority => { }
-        MetadataInstruction::RevokeCollectionAuthority => { }
-        MetadataInstruction::FreezeDelegatedAccount => { }
-        MetadataInstruction::ThawDelegatedAccount => { }
+        // the program derives this from the mint and edition same as `token_standard`
+        TokenMetadataInstruction::SetTokenStandard => { }
+        // token burns are picked up from their CPIs into the token program
+        TokenMetadataInstruction::BurnNft => { }
+        TokenMetadataInstruction::BurnEditionNft => { }
+        TokenMetadataInstruction::Burn(_) => { }
+        TokenMetadataInstruction::SetCollectionSize(args)
+                | TokenMetadataInstruction::BubblegumSetCollectionSize(args) => {
+            check_metadata_key(0, ErrorCode::InvalidMetadataUpdate)?;
+            bonbon.collection_size = Some(args.size);
+        }
+        TokenMetadataInstruction::CreateEscrowAccount => { }
+        TokenMetadataInstruction::CloseEscrowAccount => { }
+        TokenMetadataInstruction::TransferOutOfEscrow => { }
+        TokenMetadataInstruction::Use => { }
+        TokenMetadataInstruction::Collect => { }
     }
 
     Ok(())
@@ -410,101 +1968,782 @@ pub fn update_metadata_instruction(
 pub fn update_token_instruction(
     bonbon: &mut Bonbon,
     InstructionContext {
-        instruction, account_keys, owners, ..
+        instruction, account_keys, owners, token_accounts,
+        instruction_index, ..
     }: InstructionContext,
-) -> Result<(), ErrorCode> {
-    let get_account_key = |index: usize| account_keys.get(
-        usize::from(instruction.accounts[index])
-    ).ok_or(ErrorCode::BadAccountKeyIndex);
-
-    let get_token_meta_for = |index: usize| {
-        let index = instruction.accounts[index];
-        owners.iter().find(|m| m.account_index == index)
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let get_owner_for = |index: usize| -> Result<Option<Pubkey>, ErrorCode> {
+        let account_index = *instruction.accounts.get(index)
+            .ok_or(ErrorCode::FailedInstructionDeserialization)?;
+        if let Some(meta) = owners.iter().find(|m| m.account_index == account_index) {
+            return Ok(Some(meta.owner_key));
+        }
+        Ok(token_accounts.owner_at(get_account_key(index)?, &instruction_index))
     };
 
-    let token_instruction = TokenInstruction::unpack(&instruction.data)
-        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    let token_instruction = match SplTokenInstruction::unpack(&instruction.data)
+            .map_err(|_| ErrorCode::FailedInstructionDeserialization)?
+            .into_legacy() {
+        Some(token_instruction) => token_instruction,
+        None => return Ok(()),
+    };
 
     match token_instruction {
-        TokenInstruction::InitializeMint { .. } => {
+        // and `InitializeMint2`
+        TokenInstruction::InitializeMint { decimals, mint_authority, freeze_authority } => {
             bonbon.mint_key = *get_account_key(0)?;
+            bonbon.decimals = Some(decimals);
+            bonbon.mint_authorities.push(AuthorityChange {
+                authority: Some(mint_authority),
+                instruction_index: instruction_index.clone(),
+            });
+            bonbon.freeze_authorities.push(AuthorityChange {
+                authority: freeze_authority.into(),
+                instruction_index,
+            });
         },
         // initializing an account doesn't change who currently owns it
         TokenInstruction::InitializeAccount { .. } => {},
         TokenInstruction::InitializeAccount2 { .. } => {},
-        TokenInstruction::Transfer { .. } => {
-            bonbon.current_owner = get_token_meta_for(1).map(|m| m.owner_key);
-            bonbon.current_account = Some(*get_account_key(1)?);
+        TokenInstruction::Transfer { amount } => {
+            bonbon.apply_transfer_destination(get_account_key(1)?, get_owner_for(1)?);
+            bonbon.debit_holding(get_account_key(0)?, amount, &instruction_index);
+            bonbon.credit_holding(
+                get_account_key(1)?, bonbon.current_owner, amount, &instruction_index);
         }
-        TokenInstruction::SetAuthority { authority_type, .. } => {
+        TokenInstruction::SetAuthority { authority_type, new_authority } => {
             match authority_type {
+                AuthorityType::MintTokens => {
+                    bonbon.mint_authorities.push(AuthorityChange {
+                        authority: new_authority.into(),
+                        instruction_index,
+                    });
+                }
+                AuthorityType::FreezeAccount => {
+                    bonbon.freeze_authorities.push(AuthorityChange {
+                        authority: new_authority.into(),
+                        instruction_index,
+                    });
+                }
                 AuthorityType::AccountOwner => {
                     // no account change. owner changes though possibly
-                    // TODO
+                    let account_key = get_account_key(0)?;
+                    if bonbon.current_account == Some(*account_key) {
+                        bonbon.current_owner = new_authority.into();
+                    }
+                    if let Some(holding) = bonbon.holdings.iter_mut()
+                            .find(|h| h.account == *account_key) {
+                        holding.owner = new_authority.into();
+                    }
                 }
                 _ => {}
             }
         }
-        TokenInstruction::MintTo { .. } => {
-            bonbon.current_owner = get_token_meta_for(1).map(|m| m.owner_key);
+        TokenInstruction::MintTo { amount } => {
+            bonbon.current_owner = get_owner_for(1)?;
             bonbon.current_account = Some(*get_account_key(1)?);
+            bonbon.apply_mint(amount);
+            bonbon.credit_holding(
+                get_account_key(1)?, bonbon.current_owner, amount, &instruction_index);
         }
-        TokenInstruction::Burn { .. } => {
+        TokenInstruction::Burn { amount } => {
             bonbon.current_owner = None;
             bonbon.current_account = None;
+            bonbon.supply = bonbon.supply.saturating_sub(amount);
+            bonbon.debit_holding(get_account_key(0)?, amount, &instruction_index);
         }
-        TokenInstruction::TransferChecked { .. } => {
-            bonbon.current_owner = get_token_meta_for(2).map(|m| m.owner_key);
-            bonbon.current_account = Some(*get_account_key(2)?);
+        TokenInstruction::TransferChecked { amount, .. } => {
+            bonbon.apply_transfer_destination(get_account_key(2)?, get_owner_for(2)?);
+            bonbon.debit_holding(get_account_key(0)?, amount, &instruction_index);
+            bonbon.credit_holding(
+                get_account_key(2)?, bonbon.current_owner, amount, &instruction_index);
         }
-        TokenInstruction::MintToChecked { .. } => {
-            bonbon.current_owner = get_token_meta_for(1).map(|m| m.owner_key);
+        TokenInstruction::MintToChecked { amount, .. } => {
+            bonbon.current_owner = get_owner_for(1)?;
             bonbon.current_account = Some(*get_account_key(1)?);
+            bonbon.apply_mint(amount);
+            bonbon.credit_holding(
+                get_account_key(1)?, bonbon.current_owner, amount, &instruction_index);
         }
-        TokenInstruction::BurnChecked { .. } => {
+        TokenInstruction::BurnChecked { amount, .. } => {
             bonbon.current_owner = None;
             bonbon.current_account = None;
+            bonbon.supply = bonbon.supply.saturating_sub(amount);
+            bonbon.debit_holding(get_account_key(0)?, amount, &instruction_index);
         }
         TokenInstruction::InitializeMultisig { .. } => {}
-        TokenInstruction::Approve { .. } => {}
-        TokenInstruction::Revoke => {}
+        TokenInstruction::Approve { amount } => {
+            bonbon.apply_delegate_listing(
+                get_account_key(0)?, get_account_key(1)?, get_account_key(2)?, amount,
+                instruction_index);
+        }
+        TokenInstruction::Revoke => {
+            bonbon.revoke_delegate_listing(get_account_key(0)?);
+        }
         TokenInstruction::CloseAccount => {
             // mints can't be closed and a token account must have zero balance to be closed so...
         }
-        TokenInstruction::FreezeAccount => {}
-        TokenInstruction::ThawAccount => {}
-        TokenInstruction::ApproveChecked { .. } => {}
+        // programmable assets are thawed and frozen again around every token metadata
+        // instruction that moves them
+        TokenInstruction::FreezeAccount => {
+            bonbon.apply_frozen(get_account_key(0)?, true);
+        }
+        TokenInstruction::ThawAccount => {
+            bonbon.apply_frozen(get_account_key(0)?, false);
+        }
+        TokenInstruction::ApproveChecked { amount, .. } => {
+            bonbon.apply_delegate_listing(
+                get_account_key(0)?, get_account_key(2)?, get_account_key(3)?, amount,
+                instruction_index);
+        }
         TokenInstruction::SyncNative => {}
     }
 
     Ok(())
 }
 
+pub fn update_bubblegum_instruction(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction, account_keys,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    // leaf instructions pass the tree and nonce. we may not have seen the mint (or its leaf
+    // schema) so the first of them can also tell us the asset id
+    let check_leaf = |bonbon: &mut Bonbon, tree_index: usize, nonce: u64| -> Result<(), Error> {
+        let tree_key = get_account_key(tree_index)?;
+        let asset_id = find_asset_id(tree_key, nonce);
+        if bonbon.mint_key == Pubkey::default() {
+            bonbon.mint_key = asset_id;
+            bonbon.metadata_key = asset_id;
+        } else if bonbon.mint_key != asset_id {
+            return Err(Error::from(ErrorCode::InvalidCompressedLeafOperation)
+                .with_keys(&bonbon.mint_key, &asset_id));
+        }
+        bonbon.compressed_leaf.get_or_insert(CompressedLeaf {
+            tree: *tree_key,
+            nonce,
+            redeemed: false,
+        });
+        Ok(())
+    };
+
+    let bubblegum_instruction = BubblegumInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+
+    match bubblegum_instruction {
+        BubblegumInstruction::CreateTree => {}
+        BubblegumInstruction::SetTreeDelegate => {}
+        BubblegumInstruction::MintV1(args) => {
+            mint_compressed_leaf(
+                bonbon, args, get_account_key(3)?, None,
+                get_account_key(1)?, get_account_key(2)?, instruction_index);
+        }
+        BubblegumInstruction::MintToCollectionV1(args) => {
+            mint_compressed_leaf(
+                bonbon, args, get_account_key(3)?, Some(get_account_key(8)?),
+                get_account_key(1)?, get_account_key(2)?, instruction_index);
+        }
+        BubblegumInstruction::Transfer(args) => {
+            check_leaf(bonbon, 4, args.nonce)?;
+            // the delegate is reset to the new owner
+            let new_owner = get_account_key(3)?;
+            bonbon.apply_leaf_owner(new_owner, new_owner, instruction_index);
+        }
+        BubblegumInstruction::Delegate(args) => {
+            check_leaf(bonbon, 4, args.nonce)?;
+            bonbon.apply_leaf_owner(
+                get_account_key(1)?, get_account_key(3)?, instruction_index);
+        }
+        BubblegumInstruction::Burn(args) => {
+            check_leaf(bonbon, 3, args.nonce)?;
+            bonbon.current_owner = None;
+            bonbon.supply = 0;
+        }
+        BubblegumInstruction::Redeem(args) => {
+            check_leaf(bonbon, 3, args.nonce)?;
+            if let Some(leaf) = &mut bonbon.compressed_leaf {
+                leaf.redeemed = true;
+            }
+        }
+        BubblegumInstruction::CancelRedeem => {
+            // no nonce but it's partitioned by the leaf logged after it
+            if let Some(leaf) = &mut bonbon.compressed_leaf {
+                leaf.redeemed = false;
+            }
+        }
+        BubblegumInstruction::DecompressV1(_) => {
+            // a regular mint (at the asset id) and metadata from here on, which the token and
+            // token metadata CPIs that follow set up
+            let mint_key = get_account_key(3)?;
+            if bonbon.mint_key != Pubkey::default() && bonbon.mint_key != *mint_key {
+                return Err(Error::from(ErrorCode::InvalidCompressedLeafOperation)
+                    .with_keys(&bonbon.mint_key, mint_key));
+            }
+            bonbon.compressed_leaf = None;
+        }
+        BubblegumInstruction::Compress => {
+            // the mint is burned into a leaf, which carries on under its asset id from the leaf
+            // schema logged after this. the nonce is only known there
+            let mint_key = get_account_key(5)?;
+            if bonbon.mint_key != Pubkey::default() && bonbon.mint_key != *mint_key {
+                return Err(Error::from(ErrorCode::InvalidCompressedLeafOperation)
+                    .with_keys(&bonbon.mint_key, mint_key));
+            }
+            bonbon.compressed_leaf = Some(CompressedLeaf {
+                tree: *get_account_key(3)?,
+                nonce: 0,
+                redeemed: false,
+            });
+            // the token burn CPI'd through token metadata debits the same again, which is a no-op
+            let token_account = get_account_key(4)?;
+            let held = bonbon.holdings.iter()
+                .find(|h| h.account == *token_account)
+                .map_or(0, |h| h.amount);
+            bonbon.current_owner = None;
+            bonbon.current_account = None;
+            bonbon.supply = bonbon.supply.saturating_sub(held);
+            bonbon.debit_holding(token_account, held, &instruction_index);
+        }
+        BubblegumInstruction::VerifyCreator(args) => {
+            check_leaf(bonbon, 3, args.nonce)?;
+            bonbon.apply_creator_verification(get_account_key(5)?, true, instruction_index);
+        }
+        BubblegumInstruction::UnverifyCreator(args) => {
+            check_leaf(bonbon, 3, args.nonce)?;
+            bonbon.apply_creator_verification(get_account_key(5)?, false, instruction_index);
+        }
+        BubblegumInstruction::VerifyCollection(args) => {
+            check_leaf(bonbon, 3, args.nonce)?;
+            bonbon.apply_collection_verification(
+                get_account_key(8)?, true, instruction_index);
+        }
+        BubblegumInstruction::UnverifyCollection(args) => {
+            check_leaf(bonbon, 3, args.nonce)?;
+            bonbon.apply_collection_verification(
+                get_account_key(8)?, false, instruction_index);
+        }
+        BubblegumInstruction::SetAndVerifyCollection(args, collection_args) => {
+            check_leaf(bonbon, 3, args.nonce)?;
+            bonbon.apply_collection_verification(
+                &collection_args.collection, true, instruction_index);
+        }
+        BubblegumInstruction::SetDecompressibleState => {}
+        BubblegumInstruction::UpdateMetadata(args) => {
+            check_leaf(bonbon, 8, args.nonce)?;
+
+            // the current metadata is proven against the leaf so it's what the update applies to,
+            // whatever we've seen
+            let update_authority = bonbon.glazings.last().and_then(|g| g.update_authority);
+            let mut next = Glazing {
+                update_authority,
+                instruction_index,
+                ..Glazing::default()
+            };
+            next.apply_metadata_args(args.current_metadata);
+            next.apply_update_args(args.update_args);
+            bonbon.glazings.push(next);
+        }
+    }
+
+    Ok(())
+}
+
+// the asset id and nonce come with the leaf schema logged after the mint
+fn mint_compressed_leaf(
+    bonbon: &mut Bonbon,
+    args: MetadataArgs,
+    tree_key: &Pubkey,
+    collection_key: Option<&Pubkey>,
+    owner: &Pubkey,
+    delegate: &Pubkey,
+    instruction_index: InstructionIndex,
+) {
+    bonbon.recorded_token_standard = Some(args.token_standard
+        .map(TokenStandard::from)
+        .unwrap_or(TokenStandard::NonFungible));
+    let mut glazing = Glazing {
+        instruction_index: instruction_index.clone(),
+        ..Glazing::default()
+    };
+    glazing.apply_metadata_args(args);
+    if let Some(collection_key) = collection_key {
+        // the program verifies the collection it mints into
+        glazing.collection = Some(Collection {
+            address: *collection_key,
+            verified: true,
+        });
+    }
+    bonbon.glazings.push(glazing);
+
+    bonbon.compressed_leaf = Some(CompressedLeaf {
+        tree: *tree_key,
+        nonce: 0,
+        redeemed: false,
+    });
+    bonbon.supply = 1;
+    bonbon.peak_supply = 1;
+    bonbon.apply_leaf_owner(owner, delegate, instruction_index);
+}
+
+// The leaf bubblegum logs after changing it. Sets the asset id and nonce for mints and keeps
+// the owner and delegate in step with the tree
+pub fn update_leaf_schema_event(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    let LeafSchema::V1 { id, owner, delegate, nonce, .. } = unpack_leaf_schema(&instruction.data)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)?;
+
+    if bonbon.mint_key == Pubkey::default() {
+        bonbon.mint_key = id;
+        bonbon.metadata_key = id;
+    } else if bonbon.mint_key != id {
+        return Err(Error::from(ErrorCode::InvalidCompressedLeafOperation)
+            .with_keys(&bonbon.mint_key, &id));
+    }
+    if let Some(leaf) = &mut bonbon.compressed_leaf {
+        leaf.nonce = nonce;
+    }
+    bonbon.apply_leaf_owner(&owner, &delegate, instruction_index);
+
+    Ok(())
+}
+
+pub fn update_candy_machine_instruction(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction, account_keys, pre_balances, post_balances,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let lamports_received = |index: usize| -> Option<u64> {
+        let key_index = usize::from(*instruction.accounts.get(index)?);
+        Some(post_balances.get(key_index)?.saturating_sub(*pre_balances.get(key_index)?))
+    };
+
+    let candy_machine_instruction = CandyMachineInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    if !matches!(candy_machine_instruction, CandyMachineInstruction::MintNft) {
+        // only mints are partitioned
+        return Ok(());
+    }
+
+    // v1 and v2 both take the payer at 2 and the wallet it pays at 3
+    let program_key = account_keys.get(usize::from(instruction.program_id_index));
+    let candy_machine_mint = if program_key == Some(&programs::candy_machine::id()) {
+        CandyMachineMint {
+            version: CandyMachineVersion::V1,
+            candy_machine: *get_account_key(1)?,
+            config: Some(*get_account_key(0)?),
+            payer: *get_account_key(2)?,
+            price: lamports_received(3),
+            instruction_index,
+        }
+    } else {
+        CandyMachineMint {
+            version: CandyMachineVersion::V2,
+            candy_machine: *get_account_key(0)?,
+            config: None,
+            payer: *get_account_key(2)?,
+            price: lamports_received(3),
+            instruction_index,
+        }
+    };
+    bonbon.candy_machine_mint = Some(candy_machine_mint);
+
+    Ok(())
+}
+
+pub fn update_candy_guard_instruction(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction, account_keys,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let candy_guard_instruction = CandyGuardInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    if !matches!(
+            candy_guard_instruction,
+            CandyGuardInstruction::Mint | CandyGuardInstruction::MintV2) {
+        // only mints are partitioned
+        return Ok(());
+    }
+
+    bonbon.candy_machine_mint = Some(CandyMachineMint {
+        version: CandyMachineVersion::Guard,
+        candy_machine: *get_account_key(2)?,
+        config: Some(*get_account_key(0)?),
+        payer: *get_account_key(4)?,
+        price: None,
+        instruction_index,
+    });
+
+    Ok(())
+}
+
+pub fn update_auction_house_instruction(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction, account_keys, owners, pre_balances, post_balances, token_accounts,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let auction_house_instruction = AuctionHouseInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    let program_key = programs::auction_house::id();
+
+    let apply_bid = |
+        bonbon: &mut Bonbon,
+        args: BuyArgs,
+        auction_house_index: usize,
+    | -> Result<(), Error> {
+        bonbon.apply_open_order(OpenOrder {
+            side: OrderSide::Bid,
+            program_key,
+            marketplace: Some(*get_account_key(auction_house_index)?),
+            wallet: *get_account_key(0)?,
+            token_account: Some(*get_account_key(4)?),
+            delegate: None,
+            price: Some(args.buyer_price),
+            currency_mint: Some(*get_account_key(3)?),
+            amount: args.token_size,
+            instruction_index: instruction_index.clone(),
+        });
+        Ok(())
+    };
+
+    // Listings and bids are both cancelled with the wallet, the token account, the price and the
+    // trade state derived from them. The seller's is their own token account, a bidder's is the
+    // one they bid on. Without an owner for it we go by the wallet's open listings
+    let cancel = |
+        bonbon: &mut Bonbon,
+        price: Option<u64>,
+        auction_house_index: usize,
+    | -> Result<(), Error> {
+        let wallet = get_account_key(0)?;
+        let token_account = get_account_key(1)?;
+        let account_index = *instruction.accounts.get(1)
+            .ok_or(ErrorCode::FailedInstructionDeserialization)?;
+        let owner = owners.iter()
+            .find(|m| m.account_index == account_index)
+            .map(|m| m.owner_key)
+            .or_else(|| token_accounts.owner_at(token_account, &instruction_index));
+        let side = match owner {
+            Some(owner) if owner == *wallet => OrderSide::Listing,
+            Some(_) => OrderSide::Bid,
+            None if bonbon.open_orders.iter().any(|o| {
+                o.side == OrderSide::Listing
+                    && o.wallet == *wallet
+                    && o.token_account == Some(*token_account)
+            }) => OrderSide::Listing,
+            None => OrderSide::Bid,
+        };
+        bonbon.cancel_orders(
+            &program_key, side, Some(*get_account_key(auction_house_index)?), wallet, price);
+        Ok(())
+    };
+
+    // the auctioneer variants take the auctioneer's authority just before the auction house, so
+    // the auction house and the accounts after it are one further along
+    let (price, amount, auction_house_index) = match auction_house_instruction {
+        AuctionHouseInstruction::Sell(args) => {
+            bonbon.apply_open_order(OpenOrder {
+                side: OrderSide::Listing,
+                program_key,
+                marketplace: Some(*get_account_key(4)?),
+                wallet: *get_account_key(0)?,
+                token_account: Some(*get_account_key(1)?),
+                delegate: None,
+                price: Some(args.buyer_price),
+                // listings don't pass the auction house's treasury mint
+                currency_mint: None,
+                amount: args.token_size,
+                instruction_index,
+            });
+            return Ok(());
+        }
+        AuctionHouseInstruction::AuctioneerSell(args) => {
+            bonbon.apply_open_order(OpenOrder {
+                side: OrderSide::Listing,
+                program_key,
+                marketplace: Some(*get_account_key(5)?),
+                wallet: *get_account_key(0)?,
+                token_account: Some(*get_account_key(1)?),
+                delegate: None,
+                // the auctioneer decides the price
+                price: None,
+                currency_mint: None,
+                amount: args.token_size,
+                instruction_index,
+            });
+            return Ok(());
+        }
+        AuctionHouseInstruction::Buy(args) | AuctionHouseInstruction::PublicBuy(args) => {
+            return apply_bid(bonbon, args, 8);
+        }
+        AuctionHouseInstruction::AuctioneerBuy(args)
+                | AuctionHouseInstruction::AuctioneerPublicBuy(args) => {
+            return apply_bid(bonbon, args, 9);
+        }
+        AuctionHouseInstruction::Cancel(args) => {
+            return cancel(bonbon, Some(args.buyer_price), 4);
+        }
+        AuctionHouseInstruction::AuctioneerCancel(args) => {
+            let price = (args.buyer_price != AUCTIONEER_LISTING_PRICE).then(|| args.buyer_price);
+            return cancel(bonbon, price, 5);
+        }
+        AuctionHouseInstruction::ExecuteSale(args) => {
+            (args.buyer_price, args.token_size, 10)
+        }
+        AuctionHouseInstruction::ExecutePartialSale(args) => (
+            args.partial_order_price.unwrap_or(args.buyer_price),
+            args.partial_order_size.unwrap_or(args.token_size),
+            10,
+        ),
+        AuctionHouseInstruction::AuctioneerExecuteSale(args) => {
+            (args.buyer_price, args.token_size, 11)
+        }
+        AuctionHouseInstruction::AuctioneerExecutePartialSale(args) => (
+            args.partial_order_price.unwrap_or(args.buyer_price),
+            args.partial_order_size.unwrap_or(args.token_size),
+            11,
+        ),
+        _ => return Ok(()),
+    };
+
+    let mut sale = Sale {
+        program_key,
+        marketplace: Some(*get_account_key(auction_house_index)?),
+        buyer: *get_account_key(0)?,
+        seller: *get_account_key(1)?,
+        price,
+        currency_mint: *get_account_key(5)?,
+        amount,
+        royalties: vec![],
+        instruction_index,
+    };
+    sale.royalties = RoyaltyPayment::for_sale(
+        bonbon.glazings.last(), &sale, account_keys, owners, pre_balances, post_balances);
+    bonbon.fill_orders(&sale);
+    bonbon.sales.push(sale);
+
+    Ok(())
+}
+
+pub fn update_metaplex_instruction(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction, account_keys,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let metaplex_instruction = MetaplexInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+
+    let kind = match metaplex_instruction {
+        MetaplexInstruction::RedeemBid => StorefrontSaleKind::Auction,
+        MetaplexInstruction::RedeemFullRightsTransferBid => StorefrontSaleKind::FullRightsTransfer,
+        MetaplexInstruction::RedeemPrintingV2Bid => StorefrontSaleKind::AuctionPrint,
+        MetaplexInstruction::DeprecatedRedeemParticipationBid
+                | MetaplexInstruction::DeprecatedRedeemParticipationBidV2
+                | MetaplexInstruction::RedeemParticipationBidV3 => {
+            StorefrontSaleKind::Participation
+        }
+        _ => return Ok(()),
+    };
+
+    // see `MetaplexInstruction::is_redemption`
+    bonbon.storefront_sales.push(StorefrontSale {
+        kind,
+        program_key: programs::metaplex::id(),
+        manager: *get_account_key(0)?,
+        auction: Some(*get_account_key(7)?),
+        vault: Some(*get_account_key(5)?),
+        buyer: *get_account_key(9)?,
+        bidder_metadata: Some(*get_account_key(8)?),
+        price: None,
+        currency_mint: None,
+        instruction_index,
+    });
+
+    Ok(())
+}
+
+pub fn update_token_vault_instruction(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction, account_keys,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let vault_instruction = VaultInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+
+    let (safety_deposit_box_index, deposit, amount) = match vault_instruction {
+        VaultInstruction::AddTokenToInactiveVault(args) => (0, true, args.amount),
+        VaultInstruction::WithdrawTokenFromSafetyDepositBox(args) => (1, false, args.amount),
+        _ => return Ok(()),
+    };
+
+    bonbon.vault_custody.push(VaultCustody {
+        vault: *get_account_key(3)?,
+        safety_deposit_box: *get_account_key(safety_deposit_box_index)?,
+        deposit,
+        amount,
+        instruction_index,
+    });
+
+    Ok(())
+}
+
+pub fn update_fixed_price_sale_instruction(
+    bonbon: &mut Bonbon,
+    InstructionContext {
+        instruction, account_keys, owners, pre_balances, post_balances,
+        instruction_index, ..
+    }: InstructionContext,
+) -> Result<(), Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let fixed_price_sale_instruction = FixedPriceSaleInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    if !matches!(fixed_price_sale_instruction, FixedPriceSaleInstruction::Buy) {
+        return Ok(());
+    }
+
+    // the market's price isn't passed, so take what its treasury holder received. That's a
+    // token account for markets selling in tokens and a plain account for SOL
+    let treasury_index = *instruction.accounts.get(5)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)?;
+    let (price, currency_mint) = match owners.iter()
+            .find(|m| m.account_index == treasury_index) {
+        Some(meta) => (
+            meta.post_amount.map(|post| post.saturating_sub(meta.pre_amount.unwrap_or(0))),
+            meta.mint_key,
+        ),
+        None => (
+            post_balances.get(usize::from(treasury_index))
+                .zip(pre_balances.get(usize::from(treasury_index)))
+                .map(|(post, pre)| post.saturating_sub(*pre)),
+            spl_token::native_mint::id(),
+        ),
+    };
+
+    bonbon.storefront_sales.push(StorefrontSale {
+        kind: StorefrontSaleKind::FixedPrice,
+        program_key: programs::fixed_price_sale::id(),
+        manager: *get_account_key(0)?,
+        auction: None,
+        vault: None,
+        buyer: *get_account_key(3)?,
+        bidder_metadata: None,
+        price,
+        currency_mint: Some(currency_mint),
+        instruction_index,
+    });
+
+    Ok(())
+}
+
 pub struct BonbonUpdater {
     pub program_id: Pubkey,
 
     pub update: fn (
         bonbon: &mut Bonbon,
         instruction_context: InstructionContext,
-    ) -> Result<(), ErrorCode>,
+    ) -> Result<(), Error>,
+
+    // only used to describe errors
+    pub instruction_name: fn (data: &[u8]) -> Option<String>,
 }
 
 impl Bonbon {
     pub fn update(
         &mut self,
-        instruction_context @ InstructionContext {
-            instruction, account_keys, ..
-        }: InstructionContext,
+        instruction_context: InstructionContext,
         updaters: &[BonbonUpdater],
-    ) -> Result<(), ErrorCode> {
+    ) -> Result<(), Error> {
+        let InstructionContext {
+            instruction, account_keys, owners, signature, ..
+        } = instruction_context;
+        let instruction_index = instruction_context.instruction_index.clone();
+
         let program_id = account_keys.get(usize::from(instruction.program_id_index))
-            .ok_or(ErrorCode::BadAccountKeyIndex)?;
+            .ok_or_else(|| Error {
+                signature: Some(signature.to_vec()),
+                instruction_index: Some(instruction_index.clone()),
+                ..Error::from(ErrorCode::BadAccountKeyIndex)
+            })?;
 
-        if let Some(BonbonUpdater { update, .. }) = updaters.iter().find(
+        if let Some(BonbonUpdater { update, instruction_name, .. }) = updaters.iter().find(
                 |u| u.program_id == *program_id) {
-            update(self, instruction_context)
-        } else {
-            Ok(())
+            update(self, instruction_context).map_err(|err| Error {
+                signature: Some(signature.to_vec()),
+                program_key: Some(*program_id),
+                instruction_index: Some(instruction_index.clone()),
+                instruction_name: instruction_name(&instruction.data),
+                ..err
+            })?;
+        }
+
+        self.apply_token_balances(account_keys, owners, &instruction_index);
+        Ok(())
+    }
+
+    // Like `update` but an instruction that fails is recorded as an anomaly and we carry on
+    // with the next one. Updaters check keys before changing anything so a skipped instruction
+    // leaves the bonbon as it was, apart from the transaction's token balances which are still
+    // applied
+    pub fn update_lenient(
+        &mut self,
+        instruction_context: InstructionContext,
+        updaters: &[BonbonUpdater],
+    ) {
+        let InstructionContext { account_keys, owners, .. } = instruction_context;
+        let instruction_index = instruction_context.instruction_index.clone();
+        if let Err(err) = self.update(instruction_context, updaters) {
+            self.anomalies.push(Anomaly {
+                instruction_index: instruction_index.clone(),
+                error_kind: err.code,
+                details: err.to_string(),
+            });
+            self.apply_token_balances(account_keys, owners, &instruction_index);
         }
     }
 }
@@ -0,0 +1,209 @@
+use {
+    crate::partition::variant_name,
+    borsh::de::BorshDeserialize,
+    std::io,
+};
+
+// Metaplex Auction House instructions, matched on the anchor discriminator. Listings (`Sell`),
+// bids (`Buy`, `PublicBuy`), cancels and sales carry their arguments, as do their auctioneer
+// variants; the rest are only named
+#[derive(Debug)]
+pub enum AuctionHouseInstruction {
+    WithdrawFromFee,
+
+    WithdrawFromTreasury,
+
+    UpdateAuctionHouse,
+
+    CreateAuctionHouse,
+
+    Withdraw,
+
+    Deposit,
+
+    Cancel(CancelArgs),
+
+    ExecuteSale(ExecuteSaleArgs),
+
+    ExecutePartialSale(ExecutePartialSaleArgs),
+
+    Sell(SellArgs),
+
+    Buy(BuyArgs),
+
+    PublicBuy(BuyArgs),
+
+    PrintListingReceipt,
+
+    CancelListingReceipt,
+
+    PrintBidReceipt,
+
+    CancelBidReceipt,
+
+    PrintPurchaseReceipt,
+
+    CloseEscrowAccount,
+
+    DelegateAuctioneer,
+
+    UpdateAuctioneer,
+
+    AuctioneerWithdraw,
+
+    AuctioneerDeposit,
+
+    AuctioneerCancel(CancelArgs),
+
+    AuctioneerExecuteSale(ExecuteSaleArgs),
+
+    AuctioneerExecutePartialSale(ExecutePartialSaleArgs),
+
+    AuctioneerSell(AuctioneerSellArgs),
+
+    AuctioneerBuy(BuyArgs),
+
+    AuctioneerPublicBuy(BuyArgs),
+}
+
+impl AuctionHouseInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use AuctionHouseInstruction::*;
+        if data.len() < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "missing discriminator"));
+        }
+        let (discriminator, mut rest) = data.split_at(8);
+
+        let rest = &mut rest;
+        Ok(match discriminator {
+            [179, 208, 190, 154, 32, 179, 19, 59] => WithdrawFromFee,
+            [0, 164, 86, 76, 56, 72, 12, 170] => WithdrawFromTreasury,
+            [84, 215, 2, 172, 241, 0, 245, 219] => UpdateAuctionHouse,
+            [221, 66, 242, 159, 249, 206, 134, 241] => CreateAuctionHouse,
+            [183, 18, 70, 156, 148, 109, 161, 34] => Withdraw,
+            [242, 35, 198, 137, 82, 225, 242, 182] => Deposit,
+            [232, 219, 223, 41, 219, 236, 220, 190] => Cancel(BorshDeserialize::deserialize(rest)?),
+            [37, 74, 217, 157, 79, 49, 35, 6] => ExecuteSale(BorshDeserialize::deserialize(rest)?),
+            [163, 18, 35, 157, 49, 164, 203, 133] => {
+                ExecutePartialSale(BorshDeserialize::deserialize(rest)?)
+            }
+            [51, 230, 133, 164, 1, 127, 131, 173] => Sell(BorshDeserialize::deserialize(rest)?),
+            [102, 6, 61, 18, 1, 218, 235, 234] => Buy(BorshDeserialize::deserialize(rest)?),
+            [169, 84, 218, 35, 42, 206, 16, 171] => {
+                PublicBuy(BorshDeserialize::deserialize(rest)?)
+            }
+            [207, 107, 44, 160, 75, 222, 195, 27] => PrintListingReceipt,
+            [171, 59, 138, 126, 246, 189, 91, 11] => CancelListingReceipt,
+            [94, 249, 90, 230, 239, 64, 68, 218] => PrintBidReceipt,
+            [246, 108, 27, 229, 220, 42, 176, 43] => CancelBidReceipt,
+            [227, 154, 251, 7, 180, 56, 100, 143] => PrintPurchaseReceipt,
+            [209, 42, 208, 179, 140, 78, 18, 43] => CloseEscrowAccount,
+            [106, 178, 12, 122, 74, 173, 251, 222] => DelegateAuctioneer,
+            [103, 255, 80, 234, 94, 56, 168, 208] => UpdateAuctioneer,
+            [85, 166, 219, 110, 168, 143, 180, 236] => AuctioneerWithdraw,
+            [79, 122, 37, 162, 120, 173, 57, 127] => AuctioneerDeposit,
+            [197, 97, 152, 196, 115, 204, 64, 215] => {
+                AuctioneerCancel(BorshDeserialize::deserialize(rest)?)
+            }
+            [68, 125, 32, 65, 251, 43, 35, 53] => {
+                AuctioneerExecuteSale(BorshDeserialize::deserialize(rest)?)
+            }
+            [9, 44, 46, 15, 161, 143, 21, 54] => {
+                AuctioneerExecutePartialSale(BorshDeserialize::deserialize(rest)?)
+            }
+            [251, 60, 142, 195, 121, 203, 26, 183] => {
+                AuctioneerSell(BorshDeserialize::deserialize(rest)?)
+            }
+            [17, 106, 133, 46, 229, 48, 45, 208] => {
+                AuctioneerBuy(BorshDeserialize::deserialize(rest)?)
+            }
+            [221, 239, 99, 240, 86, 46, 213, 126] => {
+                AuctioneerPublicBuy(BorshDeserialize::deserialize(rest)?)
+            }
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown auction house instruction")),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        variant_name(self)
+    }
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct CancelArgs {
+    pub buyer_price: u64,
+
+    pub token_size: u64,
+}
+
+// prices are in the smallest unit of the auction house's treasury mint
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct ExecuteSaleArgs {
+    pub escrow_payment_bump: u8,
+
+    pub free_trade_state_bump: u8,
+
+    pub program_as_signer_bump: u8,
+
+    pub buyer_price: u64,
+
+    pub token_size: u64,
+}
+
+// fills part of a bid for more than one token. None means the whole bid
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct ExecutePartialSaleArgs {
+    pub escrow_payment_bump: u8,
+
+    pub free_trade_state_bump: u8,
+
+    pub program_as_signer_bump: u8,
+
+    pub buyer_price: u64,
+
+    pub token_size: u64,
+
+    pub partial_order_size: Option<u64>,
+
+    pub partial_order_price: Option<u64>,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct SellArgs {
+    pub trade_state_bump: u8,
+
+    pub free_trade_state_bump: u8,
+
+    pub program_as_signer_bump: u8,
+
+    pub buyer_price: u64,
+
+    pub token_size: u64,
+}
+
+// the auctioneer sets the price, so its listings are all at `AUCTIONEER_LISTING_PRICE`
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct AuctioneerSellArgs {
+    pub trade_state_bump: u8,
+
+    pub free_trade_state_bump: u8,
+
+    pub program_as_signer_bump: u8,
+
+    pub token_size: u64,
+}
+
+// the price auctioneer listings are recorded (and cancelled) at
+pub const AUCTIONEER_LISTING_PRICE: u64 = u64::MAX;
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct BuyArgs {
+    pub trade_state_bump: u8,
+
+    pub escrow_payment_bump: u8,
+
+    pub buyer_price: u64,
+
+    pub token_size: u64,
+}
@@ -0,0 +1,273 @@
+use {
+    crate::{
+        metadata::TokenStandard,
+        partition::variant_name,
+        programs::bubblegum,
+    },
+    borsh::de::BorshDeserialize,
+    mpl_token_metadata::state::{
+        Collection as MplCollection,
+        Creator as MplCreator,
+        Uses as MplUses,
+    },
+    solana_sdk::pubkey::Pubkey,
+    std::io,
+};
+
+// Bubblegum (compressed NFT) instructions. Anchor prefixes the arguments with the first 8 bytes
+// of sha256("global:<instruction>"). Like `TokenMetadataInstruction`, arguments are only read as
+// far as we use them
+#[derive(Debug)]
+pub enum BubblegumInstruction {
+    CreateTree,
+
+    SetTreeDelegate,
+
+    MintV1(MetadataArgs),
+
+    MintToCollectionV1(MetadataArgs),
+
+    Transfer(LeafArgs),
+
+    Delegate(LeafArgs),
+
+    Burn(LeafArgs),
+
+    Redeem(LeafArgs),
+
+    CancelRedeem,
+
+    DecompressV1(MetadataArgs),
+
+    Compress,
+
+    VerifyCreator(LeafArgs),
+
+    UnverifyCreator(LeafArgs),
+
+    VerifyCollection(LeafArgs),
+
+    UnverifyCollection(LeafArgs),
+
+    SetAndVerifyCollection(LeafArgs, SetAndVerifyCollectionArgs),
+
+    SetDecompressibleState,
+
+    UpdateMetadata(UpdateMetadataArgs),
+}
+
+impl BubblegumInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use BubblegumInstruction::*;
+        if data.len() < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "missing discriminator"));
+        }
+        let (discriminator, mut rest) = data.split_at(8);
+
+        let rest = &mut rest;
+        Ok(match discriminator {
+            [165, 83, 136, 142, 89, 202, 47, 220] => CreateTree,
+            [253, 118, 66, 37, 190, 49, 154, 102] => SetTreeDelegate,
+            [145, 98, 192, 118, 184, 147, 118, 104] => MintV1(BorshDeserialize::deserialize(rest)?),
+            [153, 18, 178, 47, 197, 158, 86, 15] => {
+                MintToCollectionV1(BorshDeserialize::deserialize(rest)?)
+            }
+            [163, 52, 200, 231, 140, 3, 69, 186] => Transfer(BorshDeserialize::deserialize(rest)?),
+            [90, 147, 75, 178, 85, 88, 4, 137] => Delegate(BorshDeserialize::deserialize(rest)?),
+            [116, 110, 29, 56, 107, 219, 42, 93] => Burn(BorshDeserialize::deserialize(rest)?),
+            [184, 12, 86, 149, 70, 196, 97, 225] => Redeem(BorshDeserialize::deserialize(rest)?),
+            [111, 76, 232, 50, 39, 175, 48, 242] => CancelRedeem,
+            [54, 85, 76, 70, 228, 250, 164, 81] => {
+                DecompressV1(BorshDeserialize::deserialize(rest)?)
+            }
+            [82, 193, 176, 117, 176, 21, 115, 253] => Compress,
+            [52, 17, 96, 132, 71, 4, 85, 194] => {
+                VerifyCreator(BorshDeserialize::deserialize(rest)?)
+            }
+            [107, 178, 57, 39, 105, 115, 112, 152] => {
+                UnverifyCreator(BorshDeserialize::deserialize(rest)?)
+            }
+            [56, 113, 101, 253, 79, 55, 122, 169] => {
+                VerifyCollection(BorshDeserialize::deserialize(rest)?)
+            }
+            [250, 251, 42, 106, 41, 137, 186, 168] => {
+                UnverifyCollection(BorshDeserialize::deserialize(rest)?)
+            }
+            [235, 242, 121, 216, 158, 234, 180, 234] => SetAndVerifyCollection(
+                BorshDeserialize::deserialize(rest)?,
+                BorshDeserialize::deserialize(rest)?,
+            ),
+            // renamed from `set_decompressable_state`
+            [82, 104, 152, 6, 149, 111, 100, 13]
+                    | [18, 135, 238, 168, 246, 195, 61, 115] => SetDecompressibleState,
+            [170, 182, 43, 239, 97, 78, 225, 186] => {
+                UpdateMetadata(BorshDeserialize::deserialize(rest)?)
+            }
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown bubblegum instruction")),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        variant_name(self)
+    }
+}
+
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenProgramVersion {
+    Original,
+
+    Token2022,
+}
+
+// same layout as the metadata account's data, plus the edition nonce and token program version
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct MetadataArgs {
+    pub name: String,
+
+    pub symbol: String,
+
+    pub uri: String,
+
+    pub seller_fee_basis_points: u16,
+
+    pub primary_sale_happened: bool,
+
+    pub is_mutable: bool,
+
+    pub edition_nonce: Option<u8>,
+
+    pub token_standard: Option<TokenStandard>,
+
+    pub collection: Option<MplCollection>,
+
+    pub uses: Option<MplUses>,
+
+    pub token_program_version: TokenProgramVersion,
+
+    pub creators: Vec<MplCreator>,
+}
+
+// proof of the current leaf, shared by every instruction that changes an existing one
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct LeafArgs {
+    pub root: [u8; 32],
+
+    pub data_hash: [u8; 32],
+
+    pub creator_hash: [u8; 32],
+
+    pub nonce: u64,
+
+    pub index: u32,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct SetAndVerifyCollectionArgs {
+    pub metadata: MetadataArgs,
+
+    pub collection: Pubkey,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct UpdateMetadataArgs {
+    pub root: [u8; 32],
+
+    pub nonce: u64,
+
+    pub index: u32,
+
+    // the leaf's metadata before the update, proven against the tree
+    pub current_metadata: MetadataArgs,
+
+    pub update_args: UpdateArgs,
+}
+
+// None leaves the field as it is
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct UpdateArgs {
+    pub name: Option<String>,
+
+    pub symbol: Option<String>,
+
+    pub uri: Option<String>,
+
+    pub creators: Option<Vec<MplCreator>>,
+
+    pub seller_fee_basis_points: Option<u16>,
+
+    pub primary_sale_happened: Option<bool>,
+
+    pub is_mutable: Option<bool>,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum LeafSchema {
+    V1 {
+        id: Pubkey,
+        owner: Pubkey,
+        delegate: Pubkey,
+        nonce: u64,
+        data_hash: [u8; 32],
+        creator_hash: [u8; 32],
+    },
+}
+
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum BubblegumEventType {
+    Uninitialized,
+
+    LeafSchemaEvent,
+}
+
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum Version {
+    V1,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+struct LeafSchemaEvent {
+    event_type: BubblegumEventType,
+
+    version: Version,
+
+    schema: LeafSchema,
+
+    #[allow(dead_code)]
+    leaf_hash: [u8; 32],
+}
+
+// account compression's wrapper for data other programs log through noop. the other variant is
+// the tree's own change log
+#[derive(BorshDeserialize, Debug, Clone)]
+enum AccountCompressionEvent {
+    ChangeLog,
+
+    ApplicationData(ApplicationDataEvent),
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+enum ApplicationDataEvent {
+    V1 { application_data: Vec<u8> },
+}
+
+// The leaf bubblegum logs (through a noop CPI) after every change to a leaf, wrapped as
+// account compression application data. None for anything else sent to noop
+pub fn unpack_leaf_schema(data: &[u8]) -> Option<LeafSchema> {
+    let event = match AccountCompressionEvent::try_from_slice(data).ok()? {
+        AccountCompressionEvent::ApplicationData(ApplicationDataEvent::V1 {
+            application_data,
+        }) => LeafSchemaEvent::try_from_slice(&application_data).ok()?,
+        AccountCompressionEvent::ChangeLog => return None,
+    };
+    (event.event_type == BubblegumEventType::LeafSchemaEvent && event.version == Version::V1)
+        .then(|| event.schema)
+}
+
+// the PDA bubblegum uses as the asset id of the leaf minted at `nonce`
+pub fn find_asset_id(tree: &Pubkey, nonce: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"asset", tree.as_ref(), &nonce.to_le_bytes()],
+        &bubblegum::id(),
+    ).0
+}
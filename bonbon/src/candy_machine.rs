@@ -0,0 +1,119 @@
+use {
+    crate::partition::variant_name,
+    std::io,
+};
+
+// Candy Machine v1 and v2 instructions (v2 added the collection and freeze ones). Like
+// `BubblegumInstruction` these are matched on the anchor discriminator and none of the arguments
+// are read
+#[derive(Debug)]
+pub enum CandyMachineInstruction {
+    InitializeConfig,
+
+    AddConfigLines,
+
+    InitializeCandyMachine,
+
+    UpdateCandyMachine,
+
+    MintNft,
+
+    UpdateAuthority,
+
+    WithdrawFunds,
+
+    SetCollection,
+
+    RemoveCollection,
+
+    SetCollectionDuringMint,
+
+    SetFreeze,
+
+    RemoveFreeze,
+
+    ThawNft,
+
+    UnlockFunds,
+}
+
+impl CandyMachineInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use CandyMachineInstruction::*;
+        let discriminator = data.get(..8)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing discriminator"))?;
+
+        Ok(match discriminator {
+            [208, 127, 21, 1, 194, 190, 196, 70] => InitializeConfig,
+            [223, 50, 224, 227, 151, 8, 115, 106] => AddConfigLines,
+            [142, 137, 167, 107, 47, 39, 240, 124] => InitializeCandyMachine,
+            [243, 251, 124, 156, 211, 211, 118, 239] => UpdateCandyMachine,
+            [211, 57, 6, 167, 15, 219, 35, 251] => MintNft,
+            [32, 46, 64, 28, 149, 75, 243, 88] => UpdateAuthority,
+            [241, 36, 29, 111, 208, 31, 104, 217] => WithdrawFunds,
+            [192, 254, 206, 76, 168, 182, 59, 223] => SetCollection,
+            [223, 52, 106, 217, 61, 220, 36, 160] => RemoveCollection,
+            [103, 17, 200, 25, 118, 95, 125, 61] => SetCollectionDuringMint,
+            [202, 80, 109, 208, 130, 144, 26, 233] => SetFreeze,
+            [1, 212, 80, 168, 129, 60, 46, 251] => RemoveFreeze,
+            [92, 44, 210, 187, 172, 6, 64, 183] => ThawNft,
+            [175, 119, 16, 245, 141, 55, 255, 43] => UnlockFunds,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown candy machine instruction")),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        variant_name(self)
+    }
+}
+
+// Candy Guard instructions. It wraps a (v3) candy machine and mints through it once the guards
+// pass
+#[derive(Debug)]
+pub enum CandyGuardInstruction {
+    Initialize,
+
+    Mint,
+
+    // mints programmable NFTs as well
+    MintV2,
+
+    Route,
+
+    SetAuthority,
+
+    Unwrap,
+
+    Update,
+
+    Withdraw,
+
+    Wrap,
+}
+
+impl CandyGuardInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use CandyGuardInstruction::*;
+        let discriminator = data.get(..8)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing discriminator"))?;
+
+        Ok(match discriminator {
+            [175, 175, 109, 31, 13, 152, 155, 237] => Initialize,
+            [51, 57, 225, 47, 182, 146, 137, 166] => Mint,
+            [120, 121, 23, 146, 173, 110, 199, 205] => MintV2,
+            [229, 23, 203, 151, 122, 227, 173, 42] => Route,
+            [133, 250, 37, 21, 110, 163, 26, 121] => SetAuthority,
+            [126, 175, 198, 14, 212, 69, 50, 44] => Unwrap,
+            [219, 200, 88, 176, 158, 63, 253, 127] => Update,
+            [183, 18, 70, 156, 148, 109, 161, 34] => Withdraw,
+            [178, 40, 10, 189, 228, 129, 186, 140] => Wrap,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown candy guard instruction")),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        variant_name(self)
+    }
+}
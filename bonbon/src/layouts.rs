@@ -0,0 +1,174 @@
+use {
+    solana_sdk::pubkey::Pubkey,
+    std::collections::HashMap,
+};
+
+// Accounts whose position in an instruction changed between program upgrades
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountRole {
+    MasterMetadata,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoding {
+    // deserialize with the instruction types we build against
+    Current,
+
+    // `DeprecatedSetReservationList` before `offset` and `total_spot_offset` were added to its
+    // arguments
+    SetReservationListV1,
+
+    // the data doesn't match the types we build against and we don't need the instruction
+    Ignore,
+}
+
+// How to read one instruction (by program and first data byte) from some slot onwards
+pub struct InstructionLayout {
+    pub program_id: Pubkey,
+
+    pub discriminant: u8,
+
+    // the variant the layout decodes to, as `InstructionPartitioner::instruction_name` names it.
+    // what `LogCheck::Fallback` matches logged names against
+    pub name: &'static str,
+
+    // first slot the layout was live. 0 if it predates everything we index
+    pub since_slot: i64,
+
+    pub decoding: Decoding,
+
+    pub accounts: &'static [(AccountRole, usize)],
+
+    // whether the instruction's accounts are laid out this way, for layouts that can be told
+    // apart by them. None fits any accounts
+    pub accounts_fit: Option<fn (accounts: &[Pubkey]) -> bool>,
+}
+
+impl InstructionLayout {
+    pub fn account_index(&self, role: AccountRole) -> Option<usize> {
+        self.accounts.iter().find(|(r, _)| *r == role).map(|(_, index)| *index)
+    }
+
+    pub fn fits(&self, accounts: &[Pubkey]) -> bool {
+        self.accounts_fit.map_or(true, |accounts_fit| accounts_fit(accounts))
+    }
+}
+
+#[derive(Default)]
+pub struct LayoutRegistry {
+    // per (program, discriminant), in since_slot order
+    layouts: HashMap<(Pubkey, u8), Vec<InstructionLayout>>,
+}
+
+impl LayoutRegistry {
+    // a later insert with the same since_slot takes precedence
+    pub fn insert(&mut self, layout: InstructionLayout) {
+        let layouts = self.layouts.entry((layout.program_id, layout.discriminant)).or_default();
+        let position = layouts.partition_point(|l| l.since_slot <= layout.since_slot);
+        layouts.insert(position, layout);
+    }
+
+    // latest layout live at `slot`. None means nothing is registered and the instruction is read
+    // as it is today
+    pub fn layout_for(
+        &self,
+        program_id: &Pubkey,
+        data: &[u8],
+        slot: i64,
+    ) -> Option<&InstructionLayout> {
+        let discriminant = *data.first()?;
+        self.layouts.get(&(*program_id, discriminant))?
+            .iter()
+            .rev()
+            .find(|l| l.since_slot <= slot)
+    }
+
+    // every layout registered for the instruction, latest first
+    pub fn layouts_for<'a>(
+        &'a self,
+        program_id: &Pubkey,
+        data: &[u8],
+    ) -> impl Iterator<Item = &'a InstructionLayout> {
+        data.first()
+            .and_then(|discriminant| self.layouts.get(&(*program_id, *discriminant)))
+            .into_iter()
+            .flat_map(|layouts| layouts.iter().rev())
+    }
+
+    // the layout live at `slot` and then the others, latest first, leaving out those the
+    // instruction's accounts don't fit. reading with the first of them that the data also
+    // decodes with keeps a `since_slot` that is off from reading the wrong accounts
+    pub fn layouts_to_try(
+        &self,
+        program_id: &Pubkey,
+        data: &[u8],
+        accounts: &[Pubkey],
+        slot: i64,
+    ) -> Vec<&InstructionLayout> {
+        let live = self.layout_for(program_id, data, slot);
+        live.into_iter()
+            .chain(self.layouts_for(program_id, data)
+                .filter(|l| !live.map_or(false, |live| std::ptr::eq(*l, live))))
+            .filter(|l| l.fits(accounts))
+            .collect()
+    }
+}
+
+// First slots the upgraded token metadata program ran with each change. NB: these are estimated
+// from when the commits landed and still have to be checked against the program's upgrade
+// history. Instructions around them are read with the layout their accounts (or arguments) fit
+// so an off slot only changes which layout is tried first, see `layouts_to_try`
+
+// metaplex-foundation/metaplex/commit/a29aa4cfd5c75307892254ee5ee311ca64101ea0
+pub const MASTER_METADATA_MOVED_SLOT: i64 = 80_000_000;
+
+// metaplex-foundation/metaplex/commit/3e26b6b208900181a9c42362f206690544467be9
+pub const RESERVATION_LIST_OFFSETS_SLOT: i64 = 81_000_000;
+
+pub fn default_layouts() -> LayoutRegistry {
+    let mut registry = LayoutRegistry::default();
+
+    // DeprecatedMintNewEditionFromMasterEditionViaPrintingToken. the master metadata account
+    // moves from index 10 (with the token program at 11) to index 11 (with the token program at
+    // 12)
+    registry.insert(InstructionLayout {
+        program_id: mpl_token_metadata::id(),
+        discriminant: 3,
+        name: "DeprecatedMintNewEditionFromMasterEditionViaPrintingToken",
+        since_slot: 0,
+        decoding: Decoding::Current,
+        accounts: &[(AccountRole::MasterMetadata, 10)],
+        accounts_fit: Some(|accounts| accounts.get(11) == Some(&spl_token::id())),
+    });
+    registry.insert(InstructionLayout {
+        program_id: mpl_token_metadata::id(),
+        discriminant: 3,
+        name: "DeprecatedMintNewEditionFromMasterEditionViaPrintingToken",
+        since_slot: MASTER_METADATA_MOVED_SLOT,
+        decoding: Decoding::Current,
+        accounts: &[(AccountRole::MasterMetadata, 11)],
+        accounts_fit: Some(|accounts| accounts.get(12) == Some(&spl_token::id())),
+    });
+
+    // DeprecatedSetReservationList. `offset` and `total_spot_offset` are added to the arguments
+    registry.insert(InstructionLayout {
+        program_id: mpl_token_metadata::id(),
+        discriminant: 5,
+        name: "DeprecatedSetReservationList",
+        since_slot: 0,
+        decoding: Decoding::SetReservationListV1,
+        accounts: &[],
+        accounts_fit: None,
+    });
+    registry.insert(InstructionLayout {
+        program_id: mpl_token_metadata::id(),
+        discriminant: 5,
+        name: "DeprecatedSetReservationList",
+        since_slot: RESERVATION_LIST_OFFSETS_SLOT,
+        decoding: Decoding::Current,
+        accounts: &[],
+        accounts_fit: None,
+    });
+
+    registry
+}
@@ -1,3 +1,13 @@
 pub mod partition;
 pub mod assemble;
-
+pub mod auction_house;
+pub mod bubblegum;
+pub mod candy_machine;
+pub mod layouts;
+pub mod logs;
+pub mod metadata;
+pub mod pipeline;
+pub mod programs;
+pub mod registry;
+pub mod storefront;
+pub mod token;
@@ -0,0 +1,96 @@
+use {
+    solana_sdk::pubkey::Pubkey,
+    std::str::FromStr,
+};
+
+// One program invocation as it shows up in the transaction logs
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub program_key: Pubkey,
+
+    // 1 for outer instructions, 2 for their CPIs and so on
+    pub stack_height: usize,
+
+    // `Program log: ` messages logged by this invocation itself (not its CPIs)
+    pub messages: Vec<String>,
+}
+
+// Splits the logs into invocations per outer instruction. The first invocation for an outer
+// instruction is the instruction itself and the rest line up with its inner instructions.
+//
+// Outer instructions that never show up in the logs (precompiles, or everything after the logs
+// were truncated) get None
+pub fn parse_invocations(
+    log_messages: &[String],
+    outer_program_keys: &[Pubkey],
+) -> Vec<Option<Vec<Invocation>>> {
+    let mut invocations: Vec<Option<Vec<Invocation>>> = vec![None; outer_program_keys.len()];
+
+    // next outer instruction that could match an `invoke [1]`
+    let mut next_outer = 0;
+    let mut current_outer = None;
+    // indexes into the current outer instruction's invocations
+    let mut stack: Vec<usize> = vec![];
+
+    for message in log_messages {
+        if let Some(log) = message.strip_prefix("Program log: ") {
+            if let (Some(outer), Some(top)) = (current_outer, stack.last()) {
+                if let Some(Some(outer_invocations)) = invocations.get_mut(outer) {
+                    outer_invocations[*top].messages.push(log.to_string());
+                }
+            }
+            continue;
+        }
+
+        let mut parts = message.split(' ');
+        if parts.next() != Some("Program") {
+            continue;
+        }
+        let program_key = match parts.next().and_then(|k| Pubkey::from_str(k).ok()) {
+            Some(program_key) => program_key,
+            // `Program return: `, `Program data: `, etc
+            None => continue,
+        };
+
+        match parts.next() {
+            Some("invoke") => {
+                let stack_height = parts.next()
+                    .and_then(|h| h.strip_prefix('['))
+                    .and_then(|h| h.strip_suffix(']'))
+                    .and_then(|h| h.parse::<usize>().ok());
+                let stack_height = match stack_height {
+                    Some(stack_height) => stack_height,
+                    None => continue,
+                };
+
+                if stack_height == 1 {
+                    let remaining = outer_program_keys.get(next_outer..).unwrap_or_default();
+                    let outer = match remaining.iter().position(|k| *k == program_key) {
+                        Some(offset) => next_outer + offset,
+                        // logs don't match the instructions. give up on the rest
+                        None => break,
+                    };
+                    next_outer = outer + 1;
+                    current_outer = Some(outer);
+                    invocations[outer] = Some(vec![]);
+                    stack.clear();
+                }
+
+                if let Some(Some(outer_invocations)) = current_outer.map(|o| &mut invocations[o]) {
+                    stack.push(outer_invocations.len());
+                    outer_invocations.push(Invocation {
+                        program_key,
+                        stack_height,
+                        messages: vec![],
+                    });
+                }
+            }
+            Some("success") | Some("failed:") => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    invocations
+}
@@ -0,0 +1,401 @@
+use {
+    crate::{
+        layouts::{Decoding, InstructionLayout, LayoutRegistry},
+        partition::variant_name,
+    },
+    borsh::de::BorshDeserialize,
+    mpl_token_metadata::{
+        instruction::{MetadataInstruction, SetReservationListArgs},
+        state::Collection as MplCollection,
+        state::Creator as MplCreator,
+        state::Data as MplData,
+        state::DataV2 as MplDataV2,
+        state::Reservation as MplReservation,
+        state::Uses as MplUses,
+    },
+    solana_sdk::pubkey::Pubkey,
+    std::io,
+};
+
+// Token Metadata instructions across program versions. We build against mpl-token-metadata 1.2.5
+// which stops at `RemoveCreatorVerification`; everything the program added after that is decoded
+// here. Arguments are only read as far as we use them so trailing fields (authorization data for
+// rule sets, etc) are left alone
+#[derive(Debug)]
+pub enum TokenMetadataInstruction {
+    Legacy(MetadataInstruction),
+
+    BurnNft,
+
+    VerifySizedCollectionItem,
+
+    UnverifySizedCollectionItem,
+
+    SetAndVerifySizedCollectionItem,
+
+    CreateMetadataAccountV3(CreateMetadataAccountArgsV3),
+
+    SetCollectionSize(SetCollectionSizeArgs),
+
+    SetTokenStandard,
+
+    BubblegumSetCollectionSize(SetCollectionSizeArgs),
+
+    BurnEditionNft,
+
+    CreateEscrowAccount,
+
+    CloseEscrowAccount,
+
+    TransferOutOfEscrow,
+
+    Burn(BurnArgs),
+
+    Create(CreateArgs),
+
+    Mint(MintArgs),
+
+    Delegate(DelegateArgs),
+
+    Revoke(RevokeArgs),
+
+    Lock,
+
+    Unlock,
+
+    Migrate,
+
+    Transfer(TransferArgs),
+
+    Update(UpdateArgs),
+
+    Use,
+
+    Verify(VerificationArgs),
+
+    Unverify(VerificationArgs),
+
+    Collect,
+
+    Print(PrintArgs),
+}
+
+// discriminant of `RemoveCreatorVerification`, the last variant in 1.2.5
+const LAST_LEGACY_DISCRIMINANT: u8 = 28;
+
+impl TokenMetadataInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use TokenMetadataInstruction::*;
+        let (discriminant, mut rest) = data.split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty instruction"))?;
+        if *discriminant <= LAST_LEGACY_DISCRIMINANT {
+            return Ok(Legacy(MetadataInstruction::try_from_slice(data)?));
+        }
+
+        let rest = &mut rest;
+        Ok(match *discriminant {
+            29 => BurnNft,
+            30 => VerifySizedCollectionItem,
+            31 => UnverifySizedCollectionItem,
+            32 => SetAndVerifySizedCollectionItem,
+            33 => CreateMetadataAccountV3(BorshDeserialize::deserialize(rest)?),
+            34 => SetCollectionSize(BorshDeserialize::deserialize(rest)?),
+            35 => SetTokenStandard,
+            36 => BubblegumSetCollectionSize(BorshDeserialize::deserialize(rest)?),
+            37 => BurnEditionNft,
+            38 => CreateEscrowAccount,
+            39 => CloseEscrowAccount,
+            40 => TransferOutOfEscrow,
+            41 => Burn(BorshDeserialize::deserialize(rest)?),
+            42 => Create(BorshDeserialize::deserialize(rest)?),
+            43 => Mint(BorshDeserialize::deserialize(rest)?),
+            44 => Delegate(BorshDeserialize::deserialize(rest)?),
+            45 => Revoke(BorshDeserialize::deserialize(rest)?),
+            46 => Lock,
+            47 => Unlock,
+            48 => Migrate,
+            49 => Transfer(BorshDeserialize::deserialize(rest)?),
+            50 => Update(BorshDeserialize::deserialize(rest)?),
+            51 => Use,
+            52 => Verify(BorshDeserialize::deserialize(rest)?),
+            53 => Unverify(BorshDeserialize::deserialize(rest)?),
+            54 => Collect,
+            55 => Print(BorshDeserialize::deserialize(rest)?),
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown token metadata instruction")),
+        })
+    }
+
+    // `unpack` for data in the layout registered for the instruction's slot
+    pub fn unpack_as(data: &[u8], decoding: Decoding) -> io::Result<Self> {
+        match decoding {
+            Decoding::Current | Decoding::Ignore => Self::unpack(data),
+            Decoding::SetReservationListV1 => {
+                let args = SetReservationListArgsV1::try_from_slice(
+                    data.get(1..).unwrap_or_default())?;
+                Ok(Self::Legacy(MetadataInstruction::DeprecatedSetReservationList(
+                    SetReservationListArgs {
+                        reservations: args.reservations,
+                        total_reservation_spots: args.total_reservation_spots,
+                        offset: 0,
+                        total_spot_offset: 0,
+                    }
+                )))
+            }
+        }
+    }
+
+    // `unpack_as` with the first of `LayoutRegistry::layouts_to_try` the data decodes with, along
+    // with that layout. `unpack` (and no layout) when none are registered for the instruction
+    pub fn unpack_with<'l>(
+        data: &[u8],
+        accounts: &[Pubkey],
+        slot: i64,
+        layouts: &'l LayoutRegistry,
+    ) -> io::Result<(Option<&'l InstructionLayout>, Self)> {
+        let program_id = mpl_token_metadata::id();
+        if layouts.layouts_for(&program_id, data).next().is_none() {
+            return Ok((None, Self::unpack(data)?));
+        }
+        layouts.layouts_to_try(&program_id, data, accounts, slot)
+            .into_iter()
+            .find_map(|layout| Self::unpack_as(data, layout.decoding)
+                .ok()
+                .map(|instruction| (Some(layout), instruction)))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData, "no registered layout fits the instruction"))
+    }
+
+    // variant name, looking through `Legacy`
+    pub fn name(&self) -> String {
+        match self {
+            Self::Legacy(instruction) => variant_name(instruction),
+            instruction => variant_name(instruction),
+        }
+    }
+}
+
+// `SetReservationListArgs` before `offset` and `total_spot_offset`
+#[derive(BorshDeserialize, Debug, Clone)]
+struct SetReservationListArgsV1 {
+    reservations: Vec<MplReservation>,
+
+    total_reservation_spots: Option<u64>,
+}
+
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenStandard {
+    NonFungible,
+
+    FungibleAsset,
+
+    Fungible,
+
+    NonFungibleEdition,
+
+    ProgrammableNonFungible,
+
+    ProgrammableNonFungibleEdition,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum CollectionDetails {
+    V1 { size: u64 },
+}
+
+// the size is all there is to collection details
+impl From<CollectionDetails> for u64 {
+    fn from(details: CollectionDetails) -> Self {
+        match details {
+            CollectionDetails::V1 { size } => size,
+        }
+    }
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum PrintSupply {
+    Zero,
+
+    Limited(u64),
+
+    Unlimited,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct CreateMetadataAccountArgsV3 {
+    pub data: MplDataV2,
+
+    pub is_mutable: bool,
+
+    pub collection_details: Option<CollectionDetails>,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct SetCollectionSizeArgs {
+    pub size: u64,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum BurnArgs {
+    V1 { amount: u64 },
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct AssetData {
+    pub name: String,
+
+    pub symbol: String,
+
+    pub uri: String,
+
+    pub seller_fee_basis_points: u16,
+
+    pub creators: Option<Vec<MplCreator>>,
+
+    pub primary_sale_happened: bool,
+
+    pub is_mutable: bool,
+
+    pub token_standard: TokenStandard,
+
+    pub collection: Option<MplCollection>,
+
+    pub uses: Option<MplUses>,
+
+    pub collection_details: Option<CollectionDetails>,
+
+    pub rule_set: Option<Pubkey>,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum CreateArgs {
+    V1 {
+        asset_data: AssetData,
+        decimals: Option<u8>,
+        print_supply: Option<PrintSupply>,
+    },
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum MintArgs {
+    V1 { amount: u64 },
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum TransferArgs {
+    V1 { amount: u64 },
+}
+
+// variants without an amount only carry authorization data
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum DelegateArgs {
+    CollectionV1,
+    SaleV1 { amount: u64 },
+    TransferV1 { amount: u64 },
+    DataV1,
+    UtilityV1 { amount: u64 },
+    StakingV1 { amount: u64 },
+    StandardV1 { amount: u64 },
+    LockedTransferV1 { amount: u64, locked_address: Pubkey },
+    ProgrammableConfigV1,
+    AuthorityItemV1,
+    DataItemV1,
+    CollectionItemV1,
+    ProgrammableConfigItemV1,
+}
+
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevokeArgs {
+    CollectionV1,
+    SaleV1,
+    TransferV1,
+    DataV1,
+    UtilityV1,
+    StakingV1,
+    StandardV1,
+    LockedTransferV1,
+    ProgrammableConfigV1,
+    MigrationV1,
+    AuthorityItemV1,
+    DataItemV1,
+    CollectionItemV1,
+    ProgrammableConfigItemV1,
+}
+
+// `None` leaves the value as is, `Clear` unsets it
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum Toggle<T> {
+    None,
+    Clear,
+    Set(T),
+}
+
+impl<T> Toggle<T> {
+    pub fn apply<U: From<T>>(self, value: &mut Option<U>) {
+        match self {
+            Self::None => {}
+            Self::Clear => *value = None,
+            Self::Set(v) => *value = Some(U::from(v)),
+        }
+    }
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum UpdateArgs {
+    V1 {
+        new_update_authority: Option<Pubkey>,
+        data: Option<MplData>,
+        primary_sale_happened: Option<bool>,
+        is_mutable: Option<bool>,
+        collection: Toggle<MplCollection>,
+        collection_details: Toggle<CollectionDetails>,
+        uses: Toggle<MplUses>,
+        rule_set: Toggle<Pubkey>,
+    },
+    AsUpdateAuthorityV2 {
+        new_update_authority: Option<Pubkey>,
+        data: Option<MplData>,
+        primary_sale_happened: Option<bool>,
+        is_mutable: Option<bool>,
+        collection: Toggle<MplCollection>,
+        collection_details: Toggle<CollectionDetails>,
+        uses: Toggle<MplUses>,
+        rule_set: Toggle<Pubkey>,
+        token_standard: Option<TokenStandard>,
+    },
+    AsAuthorityItemDelegateV2 {
+        new_update_authority: Option<Pubkey>,
+        primary_sale_happened: Option<bool>,
+        is_mutable: Option<bool>,
+        token_standard: Option<TokenStandard>,
+    },
+    AsCollectionDelegateV2 {
+        collection: Toggle<MplCollection>,
+    },
+    AsDataDelegateV2 {
+        data: Option<MplData>,
+    },
+    AsProgrammableConfigDelegateV2 {
+        rule_set: Toggle<Pubkey>,
+    },
+    AsDataItemDelegateV2 {
+        data: Option<MplData>,
+    },
+    AsCollectionItemDelegateV2 {
+        collection: Toggle<MplCollection>,
+    },
+    AsProgrammableConfigItemDelegateV2 {
+        rule_set: Toggle<Pubkey>,
+    },
+}
+
+#[derive(BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationArgs {
+    CreatorV1,
+    CollectionV1,
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub enum PrintArgs {
+    V1 { edition: u64 },
+}
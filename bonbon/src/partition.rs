@@ -1,18 +1,36 @@
 use {
-    borsh::de::BorshDeserialize,
+    crate::{
+        auction_house::AuctionHouseInstruction,
+        bubblegum::{find_asset_id, unpack_leaf_schema, BubblegumInstruction, LeafSchema},
+        candy_machine::{CandyGuardInstruction, CandyMachineInstruction},
+        layouts::{AccountRole, Decoding, LayoutRegistry},
+        logs::{parse_invocations, Invocation},
+        metadata::TokenMetadataInstruction,
+        programs::associated_token_account,
+        storefront::{
+            AuctionInstruction,
+            FixedPriceSaleInstruction,
+            MetaplexInstruction,
+            VaultInstruction,
+        },
+        token::SplTokenInstruction,
+    },
     mpl_token_metadata::instruction::MetadataInstruction,
     spl_token::instruction::{AuthorityType, TokenInstruction},
     solana_account_decoder::StringAmount,
     solana_sdk::{
         instruction::CompiledInstruction,
         message::{VersionedMessage, AccountKeys},
+        program_utils::limited_deserialize,
         pubkey::Pubkey,
+        system_instruction::SystemInstruction,
+        system_program,
     },
     solana_transaction_status::{
         TransactionWithStatusMeta,
         TransactionTokenBalance,
     },
-    std::collections::HashMap,
+    std::{collections::HashMap, fmt},
 };
 
 #[derive(Debug)]
@@ -38,6 +56,14 @@ pub struct InstructionContext<'a, 'k> {
     token_metas: &'a [TransactionTokenMeta],
 
     transient_metas: &'a mut Vec<TransactionTokenMeta>,
+
+    slot: i64,
+
+    layouts: &'a LayoutRegistry,
+
+    // the inner instructions invoked after this one under the same outer instruction (all of
+    // them for an outer instruction)
+    following: &'a [CompiledInstruction],
 }
 
 pub struct InstructionPartitioner {
@@ -45,25 +71,171 @@ pub struct InstructionPartitioner {
 
     pub partitioner: fn (
         instruction_context: InstructionContext,
-    ) -> Result<Option<Pubkey>, ErrorCode>,
+    ) -> Result<Option<Pubkey>, Error>,
+
+    // only used to describe errors
+    pub instruction_name: fn (data: &[u8]) -> Option<String>,
+
+    // the variant named by one of the program's `Program log: ` messages, in the same form as
+    // `instruction_name`. None for messages that don't name the instruction
+    pub logged_instruction_name: fn (log: &str) -> Option<String>,
+}
+
+// What to do with the instruction names programs log, see `InstructionPartitioner`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogCheck {
+    Off,
+
+    // diagnose instructions that decode to a different variant than the one logged
+    CrossCheck,
+
+    // as above, and when an instruction logged with a name doesn't deserialize, retry with the
+    // layouts registered under that name. if none fit, skip it (with a diagnostic) instead of
+    // failing the transaction
+    Fallback,
+}
+
+// `Foo` for `Foo`, `Foo(..)` and `Foo { .. }`
+pub(crate) fn variant_name<T: fmt::Debug>(value: &T) -> String {
+    let debug = format!("{:?}", value);
+    debug.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string()
+}
+
+pub fn token_instruction_name(data: &[u8]) -> Option<String> {
+    SplTokenInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+pub fn metadata_instruction_name(data: &[u8]) -> Option<String> {
+    TokenMetadataInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+// spl-token logs the variant name as is
+pub fn token_logged_instruction_name(log: &str) -> Option<String> {
+    log.strip_prefix("Instruction: ").map(|name| name.to_string())
+}
+
+// variants of `TokenMetadataInstruction`, looking through `Legacy`
+const METADATA_INSTRUCTION_NAMES: &[&str] = &[
+    "CreateMetadataAccount",
+    "UpdateMetadataAccount",
+    "DeprecatedCreateMasterEdition",
+    "DeprecatedMintNewEditionFromMasterEditionViaPrintingToken",
+    "UpdatePrimarySaleHappenedViaToken",
+    "DeprecatedSetReservationList",
+    "DeprecatedCreateReservationList",
+    "SignMetadata",
+    "DeprecatedMintPrintingTokensViaToken",
+    "DeprecatedMintPrintingTokens",
+    "CreateMasterEdition",
+    "MintNewEditionFromMasterEditionViaToken",
+    "ConvertMasterEditionV1ToV2",
+    "MintNewEditionFromMasterEditionViaVaultProxy",
+    "PuffMetadata",
+    "UpdateMetadataAccountV2",
+    "CreateMetadataAccountV2",
+    "CreateMasterEditionV3",
+    "VerifyCollection",
+    "Utilize",
+    "ApproveUseAuthority",
+    "RevokeUseAuthority",
+    "UnverifyCollection",
+    "ApproveCollectionAuthority",
+    "RevokeCollectionAuthority",
+    "SetAndVerifyCollection",
+    "FreezeDelegatedAccount",
+    "ThawDelegatedAccount",
+    "RemoveCreatorVerification",
+    "BurnNft",
+    "VerifySizedCollectionItem",
+    "UnverifySizedCollectionItem",
+    "SetAndVerifySizedCollectionItem",
+    "CreateMetadataAccountV3",
+    "SetCollectionSize",
+    "SetTokenStandard",
+    "BubblegumSetCollectionSize",
+    "BurnEditionNft",
+    "CreateEscrowAccount",
+    "CloseEscrowAccount",
+    "TransferOutOfEscrow",
+    "Burn",
+    "Create",
+    "Mint",
+    "Delegate",
+    "Revoke",
+    "Lock",
+    "Unlock",
+    "Migrate",
+    "Transfer",
+    "Update",
+    "Use",
+    "Verify",
+    "Unverify",
+    "Collect",
+    "Print",
+];
+
+// token metadata logs names in prose, e.g `Instruction: Create Metadata Accounts v2` or (later
+// versions) `IX: Update primary sale via token`. messages that don't match a variant we know
+// are ignored
+pub fn metadata_logged_instruction_name(log: &str) -> Option<String> {
+    let name = log.strip_prefix("Instruction: ").or_else(|| log.strip_prefix("IX: "))?;
+    let normalize = |name: &str| name
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+        .replace("accounts", "account");
+
+    let name = match normalize(name).as_str() {
+        "updateprimarysaleviatoken" => "UpdatePrimarySaleHappenedViaToken",
+        "useutilizetoken" => "Utilize",
+        "verifycollectionv2" => "VerifySizedCollectionItem",
+        "unverifysizedcollection" => "UnverifySizedCollectionItem",
+        "bubblegumprogramsetcollectionsize" => "BubblegumSetCollectionSize",
+        // logged by both the sized and unsized variants
+        "setandverifycollection" => return None,
+        // the deprecated instructions were logged before they were deprecated too
+        normalized => METADATA_INSTRUCTION_NAMES.iter().copied()
+            .find(|variant| normalize(variant) == normalized
+                || variant.strip_prefix("Deprecated").map(normalize).as_deref()
+                    == Some(normalized))?,
+    };
+    Some(name.to_string())
 }
 
 // NB: only returns a value for instructions that are 'likely' to contain an NFT-related token
 // instruction (i.e heuristic based on mint, amount, etc)
 pub fn partition_token_instruction(
+    instruction_context: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    partition_token_instruction_with(instruction_context, false)
+}
+
+// Opt-in variant that also keeps decimals-0 mints with a supply above 1 (SFTs, gaming items,
+// etc). Amounts are no longer used to filter, only decimals
+pub fn partition_semi_fungible_token_instruction(
+    instruction_context: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    partition_token_instruction_with(instruction_context, true)
+}
+
+fn partition_token_instruction_with(
     InstructionContext {
-        instruction, account_keys, token_metas, transient_metas,
+        instruction, account_keys, token_metas, transient_metas, ..
     }: InstructionContext,
-) -> Result<Option<Pubkey>, ErrorCode> {
-    let get_account_key = |index: usize| account_keys.get(
-        instruction.accounts[index].into()
-    ).ok_or(ErrorCode::BadAccountKeyIndex);
-    let get_token_meta_for = |index: usize| {
-        let index = instruction.accounts[index];
+    semi_fungible: bool,
+) -> Result<Option<Pubkey>, Error> {
+    // too few accounts is as malformed as bad instruction data
+    let get_account_index = |index: usize| instruction.accounts.get(index).copied()
+        .ok_or(ErrorCode::FailedInstructionDeserialization);
+    let get_account_key = |index: usize| account_keys.get(get_account_index(index)?.into())
+        .ok_or(ErrorCode::BadAccountKeyIndex);
+    let get_token_meta_for = |index: usize| -> Result<_, ErrorCode> {
+        let index = get_account_index(index)?;
         if let Some(v) = token_metas.iter().find(|m| m.account_index == index) {
-            Some(v)
+            Ok(Some(v))
         } else {
-            transient_metas.iter().find(|m| m.account_index == index)
+            Ok(transient_metas.iter().find(|m| m.account_index == index))
         }
     };
 
@@ -77,36 +249,62 @@ pub fn partition_token_instruction(
                 None => true,
             }
         };
-        meta.decimals == 0 && amount_ok(&meta.pre_amount) && amount_ok(&meta.post_amount)
+        meta.decimals == 0 && (semi_fungible
+            || (amount_ok(&meta.pre_amount) && amount_ok(&meta.post_amount)))
     };
 
-    let token_account_mint_key = |index| -> Result<Option<Pubkey>, ErrorCode> {
-        let token_meta = get_token_meta_for(index)
-            .ok_or(ErrorCode::BadTokenMetaAccountIndex)?;
+    let instruction_amount_ok = |amount: u64| semi_fungible || amount <= 1;
+
+    let token_account_mint_key = |index| -> Result<Option<Pubkey>, Error> {
+        let token_meta = match get_token_meta_for(index)? {
+            Some(token_meta) => token_meta,
+            None => return Err(Error::from(ErrorCode::BadTokenMetaAccountIndex)
+                .with_actual_key(get_account_key(index)?)),
+        };
         Ok(heuristic_token_meta_ok(token_meta)
             .then(|| token_meta.mint_key))
     };
 
-    let add_transient_token_meta = |
+    let initialize_token_account = |
         transient_metas: &mut Vec<TransactionTokenMeta>,
         owner_key: Pubkey,
-    | -> Result<(), ErrorCode> {
+    | -> Result<Option<Pubkey>, Error> {
+        let account_index = get_account_index(0)?;
+        if let Some(token_meta) = token_metas.iter().find(|m| m.account_index == account_index) {
+            return Ok(heuristic_token_meta_ok(token_meta).then(|| token_meta.mint_key));
+        }
+
         // didn't show up in pre- or post- balances so could be transient...
-        transient_metas.push(TransactionTokenMeta {
-            account_index: instruction.accounts[0],
-            decimals: 0,
-            pre_amount: None,
-            post_amount: None,
-            mint_key: *get_account_key(1)?,
-            owner_key,
-        });
-        Ok(())
+        let mint_key = *get_account_key(1)?;
+        match transient_metas.iter_mut().find(|m| m.account_index == account_index) {
+            // already created through the associated token account program
+            Some(meta) => {
+                meta.mint_key = mint_key;
+                meta.owner_key = owner_key;
+            }
+            None => {
+                transient_metas.push(TransactionTokenMeta {
+                    account_index,
+                    decimals: 0,
+                    pre_amount: None,
+                    post_amount: None,
+                    mint_key,
+                    owner_key,
+                });
+            }
+        }
+        Ok(None)
     };
 
-    let token_instruction = TokenInstruction::unpack(&instruction.data)
-        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    let token_instruction = match SplTokenInstruction::unpack(&instruction.data)
+            .map_err(|_| ErrorCode::FailedInstructionDeserialization)?
+            .into_legacy() {
+        Some(token_instruction) => token_instruction,
+        None => return Ok(None),
+    };
 
     match token_instruction {
+        // and `InitializeMint2`
         TokenInstruction::InitializeMint { decimals, .. } => {
             if decimals != 0 {
                 Ok(None)
@@ -115,36 +313,23 @@ pub fn partition_token_instruction(
             }
         },
         TokenInstruction::InitializeAccount { .. } => {
-            Ok(match get_token_meta_for(0) {
-                Some(token_meta) => heuristic_token_meta_ok(token_meta)
-                    .then(|| token_meta.mint_key),
-                None => {
-                    add_transient_token_meta(transient_metas, *get_account_key(2)?)?;
-                    None
-                }
-            })
+            initialize_token_account(transient_metas, *get_account_key(2)?)
         },
+        // and `InitializeAccount3`
         TokenInstruction::InitializeAccount2 { owner } => {
-            Ok(match get_token_meta_for(0) {
-                Some(token_meta) => heuristic_token_meta_ok(token_meta)
-                    .then(|| token_meta.mint_key),
-                None => {
-                    add_transient_token_meta(transient_metas, owner)?;
-                    None
-                }
-            })
+            initialize_token_account(transient_metas, owner)
         },
         TokenInstruction::InitializeMultisig { .. } => {
             Ok(None)
         }
         TokenInstruction::Transfer { amount } => {
-            if amount > 1 {
+            if !instruction_amount_ok(amount) {
                 return Ok(None);
             }
             token_account_mint_key(0)
         }
         TokenInstruction::Approve { amount } => {
-            if amount > 1 {
+            if !instruction_amount_ok(amount) {
                 return Ok(None);
             }
             token_account_mint_key(0)
@@ -152,68 +337,83 @@ pub fn partition_token_instruction(
         TokenInstruction::Revoke => {
             token_account_mint_key(0)
         }
-        TokenInstruction::SetAuthority { authority_type, .. } => {
+        TokenInstruction::SetAuthority { authority_type, new_authority } => {
             match authority_type {
-                // TODO: we probably don't care about this case?
-                // might be related to nft mint but shouldn't impact our handling...
-                AuthorityType::MintTokens => {
-                    Ok(None)
-                }
-                AuthorityType::FreezeAccount => {
-                    Ok(None)
+                // account 0 is the mint itself. we only know the decimals if some account for
+                // the mint shows up in the balances but skip the obviously fungible ones. the
+                // rest are only kept for mints with other history, see `needs_history`
+                AuthorityType::MintTokens | AuthorityType::FreezeAccount => {
+                    let mint_key = get_account_key(0)?;
+                    let fungible = token_metas.iter()
+                        .chain(transient_metas.iter())
+                        .any(|m| m.mint_key == *mint_key && m.decimals != 0);
+                    Ok((!fungible).then(|| *mint_key))
                 }
                 // here we could be changing ownership (aka transfer) so do handle this one...
+                AuthorityType::AccountOwner => {
+                    let mint_key = token_account_mint_key(0);
+                    let account_index = get_account_index(0)?;
+                    let new_owner: Option<Pubkey> = new_authority.into();
+                    let transient_meta = transient_metas.iter_mut()
+                        .find(|m| m.account_index == account_index);
+                    if let (Some(meta), Some(new_owner)) = (transient_meta, new_owner) {
+                        meta.owner_key = new_owner;
+                    }
+                    mint_key
+                }
                 _ => token_account_mint_key(0)
             }
         }
         TokenInstruction::MintTo { amount } => {
-            if amount > 1 {
+            if !instruction_amount_ok(amount) {
                 return Ok(None);
             }
             token_account_mint_key(1)
         }
         TokenInstruction::Burn { amount } => {
-            if amount > 1 {
+            if !instruction_amount_ok(amount) {
                 return Ok(None);
             }
             token_account_mint_key(0)
         }
         TokenInstruction::CloseAccount => {
             // mints can't be closed and a token account must have zero balance to be closed so...
+            let account_index = get_account_index(0)?;
             if let Some(index) = transient_metas.iter().position(
-                    |m| m.account_index == instruction.accounts[0]) {
+                    |m| m.account_index == account_index) {
                 transient_metas.swap_remove(index);
             }
             Ok(None)
         }
-        TokenInstruction::FreezeAccount => {
-            // not really important...
-            token_account_mint_key(0)
-        }
-        TokenInstruction::ThawAccount => {
-            // not really important...
-            token_account_mint_key(0)
+        // programmable assets are thawed and frozen around every transfer, delegate, etc. the mint
+        // is passed in so we don't need the account to show up in the balances
+        TokenInstruction::FreezeAccount | TokenInstruction::ThawAccount => {
+            match get_token_meta_for(0)? {
+                Some(token_meta) => Ok(heuristic_token_meta_ok(token_meta)
+                    .then(|| token_meta.mint_key)),
+                None => Ok(Some(*get_account_key(1)?)),
+            }
         }
         TokenInstruction::TransferChecked { amount, decimals } => {
-            if decimals != 0 || amount > 1 {
+            if decimals != 0 || !instruction_amount_ok(amount) {
                 return Ok(None);
             }
             token_account_mint_key(0)
         }
         TokenInstruction::ApproveChecked { amount, decimals } => {
-            if decimals != 0 || amount > 1 {
+            if decimals != 0 || !instruction_amount_ok(amount) {
                 return Ok(None);
             }
             token_account_mint_key(0)
         }
         TokenInstruction::MintToChecked { amount, decimals } => {
-            if decimals != 0 || amount > 1 {
+            if decimals != 0 || !instruction_amount_ok(amount) {
                 return Ok(None);
             }
             token_account_mint_key(1)
         }
         TokenInstruction::BurnChecked { amount, decimals } => {
-            if decimals != 0 || amount > 1 {
+            if decimals != 0 || !instruction_amount_ok(amount) {
                 return Ok(None);
             }
             token_account_mint_key(0)
@@ -227,19 +427,35 @@ pub fn partition_token_instruction(
 
 pub fn partition_metadata_instruction(
     InstructionContext {
-        instruction, account_keys, ..
+        instruction, account_keys, slot, layouts, ..
     }: InstructionContext,
-) -> Result<Option<Pubkey>, ErrorCode> {
-    let get_account_key = |index: usize| account_keys.get(
-        instruction.accounts[index].into()
-    ).ok_or(ErrorCode::BadAccountKeyIndex);
-    // TODO: skip check for SetReservationList:
-    // metaplex-foundation/metaplex/commit/3e26b6b208900181a9c42362f206690544467be9,
-    // this instruction's arguments change. we don't actually care about this instruction atm so
-    // just ignore it early...
-
-    let metadata_instruction = MetadataInstruction::try_from_slice(&instruction.data)
-        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+) -> Result<Option<Pubkey>, Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let slot_layout = layouts.layout_for(&mpl_token_metadata::id(), &instruction.data, slot);
+    if slot_layout.map(|l| l.decoding) == Some(Decoding::Ignore) {
+        return Ok(None);
+    }
+    // a bad index is left to `get_account_key` to report. it just fits no layout here
+    let accounts = instruction.accounts.iter()
+        .map(|i| account_keys.get(usize::from(*i)).copied().unwrap_or_default())
+        .collect::<Vec<_>>();
+    let (layout, metadata_instruction) = TokenMetadataInstruction::unpack_with(
+        &instruction.data, &accounts, slot, layouts,
+    ).map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    let get_role_key = |role: AccountRole| layout
+        .and_then(|l| l.account_index(role))
+        .ok_or(ErrorCode::MissingInstructionLayout)
+        .and_then(|index| get_account_key(index));
+
+    let metadata_instruction = match metadata_instruction {
+        TokenMetadataInstruction::Legacy(metadata_instruction) => metadata_instruction,
+        newer => {
+            return partition_newer_metadata_instruction(newer, instruction, account_keys);
+        }
+    };
 
     let partition_key = match metadata_instruction {
         MetadataInstruction::CreateMetadataAccount(_) => {
@@ -272,15 +488,8 @@ pub fn partition_metadata_instruction(
             // TODO: we need to track downstream that this parsing new-edition nfts instructions
             // depends on the master edition
 
-            // in metaplex-foundation/metaplex/commit/a29aa4cfd5c75307892254ee5ee311ca64101ea0,
-            // the master metadata account goes from index 10 to index 11. before, this commit, the
-            // token program was 11
-            let pivot_key = get_account_key(11)?;
-            let _master_key = if pivot_key == &spl_token::id() {
-                get_account_key(10)?
-            } else {
-                pivot_key
-            };
+            // moves between upgrades, see `default_layouts`
+            let _master_key = get_role_key(AccountRole::MasterMetadata)?;
 
             get_account_key(0)?
         }
@@ -311,7 +520,7 @@ pub fn partition_metadata_instruction(
             get_account_key(0)?
         }
         MetadataInstruction::DeprecatedSetReservationList(_) => {
-            // see note above
+            // no metadata account to partition on and nothing we track
             return Ok(None);
         }
         MetadataInstruction::DeprecatedCreateReservationList => {
@@ -360,25 +569,614 @@ pub fn partition_metadata_instruction(
     Ok(Some(*partition_key))
 }
 
+// Instructions added after the token metadata version we build against. The newer API (`Create`,
+// `Transfer`, etc) always passes its optional accounts, with the program id standing in for the
+// ones that aren't used, so the indexes are fixed
+fn partition_newer_metadata_instruction(
+    metadata_instruction: TokenMetadataInstruction,
+    instruction: &CompiledInstruction,
+    account_keys: &AccountKeys,
+) -> Result<Option<Pubkey>, Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let partition_key = match metadata_instruction {
+        TokenMetadataInstruction::Legacy(_) => {
+            // handled by `partition_metadata_instruction`
+            return Ok(None);
+        }
+        TokenMetadataInstruction::BurnNft => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::VerifySizedCollectionItem => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::UnverifySizedCollectionItem => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::SetAndVerifySizedCollectionItem => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::CreateMetadataAccountV3(_) => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::SetCollectionSize(_) => {
+            // the collection's own metadata
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::SetTokenStandard => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::BubblegumSetCollectionSize(_) => {
+            // the collection's own metadata
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::BurnEditionNft => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::CreateEscrowAccount => {
+            // TODO
+            return Ok(None);
+        }
+        TokenMetadataInstruction::CloseEscrowAccount => {
+            // TODO
+            return Ok(None);
+        }
+        TokenMetadataInstruction::TransferOutOfEscrow => {
+            // TODO
+            return Ok(None);
+        }
+        TokenMetadataInstruction::Burn(_) => {
+            get_account_key(2)?
+        }
+        TokenMetadataInstruction::Create(_) => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::Mint(_) => {
+            get_account_key(2)?
+        }
+        // programmable assets change hands and state through these (with the token account frozen
+        // in between) so they go with the mint like the token instructions they replace
+        TokenMetadataInstruction::Delegate(_) => {
+            get_account_key(5)?
+        }
+        TokenMetadataInstruction::Revoke(_) => {
+            get_account_key(5)?
+        }
+        TokenMetadataInstruction::Lock => {
+            get_account_key(3)?
+        }
+        TokenMetadataInstruction::Unlock => {
+            get_account_key(3)?
+        }
+        TokenMetadataInstruction::Migrate => {
+            get_account_key(0)?
+        }
+        TokenMetadataInstruction::Transfer(_) => {
+            get_account_key(4)?
+        }
+        TokenMetadataInstruction::Update(_) => {
+            get_account_key(4)?
+        }
+        TokenMetadataInstruction::Use => {
+            get_account_key(4)?
+        }
+        TokenMetadataInstruction::Verify(_) => {
+            get_account_key(2)?
+        }
+        TokenMetadataInstruction::Unverify(_) => {
+            get_account_key(2)?
+        }
+        TokenMetadataInstruction::Collect => {
+            // fees, nothing to do with any one mint
+            return Ok(None);
+        }
+        TokenMetadataInstruction::Print(_) => {
+            // the new edition's metadata
+            get_account_key(0)?
+        }
+    };
+
+    Ok(Some(*partition_key))
+}
+
+pub fn bubblegum_instruction_name(data: &[u8]) -> Option<String> {
+    BubblegumInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+// anchor logs the variant name as is, apart from instructions that were renamed since
+pub fn bubblegum_logged_instruction_name(log: &str) -> Option<String> {
+    let name = log.strip_prefix("Instruction: ")?;
+    let name = match name {
+        "SetDecompressableState" => "SetDecompressibleState",
+        name => name,
+    };
+    Some(name.to_string())
+}
+
+pub fn leaf_schema_event_name(data: &[u8]) -> Option<String> {
+    unpack_leaf_schema(data).map(|_| "LeafSchemaEvent".to_string())
+}
+
+// noop doesn't log anything
+pub fn leaf_schema_event_logged_instruction_name(_log: &str) -> Option<String> {
+    None
+}
+
+// Compressed NFTs have no mint or metadata account, so they're partitioned by asset id, the PDA
+// of their tree and leaf nonce. Mints don't pass the nonce, so the id comes from the leaf schema
+// bubblegum logs through noop right after
+pub fn partition_bubblegum_instruction(
+    InstructionContext {
+        instruction, account_keys, following, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    // the first leaf schema logged through `log_wrapper` after the instruction
+    let find_logged_asset_id = |log_wrapper_index: usize| -> Result<Option<Pubkey>, ErrorCode> {
+        let log_wrapper_key = get_account_key(log_wrapper_index)?;
+        Ok(following.iter()
+            .filter(|i| account_keys.get(usize::from(i.program_id_index)) == Some(log_wrapper_key))
+            .find_map(|i| unpack_leaf_schema(&i.data))
+            .map(|LeafSchema::V1 { id, .. }| id))
+    };
+
+    let partition_key = match BubblegumInstruction::unpack(&instruction.data)
+            .map_err(|_| ErrorCode::FailedInstructionDeserialization)? {
+        BubblegumInstruction::CreateTree => {
+            return Ok(None);
+        }
+        BubblegumInstruction::SetTreeDelegate => {
+            return Ok(None);
+        }
+        BubblegumInstruction::MintV1(_) => {
+            find_logged_asset_id(6)?.ok_or(ErrorCode::MissingLeafSchemaEvent)?
+        }
+        BubblegumInstruction::MintToCollectionV1(_) => {
+            find_logged_asset_id(12)?.ok_or(ErrorCode::MissingLeafSchemaEvent)?
+        }
+        BubblegumInstruction::Transfer(args) => {
+            find_asset_id(get_account_key(4)?, args.nonce)
+        }
+        BubblegumInstruction::Delegate(args) => {
+            find_asset_id(get_account_key(4)?, args.nonce)
+        }
+        BubblegumInstruction::Burn(args) => {
+            find_asset_id(get_account_key(3)?, args.nonce)
+        }
+        BubblegumInstruction::Redeem(args) => {
+            find_asset_id(get_account_key(3)?, args.nonce)
+        }
+        BubblegumInstruction::CancelRedeem => {
+            // the nonce is only in the voucher, but the re-inserted leaf is logged
+            match find_logged_asset_id(4)? {
+                Some(asset_id) => asset_id,
+                None => return Ok(None),
+            }
+        }
+        BubblegumInstruction::DecompressV1(_) => {
+            // the new mint is the asset id so it joins up with the token instructions after
+            *get_account_key(3)?
+        }
+        BubblegumInstruction::Compress => {
+            // the mint it burns. the new leaf is logged under its asset id
+            *get_account_key(5)?
+        }
+        BubblegumInstruction::VerifyCreator(args)
+                | BubblegumInstruction::UnverifyCreator(args) => {
+            find_asset_id(get_account_key(3)?, args.nonce)
+        }
+        BubblegumInstruction::VerifyCollection(args)
+                | BubblegumInstruction::UnverifyCollection(args)
+                | BubblegumInstruction::SetAndVerifyCollection(args, _) => {
+            find_asset_id(get_account_key(3)?, args.nonce)
+        }
+        BubblegumInstruction::SetDecompressibleState => {
+            return Ok(None);
+        }
+        BubblegumInstruction::UpdateMetadata(args) => {
+            find_asset_id(get_account_key(8)?, args.nonce)
+        }
+    };
+
+    Ok(Some(partition_key))
+}
+
+// The leaf schemas bubblegum logs through noop, partitioned with the instruction that logged them
+// since they're the only record of the leaf's owner and delegate after it. Anything else sent to
+// noop (e.g the tree's change logs) is skipped
+pub fn partition_leaf_schema_event(
+    InstructionContext {
+        instruction, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    Ok(unpack_leaf_schema(&instruction.data).map(|LeafSchema::V1 { id, .. }| id))
+}
+
+pub fn candy_machine_instruction_name(data: &[u8]) -> Option<String> {
+    CandyMachineInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+pub fn candy_guard_instruction_name(data: &[u8]) -> Option<String> {
+    CandyGuardInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+// anchor programs log the variant name as is
+pub fn anchor_logged_instruction_name(log: &str) -> Option<String> {
+    log.strip_prefix("Instruction: ").map(|name| name.to_string())
+}
+
+// Candy Machine v1 and v2 both mint into account 5. Everything else is configuration, or (for
+// collections and freezing) done through token metadata and token CPIs we partition anyway
+pub fn partition_candy_machine_instruction(
+    InstructionContext {
+        instruction, account_keys, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    match CandyMachineInstruction::unpack(&instruction.data)
+            .map_err(|_| ErrorCode::FailedInstructionDeserialization)? {
+        CandyMachineInstruction::MintNft => Ok(Some(*get_account_key(5)?)),
+        _ => Ok(None),
+    }
+}
+
+pub fn partition_candy_guard_instruction(
+    InstructionContext {
+        instruction, account_keys, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    match CandyGuardInstruction::unpack(&instruction.data)
+            .map_err(|_| ErrorCode::FailedInstructionDeserialization)? {
+        CandyGuardInstruction::Mint => Ok(Some(*get_account_key(5)?)),
+        // the minter (who gets the NFT) comes before the mint
+        CandyGuardInstruction::MintV2 => Ok(Some(*get_account_key(6)?)),
+        _ => Ok(None),
+    }
+}
+
+pub fn auction_house_instruction_name(data: &[u8]) -> Option<String> {
+    AuctionHouseInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+// Auction House listings and bids only pass the metadata account, cancels and sales pass the mint
+// as well. The auctioneer variants add their accounts after these, so they partition the same.
+// Deposits, withdrawals and receipts aren't for any one mint
+pub fn partition_auction_house_instruction(
+    InstructionContext {
+        instruction, account_keys, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    let partition_key = match AuctionHouseInstruction::unpack(&instruction.data)
+            .map_err(|_| ErrorCode::FailedInstructionDeserialization)? {
+        AuctionHouseInstruction::Sell(_) | AuctionHouseInstruction::AuctioneerSell(_) => {
+            get_account_key(2)?
+        }
+        AuctionHouseInstruction::Buy(_)
+                | AuctionHouseInstruction::PublicBuy(_)
+                | AuctionHouseInstruction::AuctioneerBuy(_)
+                | AuctionHouseInstruction::AuctioneerPublicBuy(_) => {
+            get_account_key(5)?
+        }
+        AuctionHouseInstruction::Cancel(_) | AuctionHouseInstruction::AuctioneerCancel(_) => {
+            get_account_key(2)?
+        }
+        AuctionHouseInstruction::ExecuteSale(_)
+                | AuctionHouseInstruction::ExecutePartialSale(_)
+                | AuctionHouseInstruction::AuctioneerExecuteSale(_)
+                | AuctionHouseInstruction::AuctioneerExecutePartialSale(_) => {
+            get_account_key(3)?
+        }
+        _ => {
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(*partition_key))
+}
+
+pub fn metaplex_instruction_name(data: &[u8]) -> Option<String> {
+    MetaplexInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+pub fn metaplex_auction_instruction_name(data: &[u8]) -> Option<String> {
+    AuctionInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+pub fn token_vault_instruction_name(data: &[u8]) -> Option<String> {
+    VaultInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+pub fn fixed_price_sale_instruction_name(data: &[u8]) -> Option<String> {
+    FixedPriceSaleInstruction::unpack(data).ok().map(|i| i.name())
+}
+
+// the storefront, auction and vault programs don't log their instruction names
+pub fn storefront_logged_instruction_name(_log: &str) -> Option<String> {
+    None
+}
+
+// The mint of the token account at `index`, for instructions that don't pass the mint itself.
+// None for mints that can't be a bonbon
+fn mint_of_token_account(
+    instruction: &CompiledInstruction, account_keys: &AccountKeys,
+    token_metas: &[TransactionTokenMeta], transient_metas: &[TransactionTokenMeta],
+    index: usize,
+) -> Result<Option<Pubkey>, Error> {
+    let account_index = *instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)?;
+    match token_metas.iter().chain(transient_metas.iter())
+            .find(|m| m.account_index == account_index) {
+        Some(token_meta) => Ok((token_meta.decimals == 0).then(|| token_meta.mint_key)),
+        None => Err(Error::from(ErrorCode::BadTokenMetaAccountIndex).with_actual_key(
+            account_keys.get(account_index.into()).ok_or(ErrorCode::BadAccountKeyIndex)?)),
+    }
+}
+
+// Storefront redemptions hand the winner their item (or a print of it) in the token account
+// at 2
+pub fn partition_metaplex_instruction(
+    InstructionContext {
+        instruction, account_keys, token_metas, transient_metas, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    let metaplex_instruction = MetaplexInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    if !metaplex_instruction.is_redemption() {
+        return Ok(None);
+    }
+    mint_of_token_account(instruction, account_keys, token_metas, transient_metas, 2)
+}
+
+// Auctions only know the vault they're selling so none of their instructions are for a mint.
+// Winners reach the bonbon through the storefront's redemptions
+pub fn partition_metaplex_auction_instruction(
+    InstructionContext {
+        instruction, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    AuctionInstruction::unpack(&instruction.data)
+        .map_err(|_| ErrorCode::FailedInstructionDeserialization)?;
+    Ok(None)
+}
+
+// deposits and withdrawals of a safety deposit box, keyed by the depositor's or recipient's
+// token account
+pub fn partition_token_vault_instruction(
+    InstructionContext {
+        instruction, account_keys, token_metas, transient_metas, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    let index = match VaultInstruction::unpack(&instruction.data)
+            .map_err(|_| ErrorCode::FailedInstructionDeserialization)? {
+        VaultInstruction::AddTokenToInactiveVault(_) => 1,
+        VaultInstruction::WithdrawTokenFromSafetyDepositBox(_) => 0,
+        _ => return Ok(None),
+    };
+    mint_of_token_account(instruction, account_keys, token_metas, transient_metas, index)
+}
+
+pub fn partition_fixed_price_sale_instruction(
+    InstructionContext {
+        instruction, account_keys, ..
+    }: InstructionContext,
+) -> Result<Option<Pubkey>, Error> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .ok_or(ErrorCode::FailedInstructionDeserialization)
+        .and_then(|i| account_keys.get(usize::from(*i)).ok_or(ErrorCode::BadAccountKeyIndex));
+
+    match FixedPriceSaleInstruction::unpack(&instruction.data)
+            .map_err(|_| ErrorCode::FailedInstructionDeserialization)? {
+        // the new edition's mint
+        FixedPriceSaleInstruction::Buy => Ok(Some(*get_account_key(9)?)),
+        _ => Ok(None),
+    }
+}
+
+// Token accounts can also be created by the system and associated token account programs. We
+// don't partition those instructions but follow them to keep `transient_metas` in step with
+// the token accounts that exist at each point of the transaction. Returns a diagnostic if an
+// earlier transient account at the same address was never seen closed
+fn track_token_account_lifecycle(
+    instruction: &CompiledInstruction,
+    program_id: &Pubkey,
+    account_keys: &AccountKeys,
+    token_metas: &[TransactionTokenMeta],
+    transient_metas: &mut Vec<TransactionTokenMeta>,
+    pending_creations: &mut Vec<u8>,
+) -> Result<Option<Error>, ErrorCode> {
+    let get_account_index = |index: usize| instruction.accounts.get(index).cloned()
+        .ok_or(ErrorCode::BadAccountKeyIndex);
+    let get_account_key = |index: usize| account_keys.get(get_account_index(index)?.into())
+        .ok_or(ErrorCode::BadAccountKeyIndex);
+
+    if *program_id == system_program::id() {
+        let owner = match limited_deserialize::<SystemInstruction>(&instruction.data) {
+            Ok(SystemInstruction::CreateAccount { owner, .. }) => owner,
+            Ok(SystemInstruction::CreateAccountWithSeed { owner, .. }) => owner,
+            _ => return Ok(None),
+        };
+        if owner != spl_token::id() {
+            return Ok(None);
+        }
+
+        // the associated token account program creating the account it was just asked for
+        let account_index = get_account_index(1)?;
+        if let Some(index) = pending_creations.iter().position(|i| *i == account_index) {
+            pending_creations.swap_remove(index);
+            return Ok(None);
+        }
+
+        // a new account at this address so whatever was here before must have been closed
+        if let Some(index) = transient_metas.iter().position(
+                |m| m.account_index == account_index) {
+            transient_metas.swap_remove(index);
+            return Ok(Some(Error::from(ErrorCode::FailedTransientTokenAccountMatching)
+                .with_actual_key(get_account_key(1)?)));
+        }
+    } else if *program_id == associated_token_account::id() {
+        // `Create` has no data in older versions of the program, `CreateIdempotent` is 1
+        match instruction.data.first() {
+            None | Some(0) | Some(1) => {}
+            _ => return Ok(None),
+        }
+
+        let account_index = get_account_index(1)?;
+        if token_metas.iter().chain(transient_metas.iter())
+                .any(|m| m.account_index == account_index) {
+            return Ok(None);
+        }
+        transient_metas.push(TransactionTokenMeta {
+            account_index,
+            decimals: 0,
+            pre_amount: None,
+            post_amount: None,
+            mint_key: *get_account_key(3)?,
+            owner_key: *get_account_key(2)?,
+        });
+        // it runs before the account gets created in its CPIs
+        pending_creations.push(account_index);
+    }
+
+    Ok(None)
+}
+
+// Mints and owners of token accounts at creation and ownership changes, from every
+// instruction that makes them. Kept regardless of partitioning so that the assembler can
+// resolve owners of accounts that only show up in other transactions' balances
+fn token_account_change(
+    instruction: &CompiledInstruction,
+    program_id: &Pubkey,
+    account_keys: &AccountKeys,
+) -> Result<Option<(Pubkey, Option<Pubkey>, Pubkey)>, ErrorCode> {
+    let get_account_key = |index: usize| instruction.accounts.get(index)
+        .and_then(|i| account_keys.get(usize::from(*i)))
+        .cloned()
+        .ok_or(ErrorCode::BadAccountKeyIndex);
+
+    if *program_id == spl_token::id() {
+        // anything else is the partitioner's problem
+        let token_instruction = match SplTokenInstruction::unpack(&instruction.data)
+                .ok().and_then(SplTokenInstruction::into_legacy) {
+            Some(token_instruction) => token_instruction,
+            None => return Ok(None),
+        };
+        match token_instruction {
+            TokenInstruction::InitializeAccount { .. } => Ok(Some((
+                get_account_key(0)?, Some(get_account_key(1)?), get_account_key(2)?))),
+            TokenInstruction::InitializeAccount2 { owner } => Ok(Some((
+                get_account_key(0)?, Some(get_account_key(1)?), owner))),
+            TokenInstruction::SetAuthority {
+                authority_type: AuthorityType::AccountOwner,
+                new_authority,
+            } => {
+                let new_owner: Option<Pubkey> = new_authority.into();
+                match new_owner {
+                    Some(new_owner) => Ok(Some((get_account_key(0)?, None, new_owner))),
+                    None => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    } else if *program_id == associated_token_account::id() {
+        match instruction.data.first() {
+            None | Some(0) | Some(1) => Ok(Some((
+                get_account_key(1)?, Some(get_account_key(3)?), get_account_key(2)?))),
+            _ => Ok(None),
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+// a token account initialized (with its mint) or handed to a new owner
+#[derive(Debug, Clone)]
+pub struct TokenAccountChange {
+    pub account_key: Pubkey,
+
+    // None for owner changes
+    pub mint_key: Option<Pubkey>,
+
+    pub owner_key: Pubkey,
+
+    pub outer_index: i64,
+
+    pub inner_index: Option<i64>,
+}
+
+pub struct PartitionedTransaction {
+    pub instructions: Vec<PartitionedInstruction>,
+
+    pub token_metas: Vec<TransactionTokenMeta>,
+
+    pub token_accounts: Vec<TokenAccountChange>,
+
+    // things that looked off but didn't stop us partitioning, e.g transient token accounts we
+    // never saw closed
+    pub diagnostics: Vec<Error>,
+}
+
 pub fn partition_transaction(
     transaction: TransactionWithStatusMeta,
-    partitioners: &[InstructionPartitioner]
-) -> Result<(Vec<PartitionedInstruction>, Vec<TransactionTokenMeta>), ErrorCode> {
+    slot: i64,
+    partitioners: &[InstructionPartitioner],
+    layouts: &LayoutRegistry,
+    log_check: LogCheck,
+) -> Result<PartitionedTransaction, Error> {
+    let signature = transaction.transaction_signature().as_ref().to_vec();
+    match partition_transaction_with(transaction, slot, partitioners, layouts, log_check) {
+        Ok(mut partitioned) => {
+            for diagnostic in partitioned.diagnostics.iter_mut() {
+                diagnostic.signature = Some(signature.clone());
+            }
+            Ok(partitioned)
+        }
+        Err(mut err) => {
+            err.signature = Some(signature);
+            Err(err)
+        }
+    }
+}
+
+fn partition_transaction_with(
+    transaction: TransactionWithStatusMeta,
+    slot: i64,
+    partitioners: &[InstructionPartitioner],
+    layouts: &LayoutRegistry,
+    log_check: LogCheck,
+) -> Result<PartitionedTransaction, Error> {
     let status_meta = transaction.get_status_meta()
         .ok_or(ErrorCode::MissingTransactionStatusMeta)?;
 
     let account_keys = &transaction.account_keys();
 
-    let meta_from_balance = |b: &TransactionTokenBalance| Ok(TransactionTokenMeta {
-        account_index: b.account_index,
-        decimals: b.ui_token_amount.decimals,
-        pre_amount: None,
-        post_amount: None,
-        mint_key: Pubkey::new(bs58::decode(b.mint.clone()).into_vec()
-            .map_err(|_| ErrorCode::BadPubkeyString)?.as_slice()),
-        owner_key: Pubkey::new(bs58::decode(b.owner.clone()).into_vec()
-            .map_err(|_| ErrorCode::BadPubkeyString)?.as_slice()),
-    });
+    let meta_from_balance = |b: &TransactionTokenBalance| -> Result<_, ErrorCode> {
+        Ok(TransactionTokenMeta {
+            account_index: b.account_index,
+            decimals: b.ui_token_amount.decimals,
+            pre_amount: None,
+            post_amount: None,
+            mint_key: Pubkey::new(bs58::decode(b.mint.clone()).into_vec()
+                .map_err(|_| ErrorCode::BadPubkeyString)?.as_slice()),
+            owner_key: Pubkey::new(bs58::decode(b.owner.clone()).into_vec()
+                .map_err(|_| ErrorCode::BadPubkeyString)?.as_slice()),
+        })
+    };
 
     let mut token_metas = HashMap::new();
     for balance in status_meta.pre_token_balances.into_iter().flatten() {
@@ -395,31 +1193,133 @@ pub fn partition_transaction(
 
     let token_metas = token_metas.into_values().collect::<Vec<_>>();
     let mut transient_metas = vec![];
+    // accounts an associated token account instruction is about to create
+    let mut pending_creations = vec![];
+    let mut diagnostics = vec![];
+    let mut token_accounts = vec![];
 
     let mut partitioned = vec![];
     let mut try_partition_instruction = |
         instruction: CompiledInstruction,
+        following: &[CompiledInstruction],
         outer_index: usize,
         inner_index: Option<usize>,
-    | -> Result<(), ErrorCode> {
+        outer_program_key: Pubkey,
+        invocation: Option<&Invocation>,
+    | -> Result<(), Error> {
+        let outer_index = outer_index as i64;
+        let inner_index = inner_index.map(|v| v as i64);
         let program_id = account_keys.get(usize::from(instruction.program_id_index))
-            .ok_or(ErrorCode::BadAccountKeyIndex)?;
+            .ok_or_else(|| Error {
+                outer_index: Some(outer_index),
+                inner_index,
+                ..Error::from(ErrorCode::BadAccountKeyIndex)
+            })?;
+
+        let in_instruction = |err: Error| Error {
+            program_key: Some(*program_id),
+            outer_index: Some(outer_index),
+            inner_index,
+            ..err
+        };
+
+        if inner_index.is_none() {
+            pending_creations.clear();
+        }
+        // instructions we only follow (or can't read) are skipped with a diagnostic rather than
+        // failing the whole transaction. the partitioner fails it if it needs the instruction
+        match track_token_account_lifecycle(
+                &instruction, program_id, account_keys, &token_metas, &mut transient_metas,
+                &mut pending_creations) {
+            Ok(Some(diagnostic)) => diagnostics.push(in_instruction(diagnostic)),
+            Ok(None) => {}
+            Err(code) => diagnostics.push(in_instruction(code.into())),
+        }
+
+        match token_account_change(&instruction, program_id, account_keys) {
+            Ok(Some((account_key, mint_key, owner_key))) => {
+                token_accounts.push(TokenAccountChange {
+                    account_key,
+                    mint_key,
+                    owner_key,
+                    outer_index,
+                    inner_index,
+                });
+            }
+            Ok(None) => {}
+            Err(code) => diagnostics.push(in_instruction(code.into())),
+        }
+
+        if let Some(InstructionPartitioner {
+            partitioner, instruction_name, logged_instruction_name, ..
+        }) = partitioners.iter().find(|p| &p.program_id == program_id) {
+            let decoded_name = instruction_name(&instruction.data);
+            let logged_name = match log_check {
+                LogCheck::Off => None,
+                _ => invocation.and_then(
+                    |i| i.messages.iter().find_map(|m| logged_instruction_name(m))),
+            };
+            let with_names = |err: Error| in_instruction(Error {
+                instruction_name: decoded_name.clone(),
+                logged_instruction_name: logged_name.clone(),
+                ..err
+            });
+
+            if decoded_name.is_some() && logged_name.is_some() && decoded_name != logged_name {
+                diagnostics.push(with_names(ErrorCode::InstructionLogMismatch.into()));
+            }
 
-        if let Some(InstructionPartitioner { partitioner, .. }) = partitioners.iter().find(
-            |p| &p.program_id == program_id) {
-            let partition_key = partitioner(InstructionContext {
+            let partition_key = match partitioner(InstructionContext {
                 instruction: &instruction,
                 account_keys,
                 token_metas: &token_metas,
                 transient_metas: &mut transient_metas,
-            })?;
+                slot,
+                layouts,
+                following,
+            }) {
+                Ok(partition_key) => partition_key,
+                Err(err) if log_check == LogCheck::Fallback
+                        && err.code == ErrorCode::FailedInstructionDeserialization
+                        && logged_name.is_some() => {
+                    // the layout for the slot doesn't fit. try the other layouts registered
+                    // for the logged instruction, as of the slot each went live
+                    let retried = layouts.layouts_for(program_id, &instruction.data)
+                        .filter(|l| Some(l.name) == logged_name.as_deref())
+                        .find_map(|l| partitioner(InstructionContext {
+                            instruction: &instruction,
+                            account_keys,
+                            token_metas: &token_metas,
+                            transient_metas: &mut transient_metas,
+                            slot: l.since_slot,
+                            layouts,
+                            following,
+                        }).ok());
+                    match retried {
+                        Some(partition_key) => partition_key,
+                        None => {
+                            diagnostics.push(with_names(err));
+                            return Ok(());
+                        }
+                    }
+                }
+                Err(err) => return Err(with_names(err)),
+            };
             if partition_key.is_none() { return Ok(()); }
+            let needs_history = *program_id == spl_token::id()
+                && sets_mint_authority(&instruction.data);
             partitioned.push(PartitionedInstruction {
                 instruction,
                 partition_key: partition_key.unwrap(),
                 program_key: *program_id,
-                outer_index: outer_index as i64,
-                inner_index: inner_index.map(|v| v as i64),
+                outer_index,
+                inner_index,
+                outer_program_key,
+                stack_height: match inner_index {
+                    None => Some(1),
+                    Some(_) => invocation.map(|i| i.stack_height as i64),
+                },
+                needs_history,
             });
         }
         Ok(())
@@ -432,27 +1332,57 @@ pub fn partition_transaction(
         VersionedMessage::V0(message) => message.instructions,
     };
 
+    let program_key_of = |instruction: &CompiledInstruction| account_keys
+        .get(usize::from(instruction.program_id_index))
+        .cloned()
+        .unwrap_or_default();
+
+    // inner instructions don't record how deeply nested they were so get that from the logs
+    let outer_program_keys = outer_instructions.iter().map(program_key_of).collect::<Vec<_>>();
+    let invocations = parse_invocations(
+        &status_meta.log_messages.unwrap_or_default(), &outer_program_keys);
+
     let inner_instructions = status_meta.inner_instructions.unwrap_or(vec![]);
     let mut inner_instructions_iter = inner_instructions.into_iter().peekable();
 
+    // the outer instruction runs first and then everything it invokes, in invocation order
     for (outer_index, instruction) in outer_instructions.into_iter().enumerate() {
-        if let Some(inner) = &inner_instructions_iter.peek() {
-            if usize::from(inner.index) == outer_index {
-                let inner = inner_instructions_iter.next().unwrap();
+        let outer_program_key = outer_program_keys[outer_index];
+        let outer_invocations = invocations[outer_index].as_deref().unwrap_or_default();
+        let inner = inner_instructions_iter
+            .next_if(|inner| usize::from(inner.index) == outer_index)
+            .map(|inner| inner.instructions)
+            .unwrap_or_default();
 
-                for (inner_index, instruction) in inner.instructions.into_iter().enumerate() {
-                    try_partition_instruction(instruction, outer_index, Some(inner_index))?;
-                }
-            }
+        try_partition_instruction(
+            instruction, &inner, outer_index, None, outer_program_key,
+            outer_invocations.first())?;
+
+        for (inner_index, instruction) in inner.iter().enumerate() {
+            // skip the outer instruction's own invocation. if the programs don't match up the
+            // logs are off somehow so don't trust them
+            let invocation = outer_invocations.get(inner_index + 1)
+                .filter(|i| i.program_key == program_key_of(instruction));
+            try_partition_instruction(
+                instruction.clone(), &inner[inner_index + 1..], outer_index, Some(inner_index),
+                outer_program_key, invocation)?;
         }
-        try_partition_instruction(instruction, outer_index, None)?;
     }
 
-    if transient_metas.len() != 0 {
-        return Err(ErrorCode::FailedTransientTokenAccountMatching);
+    // created (or at least initialized) but not closed and not in the post balances either
+    for transient_meta in transient_metas {
+        let mut diagnostic = Error::from(ErrorCode::FailedTransientTokenAccountMatching);
+        diagnostic.actual_key = account_keys.get(usize::from(transient_meta.account_index))
+            .cloned();
+        diagnostics.push(diagnostic);
     }
 
-    Ok((partitioned, token_metas))
+    Ok(PartitionedTransaction {
+        instructions: partitioned,
+        token_metas,
+        token_accounts,
+        diagnostics,
+    })
 }
 
 pub struct PartitionedInstruction {
@@ -465,9 +1395,30 @@ pub struct PartitionedInstruction {
     pub outer_index: i64,
 
     pub inner_index: Option<i64>,
+
+    // program of the outer instruction, i.e whatever invoked this one (possibly through other
+    // programs). the same as program_key for outer instructions
+    pub outer_program_key: Pubkey,
+
+    // 1 for outer instructions. None if the logs didn't tell us
+    pub stack_height: Option<i64>,
+
+    // a mint's own SetAuthority says nothing about its decimals, so it's only kept for mints
+    // partitioned some other way (in this transaction or another)
+    pub needs_history: bool,
 }
 
-#[derive(Debug)]
+// `SetAuthority` on the mint itself, see `PartitionedInstruction::needs_history`
+fn sets_mint_authority(data: &[u8]) -> bool {
+    matches!(
+        SplTokenInstruction::unpack(data).ok().and_then(|i| i.into_legacy()),
+        Some(TokenInstruction::SetAuthority {
+            authority_type: AuthorityType::MintTokens | AuthorityType::FreezeAccount, ..
+        }),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorCode {
     MissingTransactionStatusMeta,
 
@@ -480,5 +1431,95 @@ pub enum ErrorCode {
     FailedInstructionDeserialization,
 
     FailedTransientTokenAccountMatching,
+
+    // the instruction needs a layout (for an account that moved) and none was registered
+    MissingInstructionLayout,
+
+    // decoded as a different variant than the program logged
+    InstructionLogMismatch,
+
+    // a bubblegum mint without the leaf schema it logs through noop, so no asset id
+    MissingLeafSchemaEvent,
+}
+
+
+// An `ErrorCode` plus whatever context we had when it happened. Partitioners only know the
+// keys involved; `partition_transaction` fills in where the instruction is
+#[derive(Debug)]
+pub struct Error {
+    pub code: ErrorCode,
+
+    pub signature: Option<Vec<u8>>,
+
+    pub program_key: Option<Pubkey>,
+
+    pub outer_index: Option<i64>,
+
+    pub inner_index: Option<i64>,
+
+    // instruction variant, e.g `TransferChecked`
+    pub instruction_name: Option<String>,
+
+    // variant named in the program logs, when checking them
+    pub logged_instruction_name: Option<String>,
+
+    pub expected_key: Option<Pubkey>,
+
+    pub actual_key: Option<Pubkey>,
+}
+
+impl From<ErrorCode> for Error {
+    fn from(code: ErrorCode) -> Self {
+        Self {
+            code,
+            signature: None,
+            program_key: None,
+            outer_index: None,
+            inner_index: None,
+            instruction_name: None,
+            logged_instruction_name: None,
+            expected_key: None,
+            actual_key: None,
+        }
+    }
+}
+
+impl Error {
+    pub fn with_actual_key(mut self, actual_key: &Pubkey) -> Self {
+        self.actual_key = Some(*actual_key);
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.code)?;
+        if let Some(instruction_name) = &self.instruction_name {
+            write!(f, " in {}", instruction_name)?;
+        }
+        if let Some(logged_instruction_name) = &self.logged_instruction_name {
+            write!(f, " (logged {})", logged_instruction_name)?;
+        }
+        if let Some(program_key) = &self.program_key {
+            write!(f, " of {}", program_key)?;
+        }
+        if let Some(outer_index) = self.outer_index {
+            write!(f, " at instruction {}", outer_index)?;
+            if let Some(inner_index) = self.inner_index {
+                write!(f, ".{}", inner_index)?;
+            }
+        }
+        if let Some(signature) = &self.signature {
+            write!(f, " [{}]", bs58::encode(signature).into_string())?;
+        }
+        if let Some(expected_key) = &self.expected_key {
+            write!(f, ", expected {}", expected_key)?;
+        }
+        if let Some(actual_key) = &self.actual_key {
+            write!(f, ", got {}", actual_key)?;
+        }
+        Ok(())
+    }
 }
 
+impl std::error::Error for Error {}
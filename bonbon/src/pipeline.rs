@@ -0,0 +1,507 @@
+use {
+    crate::{
+        assemble::{
+            self,
+            update_auction_house_instruction,
+            update_bubblegum_instruction,
+            update_candy_guard_instruction,
+            update_candy_machine_instruction,
+            update_fixed_price_sale_instruction,
+            update_leaf_schema_event,
+            update_metadata_instruction,
+            update_metaplex_instruction,
+            update_token_instruction,
+            update_token_vault_instruction,
+            Bonbon,
+            BonbonUpdater,
+            InstructionContext,
+            InstructionIndex,
+            TransactionTokenOwnerMeta,
+        },
+        partition::{
+            self,
+            anchor_logged_instruction_name,
+            auction_house_instruction_name,
+            bubblegum_instruction_name,
+            bubblegum_logged_instruction_name,
+            candy_guard_instruction_name,
+            candy_machine_instruction_name,
+            fixed_price_sale_instruction_name,
+            leaf_schema_event_logged_instruction_name,
+            leaf_schema_event_name,
+            metadata_instruction_name,
+            metadata_logged_instruction_name,
+            metaplex_auction_instruction_name,
+            metaplex_instruction_name,
+            partition_auction_house_instruction,
+            partition_bubblegum_instruction,
+            partition_candy_guard_instruction,
+            partition_candy_machine_instruction,
+            partition_fixed_price_sale_instruction,
+            partition_leaf_schema_event,
+            partition_metadata_instruction,
+            partition_metaplex_auction_instruction,
+            partition_metaplex_instruction,
+            partition_semi_fungible_token_instruction,
+            partition_token_instruction,
+            partition_token_vault_instruction,
+            partition_transaction,
+            storefront_logged_instruction_name,
+            token_instruction_name,
+            token_logged_instruction_name,
+            token_vault_instruction_name,
+            InstructionPartitioner,
+            LogCheck,
+            PartitionedInstruction,
+            PartitionedTransaction,
+        },
+        layouts::{default_layouts, LayoutRegistry},
+        programs::{
+            auction_house,
+            bubblegum,
+            candy_guard,
+            candy_machine,
+            candy_machine_v2,
+            fixed_price_sale,
+            metaplex,
+            metaplex_auction,
+            spl_noop,
+            token_vault,
+        },
+        registry::TokenAccountRegistry,
+    },
+    mpl_token_metadata::pda::find_metadata_account,
+    solana_sdk::{
+        instruction::CompiledInstruction,
+        pubkey::Pubkey,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+    std::collections::{BTreeSet, HashMap},
+};
+
+pub fn default_partitioners() -> Vec<InstructionPartitioner> {
+    let mut partitioners = vec![
+        InstructionPartitioner {
+            partitioner: partition_token_instruction,
+            instruction_name: token_instruction_name,
+            logged_instruction_name: token_logged_instruction_name,
+            program_id: spl_token::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_metadata_instruction,
+            instruction_name: metadata_instruction_name,
+            logged_instruction_name: metadata_logged_instruction_name,
+            program_id: mpl_token_metadata::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_bubblegum_instruction,
+            instruction_name: bubblegum_instruction_name,
+            logged_instruction_name: bubblegum_logged_instruction_name,
+            program_id: bubblegum::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_leaf_schema_event,
+            instruction_name: leaf_schema_event_name,
+            logged_instruction_name: leaf_schema_event_logged_instruction_name,
+            program_id: spl_noop::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_candy_machine_instruction,
+            instruction_name: candy_machine_instruction_name,
+            logged_instruction_name: anchor_logged_instruction_name,
+            program_id: candy_machine::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_candy_machine_instruction,
+            instruction_name: candy_machine_instruction_name,
+            logged_instruction_name: anchor_logged_instruction_name,
+            program_id: candy_machine_v2::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_candy_guard_instruction,
+            instruction_name: candy_guard_instruction_name,
+            logged_instruction_name: anchor_logged_instruction_name,
+            program_id: candy_guard::id(),
+        },
+    ];
+    partitioners.extend(marketplace_partitioners());
+    partitioners
+}
+
+// same as the defaults but also picks up decimals-0 mints with supply above 1
+pub fn semi_fungible_partitioners() -> Vec<InstructionPartitioner> {
+    let mut partitioners = vec![
+        InstructionPartitioner {
+            partitioner: partition_semi_fungible_token_instruction,
+            instruction_name: token_instruction_name,
+            logged_instruction_name: token_logged_instruction_name,
+            program_id: spl_token::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_metadata_instruction,
+            instruction_name: metadata_instruction_name,
+            logged_instruction_name: metadata_logged_instruction_name,
+            program_id: mpl_token_metadata::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_bubblegum_instruction,
+            instruction_name: bubblegum_instruction_name,
+            logged_instruction_name: bubblegum_logged_instruction_name,
+            program_id: bubblegum::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_leaf_schema_event,
+            instruction_name: leaf_schema_event_name,
+            logged_instruction_name: leaf_schema_event_logged_instruction_name,
+            program_id: spl_noop::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_candy_machine_instruction,
+            instruction_name: candy_machine_instruction_name,
+            logged_instruction_name: anchor_logged_instruction_name,
+            program_id: candy_machine::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_candy_machine_instruction,
+            instruction_name: candy_machine_instruction_name,
+            logged_instruction_name: anchor_logged_instruction_name,
+            program_id: candy_machine_v2::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_candy_guard_instruction,
+            instruction_name: candy_guard_instruction_name,
+            logged_instruction_name: anchor_logged_instruction_name,
+            program_id: candy_guard::id(),
+        },
+    ];
+    partitioners.extend(marketplace_partitioners());
+    partitioners
+}
+
+pub fn default_updaters() -> Vec<BonbonUpdater> {
+    let mut updaters = vec![
+        BonbonUpdater {
+            update: update_token_instruction,
+            instruction_name: token_instruction_name,
+            program_id: spl_token::id(),
+        },
+        BonbonUpdater {
+            update: update_metadata_instruction,
+            instruction_name: metadata_instruction_name,
+            program_id: mpl_token_metadata::id(),
+        },
+        BonbonUpdater {
+            update: update_bubblegum_instruction,
+            instruction_name: bubblegum_instruction_name,
+            program_id: bubblegum::id(),
+        },
+        BonbonUpdater {
+            update: update_leaf_schema_event,
+            instruction_name: leaf_schema_event_name,
+            program_id: spl_noop::id(),
+        },
+        BonbonUpdater {
+            update: update_candy_machine_instruction,
+            instruction_name: candy_machine_instruction_name,
+            program_id: candy_machine::id(),
+        },
+        BonbonUpdater {
+            update: update_candy_machine_instruction,
+            instruction_name: candy_machine_instruction_name,
+            program_id: candy_machine_v2::id(),
+        },
+        BonbonUpdater {
+            update: update_candy_guard_instruction,
+            instruction_name: candy_guard_instruction_name,
+            program_id: candy_guard::id(),
+        },
+    ];
+    updaters.extend(marketplace_updaters());
+    updaters
+}
+
+// Marketplace programs we take sales and open orders from. Another marketplace needs a
+// partitioner that keys its listing, bid, cancel and sale instructions by mint (or metadata)
+// account and an updater that applies them to the bonbon (`apply_open_order`, `cancel_orders`,
+// `fill_orders` and a `Sale`), both registered here. The legacy storefront programs come last
+pub fn marketplace_partitioners() -> Vec<InstructionPartitioner> {
+    vec![
+        InstructionPartitioner {
+            partitioner: partition_auction_house_instruction,
+            instruction_name: auction_house_instruction_name,
+            logged_instruction_name: anchor_logged_instruction_name,
+            program_id: auction_house::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_metaplex_instruction,
+            instruction_name: metaplex_instruction_name,
+            logged_instruction_name: storefront_logged_instruction_name,
+            program_id: metaplex::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_metaplex_auction_instruction,
+            instruction_name: metaplex_auction_instruction_name,
+            logged_instruction_name: storefront_logged_instruction_name,
+            program_id: metaplex_auction::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_token_vault_instruction,
+            instruction_name: token_vault_instruction_name,
+            logged_instruction_name: storefront_logged_instruction_name,
+            program_id: token_vault::id(),
+        },
+        InstructionPartitioner {
+            partitioner: partition_fixed_price_sale_instruction,
+            instruction_name: fixed_price_sale_instruction_name,
+            logged_instruction_name: anchor_logged_instruction_name,
+            program_id: fixed_price_sale::id(),
+        },
+    ]
+}
+
+// auctions never partition so there's no updater for them
+pub fn marketplace_updaters() -> Vec<BonbonUpdater> {
+    vec![
+        BonbonUpdater {
+            update: update_auction_house_instruction,
+            instruction_name: auction_house_instruction_name,
+            program_id: auction_house::id(),
+        },
+        BonbonUpdater {
+            update: update_metaplex_instruction,
+            instruction_name: metaplex_instruction_name,
+            program_id: metaplex::id(),
+        },
+        BonbonUpdater {
+            update: update_token_vault_instruction,
+            instruction_name: token_vault_instruction_name,
+            program_id: token_vault::id(),
+        },
+        BonbonUpdater {
+            update: update_fixed_price_sale_instruction,
+            instruction_name: fixed_price_sale_instruction_name,
+            program_id: fixed_price_sale::id(),
+        },
+    ]
+}
+
+// what the assembler needs from a transaction once it's been partitioned. shared by all of the
+// transaction's partitioned instructions
+struct TransactionKeys {
+    signature: Vec<u8>,
+
+    account_keys: Vec<Pubkey>,
+
+    owners: Vec<TransactionTokenOwnerMeta>,
+
+    pre_balances: Vec<u64>,
+
+    post_balances: Vec<u64>,
+}
+
+struct Partition {
+    transaction: usize,
+
+    instruction: CompiledInstruction,
+
+    instruction_index: InstructionIndex,
+}
+
+// In-memory equivalent of chocolatier's partition + reassemble steps. Push every transaction
+// (in any order) and then `finish` to assemble a bonbon per mint.
+pub struct Pipeline {
+    partitioners: Vec<InstructionPartitioner>,
+
+    updaters: Vec<BonbonUpdater>,
+
+    transactions: Vec<TransactionKeys>,
+
+    partitions: HashMap<Pubkey, Vec<Partition>>,
+
+    // partition keys from the token and bubblegum programs. these are the mints (and compressed
+    // asset ids) we try to assemble
+    mint_keys: BTreeSet<Pubkey>,
+
+    token_accounts: TokenAccountRegistry,
+
+    layouts: LayoutRegistry,
+
+    log_check: LogCheck,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new(default_partitioners(), default_updaters())
+    }
+}
+
+impl Pipeline {
+    pub fn new(
+        partitioners: Vec<InstructionPartitioner>,
+        updaters: Vec<BonbonUpdater>,
+    ) -> Self {
+        Self {
+            partitioners,
+            updaters,
+            transactions: vec![],
+            partitions: HashMap::new(),
+            mint_keys: BTreeSet::new(),
+            token_accounts: TokenAccountRegistry::default(),
+            layouts: default_layouts(),
+            log_check: LogCheck::Off,
+        }
+    }
+
+    pub fn with_log_check(mut self, log_check: LogCheck) -> Self {
+        self.log_check = log_check;
+        self
+    }
+
+    pub fn push(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        transaction: TransactionWithStatusMeta,
+    ) -> Result<(), partition::Error> {
+        // skip errors
+        if transaction.get_status_meta().map(|m| m.status.is_err()) == Some(true) {
+            return Ok(());
+        }
+
+        let signature = transaction.transaction_signature().as_ref().to_vec();
+        let account_keys = transaction.account_keys()
+            .iter().cloned().collect::<Vec<_>>();
+        let (pre_balances, post_balances) = transaction.get_status_meta()
+            .map(|m| (m.pre_balances, m.post_balances))
+            .unwrap_or_default();
+
+        // diagnostics are dropped here, chocolatier logs them
+        let PartitionedTransaction {
+            instructions: partitioned, token_metas, token_accounts, ..
+        } = partition_transaction(
+            transaction, slot, &self.partitioners, &self.layouts, self.log_check)?;
+
+        for change in token_accounts {
+            let instruction_index = InstructionIndex {
+                slot,
+                block_index,
+                outer_index: change.outer_index,
+                inner_index: change.inner_index,
+            };
+            self.token_accounts.insert(instruction_index, change);
+        }
+
+        if partitioned.is_empty() {
+            return Ok(());
+        }
+
+        let transaction = self.transactions.len();
+        self.transactions.push(TransactionKeys {
+            signature,
+            account_keys,
+            owners: token_metas.iter().map(TransactionTokenOwnerMeta::from).collect(),
+            pre_balances,
+            post_balances,
+        });
+
+        for PartitionedInstruction {
+            instruction,
+            partition_key,
+            program_key,
+            outer_index,
+            inner_index,
+            needs_history,
+            ..
+        } in partitioned {
+            // transactions come in any order so mints with history are only known in `finish`
+            if (program_key == spl_token::id() && !needs_history)
+                    || program_key == bubblegum::id() {
+                self.mint_keys.insert(partition_key);
+            }
+            self.partitions.entry(partition_key).or_default().push(Partition {
+                transaction,
+                instruction,
+                instruction_index: InstructionIndex {
+                    slot,
+                    block_index,
+                    outer_index,
+                    inner_index,
+                },
+            });
+        }
+
+        Ok(())
+    }
+
+    // Assembles the bonbon for each mint seen, in mint key order. Mints that never got a
+    // metadata account (or, for compressed NFTs, a leaf schema) are skipped.
+    pub fn finish(
+        mut self,
+    ) -> impl Iterator<Item = (Pubkey, Result<Bonbon, assemble::Error>)> {
+        let mint_keys = std::mem::take(&mut self.mint_keys);
+        mint_keys.into_iter().filter_map(move |mint_key| {
+            self.assemble(&mint_key).map(|result| (mint_key, result))
+        })
+    }
+
+    fn assemble(
+        &mut self,
+        mint_key: &Pubkey,
+    ) -> Option<Result<Bonbon, assemble::Error>> {
+        let metadata_key = find_metadata_account(mint_key).0;
+
+        let mut partitions = self.partitions.remove(mint_key).unwrap_or_default();
+        partitions.extend(self.partitions.remove(&metadata_key).unwrap_or_default());
+        partitions.sort_by(|a, b| a.instruction_index.cmp(&b.instruction_index));
+
+        let mut bonbon = Bonbon::default();
+        for Partition { transaction, instruction, instruction_index } in partitions {
+            let TransactionKeys {
+                signature, account_keys, owners, pre_balances, post_balances,
+            } = &self.transactions[transaction];
+            let instruction_context = InstructionContext {
+                signature,
+                instruction: &instruction,
+                account_keys,
+                owners,
+                pre_balances,
+                post_balances,
+                token_accounts: &self.token_accounts,
+                layouts: &self.layouts,
+                instruction_index,
+            };
+
+            if let Err(err) = bonbon.update(instruction_context, &self.updaters) {
+                return Some(Err(err));
+            }
+        }
+
+        if bonbon.metadata_key == Pubkey::default() {
+            return None;
+        }
+
+        Some(Ok(bonbon))
+    }
+}
+
+// Runs the whole pipeline over `(slot, block_index, transaction)`s. Like chocolatier, a
+// transaction that fails to partition doesn't stop the rest; its error comes back with where the
+// transaction was, alongside the bonbons
+pub fn assemble_transactions<I>(
+    transactions: I,
+) -> (
+    impl Iterator<Item = (Pubkey, Result<Bonbon, assemble::Error>)>,
+    Vec<(i64, i64, partition::Error)>,
+)
+where
+    I: IntoIterator<Item = (i64, i64, TransactionWithStatusMeta)>,
+{
+    let mut pipeline = Pipeline::default();
+    let mut partition_errors = vec![];
+    for (slot, block_index, transaction) in transactions {
+        if let Err(err) = pipeline.push(slot, block_index, transaction) {
+            partition_errors.push((slot, block_index, err));
+        }
+    }
+    (pipeline.finish(), partition_errors)
+}
@@ -0,0 +1,47 @@
+// ids for programs we follow but don't otherwise depend on a crate for
+
+pub mod associated_token_account {
+    solana_sdk::declare_id!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+}
+
+pub mod bubblegum {
+    solana_sdk::declare_id!("BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY");
+}
+
+// bubblegum logs leaf schemas through CPIs to this program
+pub mod spl_noop {
+    solana_sdk::declare_id!("noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV");
+}
+
+pub mod candy_machine {
+    solana_sdk::declare_id!("cndyAnrLdpjq1Ssp1z8xxDsB8dxe7u4HL5Nxi2K5WXZ");
+}
+
+pub mod candy_machine_v2 {
+    solana_sdk::declare_id!("cndy3Z4yapfJBmL3ShUp5exZKqR3z33thTzeNMm2gRZ");
+}
+
+pub mod candy_guard {
+    solana_sdk::declare_id!("Guard1JwRhJkVH6XZhzoYxeBVQe872VfQBLw8YHHX3w");
+}
+
+pub mod auction_house {
+    solana_sdk::declare_id!("hausS13jsjafwWwGqZTUQRmWyvyxn9EQpqMwV1PBBmk");
+}
+
+// the legacy storefront and the programs it ran on, see `storefront`
+pub mod metaplex {
+    solana_sdk::declare_id!("p1exdMJcjVao65QdewkaZRUnU6VPSXhus9n2GzWfh98");
+}
+
+pub mod metaplex_auction {
+    solana_sdk::declare_id!("auctxRXPeJoc4817jDhf4HbjnhEcr1cCXenosMhK5R8");
+}
+
+pub mod token_vault {
+    solana_sdk::declare_id!("vau1zxA2LbssAUEF7Gpw91zMM1LvXrvpzJtmZ58rPsn");
+}
+
+pub mod fixed_price_sale {
+    solana_sdk::declare_id!("SaLeTjyUa5wXHnGuewUSyJ5JWZaHwz3TxqUntCE9czo");
+}
@@ -0,0 +1,64 @@
+use {
+    crate::{
+        assemble::InstructionIndex,
+        partition::TokenAccountChange,
+    },
+    solana_sdk::pubkey::Pubkey,
+    std::collections::HashMap,
+};
+
+// Token account mints and owners collected across transactions. The assembler falls back to
+// this for accounts without a pre- or post- token balance in the transaction at hand
+#[derive(Default)]
+pub struct TokenAccountRegistry {
+    // per account, in instruction order
+    changes: HashMap<Pubkey, Vec<(InstructionIndex, TokenAccountChange)>>,
+}
+
+impl TokenAccountRegistry {
+    pub fn insert(
+        &mut self,
+        instruction_index: InstructionIndex,
+        change: TokenAccountChange,
+    ) {
+        let changes = self.changes.entry(change.account_key).or_default();
+        let position = changes.partition_point(|(index, _)| *index <= instruction_index);
+        changes.insert(position, (instruction_index, change));
+    }
+
+    // owner once every change up to and including `instruction_index` has been applied
+    pub fn owner_at(
+        &self,
+        account_key: &Pubkey,
+        instruction_index: &InstructionIndex,
+    ) -> Option<Pubkey> {
+        self.changes_at(account_key, instruction_index)
+            .last()
+            .map(|(_, change)| change.owner_key)
+    }
+
+    pub fn mint_at(
+        &self,
+        account_key: &Pubkey,
+        instruction_index: &InstructionIndex,
+    ) -> Option<Pubkey> {
+        self.changes_at(account_key, instruction_index)
+            .iter()
+            .rev()
+            .find_map(|(_, change)| change.mint_key)
+    }
+
+    fn changes_at(
+        &self,
+        account_key: &Pubkey,
+        instruction_index: &InstructionIndex,
+    ) -> &[(InstructionIndex, TokenAccountChange)] {
+        match self.changes.get(account_key) {
+            Some(changes) => {
+                let position = changes.partition_point(|(index, _)| index <= instruction_index);
+                &changes[..position]
+            }
+            None => &[],
+        }
+    }
+}
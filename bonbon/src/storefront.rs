@@ -0,0 +1,282 @@
+use {
+    crate::partition::variant_name,
+    borsh::de::BorshDeserialize,
+    std::io,
+};
+
+// The 2021-era Metaplex storefront. Items were deposited in a token vault, auctioned through the
+// auction program and handed out by the storefront (`p1ex...`) program's redeem instructions, or
+// sold at a fixed price later on. The first three are plain borsh enums matched on their first
+// byte; only the arguments we use are read
+
+// Metaplex storefront instructions
+#[derive(Debug)]
+pub enum MetaplexInstruction {
+    DeprecatedInitAuctionManagerV1,
+
+    DeprecatedValidateSafetyDepositBoxV1,
+
+    RedeemBid,
+
+    RedeemFullRightsTransferBid,
+
+    DeprecatedRedeemParticipationBid,
+
+    StartAuction,
+
+    ClaimBid,
+
+    EmptyPaymentAccount,
+
+    SetStore,
+
+    SetWhitelistedCreator,
+
+    DeprecatedValidateParticipation,
+
+    DeprecatedPopulateParticipationPrintingAccount,
+
+    RedeemUnusedWinningConfigItemsAsAuctioneer,
+
+    DecommissionAuctionManager,
+
+    RedeemPrintingV2Bid,
+
+    WithdrawMasterEdition,
+
+    DeprecatedRedeemParticipationBidV2,
+
+    InitAuctionManagerV2,
+
+    ValidateSafetyDepositBoxV2,
+
+    RedeemParticipationBidV3,
+
+    EndAuction,
+
+    SetStoreIndex,
+
+    SetAuctionCache,
+
+    SetStoreV2,
+}
+
+impl MetaplexInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use MetaplexInstruction::*;
+        let discriminant = data.first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty instruction"))?;
+
+        Ok(match *discriminant {
+            0 => DeprecatedInitAuctionManagerV1,
+            1 => DeprecatedValidateSafetyDepositBoxV1,
+            2 => RedeemBid,
+            3 => RedeemFullRightsTransferBid,
+            4 => DeprecatedRedeemParticipationBid,
+            5 => StartAuction,
+            6 => ClaimBid,
+            7 => EmptyPaymentAccount,
+            8 => SetStore,
+            9 => SetWhitelistedCreator,
+            10 => DeprecatedValidateParticipation,
+            11 => DeprecatedPopulateParticipationPrintingAccount,
+            12 => RedeemUnusedWinningConfigItemsAsAuctioneer,
+            13 => DecommissionAuctionManager,
+            14 => RedeemPrintingV2Bid,
+            15 => WithdrawMasterEdition,
+            16 => DeprecatedRedeemParticipationBidV2,
+            17 => InitAuctionManagerV2,
+            18 => ValidateSafetyDepositBoxV2,
+            19 => RedeemParticipationBidV3,
+            20 => EndAuction,
+            21 => SetStoreIndex,
+            22 => SetAuctionCache,
+            23 => SetStoreV2,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown metaplex instruction")),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        variant_name(self)
+    }
+
+    // the redeem instructions all start with the same accounts: the auction manager, the
+    // safety deposit box's store, the winner's token account, the bid redemption, the safety
+    // deposit box, the vault, (the fraction mint or the safety deposit config), the auction,
+    // the bidder metadata and the bidder
+    pub fn is_redemption(&self) -> bool {
+        use MetaplexInstruction::*;
+        matches!(
+            self,
+            RedeemBid
+                | RedeemFullRightsTransferBid
+                | DeprecatedRedeemParticipationBid
+                | RedeemPrintingV2Bid
+                | DeprecatedRedeemParticipationBidV2
+                | RedeemParticipationBidV3
+                | RedeemUnusedWinningConfigItemsAsAuctioneer
+        )
+    }
+}
+
+// Metaplex auction instructions. Bids are placed against the auction, which only knows the vault
+// it's selling, so none of these are for any one mint
+#[derive(Debug)]
+pub enum AuctionInstruction {
+    CancelBid,
+
+    CreateAuction,
+
+    ClaimBid,
+
+    EndAuction,
+
+    StartAuction,
+
+    SetAuthority,
+
+    PlaceBid,
+
+    CreateAuctionV2,
+}
+
+impl AuctionInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use AuctionInstruction::*;
+        let discriminant = data.first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty instruction"))?;
+
+        Ok(match *discriminant {
+            0 => CancelBid,
+            1 => CreateAuction,
+            2 => ClaimBid,
+            3 => EndAuction,
+            4 => StartAuction,
+            5 => SetAuthority,
+            6 => PlaceBid,
+            7 => CreateAuctionV2,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown auction instruction")),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        variant_name(self)
+    }
+}
+
+// Token vault instructions
+#[derive(Debug)]
+pub enum VaultInstruction {
+    InitVault,
+
+    AddTokenToInactiveVault(AmountArgs),
+
+    ActivateVault,
+
+    CombineVault,
+
+    RedeemShares,
+
+    WithdrawTokenFromSafetyDepositBox(AmountArgs),
+
+    MintFractionalShares,
+
+    WithdrawSharesFromTreasury,
+
+    AddSharesToTreasury,
+
+    UpdateExternalPriceAccount,
+
+    SetAuthority,
+}
+
+impl VaultInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use VaultInstruction::*;
+        let (discriminant, mut rest) = data.split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty instruction"))?;
+
+        let rest = &mut rest;
+        Ok(match *discriminant {
+            0 => InitVault,
+            1 => AddTokenToInactiveVault(BorshDeserialize::deserialize(rest)?),
+            2 => ActivateVault,
+            3 => CombineVault,
+            4 => RedeemShares,
+            5 => WithdrawTokenFromSafetyDepositBox(BorshDeserialize::deserialize(rest)?),
+            6 => MintFractionalShares,
+            7 => WithdrawSharesFromTreasury,
+            8 => AddSharesToTreasury,
+            9 => UpdateExternalPriceAccount,
+            10 => SetAuthority,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown token vault instruction")),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        variant_name(self)
+    }
+}
+
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct AmountArgs {
+    pub amount: u64,
+}
+
+// Fixed price sale instructions, matched on the anchor discriminator. `Buy` prints an edition of
+// the selling resource's master edition for the buyer
+#[derive(Debug)]
+pub enum FixedPriceSaleInstruction {
+    InitSellingResource,
+
+    CreateStore,
+
+    Buy,
+
+    CloseMarket,
+
+    SuspendMarket,
+
+    ChangeMarket,
+
+    ResumeMarket,
+
+    Withdraw,
+
+    CreateMarket,
+
+    ClaimResource,
+
+    SavePrimaryMetadataCreators,
+}
+
+impl FixedPriceSaleInstruction {
+    pub fn unpack(data: &[u8]) -> io::Result<Self> {
+        use FixedPriceSaleInstruction::*;
+        let discriminator = data.get(..8)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing discriminator"))?;
+
+        Ok(match discriminator {
+            [56, 15, 222, 211, 147, 205, 4, 145] => InitSellingResource,
+            [132, 152, 9, 27, 112, 19, 95, 83] => CreateStore,
+            [102, 6, 61, 18, 1, 218, 235, 234] => Buy,
+            [88, 154, 248, 186, 48, 14, 123, 244] => CloseMarket,
+            [246, 27, 129, 46, 10, 196, 165, 118] => SuspendMarket,
+            [130, 59, 109, 101, 85, 226, 37, 88] => ChangeMarket,
+            [198, 120, 104, 87, 44, 103, 108, 143] => ResumeMarket,
+            [183, 18, 70, 156, 148, 109, 161, 34] => Withdraw,
+            [103, 226, 97, 235, 200, 188, 251, 254] => CreateMarket,
+            [0, 160, 164, 96, 237, 118, 74, 27] => ClaimResource,
+            [66, 240, 213, 46, 185, 60, 192, 254] => SavePrimaryMetadataCreators,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "unknown fixed price sale instruction")),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        variant_name(self)
+    }
+}
@@ -0,0 +1,113 @@
+use {
+    crate::partition::variant_name,
+    solana_sdk::{program_error::ProgramError, program_option::COption, pubkey::Pubkey},
+    spl_token::instruction::TokenInstruction,
+};
+
+// spl-token instructions across program versions. We build against spl-token 3.2.0 which stops
+// at `SyncNative`; everything the program added after that is decoded here. Only the mint and
+// token account initializers carry arguments we use
+#[derive(Debug)]
+pub enum SplTokenInstruction {
+    Legacy(TokenInstruction),
+
+    // `InitializeAccount2` without the rent sysvar
+    InitializeAccount3 {
+        owner: Pubkey,
+    },
+
+    InitializeMultisig2,
+
+    // `InitializeMint` without the rent sysvar
+    InitializeMint2 {
+        decimals: u8,
+        mint_authority: Pubkey,
+        freeze_authority: COption<Pubkey>,
+    },
+
+    GetAccountDataSize,
+
+    InitializeImmutableOwner,
+
+    AmountToUiAmount,
+
+    UiAmountToAmount,
+}
+
+// `SyncNative`
+const LAST_LEGACY_DISCRIMINANT: u8 = 17;
+
+// same encoding as spl-token's own unpacking
+fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+    if input.len() < 32 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let (key, rest) = input.split_at(32);
+    Ok((Pubkey::new(key), rest))
+}
+
+fn unpack_pubkey_option(input: &[u8]) -> Result<COption<Pubkey>, ProgramError> {
+    match input.split_first() {
+        Some((0, _)) => Ok(COption::None),
+        Some((1, rest)) => Ok(COption::Some(unpack_pubkey(rest)?.0)),
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+impl SplTokenInstruction {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        use SplTokenInstruction::*;
+        let (discriminant, rest) = data.split_first()
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        if *discriminant <= LAST_LEGACY_DISCRIMINANT {
+            return Ok(Legacy(TokenInstruction::unpack(data)?));
+        }
+
+        Ok(match *discriminant {
+            18 => InitializeAccount3 { owner: unpack_pubkey(rest)?.0 },
+            19 => InitializeMultisig2,
+            20 => {
+                let (decimals, rest) = rest.split_first()
+                    .ok_or(ProgramError::InvalidInstructionData)?;
+                let (mint_authority, rest) = unpack_pubkey(rest)?;
+                InitializeMint2 {
+                    decimals: *decimals,
+                    mint_authority,
+                    freeze_authority: unpack_pubkey_option(rest)?,
+                }
+            }
+            21 => GetAccountDataSize,
+            22 => InitializeImmutableOwner,
+            23 => AmountToUiAmount,
+            24 => UiAmountToAmount,
+            _ => return Err(ProgramError::InvalidInstructionData),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Legacy(instruction) => variant_name(instruction),
+            instruction => variant_name(instruction),
+        }
+    }
+
+    // The 3.2.0 instruction this one is handled as. The newer initializers only drop the rent
+    // sysvar, which comes after every account we read. None for the rest, which don't change
+    // mints, balances or owners
+    pub fn into_legacy(self) -> Option<TokenInstruction> {
+        match self {
+            Self::Legacy(instruction) => Some(instruction),
+            Self::InitializeAccount3 { owner } => {
+                Some(TokenInstruction::InitializeAccount2 { owner })
+            }
+            Self::InitializeMint2 { decimals, mint_authority, freeze_authority } => {
+                Some(TokenInstruction::InitializeMint {
+                    decimals,
+                    mint_authority,
+                    freeze_authority,
+                })
+            }
+            _ => None,
+        }
+    }
+}
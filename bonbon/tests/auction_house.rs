@@ -0,0 +1,421 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble::{Bonbon, OrderSide},
+        pipeline::assemble_transactions,
+        programs::{associated_token_account, auction_house},
+    },
+    borsh::BorshSerialize,
+    common::confirmed_transaction,
+    mpl_token_metadata::{instruction::create_metadata_accounts_v2, pda::find_metadata_account},
+    solana_sdk::{
+        hash::hash,
+        instruction::{AccountMeta, Instruction},
+        native_token::LAMPORTS_PER_SOL,
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction, system_program, sysvar,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+const SLOT: i64 = 150_000_000;
+
+const PRICE: u64 = 2 * LAMPORTS_PER_SOL;
+
+// the first 8 bytes of sha256("global:<instruction>") and then the arguments
+fn anchor_data(instruction: &str, args: &[u8]) -> Vec<u8> {
+    let mut data = hash(format!("global:{}", instruction).as_bytes()).to_bytes()[..8].to_vec();
+    data.extend_from_slice(args);
+    data
+}
+
+// an item minted to the seller and the auction house it's traded on. `auctioneer` is the
+// auctioneer's authority for the auctioneer variants
+struct Market {
+    seller: Keypair,
+
+    buyer: Keypair,
+
+    mint: Keypair,
+
+    account: Keypair,
+
+    buyer_account: Pubkey,
+
+    auction_house: Pubkey,
+
+    authority: Pubkey,
+
+    auctioneer: Option<Pubkey>,
+}
+
+impl Market {
+    fn new(auctioneer: bool) -> Self {
+        Self {
+            seller: Keypair::new(),
+            buyer: Keypair::new(),
+            mint: Keypair::new(),
+            account: Keypair::new(),
+            buyer_account: Pubkey::new_unique(),
+            auction_house: Pubkey::new_unique(),
+            authority: Pubkey::new_unique(),
+            auctioneer: auctioneer.then(Pubkey::new_unique),
+        }
+    }
+
+    fn metadata(&self) -> Pubkey {
+        find_metadata_account(&self.mint.pubkey()).0
+    }
+
+    fn mint_transaction(&self) -> TransactionWithStatusMeta {
+        let (seller, mint) = (self.seller.pubkey(), self.mint.pubkey());
+        let account = self.account.pubkey();
+        confirmed_transaction(
+            &[
+                system_instruction::create_account(
+                    &seller, &mint, 1_000_000, 82, &spl_token::id()),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint, &seller, None, 0).unwrap(),
+                system_instruction::create_account(
+                    &seller, &account, 1_000_000, 165, &spl_token::id()),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &account, &mint, &seller).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint, &account, &seller, &[], 1).unwrap(),
+                create_metadata_accounts_v2(
+                    mpl_token_metadata::id(), self.metadata(), mint, seller, seller, seller,
+                    "Bonbon #1".to_string(), "BON".to_string(),
+                    "https://example.com/1.json".to_string(), None, 500, true, true, None, None,
+                ),
+            ],
+            &[],
+            &[&self.seller, &self.mint, &self.account],
+            &[(account, mint, seller, None, Some(1))],
+        )
+    }
+
+    // the auctioneer's authority goes just before the auction house
+    fn with_auction_house(&self, mut accounts: Vec<AccountMeta>) -> Vec<AccountMeta> {
+        let at = accounts.iter().position(|a| a.pubkey == self.auction_house).unwrap();
+        if let Some(auctioneer) = self.auctioneer {
+            accounts.insert(at, AccountMeta::new_readonly(auctioneer, false));
+        }
+        accounts
+    }
+
+    fn instruction(&self, name: &str, accounts: Vec<AccountMeta>, args: &[u8]) -> Instruction {
+        let name = match self.auctioneer {
+            Some(_) => format!("auctioneer_{}", name),
+            None => name.to_string(),
+        };
+        Instruction {
+            program_id: auction_house::id(),
+            accounts: self.with_auction_house(accounts),
+            data: anchor_data(&name, args),
+        }
+    }
+
+    // wallet, token account, metadata, authority, auction house, fee account, seller trade state,
+    // free trade state, token program, system program, program as signer, rent
+    fn sell(&self, price: u64) -> TransactionWithStatusMeta {
+        self.list(&self.seller, self.account.pubkey(), price)
+    }
+
+    fn list(&self, wallet: &Keypair, account: Pubkey, price: u64) -> TransactionWithStatusMeta {
+        let seller = wallet.pubkey();
+        let program_as_signer = Pubkey::new_unique();
+        let mut accounts = vec![
+            AccountMeta::new(seller, true),
+            AccountMeta::new(account, false),
+            AccountMeta::new_readonly(self.metadata(), false),
+            AccountMeta::new_readonly(self.authority, false),
+            AccountMeta::new_readonly(self.auction_house, false),
+        ];
+        accounts.extend((0..3).map(|_| AccountMeta::new(Pubkey::new_unique(), false)));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        accounts.push(AccountMeta::new_readonly(program_as_signer, false));
+        accounts.push(AccountMeta::new_readonly(sysvar::rent::id(), false));
+        // auctioneer listings leave the price to the auctioneer
+        let args = match self.auctioneer {
+            Some(_) => (255u8, 254u8, 253u8, 1u64).try_to_vec().unwrap(),
+            None => (255u8, 254u8, 253u8, price, 1u64).try_to_vec().unwrap(),
+        };
+        // the auction house approves itself as the token account's delegate
+        let approve = spl_token::instruction::approve(
+            &spl_token::id(), &account, &program_as_signer, &seller, &[], 1).unwrap();
+        confirmed_transaction(
+            &[self.instruction("sell", accounts, &args)],
+            &[(0, vec![approve])],
+            &[wallet],
+            &[(account, self.mint.pubkey(), seller, Some(1), Some(1))],
+        )
+    }
+
+    // wallet, payment account, transfer authority, treasury mint, token account, metadata,
+    // escrow payment account, authority, auction house, fee account, buyer trade state, token
+    // program, system program, rent
+    fn buy(&self, price: u64) -> TransactionWithStatusMeta {
+        let buyer = self.buyer.pubkey();
+        let mut accounts = vec![
+            AccountMeta::new(buyer, true),
+            AccountMeta::new(buyer, false),
+            AccountMeta::new_readonly(buyer, false),
+            AccountMeta::new_readonly(spl_token::native_mint::id(), false),
+            AccountMeta::new_readonly(self.account.pubkey(), false),
+            AccountMeta::new_readonly(self.metadata(), false),
+            AccountMeta::new(Pubkey::new_unique(), false),
+            AccountMeta::new_readonly(self.authority, false),
+            AccountMeta::new_readonly(self.auction_house, false),
+        ];
+        accounts.extend((0..2).map(|_| AccountMeta::new(Pubkey::new_unique(), false)));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        accounts.push(AccountMeta::new_readonly(sysvar::rent::id(), false));
+        let args = (255u8, 254u8, price, 1u64).try_to_vec().unwrap();
+        confirmed_transaction(
+            &[self.instruction("buy", accounts, &args)], &[], &[&self.buyer], &[])
+    }
+
+    // buyer, seller, token account, token mint, metadata, treasury mint, escrow payment account,
+    // seller payment receipt account, buyer receipt token account, authority, auction house, fee
+    // account, treasury account, buyer trade state, seller trade state, free trade state, token
+    // program, system program, associated token program, program as signer, rent
+    fn execute_sale(&self, price: u64) -> TransactionWithStatusMeta {
+        let (buyer, seller) = (self.buyer.pubkey(), self.seller.pubkey());
+        let (mint, account) = (self.mint.pubkey(), self.account.pubkey());
+        let program_as_signer = Pubkey::new_unique();
+        let mut accounts = vec![
+            AccountMeta::new(buyer, true),
+            AccountMeta::new(seller, false),
+            AccountMeta::new(account, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new_readonly(self.metadata(), false),
+            AccountMeta::new_readonly(spl_token::native_mint::id(), false),
+            AccountMeta::new(Pubkey::new_unique(), false),
+            AccountMeta::new(seller, false),
+            AccountMeta::new(self.buyer_account, false),
+            AccountMeta::new_readonly(self.authority, false),
+            AccountMeta::new_readonly(self.auction_house, false),
+        ];
+        accounts.extend((0..5).map(|_| AccountMeta::new(Pubkey::new_unique(), false)));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        accounts.push(AccountMeta::new_readonly(associated_token_account::id(), false));
+        accounts.push(AccountMeta::new_readonly(program_as_signer, false));
+        accounts.push(AccountMeta::new_readonly(sysvar::rent::id(), false));
+        let args = (255u8, 254u8, 253u8, price, 1u64).try_to_vec().unwrap();
+        // the buyer's receipt account is created and the item moved into it by the delegate
+        let inner = vec![
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &self.buyer_account, &mint, &buyer).unwrap(),
+            spl_token::instruction::transfer(
+                &spl_token::id(), &account, &self.buyer_account, &program_as_signer, &[], 1,
+            ).unwrap(),
+        ];
+        confirmed_transaction(
+            &[self.instruction("execute_sale", accounts, &args)],
+            &[(0, inner)],
+            &[&self.buyer],
+            &[
+                (account, mint, seller, Some(1), Some(0)),
+                (self.buyer_account, mint, buyer, None, Some(1)),
+            ],
+        )
+    }
+
+    // wallet, token account, token mint, authority, auction house, fee account, trade state,
+    // token program. `holder` owns the token account
+    fn cancel(
+        &self, wallet: &Keypair, account: Pubkey, holder: Pubkey, price: u64,
+    ) -> TransactionWithStatusMeta {
+        let mint = self.mint.pubkey();
+        let accounts = vec![
+            AccountMeta::new(wallet.pubkey(), true),
+            AccountMeta::new(account, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new_readonly(self.authority, false),
+            AccountMeta::new_readonly(self.auction_house, false),
+            AccountMeta::new(Pubkey::new_unique(), false),
+            AccountMeta::new(Pubkey::new_unique(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ];
+        let args = (price, 1u64).try_to_vec().unwrap();
+        // the seller's cancel takes back the delegate
+        let inner = match holder == wallet.pubkey() {
+            true => vec![(0, vec![spl_token::instruction::revoke(
+                &spl_token::id(), &account, &holder, &[]).unwrap()])],
+            false => vec![],
+        };
+        confirmed_transaction(
+            &[self.instruction("cancel", accounts, &args)],
+            &inner,
+            &[wallet],
+            &[(account, mint, holder, Some(1), Some(1))],
+        )
+    }
+
+    // the seller hands the item to the buyer outside the auction house
+    fn transfer_to_buyer(&self) -> TransactionWithStatusMeta {
+        let (buyer, seller) = (self.buyer.pubkey(), self.seller.pubkey());
+        let (mint, account) = (self.mint.pubkey(), self.account.pubkey());
+        confirmed_transaction(
+            &[
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &self.buyer_account, &mint, &buyer).unwrap(),
+                spl_token::instruction::transfer(
+                    &spl_token::id(), &account, &self.buyer_account, &seller, &[], 1).unwrap(),
+            ],
+            &[],
+            &[&self.seller],
+            &[
+                (account, mint, seller, Some(1), Some(0)),
+                (self.buyer_account, mint, buyer, None, Some(1)),
+            ],
+        )
+    }
+}
+
+fn only_bonbon(transactions: Vec<(i64, i64, TransactionWithStatusMeta)>) -> Bonbon {
+    let (bonbons, partition_errors) = assemble_transactions(transactions);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+    let mut bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    bonbons.remove(0).1.expect("bonbon assembles")
+}
+
+// (side, marketplace, wallet, price)
+fn open_orders(bonbon: &Bonbon) -> Vec<(OrderSide, Option<Pubkey>, Pubkey, Option<u64>)> {
+    bonbon.open_orders.iter()
+        .map(|o| (o.side, o.marketplace, o.wallet, o.price))
+        .collect()
+}
+
+#[test]
+fn execute_sale_fills_the_listing_and_bid() {
+    let market = Market::new(false);
+    let (buyer, seller) = (market.buyer.pubkey(), market.seller.pubkey());
+
+    let listed = only_bonbon(vec![
+        (SLOT, 0, market.mint_transaction()),
+        (SLOT + 1, 0, market.sell(PRICE)),
+        (SLOT + 2, 0, market.buy(PRICE)),
+    ]);
+    // the delegate the listing approved isn't a listing of its own
+    assert_eq!(open_orders(&listed), vec![
+        (OrderSide::Listing, Some(market.auction_house), seller, Some(PRICE)),
+        (OrderSide::Bid, Some(market.auction_house), buyer, Some(PRICE)),
+    ]);
+
+    let bonbon = only_bonbon(vec![
+        (SLOT, 0, market.mint_transaction()),
+        (SLOT + 1, 0, market.sell(PRICE)),
+        (SLOT + 2, 0, market.buy(PRICE)),
+        (SLOT + 3, 0, market.execute_sale(PRICE)),
+    ]);
+    assert_eq!(bonbon.sales.len(), 1);
+    let sale = &bonbon.sales[0];
+    assert_eq!(
+        (sale.program_key, sale.marketplace, sale.buyer, sale.seller, sale.price, sale.amount),
+        (auction_house::id(), Some(market.auction_house), buyer, seller, PRICE, 1),
+    );
+    assert_eq!(sale.currency_mint, spl_token::native_mint::id());
+    assert_eq!(sale.instruction_index.slot, SLOT + 3);
+    assert!(bonbon.open_orders.is_empty(), "{:?}", bonbon.open_orders);
+    assert_eq!(bonbon.current_owner, Some(buyer));
+    assert_eq!(bonbon.current_account, Some(market.buyer_account));
+}
+
+#[test]
+fn auctioneer_sale_fills_the_listing_and_bid() {
+    let market = Market::new(true);
+    let (buyer, seller) = (market.buyer.pubkey(), market.seller.pubkey());
+
+    let listed = only_bonbon(vec![
+        (SLOT, 0, market.mint_transaction()),
+        (SLOT + 1, 0, market.sell(PRICE)),
+        (SLOT + 2, 0, market.buy(PRICE)),
+    ]);
+    // on the auction house, not the auctioneer
+    assert_eq!(open_orders(&listed), vec![
+        (OrderSide::Listing, Some(market.auction_house), seller, None),
+        (OrderSide::Bid, Some(market.auction_house), buyer, Some(PRICE)),
+    ]);
+
+    let bonbon = only_bonbon(vec![
+        (SLOT, 0, market.mint_transaction()),
+        (SLOT + 1, 0, market.sell(PRICE)),
+        (SLOT + 2, 0, market.buy(PRICE)),
+        (SLOT + 3, 0, market.execute_sale(PRICE)),
+    ]);
+    assert_eq!(bonbon.sales.len(), 1);
+    let sale = &bonbon.sales[0];
+    assert_eq!(
+        (sale.marketplace, sale.buyer, sale.seller, sale.price),
+        (Some(market.auction_house), buyer, seller, PRICE),
+    );
+    assert!(bonbon.open_orders.is_empty(), "{:?}", bonbon.open_orders);
+    assert_eq!(bonbon.current_owner, Some(buyer));
+}
+
+#[test]
+fn bid_then_cancel_leaves_the_listing() {
+    let market = Market::new(false);
+    let seller = market.seller.pubkey();
+
+    // both at the same price, only the bid is cancelled
+    let bonbon = only_bonbon(vec![
+        (SLOT, 0, market.mint_transaction()),
+        (SLOT + 1, 0, market.sell(PRICE)),
+        (SLOT + 2, 0, market.buy(PRICE)),
+        (SLOT + 3, 0, market.cancel(&market.buyer, market.account.pubkey(), seller, PRICE)),
+    ]);
+    assert_eq!(open_orders(&bonbon), vec![
+        (OrderSide::Listing, Some(market.auction_house), seller, Some(PRICE)),
+    ]);
+    assert!(bonbon.sales.is_empty());
+    assert_eq!(bonbon.current_owner, Some(seller));
+}
+
+#[test]
+fn list_then_cancel_leaves_the_bid() {
+    let market = Market::new(false);
+    let buyer = market.buyer.pubkey();
+
+    // the buyer bid on the seller's account and got the item some other way. their listing
+    // and the stale bid are from the same wallet at the same price
+    let listed = || vec![
+        (SLOT, 0, market.mint_transaction()),
+        (SLOT + 1, 0, market.buy(PRICE)),
+        (SLOT + 2, 0, market.transfer_to_buyer()),
+        (SLOT + 3, 0, market.list(&market.buyer, market.buyer_account, PRICE)),
+    ];
+    assert_eq!(open_orders(&only_bonbon(listed())), vec![
+        (OrderSide::Bid, Some(market.auction_house), buyer, Some(PRICE)),
+        (OrderSide::Listing, Some(market.auction_house), buyer, Some(PRICE)),
+    ]);
+
+    let mut transactions = listed();
+    transactions.push(
+        (SLOT + 4, 0, market.cancel(&market.buyer, market.buyer_account, buyer, PRICE)));
+    let bonbon = only_bonbon(transactions);
+    assert_eq!(open_orders(&bonbon), vec![
+        (OrderSide::Bid, Some(market.auction_house), buyer, Some(PRICE)),
+    ]);
+}
+
+#[test]
+fn auctioneer_cancel_closes_the_unpriced_listing() {
+    let market = Market::new(true);
+    let seller = market.seller.pubkey();
+
+    // auctioneer listings are cancelled at the price the auction house recorded them at
+    let bonbon = only_bonbon(vec![
+        (SLOT, 0, market.mint_transaction()),
+        (SLOT + 1, 0, market.sell(PRICE)),
+        (SLOT + 2, 0, market.cancel(&market.seller, market.account.pubkey(), seller, u64::MAX)),
+    ]);
+    assert!(bonbon.open_orders.is_empty(), "{:?}", bonbon.open_orders);
+}
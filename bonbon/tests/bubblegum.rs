@@ -0,0 +1,195 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble::Bonbon,
+        bubblegum::find_asset_id,
+        pipeline::assemble_transactions,
+        programs::bubblegum,
+    },
+    borsh::BorshSerialize,
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::create_metadata_accounts_v2,
+        pda::{find_master_edition_account, find_metadata_account},
+        state::Creator,
+    },
+    solana_sdk::{
+        hash::hash,
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction, system_program,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+const SLOT: i64 = 190_000_000;
+
+// the first 8 bytes of sha256("global:<instruction>") and then the arguments
+fn anchor_data(instruction: &str, args: &[u8]) -> Vec<u8> {
+    let mut data = hash(format!("global:{}", instruction).as_bytes()).to_bytes()[..8].to_vec();
+    data.extend_from_slice(args);
+    data
+}
+
+// `MetadataArgs` for a mutable leaf without an edition nonce, token standard, collection or uses
+fn metadata_args(name: &str, uri: &str, creators: &[Creator]) -> Vec<u8> {
+    let mut data = (
+        name.to_string(), "BON".to_string(), uri.to_string(), 500u16, false, true,
+    ).try_to_vec().unwrap();
+    // edition nonce, token standard, collection and uses
+    data.extend([0, 0, 0, 0]);
+    // `TokenProgramVersion::Original`
+    data.push(0);
+    data.extend(creators.to_vec().try_to_vec().unwrap());
+    data
+}
+
+fn only_bonbon(transactions: Vec<(i64, i64, TransactionWithStatusMeta)>) -> (Pubkey, Bonbon) {
+    let (bonbons, partition_errors) = assemble_transactions(transactions);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+    let mut bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    let (mint_key, bonbon) = bonbons.remove(0);
+    (mint_key, bonbon.expect("bonbon assembles"))
+}
+
+#[test]
+fn update_metadata_glazes_the_leaf() {
+    let authority = Keypair::new();
+    let tree = Pubkey::new_unique();
+    let nonce = 7u64;
+    let creator = Creator { address: authority.pubkey(), verified: true, share: 100 };
+
+    let mut args = ([0u8; 32], nonce, 7u32).try_to_vec().unwrap();
+    args.extend(metadata_args("Leaf #7", "https://example.com/7.json", &[creator]));
+    // name, symbol, uri, creators, seller fee basis points, primary sale happened, is mutable
+    let update_args: (
+        Option<String>, Option<String>, Option<String>, Option<Vec<Creator>>,
+        Option<u16>, Option<bool>, Option<bool>,
+    ) = (
+        Some("Leaf #7 (revealed)".to_string()), None,
+        Some("https://example.com/7-revealed.json".to_string()), None, Some(250), None, None,
+    );
+    args.extend(update_args.try_to_vec().unwrap());
+
+    // tree authority, authority, collection mint, collection metadata, collection authority
+    // record, leaf owner, leaf delegate, payer, merkle tree, log wrapper, compression program,
+    // token metadata program, system program
+    let mut accounts = (0..13)
+        .map(|_| AccountMeta::new_readonly(Pubkey::new_unique(), false))
+        .collect::<Vec<_>>();
+    accounts[1] = AccountMeta::new_readonly(authority.pubkey(), true);
+    accounts[7] = AccountMeta::new(authority.pubkey(), true);
+    accounts[8] = AccountMeta::new(tree, false);
+    let update_metadata = Instruction {
+        program_id: bubblegum::id(),
+        accounts,
+        data: anchor_data("update_metadata", &args),
+    };
+
+    let (asset_id, bonbon) = only_bonbon(vec![
+        (SLOT, 0, confirmed_transaction(&[update_metadata], &[], &[&authority], &[])),
+    ]);
+    assert_eq!(asset_id, find_asset_id(&tree, nonce));
+    assert_eq!(bonbon.compressed_leaf.as_ref().map(|l| (l.tree, l.nonce)), Some((tree, nonce)));
+
+    // the current metadata with the update applied over it
+    assert_eq!(bonbon.glazings.len(), 1);
+    let glazing = &bonbon.glazings[0];
+    assert_eq!(glazing.name, b"Leaf #7 (revealed)");
+    assert_eq!(glazing.symbol, b"BON");
+    assert_eq!(glazing.uri, b"https://example.com/7-revealed.json");
+    assert_eq!(glazing.seller_fee_basis_points, 250);
+    assert!(glazing.is_mutable);
+    assert_eq!(
+        glazing.creators.iter().map(|c| (c.address, c.verified)).collect::<Vec<_>>(),
+        vec![(authority.pubkey(), true)],
+    );
+    assert_eq!(glazing.instruction_index.slot, SLOT);
+}
+
+#[test]
+fn compress_burns_the_mint_into_a_leaf() {
+    let authority = Keypair::new();
+    let mint = Keypair::new();
+    let account = Keypair::new();
+    let tree = Pubkey::new_unique();
+    let metadata = find_metadata_account(&mint.pubkey()).0;
+
+    let mint_transaction = confirmed_transaction(
+        &[
+            system_instruction::create_account(
+                &authority.pubkey(), &mint.pubkey(), 1_000_000, 82, &spl_token::id()),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(), &mint.pubkey(), &authority.pubkey(), None, 0,
+            ).unwrap(),
+            system_instruction::create_account(
+                &authority.pubkey(), &account.pubkey(), 1_000_000, 165, &spl_token::id()),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &account.pubkey(), &mint.pubkey(), &authority.pubkey(),
+            ).unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::id(), &mint.pubkey(), &account.pubkey(), &authority.pubkey(), &[], 1,
+            ).unwrap(),
+            create_metadata_accounts_v2(
+                mpl_token_metadata::id(), metadata, mint.pubkey(), authority.pubkey(),
+                authority.pubkey(), authority.pubkey(), "Bonbon #1".to_string(),
+                "BON".to_string(), "https://example.com/1.json".to_string(), None, 500, true,
+                true, None, None,
+            ),
+        ],
+        &[],
+        &[&authority, &mint, &account],
+        &[(account.pubkey(), mint.pubkey(), authority.pubkey(), None, Some(1))],
+    );
+
+    // tree authority, leaf owner, leaf delegate, merkle tree, token account, mint, metadata,
+    // master edition, payer, log wrapper, compression program, token program, token metadata
+    // program, system program
+    let compress = Instruction {
+        program_id: bubblegum::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            AccountMeta::new_readonly(authority.pubkey(), true),
+            AccountMeta::new_readonly(authority.pubkey(), false),
+            AccountMeta::new(tree, false),
+            AccountMeta::new(account.pubkey(), false),
+            AccountMeta::new(mint.pubkey(), false),
+            AccountMeta::new(metadata, false),
+            AccountMeta::new(find_master_edition_account(&mint.pubkey()).0, false),
+            AccountMeta::new(authority.pubkey(), true),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(mpl_token_metadata::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: anchor_data("compress", &[]),
+    };
+    // the burn the program CPIs (through token metadata)
+    let burn = spl_token::instruction::burn(
+        &spl_token::id(), &account.pubkey(), &mint.pubkey(), &authority.pubkey(), &[], 1,
+    ).unwrap();
+    let compress_transaction = confirmed_transaction(
+        &[compress],
+        &[(0, vec![burn])],
+        &[&authority],
+        &[(account.pubkey(), mint.pubkey(), authority.pubkey(), Some(1), Some(0))],
+    );
+
+    let (mint_key, bonbon) = only_bonbon(vec![
+        (SLOT, 0, mint_transaction),
+        (SLOT + 1, 0, compress_transaction),
+    ]);
+    assert_eq!(mint_key, mint.pubkey());
+    assert_eq!(bonbon.compressed_leaf.as_ref().map(|l| (l.tree, l.redeemed)), Some((tree, false)));
+
+    // burned once, though both the compress and its CPI record it
+    assert_eq!(bonbon.supply, 0);
+    assert_eq!(bonbon.peak_supply, 1);
+    assert!(bonbon.holdings.is_empty(), "{:?}", bonbon.holdings);
+    assert_eq!(bonbon.current_owner, None);
+    assert_eq!(bonbon.current_account, None);
+}
@@ -0,0 +1,245 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble::{Bonbon, CandyMachineVersion},
+        pipeline::assemble_transactions,
+        programs::{candy_guard, candy_machine, candy_machine_v2},
+    },
+    common::confirmed_transaction,
+    mpl_token_metadata::{instruction::create_metadata_accounts_v2, pda::find_metadata_account},
+    solana_sdk::{
+        hash::hash,
+        instruction::{AccountMeta, Instruction},
+        message::VersionedMessage,
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+const SLOT: i64 = 200_000_000;
+
+const PRICE: u64 = 1_500_000_000;
+
+// the first 8 bytes of sha256("global:<instruction>") and then the arguments
+fn anchor_data(instruction: &str, args: &[u8]) -> Vec<u8> {
+    let mut data = hash(format!("global:{}", instruction).as_bytes()).to_bytes()[..8].to_vec();
+    data.extend_from_slice(args);
+    data
+}
+
+// A mint the payer sets up in the outer instructions and then hands to a candy machine, which
+// creates the metadata in a CPI
+struct Mint {
+    payer: Keypair,
+
+    mint: Keypair,
+
+    account: Keypair,
+}
+
+impl Mint {
+    fn new() -> Self {
+        Self { payer: Keypair::new(), mint: Keypair::new(), account: Keypair::new() }
+    }
+
+    fn metadata(&self) -> Pubkey {
+        find_metadata_account(&self.mint.pubkey()).0
+    }
+
+    // `mint` is the candy machine instruction and `lamports` what wallets held before and after
+    fn assemble(&self, mint: Instruction, lamports: &[(Pubkey, u64, u64)]) -> Bonbon {
+        let (payer, mint_key, account) =
+            (self.payer.pubkey(), self.mint.pubkey(), self.account.pubkey());
+        let create_metadata = create_metadata_accounts_v2(
+            mpl_token_metadata::id(), self.metadata(), mint_key, payer, payer, payer,
+            "Bonbon #5".to_string(), "BON".to_string(), "https://example.com/5.json".to_string(),
+            None, 500, true, true, None, None,
+        );
+        let transaction = confirmed_transaction(
+            &[
+                system_instruction::create_account(
+                    &payer, &mint_key, 1_000_000, 82, &spl_token::id()),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint_key, &payer, Some(&payer), 0).unwrap(),
+                system_instruction::create_account(
+                    &payer, &account, 1_000_000, 165, &spl_token::id()),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &account, &mint_key, &payer).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint_key, &account, &payer, &[], 1).unwrap(),
+                mint,
+            ],
+            &[(5, vec![create_metadata])],
+            &[&self.payer, &self.mint, &self.account],
+            &[(account, mint_key, payer, None, Some(1))],
+        );
+
+        let (bonbons, partition_errors) =
+            assemble_transactions(vec![(SLOT, 0, with_lamports(transaction, lamports))]);
+        assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+        let mut bonbons = bonbons.collect::<Vec<_>>();
+        assert_eq!(bonbons.len(), 1);
+        let (mint_key, bonbon) = bonbons.remove(0);
+        assert_eq!(mint_key, self.mint.pubkey());
+        bonbon.expect("bonbon assembles")
+    }
+
+    // the accounts v1 and v2 `mint_nft` share from the payer on: payer, wallet, metadata, mint,
+    // mint authority, update authority, master edition
+    fn mint_nft_accounts(&self, wallet: &Pubkey) -> Vec<AccountMeta> {
+        let payer = self.payer.pubkey();
+        vec![
+            AccountMeta::new(payer, true),
+            AccountMeta::new(*wallet, false),
+            AccountMeta::new(self.metadata(), false),
+            AccountMeta::new(self.mint.pubkey(), false),
+            AccountMeta::new_readonly(payer, true),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            AccountMeta::new(Pubkey::new_unique(), false),
+            AccountMeta::new_readonly(mpl_token_metadata::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ]
+    }
+}
+
+fn with_lamports(
+    transaction: TransactionWithStatusMeta,
+    lamports: &[(Pubkey, u64, u64)],
+) -> TransactionWithStatusMeta {
+    match transaction {
+        TransactionWithStatusMeta::Complete(mut transaction) => {
+            let keys = match &transaction.transaction.message {
+                VersionedMessage::Legacy(message) => message.account_keys.clone(),
+                VersionedMessage::V0(message) => message.account_keys.clone(),
+            };
+            for (wallet, pre, post) in lamports {
+                let index = keys.iter().position(|k| k == wallet).expect("wallet is in it");
+                transaction.meta.pre_balances[index] = *pre;
+                transaction.meta.post_balances[index] = *post;
+            }
+            TransactionWithStatusMeta::Complete(transaction)
+        }
+        transaction => transaction,
+    }
+}
+
+// version, candy machine, config, payer, price and outer index of the candy machine mint
+type Provenance = (CandyMachineVersion, Pubkey, Option<Pubkey>, Pubkey, Option<u64>, i64);
+
+fn provenance(bonbon: &Bonbon) -> Option<Provenance> {
+    bonbon.candy_machine_mint.as_ref().map(|m| (
+        m.version, m.candy_machine, m.config, m.payer, m.price, m.instruction_index.outer_index,
+    ))
+}
+
+#[test]
+fn v1_mint_records_the_config_and_price() {
+    let mint = Mint::new();
+    let (config, candy_machine, wallet) =
+        (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(config, false),
+        AccountMeta::new(candy_machine, false),
+    ];
+    accounts.extend(mint.mint_nft_accounts(&wallet));
+    let mint_nft = Instruction {
+        program_id: candy_machine::id(),
+        accounts,
+        data: anchor_data("mint_nft", &[]),
+    };
+
+    let bonbon = mint.assemble(mint_nft, &[(wallet, 10_000, 10_000 + PRICE)]);
+    assert_eq!(
+        provenance(&bonbon),
+        Some((
+            CandyMachineVersion::V1, candy_machine, Some(config), mint.payer.pubkey(),
+            Some(PRICE), 5,
+        )),
+    );
+    assert_eq!(bonbon.glazings.len(), 1);
+}
+
+#[test]
+fn v2_mint_records_the_candy_machine_and_price() {
+    let mint = Mint::new();
+    let (candy_machine, wallet) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mint_nft = || {
+        let mut accounts = vec![
+            AccountMeta::new(candy_machine, false),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+        ];
+        accounts.extend(mint.mint_nft_accounts(&wallet));
+        Instruction {
+            program_id: candy_machine_v2::id(),
+            accounts,
+            // the creator bump
+            data: anchor_data("mint_nft", &[254]),
+        }
+    };
+
+    let bonbon = mint.assemble(mint_nft(), &[(wallet, 0, PRICE)]);
+    assert_eq!(
+        provenance(&bonbon),
+        Some((CandyMachineVersion::V2, candy_machine, None, mint.payer.pubkey(), Some(PRICE), 5)),
+    );
+
+    // paid in tokens, so nothing for the wallet
+    let bonbon = mint.assemble(mint_nft(), &[]);
+    assert_eq!(provenance(&bonbon).and_then(|p| p.4), Some(0));
+}
+
+#[test]
+fn candy_guard_mint_records_the_guard() {
+    let mint = Mint::new();
+    let (guard, candy_machine, minter) =
+        (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+    // candy guard, candy machine program, candy machine, candy machine authority, payer, and
+    // for `mint_v2` the minter, then the mint
+    let mint_instruction = |name: &str, minter: Option<&Pubkey>| {
+        let mut accounts = vec![
+            AccountMeta::new_readonly(guard, false),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            AccountMeta::new(candy_machine, false),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            AccountMeta::new(mint.payer.pubkey(), true),
+        ];
+        accounts.extend(minter.map(|m| AccountMeta::new_readonly(*m, false)));
+        accounts.push(AccountMeta::new(mint.mint.pubkey(), false));
+        accounts.push(AccountMeta::new_readonly(mpl_token_metadata::id(), false));
+        Instruction {
+            program_id: candy_guard::id(),
+            accounts,
+            // no mint args or label
+            data: anchor_data(name, &[0, 0, 0, 0, 0]),
+        }
+    };
+    let expected = Some((
+        CandyMachineVersion::Guard, candy_machine, Some(guard), mint.payer.pubkey(), None, 5,
+    ));
+
+    // whatever the wallets got, since the payment guards pay their own destinations
+    let bonbon = mint.assemble(mint_instruction("mint", None), &[(guard, 0, PRICE)]);
+    assert_eq!(provenance(&bonbon), expected);
+
+    let bonbon = mint.assemble(mint_instruction("mint_v2", Some(&minter)), &[]);
+    assert_eq!(provenance(&bonbon), expected);
+}
+
+#[test]
+fn mints_outside_a_candy_machine_have_no_provenance() {
+    let mint = Mint::new();
+    // a memo-like instruction in the candy machine's place
+    let other = Instruction {
+        program_id: Pubkey::new_unique(),
+        accounts: vec![AccountMeta::new(mint.payer.pubkey(), true)],
+        data: vec![],
+    };
+    let bonbon = mint.assemble(other, &[]);
+    assert_eq!(provenance(&bonbon), None);
+    assert_eq!(bonbon.glazings.len(), 1);
+}
@@ -0,0 +1,299 @@
+mod common;
+
+use {
+    bonbon::{assemble::Bonbon, pipeline::assemble_transactions},
+    borsh::BorshSerialize,
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::{create_metadata_accounts_v2, MetadataInstruction},
+        pda::{find_master_edition_account, find_metadata_account},
+        state::{Collection, DataV2},
+    },
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction, system_program, sysvar,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+const SLOT: i64 = 160_000_000;
+
+// A collection NFT created with `CreateMetadataAccountV3` (sized or not) and the instructions
+// its update authority manages it with
+struct CollectionNft {
+    authority: Keypair,
+
+    mint: Keypair,
+}
+
+impl CollectionNft {
+    fn new() -> Self {
+        Self { authority: Keypair::new(), mint: Keypair::new() }
+    }
+
+    fn metadata(&self) -> Pubkey {
+        find_metadata_account(&self.mint.pubkey()).0
+    }
+
+    // `CreateMetadataAccountV3` (33) takes the v2 accounts and `size` for a sized collection
+    fn mint_transaction(&self, size: Option<u64>) -> TransactionWithStatusMeta {
+        let authority = self.authority.pubkey();
+        let mint = self.mint.pubkey();
+        let account = Keypair::new();
+
+        let mut data = vec![33];
+        data.extend(DataV2 {
+            name: "Bonbons".to_string(),
+            symbol: "BON".to_string(),
+            uri: "https://example.com/collection.json".to_string(),
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        }.try_to_vec().unwrap());
+        // is mutable and `CollectionDetails::V1`
+        data.push(1);
+        match size {
+            Some(size) => {
+                data.extend_from_slice(&[1, 0]);
+                data.extend(size.to_le_bytes());
+            }
+            None => data.push(0),
+        }
+        let create_metadata = Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new_readonly(authority, true),
+                AccountMeta::new(authority, true),
+                AccountMeta::new_readonly(authority, true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            data,
+        };
+
+        confirmed_transaction(
+            &[
+                system_instruction::create_account(
+                    &authority, &mint, 1_000_000, 82, &spl_token::id()),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint, &authority, None, 0).unwrap(),
+                system_instruction::create_account(
+                    &authority, &account.pubkey(), 1_000_000, 165, &spl_token::id()),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &account.pubkey(), &mint, &authority).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint, &account.pubkey(), &authority, &[], 1).unwrap(),
+                create_metadata,
+            ],
+            &[],
+            &[&self.authority, &self.mint, &account],
+            &[(account.pubkey(), mint, authority, None, Some(1))],
+        )
+    }
+
+    // collection authority record, new authority, update authority, payer, metadata, mint, the
+    // system program and the rent sysvar
+    fn approve(&self, delegate: &Pubkey, record: &Pubkey) -> Instruction {
+        let authority = self.authority.pubkey();
+        Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(*record, false),
+                AccountMeta::new_readonly(*delegate, false),
+                AccountMeta::new(authority, true),
+                AccountMeta::new(authority, true),
+                AccountMeta::new_readonly(self.metadata(), false),
+                AccountMeta::new_readonly(self.mint.pubkey(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            data: MetadataInstruction::ApproveCollectionAuthority.try_to_vec().unwrap(),
+        }
+    }
+
+    // collection authority record, delegate, revoking authority, metadata and mint
+    fn revoke(&self, delegate: &Pubkey, record: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(*record, false),
+                AccountMeta::new_readonly(*delegate, false),
+                AccountMeta::new(self.authority.pubkey(), true),
+                AccountMeta::new_readonly(self.metadata(), false),
+                AccountMeta::new_readonly(self.mint.pubkey(), false),
+            ],
+            data: MetadataInstruction::RevokeCollectionAuthority.try_to_vec().unwrap(),
+        }
+    }
+
+    // `SetCollectionSize` (34): metadata, update authority and mint
+    fn set_size(&self, size: u64) -> Instruction {
+        let mut data = vec![34];
+        data.extend(size.to_le_bytes());
+        Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new_readonly(self.authority.pubkey(), true),
+                AccountMeta::new_readonly(self.mint.pubkey(), false),
+            ],
+            data,
+        }
+    }
+}
+
+// the collection's mint at `SLOT` and then each instruction in a transaction of its own at the
+// slots after, signed by the collection's update authority and `signer`
+fn assemble(
+    mint_transactions: Vec<TransactionWithStatusMeta>,
+    instructions: &[(Instruction, &Keypair)],
+) -> Vec<(Pubkey, Bonbon)> {
+    let transactions = mint_transactions.into_iter().map(|t| (SLOT, 0, t))
+        .chain(instructions.iter().enumerate().map(|(i, (instruction, signer))| {
+            let fee_payer = Keypair::new();
+            let transaction = confirmed_transaction(
+                &[instruction.clone()], &[], &[&fee_payer, signer], &[]);
+            (SLOT + 1 + i as i64, 0, transaction)
+        }))
+        .collect::<Vec<_>>();
+
+    let (bonbons, partition_errors) = assemble_transactions(transactions);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+    bonbons.map(|(key, bonbon)| (key, bonbon.expect("bonbon assembles"))).collect()
+}
+
+// (authority, record, approved, slot)
+fn authorities(bonbon: &Bonbon) -> Vec<(Pubkey, Pubkey, bool, i64)> {
+    bonbon.collection_authorities.iter()
+        .map(|a| (a.authority, a.record, a.approved, a.instruction_index.slot))
+        .collect()
+}
+
+#[test]
+fn sized_collection_size_and_authorities() {
+    let collection = CollectionNft::new();
+    let (delegate, record) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let authority = &collection.authority;
+
+    let created = assemble(vec![collection.mint_transaction(Some(0))], &[]).remove(0).1;
+    assert!(created.is_collection());
+    assert_eq!(created.collection_size, Some(0));
+    assert!(created.collection_authorities.is_empty());
+
+    let mut bonbons = assemble(
+        vec![collection.mint_transaction(Some(0))],
+        &[
+            (collection.approve(&delegate, &record), authority),
+            (collection.set_size(2), authority),
+            (collection.revoke(&delegate, &record), authority),
+        ],
+    );
+    assert_eq!(bonbons.len(), 1);
+    let (mint_key, bonbon) = bonbons.remove(0);
+    assert_eq!(mint_key, collection.mint.pubkey());
+    assert_eq!(bonbon.collection_size, Some(2));
+    assert_eq!(
+        authorities(&bonbon),
+        vec![(delegate, record, true, SLOT + 1), (delegate, record, false, SLOT + 3)],
+    );
+}
+
+// an unsized collection only looks like one once it delegates an authority
+#[test]
+fn unsized_collection_with_a_delegate() {
+    let collection = CollectionNft::new();
+    let (delegate, record) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    let created = assemble(vec![collection.mint_transaction(None)], &[]).remove(0).1;
+    assert!(!created.is_collection());
+    assert_eq!(created.collection_size, None);
+
+    let approved = assemble(
+        vec![collection.mint_transaction(None)],
+        &[(collection.approve(&delegate, &record), &collection.authority)],
+    ).remove(0).1;
+    assert!(approved.is_collection());
+    assert_eq!(approved.collection_size, None);
+    assert_eq!(authorities(&approved), vec![(delegate, record, true, SLOT + 1)]);
+}
+
+// an item verified into the collection by the collection's delegated authority
+#[test]
+fn delegate_verifies_a_member() {
+    let collection = CollectionNft::new();
+    let delegate = Keypair::new();
+    let record = Pubkey::new_unique();
+    let (creator, item_mint, item_account) = (Keypair::new(), Keypair::new(), Keypair::new());
+    let (creator_key, item_key) = (creator.pubkey(), item_mint.pubkey());
+    let item_metadata = find_metadata_account(&item_key).0;
+
+    let item_transaction = confirmed_transaction(
+        &[
+            system_instruction::create_account(
+                &creator_key, &item_key, 1_000_000, 82, &spl_token::id()),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(), &item_key, &creator_key, None, 0).unwrap(),
+            system_instruction::create_account(
+                &creator_key, &item_account.pubkey(), 1_000_000, 165, &spl_token::id()),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &item_account.pubkey(), &item_key, &creator_key).unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::id(), &item_key, &item_account.pubkey(), &creator_key, &[], 1,
+            ).unwrap(),
+            create_metadata_accounts_v2(
+                mpl_token_metadata::id(), item_metadata, item_key, creator_key, creator_key,
+                creator_key, "Bonbon #1".to_string(), "BON".to_string(),
+                "https://example.com/1.json".to_string(), None, 500, true, true,
+                Some(Collection { verified: false, key: collection.mint.pubkey() }), None,
+            ),
+        ],
+        &[],
+        &[&creator, &item_mint, &item_account],
+        &[(item_account.pubkey(), item_key, creator_key, None, Some(1))],
+    );
+
+    // `VerifySizedCollectionItem` (30): metadata, collection authority, payer, collection mint,
+    // collection metadata and master edition, and the authority's record
+    let collection_edition = find_master_edition_account(&collection.mint.pubkey()).0;
+    let verify = Instruction {
+        program_id: mpl_token_metadata::id(),
+        accounts: vec![
+            AccountMeta::new(item_metadata, false),
+            AccountMeta::new_readonly(delegate.pubkey(), true),
+            AccountMeta::new(delegate.pubkey(), true),
+            AccountMeta::new_readonly(collection.mint.pubkey(), false),
+            AccountMeta::new(collection.metadata(), false),
+            AccountMeta::new_readonly(collection_edition, false),
+            AccountMeta::new_readonly(record, false),
+        ],
+        data: vec![30],
+    };
+
+    let bonbons = assemble(
+        vec![collection.mint_transaction(Some(0)), item_transaction],
+        &[
+            (collection.approve(&delegate.pubkey(), &record), &collection.authority),
+            (verify, &delegate),
+        ],
+    );
+    assert_eq!(bonbons.len(), 2);
+    let bonbon = |key: &Pubkey| &bonbons.iter().find(|(k, _)| k == key).expect("assembled").1;
+
+    let collection_bonbon = bonbon(&collection.mint.pubkey());
+    assert!(collection_bonbon.is_collection());
+    assert_eq!(authorities(collection_bonbon), vec![(delegate.pubkey(), record, true, SLOT + 1)]);
+
+    let item = bonbon(&item_key);
+    assert!(!item.is_collection());
+    assert_eq!(
+        item.collection_memberships().iter()
+            .map(|m| (m.collection, m.verified, m.instruction_index.slot))
+            .collect::<Vec<_>>(),
+        vec![(collection.mint.pubkey(), true, SLOT + 2)],
+    );
+}
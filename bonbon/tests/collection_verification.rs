@@ -0,0 +1,283 @@
+mod common;
+
+use {
+    bonbon::{assemble::Bonbon, pipeline::assemble_transactions},
+    borsh::BorshSerialize,
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::{create_metadata_accounts_v2, MetadataInstruction},
+        pda::{find_master_edition_account, find_metadata_account},
+        state::Collection,
+    },
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction, system_program, sysvar,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+// Each instruction is laid out as the token metadata builders (or, for the instructions newer
+// than the version we depend on, the program's own account list) lay it out and runs in its own
+// signed transaction, one slot after the other, through partitioning and assembly
+
+const SLOT: i64 = 150_000_000;
+
+// `VerificationArgs::CollectionV1`
+const COLLECTION_V1: u8 = 1;
+
+// an NFT created into a collection it isn't verified in yet. one keypair is the update authority
+// of both and pays for everything
+struct Nft {
+    authority: Keypair,
+
+    mint: Keypair,
+
+    collection_mint: Pubkey,
+}
+
+impl Nft {
+    fn new() -> Self {
+        Self {
+            authority: Keypair::new(),
+            mint: Keypair::new(),
+            collection_mint: Pubkey::new_unique(),
+        }
+    }
+
+    fn metadata(&self) -> Pubkey {
+        find_metadata_account(&self.mint.pubkey()).0
+    }
+
+    fn collection_metadata(&self) -> Pubkey {
+        find_metadata_account(&self.collection_mint).0
+    }
+
+    fn collection_master_edition(&self) -> Pubkey {
+        find_master_edition_account(&self.collection_mint).0
+    }
+
+    fn mint_transaction(&self) -> TransactionWithStatusMeta {
+        let authority = self.authority.pubkey();
+        let mint = self.mint.pubkey();
+        let account = Keypair::new();
+        confirmed_transaction(
+            &[
+                system_instruction::create_account(
+                    &authority, &mint, 1_000_000, 82, &spl_token::id()),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint, &authority, None, 0,
+                ).unwrap(),
+                system_instruction::create_account(
+                    &authority, &account.pubkey(), 1_000_000, 165, &spl_token::id()),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &account.pubkey(), &mint, &authority,
+                ).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint, &account.pubkey(), &authority, &[], 1,
+                ).unwrap(),
+                create_metadata_accounts_v2(
+                    mpl_token_metadata::id(), self.metadata(), mint, authority, authority,
+                    authority, "Bonbon #1".to_string(), "BON".to_string(),
+                    "https://example.com/1.json".to_string(), None, 500, true, true,
+                    Some(Collection { verified: false, key: self.collection_mint }), None,
+                ),
+            ],
+            &[],
+            &[&self.authority, &self.mint, &account],
+            &[(account.pubkey(), mint, authority, None, Some(1))],
+        )
+    }
+
+    // metadata, collection authority, payer, collection mint, collection metadata and master
+    // edition
+    fn verify_collection(&self) -> Instruction {
+        let authority = self.authority.pubkey();
+        Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new(authority, true),
+                AccountMeta::new(authority, true),
+                AccountMeta::new_readonly(self.collection_mint, false),
+                AccountMeta::new_readonly(self.collection_metadata(), false),
+                AccountMeta::new_readonly(self.collection_master_edition(), false),
+            ],
+            data: MetadataInstruction::VerifyCollection.try_to_vec().unwrap(),
+        }
+    }
+
+    // as `verify_collection` without the payer
+    fn unverify_collection(&self) -> Instruction {
+        Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new(self.authority.pubkey(), true),
+                AccountMeta::new_readonly(self.collection_mint, false),
+                AccountMeta::new_readonly(self.collection_metadata(), false),
+                AccountMeta::new_readonly(self.collection_master_edition(), false),
+            ],
+            data: MetadataInstruction::UnverifyCollection.try_to_vec().unwrap(),
+        }
+    }
+
+    // `VerifySizedCollectionItem` (30) and `UnverifySizedCollectionItem` (31) take the same
+    // accounts as `verify_collection` but write to the collection metadata for its size
+    fn sized_collection_item(&self, discriminant: u8) -> Instruction {
+        let authority = self.authority.pubkey();
+        Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new_readonly(authority, true),
+                AccountMeta::new(authority, true),
+                AccountMeta::new_readonly(self.collection_mint, false),
+                AccountMeta::new(self.collection_metadata(), false),
+                AccountMeta::new_readonly(self.collection_master_edition(), false),
+            ],
+            data: vec![discriminant],
+        }
+    }
+
+    // `Verify` (52) and `Unverify` (53): authority, delegate record (the program when there
+    // isn't one), metadata, collection mint, collection metadata and master edition, the system
+    // program and the instructions sysvar
+    fn verification(&self, discriminant: u8) -> Instruction {
+        Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new_readonly(self.authority.pubkey(), true),
+                AccountMeta::new_readonly(mpl_token_metadata::id(), false),
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new_readonly(self.collection_mint, false),
+                AccountMeta::new(self.collection_metadata(), false),
+                AccountMeta::new_readonly(self.collection_master_edition(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            ],
+            data: vec![discriminant, COLLECTION_V1],
+        }
+    }
+
+    // the mint at `SLOT` and then each instruction in a transaction of its own at the slots
+    // after. a new fee payer each time keeps repeated instructions from having the same
+    // signature
+    fn assemble(&self, instructions: &[Instruction]) -> Bonbon {
+        let transactions = std::iter::once((SLOT, 0, self.mint_transaction()))
+            .chain(instructions.iter().enumerate().map(|(i, instruction)| {
+                let fee_payer = Keypair::new();
+                let transaction = confirmed_transaction(
+                    &[instruction.clone()], &[], &[&fee_payer, &self.authority], &[]);
+                (SLOT + 1 + i as i64, 0, transaction)
+            }))
+            .collect::<Vec<_>>();
+
+        let (bonbons, partition_errors) = assemble_transactions(transactions);
+        assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+        let mut bonbons = bonbons.collect::<Vec<_>>();
+        assert_eq!(bonbons.len(), 1);
+        let (mint_key, bonbon) = bonbons.remove(0);
+        assert_eq!(mint_key, self.mint.pubkey());
+        bonbon.expect("bonbon assembles")
+    }
+}
+
+fn current_collection(bonbon: &Bonbon) -> Option<(Pubkey, bool)> {
+    bonbon.glazings.last()
+        .and_then(|g| g.collection.as_ref())
+        .map(|c| (c.address, c.verified))
+}
+
+// (collection, verified, slot)
+fn memberships(bonbon: &Bonbon) -> Vec<(Pubkey, bool, i64)> {
+    bonbon.collection_memberships().into_iter()
+        .map(|m| (m.collection, m.verified, m.instruction_index.slot))
+        .collect()
+}
+
+#[test]
+fn created_unverified() {
+    let nft = Nft::new();
+    let bonbon = nft.assemble(&[]);
+    assert_eq!(bonbon.metadata_key, nft.metadata());
+    assert_eq!(current_collection(&bonbon), Some((nft.collection_mint, false)));
+    assert!(memberships(&bonbon).is_empty());
+}
+
+#[test]
+fn unverify_collection() {
+    let nft = Nft::new();
+    let collection = nft.collection_mint;
+
+    let verified = nft.assemble(&[nft.verify_collection()]);
+    assert_eq!(current_collection(&verified), Some((collection, true)));
+
+    let unverified = nft.assemble(&[nft.verify_collection(), nft.unverify_collection()]);
+    assert_eq!(current_collection(&unverified), Some((collection, false)));
+    assert_eq!(
+        memberships(&unverified),
+        vec![(collection, true, SLOT + 1), (collection, false, SLOT + 2)],
+    );
+}
+
+#[test]
+fn reverify_after_unverify_collection() {
+    let nft = Nft::new();
+    let collection = nft.collection_mint;
+
+    let bonbon = nft.assemble(&[
+        nft.verify_collection(),
+        nft.unverify_collection(),
+        nft.verify_collection(),
+    ]);
+    assert_eq!(current_collection(&bonbon), Some((collection, true)));
+    assert_eq!(
+        memberships(&bonbon),
+        vec![
+            (collection, true, SLOT + 1),
+            (collection, false, SLOT + 2),
+            (collection, true, SLOT + 3),
+        ],
+    );
+}
+
+#[test]
+fn unverify_sized_collection_item() {
+    let nft = Nft::new();
+    let collection = nft.collection_mint;
+
+    let bonbon = nft.assemble(&[nft.sized_collection_item(30), nft.sized_collection_item(31)]);
+    assert_eq!(current_collection(&bonbon), Some((collection, false)));
+    assert_eq!(
+        memberships(&bonbon),
+        vec![(collection, true, SLOT + 1), (collection, false, SLOT + 2)],
+    );
+}
+
+#[test]
+fn unverify_collection_v1() {
+    let nft = Nft::new();
+    let collection = nft.collection_mint;
+
+    let bonbon = nft.assemble(&[nft.verification(52), nft.verification(53)]);
+    assert_eq!(current_collection(&bonbon), Some((collection, false)));
+    assert_eq!(
+        memberships(&bonbon),
+        vec![(collection, true, SLOT + 1), (collection, false, SLOT + 2)],
+    );
+}
+
+// verified one way and unverified another, as when a collection moves to sized
+#[test]
+fn unverify_after_a_different_verify() {
+    let nft = Nft::new();
+    let collection = nft.collection_mint;
+
+    let bonbon = nft.assemble(&[nft.verify_collection(), nft.sized_collection_item(31)]);
+    assert_eq!(current_collection(&bonbon), Some((collection, false)));
+
+    let bonbon = nft.assemble(&[nft.sized_collection_item(30), nft.verification(53)]);
+    assert_eq!(current_collection(&bonbon), Some((collection, false)));
+}
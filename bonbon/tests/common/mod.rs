@@ -0,0 +1,80 @@
+use {
+    solana_account_decoder::parse_token::UiTokenAmount,
+    solana_sdk::{
+        hash::Hash,
+        instruction::{CompiledInstruction, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        transaction::{Transaction, VersionedTransaction},
+    },
+    solana_transaction_status::{
+        InnerInstructions, TransactionStatusMeta, TransactionTokenBalance,
+        TransactionWithStatusMeta, VersionedTransactionWithStatusMeta,
+    },
+};
+
+// a token account, its mint and owner and what it held before and after the transaction (None
+// if it didn't exist)
+pub type TokenBalance = (Pubkey, Pubkey, Pubkey, Option<u64>, Option<u64>);
+
+// The successful transaction as the runtime records it: signed by `signers` (the first pays)
+// with the token balances and inner instructions in its status meta. `inner` are the
+// instructions invoked by the outer instruction at that index
+pub fn confirmed_transaction(
+    instructions: &[Instruction],
+    inner: &[(u8, Vec<Instruction>)],
+    signers: &[&Keypair],
+    balances: &[TokenBalance],
+) -> TransactionWithStatusMeta {
+    let transaction = Transaction::new_signed_with_payer(
+        instructions, Some(&signers[0].pubkey()), signers, Hash::default());
+    let keys = transaction.message.account_keys.clone();
+    let index_of = |key: &Pubkey| keys.iter().position(|k| k == key)
+        .expect("key is in the transaction") as u8;
+
+    let compile = |instruction: &Instruction| CompiledInstruction {
+        program_id_index: index_of(&instruction.program_id),
+        accounts: instruction.accounts.iter().map(|a| index_of(&a.pubkey)).collect(),
+        data: instruction.data.clone(),
+    };
+    let token_balance = |account: &Pubkey, mint: &Pubkey, owner: &Pubkey, amount: u64| {
+        TransactionTokenBalance {
+            account_index: index_of(account),
+            mint: mint.to_string(),
+            ui_token_amount: UiTokenAmount {
+                ui_amount: Some(amount as f64),
+                decimals: 0,
+                amount: amount.to_string(),
+                ui_amount_string: amount.to_string(),
+            },
+            owner: owner.to_string(),
+        }
+    };
+
+    let meta = TransactionStatusMeta {
+        pre_balances: vec![0; keys.len()],
+        post_balances: vec![0; keys.len()],
+        inner_instructions: Some(inner.iter()
+            .map(|(index, instructions)| InnerInstructions {
+                index: *index,
+                instructions: instructions.iter().map(compile).collect(),
+            })
+            .collect()),
+        pre_token_balances: Some(balances.iter()
+            .filter_map(|(account, mint, owner, pre, _)| {
+                pre.map(|amount| token_balance(account, mint, owner, amount))
+            })
+            .collect()),
+        post_token_balances: Some(balances.iter()
+            .filter_map(|(account, mint, owner, _, post)| {
+                post.map(|amount| token_balance(account, mint, owner, amount))
+            })
+            .collect()),
+        ..TransactionStatusMeta::default()
+    };
+
+    TransactionWithStatusMeta::Complete(VersionedTransactionWithStatusMeta {
+        transaction: VersionedTransaction::from(transaction),
+        meta,
+    })
+}
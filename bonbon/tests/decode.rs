@@ -0,0 +1,220 @@
+mod common;
+
+use {
+    bonbon::{
+        bubblegum::find_asset_id,
+        layouts::default_layouts,
+        partition::{partition_transaction, Error, ErrorCode, LogCheck},
+        pipeline::default_partitioners,
+        programs::{
+            associated_token_account, auction_house, bubblegum, candy_guard, candy_machine_v2,
+            fixed_price_sale, metaplex,
+        },
+    },
+    borsh::BorshSerialize,
+    common::{confirmed_transaction, TokenBalance},
+    mpl_token_metadata::{
+        instruction::update_metadata_accounts,
+        pda::{find_master_edition_account, find_metadata_account},
+    },
+    solana_sdk::{
+        hash::hash,
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_program, sysvar,
+    },
+};
+
+const SLOT: i64 = 180_000_000;
+
+// what each program reads its instruction data as. anchor programs start it with the first 8
+// bytes of sha256("global:<instruction>")
+fn anchor_data(instruction: &str, args: &impl BorshSerialize) -> Vec<u8> {
+    let mut data = hash(format!("global:{}", instruction).as_bytes()).to_bytes()[..8].to_vec();
+    data.extend(args.try_to_vec().unwrap());
+    data
+}
+
+fn instruction(program_id: Pubkey, accounts: &[Pubkey], data: Vec<u8>) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: accounts.iter().map(|key| AccountMeta::new(*key, false)).collect(),
+        data,
+    }
+}
+
+// the partition keys of the instruction on its own in a transaction, paid for by a new keypair
+fn partition(
+    instruction: &Instruction,
+    signers: &[&Keypair],
+    balances: &[TokenBalance],
+) -> Result<Vec<Pubkey>, Error> {
+    let payer = Keypair::new();
+    let signers = std::iter::once(&payer).chain(signers.iter().copied()).collect::<Vec<_>>();
+    let transaction = confirmed_transaction(&[instruction.clone()], &[], &signers, balances);
+    let partitioned = partition_transaction(
+        transaction, SLOT, &default_partitioners(), &default_layouts(), LogCheck::Off)?;
+    Ok(partitioned.instructions.iter().map(|i| i.partition_key).collect())
+}
+
+// the same instruction without the accounts from `keep` on is malformed, not a panic
+fn assert_too_few_accounts(instruction: &Instruction, keep: usize) {
+    let mut truncated = instruction.clone();
+    truncated.accounts.truncate(keep);
+    let err = partition(&truncated, &[], &[]).expect_err("too few accounts");
+    assert_eq!(err.code, ErrorCode::FailedInstructionDeserialization);
+    assert_eq!(err.program_key, Some(instruction.program_id));
+    assert_eq!(err.outer_index, Some(0));
+}
+
+fn keys(n: usize) -> Vec<Pubkey> {
+    (0..n).map(|_| Pubkey::new_unique()).collect()
+}
+
+#[test]
+fn token_metadata() {
+    let mint = Pubkey::new_unique();
+    let metadata = find_metadata_account(&mint).0;
+    let update_authority = Keypair::new();
+
+    let update = update_metadata_accounts(
+        mpl_token_metadata::id(), metadata, update_authority.pubkey(), None, None, Some(true));
+    assert_eq!(partition(&update, &[&update_authority], &[]).unwrap(), vec![metadata]);
+    assert_too_few_accounts(&update, 0);
+
+    // `Verify` with `VerificationArgs::CollectionV1`: authority, delegate record, metadata,
+    // collection mint, collection metadata, collection master edition, system program and the
+    // instructions sysvar
+    let collection_mint = Pubkey::new_unique();
+    let verify = instruction(
+        mpl_token_metadata::id(),
+        &[
+            update_authority.pubkey(),
+            mpl_token_metadata::id(),
+            metadata,
+            collection_mint,
+            find_metadata_account(&collection_mint).0,
+            find_master_edition_account(&collection_mint).0,
+            system_program::id(),
+            sysvar::instructions::id(),
+        ],
+        vec![52, 1],
+    );
+    assert_eq!(partition(&verify, &[], &[]).unwrap(), vec![metadata]);
+    assert_too_few_accounts(&verify, 2);
+}
+
+// instructions that are only followed for token accounts don't fail the transaction when they're
+// malformed, they're skipped with a diagnostic
+#[test]
+fn malformed_token_account_creation() {
+    let mint = Pubkey::new_unique();
+    let metadata = find_metadata_account(&mint).0;
+    let update_authority = Keypair::new();
+
+    // payer and the account, without the owner and mint
+    let create = instruction(associated_token_account::id(), &keys(2), vec![]);
+    let update = update_metadata_accounts(
+        mpl_token_metadata::id(), metadata, update_authority.pubkey(), None, None, Some(true));
+    let transaction = confirmed_transaction(&[create, update], &[], &[&update_authority], &[]);
+
+    let partitioned = partition_transaction(
+        transaction, SLOT, &default_partitioners(), &default_layouts(), LogCheck::Off,
+    ).expect("partitions the rest of the transaction");
+    assert_eq!(
+        partitioned.instructions.iter().map(|i| i.partition_key).collect::<Vec<_>>(),
+        vec![metadata],
+    );
+    assert!(partitioned.token_accounts.is_empty());
+    let codes = partitioned.diagnostics.iter()
+        .map(|d| (d.code, d.program_key, d.outer_index))
+        .collect::<Vec<_>>();
+    assert!(!codes.is_empty());
+    assert!(codes.iter().all(|code| *code == (
+        ErrorCode::BadAccountKeyIndex, Some(associated_token_account::id()), Some(0),
+    )), "{:?}", codes);
+}
+
+#[test]
+fn bubblegum_transfer() {
+    // tree authority, leaf owner, leaf delegate, new leaf owner, merkle tree, log wrapper,
+    // compression program, system program and then the proof
+    let accounts = keys(8);
+    let merkle_tree = accounts[4];
+    let nonce = 42u64;
+    let transfer = instruction(
+        bubblegum::id(),
+        &accounts,
+        anchor_data("transfer", &([1u8; 32], [2u8; 32], [3u8; 32], nonce, 42u32)),
+    );
+    assert_eq!(partition(&transfer, &[], &[]).unwrap(), vec![find_asset_id(&merkle_tree, nonce)]);
+    assert_too_few_accounts(&transfer, 4);
+}
+
+#[test]
+fn candy_machine_v2_mint_nft() {
+    // candy machine, creator PDA, payer, wallet, metadata, mint, ...
+    let accounts = keys(16);
+    // the creator PDA's bump
+    let mint_nft = instruction(
+        candy_machine_v2::id(), &accounts, anchor_data("mint_nft", &254u8));
+    assert_eq!(partition(&mint_nft, &[], &[]).unwrap(), vec![accounts[5]]);
+    assert_too_few_accounts(&mint_nft, 5);
+}
+
+#[test]
+fn candy_guard_mint_v2() {
+    // candy guard, candy machine program, candy machine, authority PDA, payer, minter, mint, ...
+    let accounts = keys(22);
+    let mint_args: (Vec<u8>, Option<String>) = (vec![], Some("public".to_string()));
+    let mint_v2 = instruction(candy_guard::id(), &accounts, anchor_data("mint_v2", &mint_args));
+    assert_eq!(partition(&mint_v2, &[], &[]).unwrap(), vec![accounts[6]]);
+    assert_too_few_accounts(&mint_v2, 6);
+}
+
+#[test]
+fn auction_house_sell_and_execute_sale() {
+    // wallet, token account, metadata, authority, auction house, fee account, trade states, ...
+    let sell_accounts = keys(12);
+    // trade state bumps, buyer price and token size
+    let sell = instruction(
+        auction_house::id(),
+        &sell_accounts,
+        anchor_data("sell", &(255u8, 254u8, 253u8, 2_000_000_000u64, 1u64)),
+    );
+    assert_eq!(partition(&sell, &[], &[]).unwrap(), vec![sell_accounts[2]]);
+    assert_too_few_accounts(&sell, 2);
+
+    // buyer, seller, token account, token mint, ...
+    let sale_accounts = keys(22);
+    let execute_sale = instruction(
+        auction_house::id(),
+        &sale_accounts,
+        anchor_data("execute_sale", &(255u8, 254u8, 253u8, 2_000_000_000u64, 1u64)),
+    );
+    assert_eq!(partition(&execute_sale, &[], &[]).unwrap(), vec![sale_accounts[3]]);
+    assert_too_few_accounts(&execute_sale, 3);
+}
+
+#[test]
+fn storefront_redemption_and_fixed_price_sale() {
+    // auction manager, safety deposit store, the winner's token account, ...
+    let accounts = keys(16);
+    let mint = Pubkey::new_unique();
+    let winner = Pubkey::new_unique();
+    let balances = [(accounts[2], mint, winner, Some(0), Some(1))];
+    // `RedeemBid` has no arguments
+    let redeem_bid = instruction(metaplex::id(), &accounts, vec![2]);
+    assert_eq!(partition(&redeem_bid, &[], &balances).unwrap(), vec![mint]);
+    assert_too_few_accounts(&redeem_bid, 2);
+
+    // market, selling resource, user token account, user wallet, trade history, treasury
+    // holder, new metadata, new edition, master edition, new mint, ...
+    let accounts = keys(20);
+    // trade history and vault owner bumps
+    let buy = instruction(
+        fixed_price_sale::id(), &accounts, anchor_data("buy", &(255u8, 254u8)));
+    assert_eq!(partition(&buy, &[], &[]).unwrap(), vec![accounts[9]]);
+    assert_too_few_accounts(&buy, 9);
+}
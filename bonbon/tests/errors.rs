@@ -0,0 +1,158 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble, partition, pipeline::assemble_transactions, programs::associated_token_account,
+    },
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::{create_metadata_accounts_v2, update_metadata_accounts},
+        pda::{find_master_edition_account, find_metadata_account},
+    },
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signature, Signer},
+        system_instruction, system_program, sysvar,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+const SLOT: i64 = 170_000_000;
+
+fn signature_of(transaction: &TransactionWithStatusMeta) -> Signature {
+    *transaction.transaction_signature()
+}
+
+#[test]
+fn assembly_error_names_the_instruction_and_keys() {
+    let creator = Keypair::new();
+    let mint = Keypair::new();
+    let account = Keypair::new();
+    let (mint_key, creator_key) = (mint.pubkey(), creator.pubkey());
+    let metadata_key = find_metadata_account(&mint_key).0;
+
+    let mint_transaction = confirmed_transaction(
+        &[
+            system_instruction::create_account(
+                &creator_key, &mint_key, 1_000_000, 82, &spl_token::id()),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(), &mint_key, &creator_key, None, 0).unwrap(),
+            system_instruction::create_account(
+                &creator_key, &account.pubkey(), 1_000_000, 165, &spl_token::id()),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &account.pubkey(), &mint_key, &creator_key).unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::id(), &mint_key, &account.pubkey(), &creator_key, &[], 1).unwrap(),
+            create_metadata_accounts_v2(
+                mpl_token_metadata::id(), metadata_key, mint_key, creator_key, creator_key,
+                creator_key, "Bonbon #1".to_string(), "BON".to_string(),
+                "https://example.com/1.json".to_string(), None, 500, true, true, None, None,
+            ),
+        ],
+        &[],
+        &[&creator, &mint, &account],
+        &[(account.pubkey(), mint_key, creator_key, None, Some(1))],
+    );
+
+    // a token metadata `Transfer` of the mint that passes some other metadata account: token,
+    // token owner, destination, destination owner, mint, metadata, edition, token record,
+    // destination token record, authority, payer, system program, instructions sysvar, token
+    // program, associated token program, rule set program, rule set
+    let (destination, destination_owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let wrong_metadata = Pubkey::new_unique();
+    let none = mpl_token_metadata::id();
+    let mut data = vec![49, 0];
+    data.extend(1u64.to_le_bytes());
+    let transfer = Instruction {
+        program_id: mpl_token_metadata::id(),
+        accounts: vec![
+            AccountMeta::new(account.pubkey(), false),
+            AccountMeta::new_readonly(creator_key, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(destination_owner, false),
+            AccountMeta::new_readonly(mint_key, false),
+            AccountMeta::new(wrong_metadata, false),
+            AccountMeta::new_readonly(find_master_edition_account(&mint_key).0, false),
+            AccountMeta::new_readonly(none, false),
+            AccountMeta::new_readonly(none, false),
+            AccountMeta::new_readonly(creator_key, true),
+            AccountMeta::new(creator_key, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(associated_token_account::id(), false),
+            AccountMeta::new_readonly(none, false),
+            AccountMeta::new_readonly(none, false),
+        ],
+        data,
+    };
+    let transfer_transaction = confirmed_transaction(&[transfer], &[], &[&creator], &[]);
+    let signature = signature_of(&transfer_transaction);
+
+    let (bonbons, partition_errors) = assemble_transactions(vec![
+        (SLOT, 0, mint_transaction),
+        (SLOT + 1, 3, transfer_transaction),
+    ]);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+    let mut bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    let err = bonbons.remove(0).1.expect_err("the transfer doesn't fit the bonbon");
+
+    assert_eq!(err.code, assemble::ErrorCode::InvalidMetadataUpdate);
+    assert_eq!(err.signature.as_deref(), Some(signature.as_ref()));
+    assert_eq!(err.program_key, Some(mpl_token_metadata::id()));
+    assert_eq!(err.instruction_name.as_deref(), Some("Transfer"));
+    assert_eq!(
+        err.instruction_index.as_ref()
+            .map(|i| (i.slot, i.block_index, i.outer_index, i.inner_index)),
+        Some((SLOT + 1, 3, 0, None)),
+    );
+    assert_eq!((err.expected_key, err.actual_key), (Some(metadata_key), Some(wrong_metadata)));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "InvalidMetadataUpdate in Transfer of {} at {}.0003 instruction 0 [{}], \
+                expected {}, got {}",
+            mpl_token_metadata::id(), SLOT + 1, signature, metadata_key, wrong_metadata,
+        ),
+    );
+}
+
+#[test]
+fn partition_error_names_the_transaction_and_instruction() {
+    let payer = Keypair::new();
+    let update_authority = Keypair::new();
+    let metadata = find_metadata_account(&Pubkey::new_unique()).0;
+
+    // an update without any of its accounts, after a plain lamport transfer
+    let mut update = update_metadata_accounts(
+        mpl_token_metadata::id(), metadata, update_authority.pubkey(), None, None, Some(true));
+    update.accounts.clear();
+    let transaction = confirmed_transaction(
+        &[system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1), update],
+        &[],
+        &[&payer],
+        &[],
+    );
+    let signature = signature_of(&transaction);
+
+    let (bonbons, partition_errors) = assemble_transactions(vec![(SLOT, 7, transaction)]);
+    assert_eq!(bonbons.count(), 0);
+    assert_eq!(partition_errors.len(), 1);
+    let (slot, block_index, err) = &partition_errors[0];
+    assert_eq!((*slot, *block_index), (SLOT, 7));
+    assert_eq!(err.code, partition::ErrorCode::FailedInstructionDeserialization);
+    assert_eq!(err.signature.as_deref(), Some(signature.as_ref()));
+    assert_eq!(err.program_key, Some(mpl_token_metadata::id()));
+    assert_eq!((err.outer_index, err.inner_index), (Some(1), None));
+    assert_eq!(err.instruction_name.as_deref(), Some("UpdateMetadataAccount"));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "FailedInstructionDeserialization in UpdateMetadataAccount of {} at instruction 1 \
+                [{}]",
+            mpl_token_metadata::id(), signature,
+        ),
+    );
+}
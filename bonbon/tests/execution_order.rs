@@ -0,0 +1,289 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble::CandyMachineVersion,
+        layouts::default_layouts,
+        partition::{partition_transaction, LogCheck},
+        pipeline::{assemble_transactions, default_partitioners},
+        programs::candy_machine_v2,
+    },
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::{
+            create_master_edition_v3, create_metadata_accounts_v2, update_metadata_accounts_v2,
+        },
+        pda::{find_master_edition_account, find_metadata_account},
+    },
+    solana_sdk::{
+        hash::hash,
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction, system_program, sysvar,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+    spl_token::instruction::AuthorityType,
+};
+
+const SLOT: i64 = 150_000_000;
+
+// `Program <id> invoke [<height>]` and the matching `success` for each invocation, in the order
+// the runtime made them
+fn logs(invocations: &[(Pubkey, usize)]) -> Vec<String> {
+    let mut logs = vec![];
+    let mut stack: Vec<Pubkey> = vec![];
+    for (program, stack_height) in invocations {
+        while stack.len() >= *stack_height {
+            logs.push(format!("Program {} success", stack.pop().unwrap()));
+        }
+        logs.push(format!("Program {} invoke [{}]", program, stack_height));
+        stack.push(*program);
+    }
+    while let Some(program) = stack.pop() {
+        logs.push(format!("Program {} success", program));
+    }
+    logs
+}
+
+fn with_logs(
+    transaction: TransactionWithStatusMeta,
+    logs: Vec<String>,
+) -> TransactionWithStatusMeta {
+    match transaction {
+        TransactionWithStatusMeta::Complete(mut transaction) => {
+            transaction.meta.log_messages = Some(logs);
+            TransactionWithStatusMeta::Complete(transaction)
+        }
+        transaction => transaction,
+    }
+}
+
+// A candy machine v2 mint. The mint is set up by the outer instructions and then `mint_nft`
+// pays the wallet, creates the metadata and master edition (which takes over the mint's
+// authorities) and hands the update authority over to the candy machine's authority
+struct CandyMachineMint {
+    payer: Keypair,
+
+    mint: Keypair,
+
+    account: Keypair,
+
+    candy_machine: Pubkey,
+
+    creator: Pubkey,
+
+    wallet: Pubkey,
+
+    authority: Pubkey,
+}
+
+impl CandyMachineMint {
+    fn new() -> Self {
+        Self {
+            payer: Keypair::new(),
+            mint: Keypair::new(),
+            account: Keypair::new(),
+            candy_machine: Pubkey::new_unique(),
+            creator: Pubkey::new_unique(),
+            wallet: Pubkey::new_unique(),
+            authority: Pubkey::new_unique(),
+        }
+    }
+
+    fn transaction(&self) -> TransactionWithStatusMeta {
+        let (payer, mint_key) = (self.payer.pubkey(), self.mint.pubkey());
+        let metadata = find_metadata_account(&mint_key).0;
+        let edition = find_master_edition_account(&mint_key).0;
+
+        // sha256("global:mint_nft")[..8] and the creator bump
+        let mut data = hash(b"global:mint_nft").to_bytes()[..8].to_vec();
+        data.push(255);
+        let mint_nft = Instruction {
+            program_id: candy_machine_v2::id(),
+            accounts: vec![
+                AccountMeta::new(self.candy_machine, false),
+                AccountMeta::new_readonly(self.creator, false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new(self.wallet, false),
+                AccountMeta::new(metadata, false),
+                AccountMeta::new(mint_key, false),
+                AccountMeta::new_readonly(payer, true),
+                AccountMeta::new_readonly(self.authority, false),
+                AccountMeta::new(edition, false),
+                AccountMeta::new_readonly(mpl_token_metadata::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(sysvar::clock::id(), false),
+                AccountMeta::new_readonly(sysvar::slot_hashes::id(), false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            ],
+            data,
+        };
+
+        let cpis = vec![
+            system_instruction::transfer(&payer, &self.wallet, 1_000_000),
+            create_metadata_accounts_v2(
+                mpl_token_metadata::id(), metadata, mint_key, payer, payer, self.creator,
+                "Bonbon #9".to_string(), "BON".to_string(),
+                "https://example.com/9.json".to_string(), None, 500, true, true, None, None,
+            ),
+            create_master_edition_v3(
+                mpl_token_metadata::id(), edition, mint_key, self.creator, payer, metadata, payer,
+                Some(0),
+            ),
+            // the master edition taking over the mint
+            spl_token::instruction::set_authority(
+                &spl_token::id(), &mint_key, Some(&edition), AuthorityType::MintTokens, &payer,
+                &[],
+            ).unwrap(),
+            spl_token::instruction::set_authority(
+                &spl_token::id(), &mint_key, Some(&edition), AuthorityType::FreezeAccount,
+                &payer, &[],
+            ).unwrap(),
+            update_metadata_accounts_v2(
+                mpl_token_metadata::id(), metadata, self.creator, Some(self.authority), None,
+                Some(true), None,
+            ),
+        ];
+
+        confirmed_transaction(
+            &[
+                system_instruction::create_account(
+                    &payer, &mint_key, 1_000_000, 82, &spl_token::id()),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint_key, &payer, Some(&payer), 0).unwrap(),
+                system_instruction::create_account(
+                    &payer, &self.account.pubkey(), 1_000_000, 165, &spl_token::id()),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &self.account.pubkey(), &mint_key, &payer).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint_key, &self.account.pubkey(), &payer, &[], 1,
+                ).unwrap(),
+                mint_nft,
+            ],
+            &[(5, cpis)],
+            &[&self.payer, &self.mint, &self.account],
+            &[(self.account.pubkey(), mint_key, payer, None, Some(1))],
+        )
+    }
+
+    // the set up, then the candy machine with its CPIs, the edition's two nested ones deeper
+    fn logs(&self) -> Vec<String> {
+        let (system, token, metadata) =
+            (system_program::id(), spl_token::id(), mpl_token_metadata::id());
+        logs(&[
+            (system, 1),
+            (token, 1),
+            (system, 1),
+            (token, 1),
+            (token, 1),
+            (candy_machine_v2::id(), 1),
+            (system, 2),
+            (metadata, 2),
+            (metadata, 2),
+            (token, 3),
+            (token, 3),
+            (metadata, 2),
+        ])
+    }
+}
+
+// (program, inner index, outer program, stack height) of everything partitioned from `mint_nft`
+fn from_mint_nft(
+    transaction: TransactionWithStatusMeta,
+) -> Vec<(Pubkey, Option<i64>, Pubkey, Option<i64>)> {
+    let partitioned = partition_transaction(
+        transaction, SLOT, &default_partitioners(), &default_layouts(), LogCheck::Off,
+    ).expect("partitions");
+    partitioned.instructions.iter()
+        .filter(|i| i.outer_index == 5)
+        .map(|i| (i.program_key, i.inner_index, i.outer_program_key, i.stack_height))
+        .collect()
+}
+
+#[test]
+fn cpis_follow_the_instruction_that_made_them() {
+    let mint = CandyMachineMint::new();
+    let (candy_machine, token, metadata) =
+        (candy_machine_v2::id(), spl_token::id(), mpl_token_metadata::id());
+
+    // the system transfer at 0 isn't partitioned
+    assert_eq!(
+        from_mint_nft(with_logs(mint.transaction(), mint.logs())),
+        vec![
+            (candy_machine, None, candy_machine, Some(1)),
+            (metadata, Some(1), candy_machine, Some(2)),
+            (metadata, Some(2), candy_machine, Some(2)),
+            (token, Some(3), candy_machine, Some(3)),
+            (token, Some(4), candy_machine, Some(3)),
+            (metadata, Some(5), candy_machine, Some(2)),
+        ],
+    );
+
+    // without logs only the outer instruction's height is known
+    assert_eq!(
+        from_mint_nft(mint.transaction()),
+        vec![
+            (candy_machine, None, candy_machine, Some(1)),
+            (metadata, Some(1), candy_machine, None),
+            (metadata, Some(2), candy_machine, None),
+            (token, Some(3), candy_machine, None),
+            (token, Some(4), candy_machine, None),
+            (metadata, Some(5), candy_machine, None),
+        ],
+    );
+
+    // logs that don't line up with the inner instructions are ignored
+    let mut logs = mint.logs();
+    let edition_cpi = format!("Program {} invoke [3]", token);
+    for log in logs.iter_mut().filter(|l| **l == edition_cpi) {
+        *log = format!("Program {} invoke [3]", system_program::id());
+    }
+    let heights = from_mint_nft(with_logs(mint.transaction(), logs)).into_iter()
+        .map(|(program, _, _, stack_height)| (program, stack_height))
+        .collect::<Vec<_>>();
+    assert_eq!(heights[3..5], [(token, None), (token, None)]);
+}
+
+#[test]
+fn candy_machine_mint_comes_before_its_cpis() {
+    let mint = CandyMachineMint::new();
+    let (bonbons, partition_errors) = assemble_transactions(vec![
+        (SLOT, 0, with_logs(mint.transaction(), mint.logs())),
+    ]);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+    let mut bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    let bonbon = bonbons.remove(0).1.expect("bonbon assembles");
+
+    let candy_machine_mint = bonbon.candy_machine_mint.as_ref().expect("minted by the machine");
+    assert_eq!(candy_machine_mint.version, CandyMachineVersion::V2);
+    assert_eq!(candy_machine_mint.candy_machine, mint.candy_machine);
+    let index = &candy_machine_mint.instruction_index;
+    assert_eq!((index.outer_index, index.inner_index), (5, None));
+
+    // the metadata it created and then updated, both after the mint itself
+    assert_eq!(
+        bonbon.glazings.iter()
+            .map(|g| (g.instruction_index.inner_index, g.primary_sale_happened))
+            .collect::<Vec<_>>(),
+        vec![(Some(1), false), (Some(5), true)],
+    );
+    assert!(bonbon.glazings.iter().all(|g| *index < g.instruction_index));
+    assert_eq!(
+        bonbon.update_authorities.iter().map(|a| a.authority).collect::<Vec<_>>(),
+        vec![Some(mint.creator), Some(mint.authority)],
+    );
+
+    // initialized by the payer and then taken over by the edition, between the two updates
+    let edition = find_master_edition_account(&mint.mint.pubkey()).0;
+    for authorities in [&bonbon.mint_authorities, &bonbon.freeze_authorities] {
+        assert_eq!(
+            authorities.iter().map(|a| a.authority).collect::<Vec<_>>(),
+            vec![Some(mint.payer.pubkey()), Some(edition)],
+        );
+    }
+    assert!(bonbon.mint_authorities[1].instruction_index < bonbon.glazings[1].instruction_index);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble::Bonbon,
+        pipeline::{default_updaters, semi_fungible_partitioners, Pipeline},
+    },
+    common::confirmed_transaction,
+    mpl_token_metadata::{instruction::create_metadata_accounts_v2, pda::find_metadata_account},
+    solana_sdk::{
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+const SLOT: i64 = 160_000_000;
+
+fn assemble(transactions: Vec<TransactionWithStatusMeta>) -> Bonbon {
+    let mut pipeline = Pipeline::new(semi_fungible_partitioners(), default_updaters());
+    for (slot, transaction) in (SLOT..).zip(transactions) {
+        pipeline.push(slot, 0, transaction).expect("partitions");
+    }
+    let mut bonbons = pipeline.finish().collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    bonbons.remove(0).1.expect("bonbon assembles")
+}
+
+// (account, owner, amount, slot of the last change) by account
+fn holders(bonbon: &Bonbon) -> Vec<(Pubkey, Option<Pubkey>, u64, i64)> {
+    let mut holders = bonbon.holdings.iter()
+        .map(|h| (h.account, h.owner, h.amount, h.instruction_index.slot))
+        .collect::<Vec<_>>();
+    holders.sort();
+    holders
+}
+
+// ten of a semi-fungible token handed out to two holders, who then trade between themselves
+#[test]
+fn balances_per_holder() {
+    let creator = Keypair::new();
+    let mint = Keypair::new();
+    let account = Keypair::new();
+    let (mint_key, creator_key) = (mint.pubkey(), creator.pubkey());
+    let (alice, bob) = (Keypair::new(), Keypair::new());
+    let (alice_account, bob_account) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    let mint_transaction = || confirmed_transaction(
+        &[
+            system_instruction::create_account(
+                &creator_key, &mint_key, 1_000_000, 82, &spl_token::id()),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(), &mint_key, &creator_key, None, 0).unwrap(),
+            system_instruction::create_account(
+                &creator_key, &account.pubkey(), 1_000_000, 165, &spl_token::id()),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &account.pubkey(), &mint_key, &creator_key).unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::id(), &mint_key, &account.pubkey(), &creator_key, &[], 10).unwrap(),
+            create_metadata_accounts_v2(
+                mpl_token_metadata::id(), find_metadata_account(&mint_key).0, mint_key,
+                creator_key, creator_key, creator_key, "Bonbon Box".to_string(),
+                "BON".to_string(), "https://example.com/box.json".to_string(), None, 0, true,
+                true, None, None,
+            ),
+        ],
+        &[],
+        &[&creator, &mint, &account],
+        &[(account.pubkey(), mint_key, creator_key, None, Some(10))],
+    );
+
+    let airdrop_transaction = confirmed_transaction(
+        &[
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &alice_account, &mint_key, &alice.pubkey()).unwrap(),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &bob_account, &mint_key, &bob.pubkey()).unwrap(),
+            spl_token::instruction::transfer(
+                &spl_token::id(), &account.pubkey(), &alice_account, &creator_key, &[], 3,
+            ).unwrap(),
+            spl_token::instruction::transfer(
+                &spl_token::id(), &account.pubkey(), &bob_account, &creator_key, &[], 7,
+            ).unwrap(),
+        ],
+        &[],
+        &[&creator],
+        &[
+            (account.pubkey(), mint_key, creator_key, Some(10), Some(0)),
+            (alice_account, mint_key, alice.pubkey(), None, Some(3)),
+            (bob_account, mint_key, bob.pubkey(), None, Some(7)),
+        ],
+    );
+
+    let trade_transaction = confirmed_transaction(
+        &[spl_token::instruction::transfer(
+            &spl_token::id(), &alice_account, &bob_account, &alice.pubkey(), &[], 1,
+        ).unwrap()],
+        &[],
+        &[&alice],
+        &[
+            (alice_account, mint_key, alice.pubkey(), Some(3), Some(2)),
+            (bob_account, mint_key, bob.pubkey(), Some(7), Some(8)),
+        ],
+    );
+
+    let minted = assemble(vec![mint_transaction()]);
+    assert_eq!(holders(&minted), vec![(account.pubkey(), Some(creator_key), 10, SLOT)]);
+
+    let bonbon = assemble(vec![mint_transaction(), airdrop_transaction, trade_transaction]);
+    // the creator's emptied account is no longer a holder
+    let mut expected = vec![
+        (alice_account, Some(alice.pubkey()), 2, SLOT + 2),
+        (bob_account, Some(bob.pubkey()), 8, SLOT + 2),
+    ];
+    expected.sort();
+    assert_eq!(holders(&bonbon), expected);
+    assert_eq!(bonbon.supply, 10);
+    assert_eq!(bonbon.holdings.iter().map(|h| h.amount).sum::<u64>(), bonbon.supply);
+}
@@ -0,0 +1,282 @@
+mod common;
+
+use {
+    bonbon::{
+        layouts::{
+            default_layouts, AccountRole, Decoding, MASTER_METADATA_MOVED_SLOT,
+            RESERVATION_LIST_OFFSETS_SLOT,
+        },
+        metadata::TokenMetadataInstruction,
+        partition::ErrorCode,
+        pipeline::assemble_transactions,
+    },
+    borsh::BorshSerialize,
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::{MetadataInstruction, SetReservationListArgs},
+        pda::{find_master_edition_account, find_metadata_account},
+        state::Reservation,
+    },
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction, system_program, sysvar,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+// DeprecatedMintNewEditionFromMasterEditionViaPrintingToken for `mint` off the master edition of
+// `master_mint`, laid out as the program read it after the upgrade that `moved` the master
+// metadata or before it, when there was no update authority account so the master metadata was at
+// 10 and the token program at 11
+fn print_via_printing_token(
+    moved: bool,
+    mint: &Pubkey,
+    master_mint: &Pubkey,
+    authority: &Pubkey,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_metadata_account(mint).0, false),
+        AccountMeta::new(find_master_edition_account(mint).0, false),
+        AccountMeta::new(find_master_edition_account(master_mint).0, false),
+        AccountMeta::new(*mint, false),
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(Pubkey::new_unique(), false),
+        AccountMeta::new(Pubkey::new_unique(), false),
+        AccountMeta::new(Pubkey::new_unique(), false),
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*authority, true),
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new_readonly(find_metadata_account(master_mint).0, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    if !moved {
+        accounts.remove(10);
+    }
+    Instruction {
+        program_id: mpl_token_metadata::id(),
+        accounts,
+        data: vec![3],
+    }
+}
+
+// the new mint with one token for `authority`, then the print at `slot`
+fn print_transactions(
+    slot: i64,
+    moved: bool,
+) -> (Pubkey, Pubkey, Vec<(i64, i64, TransactionWithStatusMeta)>) {
+    let authority = Keypair::new();
+    let mint = Keypair::new();
+    let account = Keypair::new();
+    let master_mint = Pubkey::new_unique();
+
+    let mint_transaction = confirmed_transaction(
+        &[
+            system_instruction::create_account(
+                &authority.pubkey(), &mint.pubkey(), 1_000_000, 82, &spl_token::id()),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(), &mint.pubkey(), &authority.pubkey(), None, 0,
+            ).unwrap(),
+            system_instruction::create_account(
+                &authority.pubkey(), &account.pubkey(), 1_000_000, 165, &spl_token::id()),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &account.pubkey(), &mint.pubkey(), &authority.pubkey(),
+            ).unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::id(), &mint.pubkey(), &account.pubkey(), &authority.pubkey(), &[], 1,
+            ).unwrap(),
+        ],
+        &[],
+        &[&authority, &mint, &account],
+        &[(account.pubkey(), mint.pubkey(), authority.pubkey(), None, Some(1))],
+    );
+    let print_transaction = confirmed_transaction(
+        &[print_via_printing_token(moved, &mint.pubkey(), &master_mint, &authority.pubkey())],
+        &[],
+        &[&authority],
+        &[],
+    );
+
+    (
+        mint.pubkey(),
+        master_mint,
+        vec![(slot - 10, 0, mint_transaction), (slot, 0, print_transaction)],
+    )
+}
+
+// old arguments, without `offset` and `total_spot_offset`
+fn set_reservation_list_v1_data(reservations: &[Reservation], total_spots: u64) -> Vec<u8> {
+    let mut data = vec![5];
+    data.extend((reservations.to_vec(), Some(total_spots)).try_to_vec().unwrap());
+    data
+}
+
+fn set_reservation_list_data(reservations: &[Reservation], total_spots: u64) -> Vec<u8> {
+    MetadataInstruction::DeprecatedSetReservationList(SetReservationListArgs {
+        reservations: reservations.to_vec(),
+        total_reservation_spots: Some(total_spots),
+        offset: 0,
+        total_spot_offset: 0,
+    }).try_to_vec().unwrap()
+}
+
+fn set_reservation_list_transaction(data: Vec<u8>) -> TransactionWithStatusMeta {
+    let authority = Keypair::new();
+    confirmed_transaction(
+        &[Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(authority.pubkey(), true),
+            ],
+            data,
+        }],
+        &[],
+        &[&authority],
+        &[],
+    )
+}
+
+fn reservations() -> Vec<Reservation> {
+    vec![Reservation { address: Pubkey::new_unique(), spots_remaining: 2, total_spots: 2 }]
+}
+
+#[test]
+fn master_metadata_index_follows_the_slot() {
+    let layouts = default_layouts();
+    let master_metadata_at = |slot| layouts
+        .layout_for(&mpl_token_metadata::id(), &[3], slot)
+        .and_then(|l| l.account_index(AccountRole::MasterMetadata));
+
+    assert_eq!(master_metadata_at(0), Some(10));
+    assert_eq!(master_metadata_at(MASTER_METADATA_MOVED_SLOT - 1), Some(10));
+    assert_eq!(master_metadata_at(MASTER_METADATA_MOVED_SLOT), Some(11));
+    assert_eq!(master_metadata_at(MASTER_METADATA_MOVED_SLOT + 1_000_000), Some(11));
+}
+
+fn printed_master_key(slot: i64, moved: bool) -> Option<(Pubkey, Pubkey)> {
+    let (mint_key, master_mint, transactions) = print_transactions(slot, moved);
+    let (bonbons, partition_errors) = assemble_transactions(transactions);
+    assert!(partition_errors.is_empty(), "{}: {:?}", slot, partition_errors);
+
+    let bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    assert_eq!(bonbons[0].0, mint_key);
+    let bonbon = bonbons[0].1.as_ref().expect("bonbon assembles");
+    bonbon.limited_edition.as_ref()
+        .map(|e| (e.master_key, find_metadata_account(&master_mint).0))
+}
+
+#[test]
+fn print_via_printing_token_links_the_master_on_both_sides() {
+    for (slot, moved) in [
+        (MASTER_METADATA_MOVED_SLOT - 1, false),
+        (MASTER_METADATA_MOVED_SLOT, true),
+    ] {
+        let (master_key, expected) = printed_master_key(slot, moved).expect("printed");
+        assert_eq!(master_key, expected, "slot {}", slot);
+    }
+}
+
+// the upgrade slot can be off, so an instruction in the other layout than the slot's is read with
+// the layout its accounts fit rather than taking whichever account sits at the slot's index
+#[test]
+fn print_via_printing_token_across_an_off_pivot() {
+    for (slot, moved) in [
+        (MASTER_METADATA_MOVED_SLOT + 1_000, false),
+        (MASTER_METADATA_MOVED_SLOT - 1_000, true),
+    ] {
+        let (master_key, expected) = printed_master_key(slot, moved).expect("printed");
+        assert_eq!(master_key, expected, "slot {}", slot);
+    }
+}
+
+#[test]
+fn layouts_to_try_leave_out_the_ones_the_accounts_dont_fit() {
+    let layouts = default_layouts();
+    let mut accounts = (0..15).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+    accounts[11] = spl_token::id();
+    let master_metadata_indexes = |accounts: &[Pubkey], slot| layouts
+        .layouts_to_try(&mpl_token_metadata::id(), &[3], accounts, slot)
+        .into_iter()
+        .map(|l| l.account_index(AccountRole::MasterMetadata))
+        .collect::<Vec<_>>();
+
+    assert_eq!(master_metadata_indexes(&accounts, MASTER_METADATA_MOVED_SLOT), vec![Some(10)]);
+
+    accounts.insert(10, Pubkey::new_unique());
+    assert_eq!(master_metadata_indexes(&accounts, 0), vec![Some(11)]);
+
+    accounts[12] = Pubkey::new_unique();
+    assert!(master_metadata_indexes(&accounts, 0).is_empty());
+}
+
+#[test]
+fn set_reservation_list_decoding_follows_the_slot() {
+    let layouts = default_layouts();
+    let decoding_at = |slot| layouts
+        .layout_for(&mpl_token_metadata::id(), &[5], slot)
+        .map(|l| l.decoding);
+    assert_eq!(
+        decoding_at(RESERVATION_LIST_OFFSETS_SLOT - 1), Some(Decoding::SetReservationListV1));
+    assert_eq!(decoding_at(RESERVATION_LIST_OFFSETS_SLOT), Some(Decoding::Current));
+
+    let reservations = reservations();
+    let old = set_reservation_list_v1_data(&reservations, 3);
+    match TokenMetadataInstruction::unpack_as(&old, Decoding::SetReservationListV1).unwrap() {
+        TokenMetadataInstruction::Legacy(
+            MetadataInstruction::DeprecatedSetReservationList(args)
+        ) => {
+            assert_eq!(args.reservations, reservations);
+            assert_eq!(args.total_reservation_spots, Some(3));
+            assert_eq!((args.offset, args.total_spot_offset), (0, 0));
+        }
+        other => panic!("decoded as {:?}", other),
+    }
+
+    // each layout only reads its own arguments
+    assert!(TokenMetadataInstruction::unpack_as(&old, Decoding::Current).is_err());
+    let current = set_reservation_list_data(&reservations, 3);
+    assert!(TokenMetadataInstruction::unpack_as(&current, Decoding::Current).is_ok());
+    assert!(
+        TokenMetadataInstruction::unpack_as(&current, Decoding::SetReservationListV1).is_err());
+}
+
+#[test]
+fn set_reservation_list_partitions_on_both_sides() {
+    let before = RESERVATION_LIST_OFFSETS_SLOT - 1;
+    let after = RESERVATION_LIST_OFFSETS_SLOT;
+    let reservations = reservations();
+
+    let (_, partition_errors) = assemble_transactions(vec![
+        (before, 0, set_reservation_list_transaction(
+            set_reservation_list_v1_data(&reservations, 3))),
+        (after, 0, set_reservation_list_transaction(
+            set_reservation_list_data(&reservations, 3))),
+    ]);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+
+    // either arguments on the other side of the upgrade decode with the layout they fit
+    let (_, partition_errors) = assemble_transactions(vec![
+        (after + 1_000, 1, set_reservation_list_transaction(
+            set_reservation_list_v1_data(&reservations, 3))),
+        (before - 1_000, 1, set_reservation_list_transaction(
+            set_reservation_list_data(&reservations, 3))),
+    ]);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+
+    // and arguments neither layout reads don't
+    let mut data = set_reservation_list_data(&reservations, 3);
+    data.push(7);
+    let (_, partition_errors) = assemble_transactions(vec![
+        (after, 2, set_reservation_list_transaction(data)),
+    ]);
+    assert_eq!(partition_errors.len(), 1);
+    let (slot, block_index, err) = &partition_errors[0];
+    assert_eq!((*slot, *block_index), (after, 2));
+    assert_eq!(err.code, ErrorCode::FailedInstructionDeserialization);
+}
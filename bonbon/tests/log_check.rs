@@ -0,0 +1,156 @@
+mod common;
+
+use {
+    bonbon::{
+        layouts::{default_layouts, RESERVATION_LIST_OFFSETS_SLOT},
+        partition::{
+            metadata_logged_instruction_name, partition_transaction,
+            token_logged_instruction_name, ErrorCode, LogCheck,
+        },
+        pipeline::default_partitioners,
+    },
+    borsh::BorshSerialize,
+    common::confirmed_transaction,
+    mpl_token_metadata::state::Reservation,
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+fn metadata_name(log: &str) -> Option<String> {
+    metadata_logged_instruction_name(log)
+}
+
+#[test]
+fn metadata_prose_maps_to_variants() {
+    let cases = [
+        ("Instruction: Create Metadata Accounts", "CreateMetadataAccount"),
+        ("Instruction: Create Metadata Accounts v2", "CreateMetadataAccountV2"),
+        ("Instruction: Update Metadata Accounts v2", "UpdateMetadataAccountV2"),
+        ("Instruction: Create Master Edition v3", "CreateMasterEditionV3"),
+        (
+            "Instruction: Mint New Edition from Master Edition Via Token",
+            "MintNewEditionFromMasterEditionViaToken",
+        ),
+        ("Instruction: Sign Metadata", "SignMetadata"),
+        ("Instruction: Verify Collection", "VerifyCollection"),
+        ("Instruction: Unverify Collection", "UnverifyCollection"),
+        ("IX: Update primary sale via token", "UpdatePrimarySaleHappenedViaToken"),
+        ("Instruction: Use/Utilize Token", "Utilize"),
+        ("Instruction: Verify Collection V2", "VerifySizedCollectionItem"),
+        ("Instruction: Unverify Sized Collection", "UnverifySizedCollectionItem"),
+        ("IX: Bubblegum Program Set Collection Size", "BubblegumSetCollectionSize"),
+        ("IX: Burn", "Burn"),
+        ("IX: Transfer", "Transfer"),
+        // logged before the deprecation
+        ("Instruction: Set Reservation List", "DeprecatedSetReservationList"),
+        (
+            "Instruction: Mint New Edition from Master Edition via Printing Token",
+            "DeprecatedMintNewEditionFromMasterEditionViaPrintingToken",
+        ),
+        ("Instruction: Deprecated Create Reservation List", "DeprecatedCreateReservationList"),
+    ];
+    for (log, variant) in cases {
+        assert_eq!(metadata_name(log).as_deref(), Some(variant), "{}", log);
+    }
+}
+
+#[test]
+fn metadata_prose_that_names_nothing() {
+    // both the sized and unsized variants log this
+    assert_eq!(metadata_name("Instruction: Set And Verify Collection"), None);
+    // not an instruction name
+    assert_eq!(metadata_name("Instruction: Frobnicate"), None);
+    assert_eq!(metadata_name("Create Metadata Accounts v2"), None);
+    assert_eq!(metadata_name("Transfer: 1 token"), None);
+}
+
+#[test]
+fn token_names_are_logged_as_is() {
+    assert_eq!(token_logged_instruction_name("Instruction: Transfer").as_deref(), Some("Transfer"));
+    assert_eq!(
+        token_logged_instruction_name("Instruction: InitializeMint2").as_deref(),
+        Some("InitializeMint2"),
+    );
+    assert_eq!(token_logged_instruction_name("Please upgrade to SPL Token 2022"), None);
+}
+
+// SetReservationList with the arguments from before `RESERVATION_LIST_OFFSETS_SLOT`, logged by
+// the program as it ran
+fn old_set_reservation_list(data_suffix: &[u8]) -> TransactionWithStatusMeta {
+    let authority = Keypair::new();
+    let reservations = vec![
+        Reservation { address: Pubkey::new_unique(), spots_remaining: 1, total_spots: 1 },
+    ];
+    let mut data = vec![5];
+    data.extend((reservations, Some(1u64)).try_to_vec().unwrap());
+    data.extend_from_slice(data_suffix);
+
+    let transaction = confirmed_transaction(
+        &[Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(authority.pubkey(), true),
+            ],
+            data,
+        }],
+        &[],
+        &[&authority],
+        &[],
+    );
+
+    let program = mpl_token_metadata::id();
+    let logs = vec![
+        format!("Program {} invoke [1]", program),
+        "Program log: Instruction: Set Reservation List".to_string(),
+        format!("Program {} consumed 12000 of 200000 compute units", program),
+        format!("Program {} success", program),
+    ];
+    match transaction {
+        TransactionWithStatusMeta::Complete(mut transaction) => {
+            transaction.meta.log_messages = Some(logs);
+            TransactionWithStatusMeta::Complete(transaction)
+        }
+        transaction => transaction,
+    }
+}
+
+#[test]
+fn older_layout_past_the_pivot_decodes_with_any_log_check() {
+    let layouts = default_layouts();
+    let partitioners = default_partitioners();
+    // past the pivot, e.g because the program was upgraded later than we have it. the
+    // arguments only fit the older layout, which is tried whatever the logs say
+    let slot = RESERVATION_LIST_OFFSETS_SLOT + 10;
+
+    for log_check in [LogCheck::Off, LogCheck::CrossCheck, LogCheck::Fallback] {
+        let partitioned = partition_transaction(
+            old_set_reservation_list(&[]), slot, &partitioners, &layouts, log_check,
+        ).expect("decodes with the older layout");
+        assert!(
+            partitioned.diagnostics.is_empty(), "{:?}: {:?}", log_check, partitioned.diagnostics);
+    }
+}
+
+#[test]
+fn fallback_skips_when_no_layout_fits() {
+    let layouts = default_layouts();
+    let partitioners = default_partitioners();
+    let slot = RESERVATION_LIST_OFFSETS_SLOT + 10;
+
+    // a trailing byte neither layout reads
+    let partitioned = partition_transaction(
+        old_set_reservation_list(&[7]), slot, &partitioners, &layouts, LogCheck::Fallback,
+    ).expect("skipped instead of failing");
+    assert!(partitioned.instructions.is_empty());
+    assert_eq!(partitioned.diagnostics.len(), 1);
+    let diagnostic = &partitioned.diagnostics[0];
+    assert_eq!(diagnostic.code, ErrorCode::FailedInstructionDeserialization);
+    assert_eq!(diagnostic.program_key, Some(mpl_token_metadata::id()));
+    assert_eq!(diagnostic.outer_index, Some(0));
+}
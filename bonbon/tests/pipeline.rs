@@ -0,0 +1,192 @@
+mod common;
+
+use {
+    bonbon::{
+        partition::ErrorCode,
+        pipeline::assemble_transactions,
+    },
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::create_metadata_accounts_v2,
+        pda::find_metadata_account,
+        state::Creator,
+    },
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+const MINT_SLOT: i64 = 130_000_000;
+
+const TRANSFER_SLOT: i64 = 130_000_100;
+
+fn create_account(payer: &Keypair, account: &Keypair, space: u64) -> Instruction {
+    system_instruction::create_account(
+        &payer.pubkey(), &account.pubkey(), 1_000_000, space, &spl_token::id())
+}
+
+struct Mint {
+    creator: Keypair,
+
+    mint_key: Pubkey,
+
+    creator_account: Keypair,
+
+    buyer: Keypair,
+
+    buyer_account: Keypair,
+}
+
+impl Mint {
+    fn new() -> (Self, Keypair) {
+        let mint = Keypair::new();
+        (
+            Self {
+                creator: Keypair::new(),
+                mint_key: mint.pubkey(),
+                creator_account: Keypair::new(),
+                buyer: Keypair::new(),
+                buyer_account: Keypair::new(),
+            },
+            mint,
+        )
+    }
+
+    // the mint, the creator's token account with the one token and the metadata
+    fn mint_transaction(&self, mint: &Keypair) -> TransactionWithStatusMeta {
+        let creator = self.creator.pubkey();
+        let creator_account = self.creator_account.pubkey();
+        confirmed_transaction(
+            &[
+                create_account(&self.creator, mint, 82),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &self.mint_key, &creator, Some(&creator), 0,
+                ).unwrap(),
+                create_account(&self.creator, &self.creator_account, 165),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &creator_account, &self.mint_key, &creator,
+                ).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &self.mint_key, &creator_account, &creator, &[], 1,
+                ).unwrap(),
+                create_metadata_accounts_v2(
+                    mpl_token_metadata::id(), find_metadata_account(&self.mint_key).0,
+                    self.mint_key, creator, creator, creator, "Bonbon #1".to_string(),
+                    "BON".to_string(), "https://example.com/1.json".to_string(),
+                    Some(vec![Creator { address: creator, verified: true, share: 100 }]),
+                    500, true, true, None, None,
+                ),
+            ],
+            &[],
+            &[&self.creator, mint, &self.creator_account],
+            &[(creator_account, self.mint_key, creator, None, Some(1))],
+        )
+    }
+
+    // the token moving from the creator to the buyer's new account
+    fn transfer_transaction(&self) -> TransactionWithStatusMeta {
+        let creator = self.creator.pubkey();
+        let creator_account = self.creator_account.pubkey();
+        let buyer = self.buyer.pubkey();
+        let buyer_account = self.buyer_account.pubkey();
+        confirmed_transaction(
+            &[
+                create_account(&self.buyer, &self.buyer_account, 165),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &buyer_account, &self.mint_key, &buyer,
+                ).unwrap(),
+                spl_token::instruction::transfer(
+                    &spl_token::id(), &creator_account, &buyer_account, &creator, &[], 1,
+                ).unwrap(),
+            ],
+            &[],
+            &[&self.buyer, &self.buyer_account, &self.creator],
+            &[
+                (creator_account, self.mint_key, creator, Some(1), Some(0)),
+                (buyer_account, self.mint_key, buyer, None, Some(1)),
+            ],
+        )
+    }
+}
+
+#[test]
+fn mint_create_metadata_and_transfer() {
+    let (fixture, mint) = Mint::new();
+
+    // pushed out of order, the pipeline sorts by slot
+    let (bonbons, partition_errors) = assemble_transactions(vec![
+        (TRANSFER_SLOT, 2, fixture.transfer_transaction()),
+        (MINT_SLOT, 5, fixture.mint_transaction(&mint)),
+    ]);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+
+    let mut bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    let (mint_key, bonbon) = bonbons.remove(0);
+    let bonbon = bonbon.expect("bonbon assembles");
+
+    assert_eq!(mint_key, fixture.mint_key);
+    assert_eq!(bonbon.mint_key, fixture.mint_key);
+    assert_eq!(bonbon.metadata_key, find_metadata_account(&fixture.mint_key).0);
+    assert_eq!(bonbon.current_owner, Some(fixture.buyer.pubkey()));
+    assert_eq!(bonbon.current_account, Some(fixture.buyer_account.pubkey()));
+    assert_eq!(bonbon.supply, 1);
+    assert_eq!(bonbon.mint_authority(), Some(fixture.creator.pubkey()));
+    assert_eq!(bonbon.update_authority(), Some(fixture.creator.pubkey()));
+
+    assert_eq!(bonbon.glazings.len(), 1);
+    let glazing = &bonbon.glazings[0];
+    assert_eq!(glazing.name, b"Bonbon #1");
+    assert_eq!(glazing.uri, b"https://example.com/1.json");
+    assert_eq!(glazing.seller_fee_basis_points, 500);
+    assert_eq!(
+        glazing.creators.iter().map(|c| (c.address, c.verified, c.share)).collect::<Vec<_>>(),
+        vec![(fixture.creator.pubkey(), true, 100)],
+    );
+
+    assert_eq!(
+        bonbon.holdings.iter().map(|h| (h.account, h.owner, h.amount)).collect::<Vec<_>>(),
+        vec![(fixture.buyer_account.pubkey(), Some(fixture.buyer.pubkey()), 1)],
+    );
+}
+
+#[test]
+fn partition_errors_come_back_with_the_bonbons() {
+    let (fixture, mint) = Mint::new();
+
+    // no token instruction has this discriminant
+    let payer = Keypair::new();
+    let garbled = confirmed_transaction(
+        &[Instruction {
+            program_id: spl_token::id(),
+            accounts: vec![AccountMeta::new(fixture.creator_account.pubkey(), false)],
+            data: vec![255],
+        }],
+        &[],
+        &[&payer],
+        &[],
+    );
+
+    let (bonbons, partition_errors) = assemble_transactions(vec![
+        (MINT_SLOT, 5, fixture.mint_transaction(&mint)),
+        (TRANSFER_SLOT, 1, garbled),
+        (TRANSFER_SLOT, 2, fixture.transfer_transaction()),
+    ]);
+
+    assert_eq!(partition_errors.len(), 1);
+    let (slot, block_index, err) = &partition_errors[0];
+    assert_eq!((*slot, *block_index), (TRANSFER_SLOT, 1));
+    assert_eq!(err.code, ErrorCode::FailedInstructionDeserialization);
+    assert_eq!(err.program_key, Some(spl_token::id()));
+    assert_eq!(err.outer_index, Some(0));
+
+    // the other transactions still make the bonbon
+    let bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    let bonbon = bonbons[0].1.as_ref().expect("bonbon assembles");
+    assert_eq!(bonbon.current_owner, Some(fixture.buyer.pubkey()));
+}
@@ -0,0 +1,398 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble::{Bonbon, TokenDelegateRole, TokenStandard, TokenState},
+        pipeline::{default_partitioners, default_updaters, Pipeline},
+        programs::associated_token_account,
+    },
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::{create_master_edition_v3, create_metadata_accounts_v2},
+        pda::{find_master_edition_account, find_metadata_account},
+    },
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction, system_program, sysvar,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+    spl_token::instruction::AuthorityType,
+};
+
+const SLOT: i64 = 180_000_000;
+
+fn assemble(transactions: Vec<TransactionWithStatusMeta>) -> Bonbon {
+    let mut pipeline = Pipeline::new(default_partitioners(), default_updaters());
+    for (slot, transaction) in (SLOT..).zip(transactions) {
+        pipeline.push(slot, 0, transaction).expect("partitions");
+    }
+    let mut bonbons = pipeline.finish().collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    bonbons.remove(0).1.expect("bonbon assembles")
+}
+
+// (state, delegate, role, slot) of the current token record
+fn token_record(
+    bonbon: &Bonbon,
+) -> Option<(TokenState, Option<Pubkey>, Option<TokenDelegateRole>, i64)> {
+    bonbon.token_record.as_ref()
+        .map(|r| (r.state, r.delegate, r.delegate_role, r.instruction_index.slot))
+}
+
+// (account, owner, frozen) of each holding
+fn holdings(bonbon: &Bonbon) -> Vec<(Pubkey, Option<Pubkey>, bool)> {
+    bonbon.holdings.iter().map(|h| (h.account, h.owner, h.frozen)).collect()
+}
+
+// An NFT migrated to a programmable one, which its owner then lists, stakes and sells through
+// token metadata. The token account stays frozen in between, so each of these thaws and
+// freezes it again through the token program
+struct Pnft {
+    owner: Keypair,
+
+    mint: Keypair,
+
+    account: Keypair,
+
+    token_record: Pubkey,
+
+    rule_set: Pubkey,
+}
+
+impl Pnft {
+    fn new() -> Self {
+        Self {
+            owner: Keypair::new(),
+            mint: Keypair::new(),
+            account: Keypair::new(),
+            token_record: Pubkey::new_unique(),
+            rule_set: Pubkey::new_unique(),
+        }
+    }
+
+    fn metadata(&self) -> Pubkey {
+        find_metadata_account(&self.mint.pubkey()).0
+    }
+
+    fn edition(&self) -> Pubkey {
+        find_master_edition_account(&self.mint.pubkey()).0
+    }
+
+    fn thaw(&self, account: &Pubkey) -> Instruction {
+        spl_token::instruction::thaw_account(
+            &spl_token::id(), account, &self.mint.pubkey(), &self.edition(), &[]).unwrap()
+    }
+
+    fn freeze(&self, account: &Pubkey) -> Instruction {
+        spl_token::instruction::freeze_account(
+            &spl_token::id(), account, &self.mint.pubkey(), &self.edition(), &[]).unwrap()
+    }
+
+    fn mint_transaction(&self) -> TransactionWithStatusMeta {
+        let (owner, mint_key, account) =
+            (self.owner.pubkey(), self.mint.pubkey(), self.account.pubkey());
+        confirmed_transaction(
+            &[
+                system_instruction::create_account(
+                    &owner, &mint_key, 1_000_000, 82, &spl_token::id()),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint_key, &owner, Some(&owner), 0).unwrap(),
+                system_instruction::create_account(
+                    &owner, &account, 1_000_000, 165, &spl_token::id()),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &account, &mint_key, &owner).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint_key, &account, &owner, &[], 1).unwrap(),
+                create_metadata_accounts_v2(
+                    mpl_token_metadata::id(), self.metadata(), mint_key, owner, owner, owner,
+                    "Bonbon #3".to_string(), "BON".to_string(),
+                    "https://example.com/3.json".to_string(), None, 500, true, true, None, None,
+                ),
+                create_master_edition_v3(
+                    mpl_token_metadata::id(), self.edition(), mint_key, owner, owner,
+                    self.metadata(), owner, Some(0),
+                ),
+            ],
+            // the master edition takes over the mint and freeze authorities
+            &[(6, vec![
+                spl_token::instruction::set_authority(
+                    &spl_token::id(), &mint_key, Some(&self.edition()),
+                    AuthorityType::MintTokens, &owner, &[],
+                ).unwrap(),
+                spl_token::instruction::set_authority(
+                    &spl_token::id(), &mint_key, Some(&self.edition()),
+                    AuthorityType::FreezeAccount, &owner, &[],
+                ).unwrap(),
+            ])],
+            &[&self.owner, &self.mint, &self.account],
+            &[(account, mint_key, owner, None, Some(1))],
+        )
+    }
+
+    // metadata, edition, token, token owner, mint, payer, authority, collection metadata,
+    // delegate record, token record, system program, instructions sysvar, token program, rule
+    // set program, rule set
+    fn migrate_transaction(&self) -> TransactionWithStatusMeta {
+        let (owner, none) = (self.owner.pubkey(), mpl_token_metadata::id());
+        // `MigrateArgs::V1` to `MigrationType::ProgrammableV1` with the rule set
+        let mut data = vec![48, 0, 1, 1];
+        data.extend_from_slice(self.rule_set.as_ref());
+        let migrate = Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new(self.edition(), false),
+                AccountMeta::new(self.account.pubkey(), false),
+                AccountMeta::new_readonly(owner, false),
+                AccountMeta::new_readonly(self.mint.pubkey(), false),
+                AccountMeta::new(owner, true),
+                AccountMeta::new_readonly(owner, true),
+                AccountMeta::new_readonly(none, false),
+                AccountMeta::new_readonly(none, false),
+                AccountMeta::new(self.token_record, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(self.rule_set, false),
+            ],
+            data,
+        };
+        confirmed_transaction(
+            &[migrate],
+            &[(0, vec![self.freeze(&self.account.pubkey())])],
+            &[&self.owner],
+            &[(self.account.pubkey(), self.mint.pubkey(), owner, Some(1), Some(1))],
+        )
+    }
+
+    // delegate (or revoke) records the token delegate on the token record: delegate record,
+    // delegate, metadata, master edition, token record, mint, token, authority, payer, system
+    // program, instructions sysvar, token program, rule set program, rule set
+    fn delegate_accounts(&self, delegate: &Pubkey) -> Vec<AccountMeta> {
+        let (owner, none) = (self.owner.pubkey(), mpl_token_metadata::id());
+        vec![
+            AccountMeta::new_readonly(none, false),
+            AccountMeta::new_readonly(*delegate, false),
+            AccountMeta::new(self.metadata(), false),
+            AccountMeta::new_readonly(self.edition(), false),
+            AccountMeta::new(self.token_record, false),
+            AccountMeta::new_readonly(self.mint.pubkey(), false),
+            AccountMeta::new(self.account.pubkey(), false),
+            AccountMeta::new_readonly(owner, true),
+            AccountMeta::new(owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(none, false),
+            AccountMeta::new_readonly(self.rule_set, false),
+        ]
+    }
+
+    // `DelegateArgs` for `variant` with an amount of 1 and no authorization data. the approve
+    // the program makes between the thaw and freeze is left out
+    fn delegate_transaction(&self, variant: u8, delegate: &Pubkey) -> TransactionWithStatusMeta {
+        let mut data = vec![44, variant];
+        data.extend(1u64.to_le_bytes());
+        data.push(0);
+        let delegate = Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: self.delegate_accounts(delegate),
+            data,
+        };
+        let account = self.account.pubkey();
+        confirmed_transaction(
+            &[delegate], &[(0, vec![self.thaw(&account), self.freeze(&account)])],
+            &[&self.owner], &[],
+        )
+    }
+
+    fn revoke_transaction(&self, variant: u8, delegate: &Pubkey) -> TransactionWithStatusMeta {
+        let revoke = Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: self.delegate_accounts(delegate),
+            data: vec![45, variant],
+        };
+        confirmed_transaction(&[revoke], &[], &[&self.owner], &[])
+    }
+
+    // `Lock` or `Unlock` by the utility delegate: authority, token owner, token, mint, metadata,
+    // edition, token record, payer, system program, instructions sysvar, token program, rule set
+    // program, rule set
+    fn lock_transaction(&self, lock: bool, delegate: &Keypair) -> TransactionWithStatusMeta {
+        let none = mpl_token_metadata::id();
+        let lock = Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new_readonly(delegate.pubkey(), true),
+                AccountMeta::new_readonly(self.owner.pubkey(), false),
+                AccountMeta::new(self.account.pubkey(), false),
+                AccountMeta::new_readonly(self.mint.pubkey(), false),
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new_readonly(self.edition(), false),
+                AccountMeta::new(self.token_record, false),
+                AccountMeta::new(delegate.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(none, false),
+                AccountMeta::new_readonly(self.rule_set, false),
+            ],
+            // `LockArgs::V1` / `UnlockArgs::V1` without authorization data
+            data: vec![if lock { 46 } else { 47 }, 0, 0],
+        };
+        confirmed_transaction(&[lock], &[], &[delegate], &[])
+    }
+
+    // token, token owner, destination, destination owner, mint, metadata, edition, owner token
+    // record, destination token record, authority, payer, system program, instructions sysvar,
+    // token program, associated token program, rule set program, rule set
+    fn transfer_transaction(
+        &self,
+        destination: &Pubkey,
+        destination_owner: &Pubkey,
+    ) -> TransactionWithStatusMeta {
+        let (owner, account, none) =
+            (self.owner.pubkey(), self.account.pubkey(), mpl_token_metadata::id());
+        let mut data = vec![49, 0];
+        data.extend(1u64.to_le_bytes());
+        data.push(0);
+        let transfer = Instruction {
+            program_id: mpl_token_metadata::id(),
+            accounts: vec![
+                AccountMeta::new(account, false),
+                AccountMeta::new_readonly(owner, false),
+                AccountMeta::new(*destination, false),
+                AccountMeta::new_readonly(*destination_owner, false),
+                AccountMeta::new_readonly(self.mint.pubkey(), false),
+                AccountMeta::new(self.metadata(), false),
+                AccountMeta::new_readonly(self.edition(), false),
+                AccountMeta::new(self.token_record, false),
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(owner, true),
+                AccountMeta::new(owner, true),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(associated_token_account::id(), false),
+                AccountMeta::new_readonly(none, false),
+                AccountMeta::new_readonly(self.rule_set, false),
+            ],
+            data,
+        };
+        // the source is thawed for the transfer and the destination frozen after it
+        let cpis = vec![
+            self.thaw(&account),
+            spl_token::instruction::transfer(
+                &spl_token::id(), &account, destination, &owner, &[], 1).unwrap(),
+            self.freeze(destination),
+        ];
+        confirmed_transaction(
+            &[transfer],
+            &[(0, cpis)],
+            &[&self.owner],
+            &[
+                (account, self.mint.pubkey(), owner, Some(1), Some(0)),
+                (*destination, self.mint.pubkey(), *destination_owner, None, Some(1)),
+            ],
+        )
+    }
+}
+
+// variants of `DelegateArgs` and `RevokeArgs`
+const SALE: u8 = 1;
+const UTILITY: u8 = 4;
+
+#[test]
+fn migrate_starts_an_unlocked_token_record() {
+    let pnft = Pnft::new();
+
+    let nft = assemble(vec![pnft.mint_transaction()]);
+    assert_eq!(nft.token_standard(), TokenStandard::NonFungible);
+    assert_eq!(nft.rule_set, None);
+    assert_eq!(token_record(&nft), None);
+    assert_eq!(holdings(&nft), vec![(pnft.account.pubkey(), Some(pnft.owner.pubkey()), false)]);
+
+    let bonbon = assemble(vec![pnft.mint_transaction(), pnft.migrate_transaction()]);
+    assert_eq!(bonbon.token_standard(), TokenStandard::ProgrammableNonFungible);
+    assert_eq!(bonbon.rule_set, Some(pnft.rule_set));
+    assert_eq!(token_record(&bonbon), Some((TokenState::Unlocked, None, None, SLOT + 1)));
+    // frozen from here on
+    assert_eq!(
+        holdings(&bonbon), vec![(pnft.account.pubkey(), Some(pnft.owner.pubkey()), true)]);
+}
+
+#[test]
+fn sale_delegate_lists_until_revoked() {
+    let pnft = Pnft::new();
+    let marketplace = Pubkey::new_unique();
+    let transactions = || vec![pnft.mint_transaction(), pnft.migrate_transaction()];
+
+    let listed = assemble(
+        [transactions(), vec![pnft.delegate_transaction(SALE, &marketplace)]].concat());
+    assert_eq!(
+        token_record(&listed),
+        Some((TokenState::Listed, Some(marketplace), Some(TokenDelegateRole::Sale), SLOT + 2)),
+    );
+    // thawed and frozen again around the delegate
+    assert!(holdings(&listed)[0].2);
+
+    let revoked = assemble([
+        transactions(),
+        vec![
+            pnft.delegate_transaction(SALE, &marketplace),
+            pnft.revoke_transaction(SALE, &marketplace),
+        ],
+    ].concat());
+    assert_eq!(token_record(&revoked), Some((TokenState::Unlocked, None, None, SLOT + 3)));
+}
+
+#[test]
+fn utility_delegate_locks_and_unlocks() {
+    let pnft = Pnft::new();
+    let staking = Keypair::new();
+    let transactions = || vec![
+        pnft.mint_transaction(),
+        pnft.migrate_transaction(),
+        pnft.delegate_transaction(UTILITY, &staking.pubkey()),
+        pnft.lock_transaction(true, &staking),
+    ];
+    let utility = Some(TokenDelegateRole::Utility);
+
+    let locked = assemble(transactions());
+    assert_eq!(
+        token_record(&locked),
+        Some((TokenState::Locked, Some(staking.pubkey()), utility, SLOT + 3)),
+    );
+
+    // the delegate stays on after unlocking
+    let unlocked = assemble(
+        [transactions(), vec![pnft.lock_transaction(false, &staking)]].concat());
+    assert_eq!(
+        token_record(&unlocked),
+        Some((TokenState::Unlocked, Some(staking.pubkey()), utility, SLOT + 4)),
+    );
+}
+
+#[test]
+fn transfer_moves_the_owner_and_resets_the_token_record() {
+    let pnft = Pnft::new();
+    let marketplace = Pubkey::new_unique();
+    let (buyer, destination) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    let bonbon = assemble(vec![
+        pnft.mint_transaction(),
+        pnft.migrate_transaction(),
+        pnft.delegate_transaction(SALE, &marketplace),
+        pnft.transfer_transaction(&destination, &buyer),
+    ]);
+    assert_eq!(bonbon.current_owner, Some(buyer));
+    assert_eq!(bonbon.current_account, Some(destination));
+    // the buyer's token record starts out without the seller's delegate
+    assert_eq!(token_record(&bonbon), Some((TokenState::Unlocked, None, None, SLOT + 3)));
+    // the emptied source is gone and the destination was frozen after the transfer
+    assert_eq!(holdings(&bonbon), vec![(destination, Some(buyer), true)]);
+    assert_eq!(bonbon.supply, 1);
+}
@@ -0,0 +1,187 @@
+use {
+    bonbon::assemble::{
+        Creator, Glazing, InstructionIndex, RoyaltyPayment, Sale, TransactionTokenOwnerMeta,
+    },
+    solana_sdk::{native_token::LAMPORTS_PER_SOL, pubkey::Pubkey},
+};
+
+struct Parties {
+    buyer: Pubkey,
+
+    seller: Pubkey,
+
+    creators: [Pubkey; 3],
+}
+
+impl Parties {
+    fn new() -> Self {
+        Self {
+            buyer: Pubkey::new_unique(),
+            seller: Pubkey::new_unique(),
+            creators: [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()],
+        }
+    }
+
+    // 5% royalties split 60/40 between the first two creators. the third never verified
+    fn glazing(&self) -> Glazing {
+        let creator = |address: Pubkey, verified: bool, share: i16| Creator {
+            address,
+            verified,
+            share,
+        };
+        Glazing {
+            seller_fee_basis_points: 500,
+            creators: vec![
+                creator(self.creators[0], true, 60),
+                creator(self.creators[1], true, 40),
+                creator(self.creators[2], false, 0),
+            ],
+            ..Glazing::default()
+        }
+    }
+
+    fn sale(&self, price: u64, currency_mint: Pubkey) -> Sale {
+        Sale {
+            program_key: Pubkey::new_unique(),
+            marketplace: None,
+            buyer: self.buyer,
+            seller: self.seller,
+            price,
+            currency_mint,
+            amount: 1,
+            royalties: vec![],
+            instruction_index: InstructionIndex::default(),
+        }
+    }
+}
+
+fn owner_meta(
+    account_index: u8,
+    mint_key: Pubkey,
+    owner_key: Pubkey,
+    pre_amount: Option<u64>,
+    post_amount: Option<u64>,
+) -> TransactionTokenOwnerMeta {
+    TransactionTokenOwnerMeta { account_index, mint_key, owner_key, pre_amount, post_amount }
+}
+
+// (creator, expected, paid, shortfall, ambiguous)
+fn summary(royalties: &[RoyaltyPayment]) -> Vec<(Pubkey, u64, Option<i64>, Option<u64>, bool)> {
+    royalties.iter()
+        .map(|r| (r.creator, r.expected, r.paid, r.shortfall, r.ambiguous))
+        .collect()
+}
+
+const PRICE: u64 = 2 * LAMPORTS_PER_SOL;
+
+#[test]
+fn sol_royalties_paid_in_full_and_short() {
+    let parties = Parties::new();
+    let sale = parties.sale(PRICE, spl_token::native_mint::id());
+    let account_keys = [parties.buyer, parties.seller, parties.creators[0], parties.creators[1]];
+    let pre_balances = [5 * PRICE, 0, 1_000, 1_000];
+    // the second creator only got 30M of their 40M
+    let post_balances = [3 * PRICE, PRICE, 60_001_000, 30_001_000];
+    let item = Pubkey::new_unique();
+    let owners = [owner_meta(4, item, parties.buyer, None, Some(1))];
+
+    let royalties = RoyaltyPayment::for_sale(
+        Some(&parties.glazing()), &sale, &account_keys, &owners, &pre_balances, &post_balances);
+    assert_eq!(summary(&royalties), vec![
+        (parties.creators[0], 60_000_000, Some(60_000_000), Some(0), false),
+        (parties.creators[1], 40_000_000, Some(30_000_000), Some(10_000_000), false),
+    ]);
+}
+
+#[test]
+fn creator_missing_from_the_transaction_is_unknown() {
+    let parties = Parties::new();
+    let sale = parties.sale(PRICE, spl_token::native_mint::id());
+    // the second creator isn't in the transaction at all
+    let account_keys = [parties.buyer, parties.seller, parties.creators[0]];
+    let pre_balances = [5 * PRICE, 0, 0];
+    let post_balances = [3 * PRICE, PRICE, 60_000_000];
+
+    let royalties = RoyaltyPayment::for_sale(
+        Some(&parties.glazing()), &sale, &account_keys, &[], &pre_balances, &post_balances);
+    assert_eq!(summary(&royalties)[1], (parties.creators[1], 40_000_000, None, None, false));
+
+    // and without a status meta no one can be checked
+    let royalties = RoyaltyPayment::for_sale(
+        Some(&parties.glazing()), &sale, &account_keys, &[], &[], &[]);
+    assert!(royalties.iter().all(|r| r.paid.is_none() && r.shortfall.is_none() && !r.ambiguous));
+}
+
+#[test]
+fn creator_selling_is_ambiguous() {
+    let parties = Parties::new();
+    let mut sale = parties.sale(PRICE, spl_token::native_mint::id());
+    sale.seller = parties.creators[0];
+    let account_keys = [parties.buyer, parties.creators[0], parties.creators[1]];
+    let pre_balances = [5 * PRICE, 0, 0];
+    // the price less the other creator's royalty, which hides the seller's own
+    let post_balances = [3 * PRICE, PRICE - 40_000_000, 40_000_000];
+
+    let royalties = RoyaltyPayment::for_sale(
+        Some(&parties.glazing()), &sale, &account_keys, &[], &pre_balances, &post_balances);
+    assert_eq!(summary(&royalties), vec![
+        (parties.creators[0], 60_000_000, None, None, true),
+        (parties.creators[1], 40_000_000, Some(40_000_000), Some(0), false),
+    ]);
+}
+
+#[test]
+fn creator_buying_is_ambiguous() {
+    let parties = Parties::new();
+    let mut sale = parties.sale(PRICE, spl_token::native_mint::id());
+    sale.buyer = parties.creators[1];
+    let account_keys = [parties.creators[1], parties.seller, parties.creators[0]];
+    let pre_balances = [5 * PRICE, 0, 0];
+    let post_balances = [3 * PRICE + 40_000_000, PRICE - 100_000_000, 60_000_000];
+
+    let royalties = RoyaltyPayment::for_sale(
+        Some(&parties.glazing()), &sale, &account_keys, &[], &pre_balances, &post_balances);
+    assert_eq!(summary(&royalties)[1], (parties.creators[1], 40_000_000, None, None, true));
+}
+
+#[test]
+fn sweeps_are_ambiguous() {
+    let parties = Parties::new();
+    let sale = parties.sale(PRICE, spl_token::native_mint::id());
+    let account_keys = [parties.buyer, parties.seller, parties.creators[0], parties.creators[1]];
+    let pre_balances = [5 * PRICE, 0, 0, 0];
+    // two items from the collection, so twice the royalties
+    let post_balances = [PRICE, 2 * PRICE, 120_000_000, 80_000_000];
+    let owners = [
+        owner_meta(4, Pubkey::new_unique(), parties.buyer, None, Some(1)),
+        owner_meta(5, Pubkey::new_unique(), parties.buyer, None, Some(1)),
+    ];
+
+    let royalties = RoyaltyPayment::for_sale(
+        Some(&parties.glazing()), &sale, &account_keys, &owners, &pre_balances, &post_balances);
+    assert!(royalties.iter().all(|r| r.ambiguous && r.paid.is_none() && r.shortfall.is_none()));
+}
+
+#[test]
+fn token_royalties() {
+    let parties = Parties::new();
+    let usdc = Pubkey::new_unique();
+    let sale = parties.sale(100_000_000, usdc);
+    let account_keys = [parties.buyer, parties.seller, parties.creators[0], parties.creators[1]];
+    let owners = [
+        owner_meta(4, Pubkey::new_unique(), parties.buyer, None, Some(1)),
+        owner_meta(5, usdc, parties.buyer, Some(100_000_000), Some(0)),
+        owner_meta(6, usdc, parties.seller, Some(0), Some(95_000_000)),
+        owner_meta(7, usdc, parties.creators[0], Some(0), Some(3_000_000)),
+        // the second creator's token account was created by the sale and got nothing
+        owner_meta(8, usdc, parties.creators[1], None, Some(0)),
+    ];
+    let balances = [0; 4];
+
+    let royalties = RoyaltyPayment::for_sale(
+        Some(&parties.glazing()), &sale, &account_keys, &owners, &balances, &balances);
+    assert_eq!(summary(&royalties), vec![
+        (parties.creators[0], 3_000_000, Some(3_000_000), Some(0), false),
+        (parties.creators[1], 2_000_000, Some(0), Some(2_000_000), false),
+    ]);
+}
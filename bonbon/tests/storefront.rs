@@ -0,0 +1,313 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble::{Bonbon, StorefrontSaleKind},
+        pipeline::assemble_transactions,
+        programs::{fixed_price_sale, metaplex, token_vault},
+    },
+    common::confirmed_transaction,
+    mpl_token_metadata::{
+        instruction::{create_metadata_accounts_v2, mint_new_edition_from_master_edition_via_token},
+        pda::{find_master_edition_account, find_metadata_account},
+    },
+    solana_sdk::{
+        hash::hash,
+        instruction::{AccountMeta, Instruction},
+        message::VersionedMessage,
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction, system_program, sysvar,
+    },
+    solana_transaction_status::TransactionWithStatusMeta,
+};
+
+const SLOT: i64 = 90_000_000;
+
+const PRICE: u64 = 2_000_000_000;
+
+fn only_bonbon(transactions: Vec<(i64, i64, TransactionWithStatusMeta)>) -> (Pubkey, Bonbon) {
+    let (bonbons, partition_errors) = assemble_transactions(transactions);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+    let mut bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    let (mint_key, bonbon) = bonbons.remove(0);
+    (mint_key, bonbon.expect("bonbon assembles"))
+}
+
+// kind, program, manager, auction, vault, buyer, bidder metadata, price and slot of a sale
+type SaleSummary = (
+    StorefrontSaleKind, Pubkey, Pubkey, Option<Pubkey>, Option<Pubkey>, Pubkey, Option<Pubkey>,
+    Option<u64>, i64,
+);
+
+fn sales(bonbon: &Bonbon) -> Vec<SaleSummary> {
+    bonbon.storefront_sales.iter()
+        .map(|s| (
+            s.kind, s.program_key, s.manager, s.auction, s.vault, s.buyer, s.bidder_metadata,
+            s.price, s.instruction_index.slot,
+        ))
+        .collect()
+}
+
+// the mint, initialized, minted to `account` and with its metadata, all by `creator`
+fn mint_instructions(creator: &Pubkey, mint: &Pubkey, account: &Pubkey) -> Vec<Instruction> {
+    vec![
+        system_instruction::create_account(creator, mint, 1_000_000, 82, &spl_token::id()),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(), mint, creator, None, 0).unwrap(),
+        system_instruction::create_account(creator, account, 1_000_000, 165, &spl_token::id()),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(), account, mint, creator).unwrap(),
+        spl_token::instruction::mint_to(
+            &spl_token::id(), mint, account, creator, &[], 1).unwrap(),
+        create_metadata_accounts_v2(
+            mpl_token_metadata::id(), find_metadata_account(mint).0, *mint, *creator, *creator,
+            *creator, "Bonbon #4".to_string(), "BON".to_string(),
+            "https://example.com/4.json".to_string(), None, 500, true, true, None, None,
+        ),
+    ]
+}
+
+// An NFT put in a token vault and auctioned. The winner redeems their bid through the
+// storefront, which withdraws the item from the vault into the winner's token account
+#[test]
+fn auction_redemption_out_of_the_vault() {
+    let (creator, mint, account) = (Keypair::new(), Keypair::new(), Keypair::new());
+    let (creator_key, mint_key) = (creator.pubkey(), mint.pubkey());
+    let mint_transaction = confirmed_transaction(
+        &mint_instructions(&creator_key, &mint_key, &account.pubkey()),
+        &[],
+        &[&creator, &mint, &account],
+        &[(account.pubkey(), mint_key, creator_key, None, Some(1))],
+    );
+
+    let (vault, vault_authority, safety_deposit_box, store) = (
+        Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(),
+    );
+    // safety deposit box, token account, store, vault, vault authority, payer, transfer
+    // authority, token program, rent sysvar, system program
+    let mut data = vec![1];
+    data.extend(1u64.to_le_bytes());
+    let add_token = Instruction {
+        program_id: token_vault::id(),
+        accounts: vec![
+            AccountMeta::new(safety_deposit_box, false),
+            AccountMeta::new(account.pubkey(), false),
+            AccountMeta::new(store, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(vault_authority, false),
+            AccountMeta::new(creator_key, true),
+            AccountMeta::new_readonly(creator_key, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    };
+    let deposit = spl_token::instruction::transfer(
+        &spl_token::id(), &account.pubkey(), &store, &creator_key, &[], 1).unwrap();
+    let deposit_transaction = confirmed_transaction(
+        &[add_token],
+        &[(0, vec![deposit])],
+        &[&creator],
+        &[
+            (account.pubkey(), mint_key, creator_key, Some(1), Some(0)),
+            (store, mint_key, vault_authority, None, Some(1)),
+        ],
+    );
+
+    let bidder = Keypair::new();
+    let (manager, auction, bidder_metadata, destination) = (
+        Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(),
+    );
+    let (bid_redemption, fraction_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    // see `MetaplexInstruction::is_redemption` for the accounts
+    let redeem_bid = Instruction {
+        program_id: metaplex::id(),
+        accounts: vec![
+            AccountMeta::new(manager, false),
+            AccountMeta::new(store, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new(bid_redemption, false),
+            AccountMeta::new(safety_deposit_box, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(fraction_mint, false),
+            AccountMeta::new_readonly(auction, false),
+            AccountMeta::new_readonly(bidder_metadata, false),
+            AccountMeta::new_readonly(bidder.pubkey(), true),
+            AccountMeta::new(bidder.pubkey(), true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(token_vault::id(), false),
+            AccountMeta::new_readonly(vault_authority, false),
+        ],
+        data: vec![2],
+    };
+    // destination, safety deposit box, store, vault, fraction mint, vault authority, transfer
+    // authority, token program
+    let mut data = vec![5];
+    data.extend(1u64.to_le_bytes());
+    let withdraw = Instruction {
+        program_id: token_vault::id(),
+        accounts: vec![
+            AccountMeta::new(destination, false),
+            AccountMeta::new(safety_deposit_box, false),
+            AccountMeta::new(store, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(fraction_mint, false),
+            AccountMeta::new_readonly(vault_authority, true),
+            AccountMeta::new_readonly(manager, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data,
+    };
+    let transfer = spl_token::instruction::transfer(
+        &spl_token::id(), &store, &destination, &vault_authority, &[], 1).unwrap();
+    let redeem_transaction = confirmed_transaction(
+        &[redeem_bid],
+        &[(0, vec![withdraw, transfer])],
+        &[&bidder],
+        &[
+            (store, mint_key, vault_authority, Some(1), Some(0)),
+            (destination, mint_key, bidder.pubkey(), None, Some(1)),
+        ],
+    );
+
+    let (bonbon_key, bonbon) = only_bonbon(vec![
+        (SLOT, 0, mint_transaction),
+        (SLOT + 1, 0, deposit_transaction),
+        (SLOT + 2, 0, redeem_transaction),
+    ]);
+    assert_eq!(bonbon_key, mint_key);
+
+    // (vault, safety deposit box, deposit, amount, slot, inner index)
+    assert_eq!(
+        bonbon.vault_custody.iter()
+            .map(|c| (
+                c.vault, c.safety_deposit_box, c.deposit, c.amount, c.instruction_index.slot,
+                c.instruction_index.inner_index,
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            (vault, safety_deposit_box, true, 1, SLOT + 1, None),
+            (vault, safety_deposit_box, false, 1, SLOT + 2, Some(0)),
+        ],
+    );
+    // the winning bid is left in the bidder metadata
+    assert_eq!(
+        sales(&bonbon),
+        vec![(
+            StorefrontSaleKind::Auction, metaplex::id(), manager, Some(auction), Some(vault),
+            bidder.pubkey(), Some(bidder_metadata), None, SLOT + 2,
+        )],
+    );
+    assert_eq!(bonbon.current_owner, Some(bidder.pubkey()));
+    assert_eq!(bonbon.current_account, Some(destination));
+}
+
+fn with_lamports(
+    transaction: TransactionWithStatusMeta,
+    lamports: &[(Pubkey, u64, u64)],
+) -> TransactionWithStatusMeta {
+    match transaction {
+        TransactionWithStatusMeta::Complete(mut transaction) => {
+            let keys = match &transaction.transaction.message {
+                VersionedMessage::Legacy(message) => message.account_keys.clone(),
+                VersionedMessage::V0(message) => message.account_keys.clone(),
+            };
+            for (wallet, pre, post) in lamports {
+                let index = keys.iter().position(|k| k == wallet).expect("wallet is in it");
+                transaction.meta.pre_balances[index] = *pre;
+                transaction.meta.post_balances[index] = *post;
+            }
+            TransactionWithStatusMeta::Complete(transaction)
+        }
+        transaction => transaction,
+    }
+}
+
+// A print of a master edition bought from a fixed price market that sells in SOL. The buyer
+// sets up the new mint and `buy` prints the edition onto it
+#[test]
+fn fixed_price_buy_prints_an_edition() {
+    let (buyer, mint, account) = (Keypair::new(), Keypair::new(), Keypair::new());
+    let (buyer_key, mint_key) = (buyer.pubkey(), mint.pubkey());
+    let (master_mint, vault_owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (market, selling_resource, treasury_holder, trade_history, vault) = (
+        Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let (new_metadata, master_metadata) =
+        (find_metadata_account(&mint_key).0, find_metadata_account(&master_mint).0);
+
+    let print = mint_new_edition_from_master_edition_via_token(
+        mpl_token_metadata::id(), new_metadata, find_master_edition_account(&mint_key).0,
+        find_master_edition_account(&master_mint).0, mint_key, buyer_key, buyer_key, vault_owner,
+        vault, buyer_key, master_metadata, master_mint, 1,
+    );
+    // market, selling resource, user token account, user wallet, trade history, treasury
+    // holder, new metadata, new edition, master edition, new mint, edition marker, vault, owner,
+    // master edition metadata, clock, rent, token metadata program, token program, system
+    // program
+    let accounts = [
+        AccountMeta::new(market, false),
+        AccountMeta::new(selling_resource, false),
+        AccountMeta::new(account.pubkey(), false),
+        AccountMeta::new(buyer_key, true),
+        AccountMeta::new(trade_history, false),
+        AccountMeta::new(treasury_holder, false),
+        print.accounts[0].clone(),
+        print.accounts[1].clone(),
+        print.accounts[2].clone(),
+        AccountMeta::new(mint_key, false),
+        print.accounts[4].clone(),
+        AccountMeta::new_readonly(vault, false),
+        AccountMeta::new_readonly(vault_owner, false),
+        AccountMeta::new_readonly(master_metadata, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(mpl_token_metadata::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    // sha256("global:buy")[..8] and the trade history and vault owner bumps
+    let mut data = hash(b"global:buy").to_bytes()[..8].to_vec();
+    data.extend([254, 253]);
+    let buy = Instruction {
+        program_id: fixed_price_sale::id(),
+        accounts: accounts.to_vec(),
+        data,
+    };
+
+    let mut instructions = mint_instructions(&buyer_key, &mint_key, &account.pubkey());
+    // the print creates the metadata
+    instructions.pop();
+    instructions.push(buy);
+    let transaction = confirmed_transaction(
+        &instructions,
+        &[(5, vec![
+            system_instruction::transfer(&buyer_key, &treasury_holder, PRICE),
+            print,
+        ])],
+        &[&buyer, &mint, &account],
+        &[(account.pubkey(), mint_key, buyer_key, None, Some(1))],
+    );
+
+    let (bonbon_key, bonbon) = only_bonbon(vec![
+        (SLOT, 0, with_lamports(transaction, &[(treasury_holder, 0, PRICE)])),
+    ]);
+    assert_eq!(bonbon_key, mint_key);
+    assert_eq!(
+        bonbon.limited_edition.as_ref().map(|e| (e.master_key, e.edition_num)),
+        Some((master_metadata, Some(1))),
+    );
+    assert_eq!(
+        sales(&bonbon),
+        vec![(
+            StorefrontSaleKind::FixedPrice, fixed_price_sale::id(), market, None, None, buyer_key,
+            None, Some(PRICE), SLOT,
+        )],
+    );
+    assert_eq!(bonbon.storefront_sales[0].currency_mint, Some(spl_token::native_mint::id()));
+    assert!(bonbon.vault_custody.is_empty());
+}
@@ -0,0 +1,224 @@
+mod common;
+
+use {
+    bonbon::{
+        assemble::TokenStandard,
+        pipeline::{
+            assemble_transactions, default_updaters, semi_fungible_partitioners, Pipeline,
+        },
+        token::SplTokenInstruction,
+    },
+    common::confirmed_transaction,
+    mpl_token_metadata::{instruction::create_metadata_accounts_v2, pda::find_metadata_account},
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction,
+    },
+    spl_token::instruction::TokenInstruction,
+};
+
+// spl-token 3.2.0 has no builders for these so the data is laid out as the program reads it
+
+fn initialize_mint2(
+    mint: &Pubkey,
+    mint_authority: &Pubkey,
+    freeze_authority: &Pubkey,
+) -> Instruction {
+    let mut data = vec![20, 0];
+    data.extend_from_slice(mint_authority.as_ref());
+    data.push(1);
+    data.extend_from_slice(freeze_authority.as_ref());
+    Instruction {
+        program_id: spl_token::id(),
+        accounts: vec![AccountMeta::new(*mint, false)],
+        data,
+    }
+}
+
+fn initialize_account3(account: &Pubkey, mint: &Pubkey, owner: &Pubkey) -> Instruction {
+    let mut data = vec![18];
+    data.extend_from_slice(owner.as_ref());
+    Instruction {
+        program_id: spl_token::id(),
+        accounts: vec![AccountMeta::new(*account, false), AccountMeta::new_readonly(*mint, false)],
+        data,
+    }
+}
+
+fn initialize_immutable_owner(account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: spl_token::id(),
+        accounts: vec![AccountMeta::new(*account, false)],
+        data: vec![22],
+    }
+}
+
+#[test]
+fn initialize_mint2_reads_as_initialize_mint() {
+    let (mint, authority) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let legacy = spl_token::instruction::initialize_mint(
+        &spl_token::id(), &mint, &authority, Some(&authority), 0).unwrap();
+
+    let data = initialize_mint2(&mint, &authority, &authority).data;
+    let instruction = SplTokenInstruction::unpack(&data).unwrap();
+    assert_eq!(instruction.name(), "InitializeMint2");
+    assert_eq!(instruction.into_legacy(), Some(TokenInstruction::unpack(&legacy.data).unwrap()));
+
+    // no freeze authority
+    let mut data = data;
+    data.truncate(35);
+    data[34] = 0;
+    assert_eq!(
+        SplTokenInstruction::unpack(&data).unwrap().into_legacy(),
+        Some(TokenInstruction::InitializeMint {
+            decimals: 0,
+            mint_authority: authority,
+            freeze_authority: None.into(),
+        }),
+    );
+}
+
+#[test]
+fn initialize_account3_reads_as_initialize_account2() {
+    let (account, mint, owner) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let legacy = spl_token::instruction::initialize_account2(
+        &spl_token::id(), &account, &mint, &owner).unwrap();
+
+    let data = initialize_account3(&account, &mint, &owner).data;
+    let instruction = SplTokenInstruction::unpack(&data).unwrap();
+    assert_eq!(instruction.name(), "InitializeAccount3");
+    assert_eq!(instruction.into_legacy(), Some(TokenInstruction::unpack(&legacy.data).unwrap()));
+}
+
+#[test]
+fn newer_instructions_are_named_and_skipped() {
+    for (data, name) in [
+        (vec![19, 1], "InitializeMultisig2"),
+        (vec![21], "GetAccountDataSize"),
+        (vec![22], "InitializeImmutableOwner"),
+        (vec![23, 1, 0, 0, 0, 0, 0, 0, 0], "AmountToUiAmount"),
+        (b"\x181".to_vec(), "UiAmountToAmount"),
+    ] {
+        let instruction = SplTokenInstruction::unpack(&data).unwrap();
+        assert_eq!(instruction.name(), name);
+        assert_eq!(instruction.into_legacy(), None);
+    }
+
+    assert!(SplTokenInstruction::unpack(&[25]).is_err());
+    assert!(SplTokenInstruction::unpack(&[]).is_err());
+    // short owner
+    assert!(SplTokenInstruction::unpack(&[18, 1, 2, 3]).is_err());
+}
+
+// a mint set up the way newer SDKs do it, without the rent sysvar
+#[test]
+fn assemble_mint_initialized_with_initialize_mint2() {
+    let creator = Keypair::new();
+    let mint = Keypair::new();
+    let account = Keypair::new();
+    let mint_key = mint.pubkey();
+
+    let transaction = confirmed_transaction(
+        &[
+            system_instruction::create_account(
+                &creator.pubkey(), &mint_key, 1_000_000, 82, &spl_token::id()),
+            initialize_mint2(&mint_key, &creator.pubkey(), &creator.pubkey()),
+            system_instruction::create_account(
+                &creator.pubkey(), &account.pubkey(), 1_000_000, 165, &spl_token::id()),
+            initialize_immutable_owner(&account.pubkey()),
+            initialize_account3(&account.pubkey(), &mint_key, &creator.pubkey()),
+            spl_token::instruction::mint_to(
+                &spl_token::id(), &mint_key, &account.pubkey(), &creator.pubkey(), &[], 1,
+            ).unwrap(),
+            create_metadata_accounts_v2(
+                mpl_token_metadata::id(), find_metadata_account(&mint_key).0, mint_key,
+                creator.pubkey(), creator.pubkey(), creator.pubkey(), "Bonbon #2".to_string(),
+                "BON".to_string(), "https://example.com/2.json".to_string(), None, 500, true,
+                true, None, None,
+            ),
+        ],
+        &[],
+        &[&creator, &mint, &account],
+        &[(account.pubkey(), mint_key, creator.pubkey(), None, Some(1))],
+    );
+
+    let (bonbons, partition_errors) = assemble_transactions(vec![(200_000_000, 0, transaction)]);
+    assert!(partition_errors.is_empty(), "{:?}", partition_errors);
+
+    let bonbons = bonbons.collect::<Vec<_>>();
+    assert_eq!(bonbons.len(), 1);
+    let bonbon = bonbons[0].1.as_ref().expect("bonbon assembles");
+    assert_eq!(bonbon.mint_key, mint_key);
+    assert_eq!(bonbon.decimals, Some(0));
+    assert_eq!(bonbon.mint_authority(), Some(creator.pubkey()));
+    assert_eq!(bonbon.freeze_authority(), Some(creator.pubkey()));
+    assert_eq!(bonbon.current_account, Some(account.pubkey()));
+    assert_eq!(bonbon.supply, 1);
+}
+
+// Without an edition only the supply tells an NFT (whose edition we may have missed) from an SFT
+#[test]
+fn token_standard_follows_peak_supply() {
+    let creator = Keypair::new();
+    let mint = Keypair::new();
+    let account = Keypair::new();
+    let (mint_key, account_key, creator_key) = (mint.pubkey(), account.pubkey(), creator.pubkey());
+
+    let mint_transaction = || confirmed_transaction(
+        &[
+            system_instruction::create_account(
+                &creator_key, &mint_key, 1_000_000, 82, &spl_token::id()),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(), &mint_key, &creator_key, None, 0).unwrap(),
+            system_instruction::create_account(
+                &creator_key, &account_key, 1_000_000, 165, &spl_token::id()),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(), &account_key, &mint_key, &creator_key).unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::id(), &mint_key, &account_key, &creator_key, &[], 1).unwrap(),
+            create_metadata_accounts_v2(
+                mpl_token_metadata::id(), find_metadata_account(&mint_key).0, mint_key,
+                creator_key, creator_key, creator_key, "Bonbon #3".to_string(),
+                "BON".to_string(), "https://example.com/3.json".to_string(), None, 500, true,
+                true, None, None,
+            ),
+        ],
+        &[],
+        &[&creator, &mint, &account],
+        &[(account_key, mint_key, creator_key, None, Some(1))],
+    );
+
+    // a second token minted and one burned again
+    let mint_and_burn_transaction = confirmed_transaction(
+        &[
+            spl_token::instruction::mint_to(
+                &spl_token::id(), &mint_key, &account_key, &creator_key, &[], 1).unwrap(),
+            spl_token::instruction::burn(
+                &spl_token::id(), &account_key, &mint_key, &creator_key, &[], 1).unwrap(),
+        ],
+        &[],
+        &[&creator],
+        &[(account_key, mint_key, creator_key, Some(1), Some(1))],
+    );
+
+    let assemble = |transactions: Vec<_>| {
+        let mut pipeline = Pipeline::new(semi_fungible_partitioners(), default_updaters());
+        for (block_index, transaction) in transactions.into_iter().enumerate() {
+            pipeline.push(300_000_000, block_index as i64, transaction).expect("partitions");
+        }
+        let mut bonbons = pipeline.finish().collect::<Vec<_>>();
+        assert_eq!(bonbons.len(), 1);
+        bonbons.remove(0).1.expect("bonbon assembles")
+    };
+
+    let bonbon = assemble(vec![mint_transaction()]);
+    assert_eq!(bonbon.supply, 1);
+    assert_eq!(bonbon.token_standard(), TokenStandard::NonFungible);
+
+    let bonbon = assemble(vec![mint_transaction(), mint_and_burn_transaction]);
+    assert_eq!(bonbon.supply, 1);
+    assert_eq!(bonbon.peak_supply, 2);
+    assert_eq!(bonbon.token_standard(), TokenStandard::FungibleAsset);
+}
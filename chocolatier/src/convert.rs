@@ -1,6 +1,7 @@
 use {
     bonbon::assemble as bb,
     bonbon::partition as bp,
+    crate::store as cs,
     postgres_types::*,
     solana_sdk::pubkey::Pubkey,
 };
@@ -33,6 +34,244 @@ impl From<bb::EditionStatus> for EditionStatus {
 }
 
 
+#[derive(Debug, ToSql)]
+#[postgres(name = "authority_type")]
+pub enum AuthorityType {
+    #[postgres(name = "update")]
+    Update,
+
+    #[postgres(name = "mint")]
+    Mint,
+
+    #[postgres(name = "freeze")]
+    Freeze,
+}
+
+#[derive(Debug, ToSql, FromSql)]
+#[postgres(name = "failure_phase")]
+pub enum FailurePhase {
+    #[postgres(name = "partition")]
+    Partition,
+
+    #[postgres(name = "assemble")]
+    Assemble,
+}
+
+impl From<cs::FailurePhase> for FailurePhase {
+    fn from(p: cs::FailurePhase) -> Self {
+        match p {
+            cs::FailurePhase::Partition => FailurePhase::Partition,
+            cs::FailurePhase::Assemble => FailurePhase::Assemble,
+        }
+    }
+}
+
+impl From<FailurePhase> for cs::FailurePhase {
+    fn from(p: FailurePhase) -> Self {
+        match p {
+            FailurePhase::Partition => cs::FailurePhase::Partition,
+            FailurePhase::Assemble => cs::FailurePhase::Assemble,
+        }
+    }
+}
+
+
+#[derive(Debug, ToSql)]
+#[postgres(name = "supply_status")]
+pub enum SupplyStatus {
+    #[postgres(name = "unknown")]
+    Unknown,
+
+    #[postgres(name = "fixed")]
+    Fixed,
+
+    #[postgres(name = "mintable")]
+    Mintable,
+}
+
+impl From<bb::SupplyStatus> for SupplyStatus {
+    fn from(s: bb::SupplyStatus) -> Self {
+        match s {
+            bb::SupplyStatus::Unknown => Self::Unknown,
+            bb::SupplyStatus::Fixed => Self::Fixed,
+            bb::SupplyStatus::Mintable => Self::Mintable,
+        }
+    }
+}
+
+
+#[derive(Debug, ToSql)]
+#[postgres(name = "token_standard")]
+pub enum TokenStandard {
+    #[postgres(name = "non_fungible")]
+    NonFungible,
+
+    #[postgres(name = "fungible_asset")]
+    FungibleAsset,
+
+    #[postgres(name = "fungible")]
+    Fungible,
+
+    #[postgres(name = "edition")]
+    Edition,
+
+    #[postgres(name = "programmable_non_fungible")]
+    ProgrammableNonFungible,
+
+    #[postgres(name = "programmable_edition")]
+    ProgrammableEdition,
+}
+
+impl From<bb::TokenStandard> for TokenStandard {
+    fn from(t: bb::TokenStandard) -> Self {
+        match t {
+            bb::TokenStandard::NonFungible => Self::NonFungible,
+            bb::TokenStandard::FungibleAsset => Self::FungibleAsset,
+            bb::TokenStandard::Fungible => Self::Fungible,
+            bb::TokenStandard::Edition => Self::Edition,
+            bb::TokenStandard::ProgrammableNonFungible => Self::ProgrammableNonFungible,
+            bb::TokenStandard::ProgrammableEdition => Self::ProgrammableEdition,
+        }
+    }
+}
+
+
+#[derive(Debug, ToSql)]
+#[postgres(name = "token_state")]
+pub enum TokenState {
+    #[postgres(name = "unlocked")]
+    Unlocked,
+
+    #[postgres(name = "locked")]
+    Locked,
+
+    #[postgres(name = "listed")]
+    Listed,
+}
+
+impl From<bb::TokenState> for TokenState {
+    fn from(s: bb::TokenState) -> Self {
+        match s {
+            bb::TokenState::Unlocked => Self::Unlocked,
+            bb::TokenState::Locked => Self::Locked,
+            bb::TokenState::Listed => Self::Listed,
+        }
+    }
+}
+
+#[derive(Debug, ToSql)]
+#[postgres(name = "token_delegate_role")]
+pub enum TokenDelegateRole {
+    #[postgres(name = "sale")]
+    Sale,
+
+    #[postgres(name = "transfer")]
+    Transfer,
+
+    #[postgres(name = "utility")]
+    Utility,
+
+    #[postgres(name = "staking")]
+    Staking,
+
+    #[postgres(name = "standard")]
+    Standard,
+
+    #[postgres(name = "locked_transfer")]
+    LockedTransfer,
+
+    #[postgres(name = "migration")]
+    Migration,
+}
+
+impl From<bb::TokenDelegateRole> for TokenDelegateRole {
+    fn from(r: bb::TokenDelegateRole) -> Self {
+        match r {
+            bb::TokenDelegateRole::Sale => Self::Sale,
+            bb::TokenDelegateRole::Transfer => Self::Transfer,
+            bb::TokenDelegateRole::Utility => Self::Utility,
+            bb::TokenDelegateRole::Staking => Self::Staking,
+            bb::TokenDelegateRole::Standard => Self::Standard,
+            bb::TokenDelegateRole::LockedTransfer => Self::LockedTransfer,
+            bb::TokenDelegateRole::Migration => Self::Migration,
+        }
+    }
+}
+
+#[derive(Debug, ToSql)]
+#[postgres(name = "candy_machine_version")]
+pub enum CandyMachineVersion {
+    #[postgres(name = "v1")]
+    V1,
+
+    #[postgres(name = "v2")]
+    V2,
+
+    #[postgres(name = "guard")]
+    Guard,
+}
+
+impl From<bb::CandyMachineVersion> for CandyMachineVersion {
+    fn from(v: bb::CandyMachineVersion) -> Self {
+        match v {
+            bb::CandyMachineVersion::V1 => Self::V1,
+            bb::CandyMachineVersion::V2 => Self::V2,
+            bb::CandyMachineVersion::Guard => Self::Guard,
+        }
+    }
+}
+
+#[derive(Debug, ToSql)]
+#[postgres(name = "order_side")]
+pub enum OrderSide {
+    #[postgres(name = "listing")]
+    Listing,
+
+    #[postgres(name = "bid")]
+    Bid,
+}
+
+impl From<bb::OrderSide> for OrderSide {
+    fn from(s: bb::OrderSide) -> Self {
+        match s {
+            bb::OrderSide::Listing => Self::Listing,
+            bb::OrderSide::Bid => Self::Bid,
+        }
+    }
+}
+
+#[derive(Debug, ToSql)]
+#[postgres(name = "storefront_sale_kind")]
+pub enum StorefrontSaleKind {
+    #[postgres(name = "auction")]
+    Auction,
+
+    #[postgres(name = "full_rights_transfer")]
+    FullRightsTransfer,
+
+    #[postgres(name = "auction_print")]
+    AuctionPrint,
+
+    #[postgres(name = "participation")]
+    Participation,
+
+    #[postgres(name = "fixed_price")]
+    FixedPrice,
+}
+
+impl From<bb::StorefrontSaleKind> for StorefrontSaleKind {
+    fn from(k: bb::StorefrontSaleKind) -> Self {
+        match k {
+            bb::StorefrontSaleKind::Auction => Self::Auction,
+            bb::StorefrontSaleKind::FullRightsTransfer => Self::FullRightsTransfer,
+            bb::StorefrontSaleKind::AuctionPrint => Self::AuctionPrint,
+            bb::StorefrontSaleKind::Participation => Self::Participation,
+            bb::StorefrontSaleKind::FixedPrice => Self::FixedPrice,
+        }
+    }
+}
+
+
 #[derive(Debug)]
 pub struct SqlPubkey(pub Pubkey);
 
@@ -73,8 +312,8 @@ pub struct LimitedEdition {
     edition_num: Option<i64>,
 }
 
-impl From<bb::LimitedEdition> for LimitedEdition {
-    fn from(e: bb::LimitedEdition) -> Self {
+impl From<&bb::LimitedEdition> for LimitedEdition {
+    fn from(e: &bb::LimitedEdition) -> Self {
         Self {
             master_key: SqlPubkey(e.master_key),
             edition_num: e.edition_num,
@@ -91,14 +330,20 @@ pub struct TransactionTokenMeta {
     pub mint_key: SqlPubkey,
 
     pub owner_key: SqlPubkey,
+
+    pub pre_amount: Option<i64>,
+
+    pub post_amount: Option<i64>,
 }
 
-impl From<bp::TransactionTokenMeta> for TransactionTokenMeta {
-    fn from(m: bp::TransactionTokenMeta) -> Self {
+impl From<&bp::TransactionTokenMeta> for TransactionTokenMeta {
+    fn from(m: &bp::TransactionTokenMeta) -> Self {
         Self {
             account_index: m.account_index.into(),
             mint_key: SqlPubkey(m.mint_key),
             owner_key: SqlPubkey(m.owner_key),
+            pre_amount: m.pre_amount.as_ref().and_then(|a| a.parse().ok()),
+            post_amount: m.post_amount.as_ref().and_then(|a| a.parse().ok()),
         }
     }
 }
@@ -124,3 +369,45 @@ impl From<&bb::Creator> for Creator {
     }
 }
 
+#[derive(Debug, ToSql)]
+#[postgres(name = "use_method")]
+pub enum UseMethod {
+    #[postgres(name = "burn")]
+    Burn,
+
+    #[postgres(name = "multiple")]
+    Multiple,
+
+    #[postgres(name = "single")]
+    Single,
+}
+
+impl From<&bb::UseMethod> for UseMethod {
+    fn from(u: &bb::UseMethod) -> Self {
+        match u {
+            bb::UseMethod::Burn => Self::Burn,
+            bb::UseMethod::Multiple => Self::Multiple,
+            bb::UseMethod::Single => Self::Single,
+        }
+    }
+}
+
+#[derive(Debug, ToSql)]
+#[postgres(name = "uses")]
+pub struct Uses {
+    pub use_method: UseMethod,
+
+    pub remaining: i64,
+
+    pub total: i64,
+}
+
+impl From<&bb::Uses> for Uses {
+    fn from(u: &bb::Uses) -> Self {
+        Self {
+            use_method: UseMethod::from(&u.use_method),
+            remaining: u.remaining,
+            total: u.total,
+        }
+    }
+}
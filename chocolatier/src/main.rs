@@ -1,25 +1,49 @@
 use {
     log::*,
-    postgres::fallible_iterator::FallibleIterator,
     prost::Message,
     solana_sdk::{
         clock::Slot,
-        instruction::CompiledInstruction,
         pubkey::Pubkey,
     },
     solana_storage_proto::convert::generated,
     solana_transaction_status::TransactionWithStatusMeta,
+    store::{FailurePhase, FailureRow, Store},
 };
 
 pub mod convert;
+pub mod store;
+
+// rows pulled from the store per query when walking transactions / partition keys
+const PAGE_SIZE: i64 = 1024;
+
+// recorded with failures so we know which ones a fix could have touched
+const CODE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[derive(Debug)]
 pub struct Config {
-    psql_config: String,
+    psql_config: Option<String>,
+    sqlite_path: Option<String>,
     log_file: String,
 }
 
-async fn fetch(
+fn open_store(config: &Config) -> Result<Box<dyn Store>, Box<dyn std::error::Error>> {
+    match (&config.psql_config, &config.sqlite_path) {
+        (Some(psql_config), None) => {
+            Ok(Box::new(store::psql::PsqlStore::connect(psql_config)?))
+        }
+        (None, Some(sqlite_path)) => {
+            Ok(Box::new(store::sqlite::SqliteStore::open(sqlite_path)?))
+        }
+        (Some(_), Some(_)) => {
+            Err("Only one of --psql_config and --sqlite_path may be given".into())
+        }
+        (None, None) => {
+            Err("Missing --psql_config or --sqlite_path".into())
+        }
+    }
+}
+
+fn fetch(
     config: &Config,
     bigtable_path: String,
     block_range: String,
@@ -37,21 +61,15 @@ async fn fetch(
         }
     })().ok_or("Invalid --block_range")?;
 
-    let (psql_client, psql_connection) = tokio_postgres::connect(
-        config.psql_config.as_str(), tokio_postgres::NoTls).await?;
+    let mut store = open_store(config)?;
 
-    let psql_join_handle = tokio::spawn(async move {
-        if let Err(e) = psql_connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-
-    let insert_transaction_statement = psql_client.prepare(
-        "INSERT INTO transactions VALUES ($1, $2, $3, $4)"
-    ).await?;
+    // only the bigtable requests are async. the store is driven synchronously in between
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
 
-    let bt = solana_storage_bigtable::LedgerStorage::new(
-        true, None, Some(bigtable_path)).await.unwrap();
+    let bt = runtime.block_on(solana_storage_bigtable::LedgerStorage::new(
+        true, None, Some(bigtable_path))).unwrap();
 
     // TODO: parameterize?
     let chunk_size = 16;
@@ -60,10 +78,11 @@ async fn fetch(
         let chunk_end = std::cmp::min(chunk_start + chunk_size, block_end);
         trace!("fetching slots {}..{}", chunk_start, chunk_end);
 
-        let chunk_slots = bt.get_confirmed_blocks(
-            chunk_start, (chunk_end - chunk_start) as usize).await?;
+        let chunk_slots = runtime.block_on(bt.get_confirmed_blocks(
+            chunk_start, (chunk_end - chunk_start) as usize))?;
 
-        for (slot, block) in bt.get_confirmed_blocks_with_data(&chunk_slots).await? {
+        let blocks = runtime.block_on(bt.get_confirmed_blocks_with_data(&chunk_slots))?;
+        for (slot, block) in blocks {
             let slot = slot as i64;
             for (index, transaction) in block.transactions.into_iter().enumerate() {
                 // skip errors
@@ -73,7 +92,9 @@ async fn fetch(
                 let index = index as i64;
                 let mut found_token_or_metadata = false;
                 for account_key in transaction.account_keys().iter() {
-                    if *account_key == spl_token::id() || *account_key == mpl_token_metadata::id() {
+                    if *account_key == spl_token::id()
+                            || *account_key == mpl_token_metadata::id()
+                            || *account_key == bonbon::programs::bubblegum::id() {
                         found_token_or_metadata = true;
                         break;
                     }
@@ -87,130 +108,155 @@ async fn fetch(
                 protobuf_tx.encode(&mut buf).unwrap();
                 // TODO: compress?
 
-                psql_client.query(
-                    &insert_transaction_statement,
-                    &[
-                        &slot,
-                        &index,
-                        &signature.as_ref(),
-                        &buf,
-                    ],
-                ).await?;
+                store.insert_transaction(slot, index, signature.as_ref(), &buf)?;
             }
         }
 
         chunk_start = chunk_end;
     }
 
-    info!("finished block fetch. waiting for db join...");
-
-    drop(psql_client);
-    psql_join_handle.await?;
+    info!("finished block fetch");
 
     Ok(())
 }
 
-fn partition(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+fn log_check(value: Option<&str>) -> bonbon::partition::LogCheck {
+    use bonbon::partition::LogCheck;
+    match value {
+        Some("cross_check") => LogCheck::CrossCheck,
+        Some("fallback") => LogCheck::Fallback,
+        _ => LogCheck::Off,
+    }
+}
+
+fn partitioners(semi_fungible: bool) -> Vec<bonbon::partition::InstructionPartitioner> {
+    if semi_fungible {
+        bonbon::pipeline::semi_fungible_partitioners()
+    } else {
+        bonbon::pipeline::default_partitioners()
+    }
+}
+
+// Partitions one stored transaction and writes out its account keys and partitions. The outer
+// result is for store errors, the inner one for transactions we couldn't partition. Returns the
+// mints that had token instructions partitioned and the compressed assets that had bubblegum ones
+fn partition_row(
+    store: &mut dyn Store,
+    partitioners: &[bonbon::partition::InstructionPartitioner],
+    layouts: &bonbon::layouts::LayoutRegistry,
+    log_check: bonbon::partition::LogCheck,
+    row: &store::TransactionRow,
+) -> Result<Result<Vec<Pubkey>, FailureRow>, Box<dyn std::error::Error>> {
     use bonbon::partition::*;
-    let partitioners = [
-        InstructionPartitioner {
-            partitioner: partition_token_instruction,
-            program_id: spl_token::id(),
-        },
-        InstructionPartitioner {
-            partitioner: partition_metadata_instruction,
-            program_id: mpl_token_metadata::id(),
-        },
-    ];
+    let store::TransactionRow { slot, block_index, signature, transaction } = row;
 
-    let mut psql_client = postgres::Client::connect(
-        config.psql_config.as_str(), postgres::NoTls)?;
+    let transaction = generated::ConfirmedTransaction::decode(&transaction[..])?;
+    let transaction = TransactionWithStatusMeta::try_from(transaction)?;
 
-    let select_all_statement = psql_client.prepare(
-        "SELECT *
-         FROM transactions
-         ORDER BY (slot, block_index)
-        ",
-    )?;
+    // skip errors
+    if transaction.get_status_meta().map(|m| m.status.is_err()) == Some(true) {
+        return Ok(Ok(vec![]));
+    }
 
-    let mut insert_client = postgres::Client::connect(
-        config.psql_config.as_str(), postgres::NoTls)?;
+    let account_keys = transaction.account_keys()
+        .iter().cloned().collect::<Vec<_>>();
+    let (pre_balances, post_balances) = transaction.get_status_meta()
+        .map(|m| (m.pre_balances, m.post_balances))
+        .unwrap_or_default();
 
-    let insert_transaction_statement = insert_client.prepare(
-        "INSERT INTO partitions VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
-    )?;
+    let PartitionedTransaction {
+        instructions: partitioned, token_metas, token_accounts, diagnostics,
+    } = match partition_transaction(
+            transaction, *slot, partitioners, layouts, log_check) {
+        Ok(v) => v,
+        Err(err) => {
+            warn!("failed to partition {}.{:04x}: {}", slot, block_index, err);
+            return Ok(Err(FailureRow {
+                phase: FailurePhase::Partition,
+                key: signature.clone(),
+                slot: Some(*slot),
+                block_index: Some(*block_index),
+                error_kind: format!("{:?}", err.code),
+                error_details: err.to_string(),
+                code_version: CODE_VERSION.to_string(),
+            }));
+        }
+    };
 
-    let insert_account_keys_statement = insert_client.prepare(
-        "INSERT INTO account_keys VALUES ($1, $2, $3)"
-    )?;
+    for diagnostic in diagnostics {
+        debug!("partitioned {}.{:04x} with {}", slot, block_index, diagnostic);
+    }
 
-    let params: &[&str] = &[];
-    let query_start = std::time::Instant::now();
-    let mut it = psql_client.query_raw(
-        &select_all_statement,
-        params,
-    )?;
-    log::info!("initial query took {:?}", query_start.elapsed());
+    // transactions are partitioned in order, so a mint's history is in the store or this
+    // transaction by now
+    let with_history = partitioned.iter()
+        .filter(|p| !p.needs_history)
+        .map(|p| p.partition_key)
+        .collect::<Vec<_>>();
+    let mut kept = vec![];
+    for partitioned_instruction in partitioned {
+        if !partitioned_instruction.needs_history
+                || with_history.contains(&partitioned_instruction.partition_key)
+                || store.has_partitions(&partitioned_instruction.partition_key)? {
+            kept.push(partitioned_instruction);
+        }
+    }
 
-    let loop_start = std::time::Instant::now();
-    while let Some(row) = it.next()? {
-        let slot: i64 = row.get(0);
-        let block_index: i64 = row.get(1);
-        let signature: Vec<u8> = row.get(2);
-        let transaction: Vec<u8> = row.get(3);
-
-        let transaction = generated::ConfirmedTransaction::decode(&transaction[..])?;
-        let transaction = TransactionWithStatusMeta::try_from(transaction)?;
-
-        // skip errors
-        if transaction.get_status_meta().map(|m| m.status.is_err()) == Some(true) {
-            continue;
+    if kept.len() != 0 {
+        store.insert_account_keys(
+            signature, &account_keys, &token_metas, &pre_balances, &post_balances)?;
+    }
+    let mut mint_keys = vec![];
+    for partitioned_instruction in kept {
+        if partitioned_instruction.program_key == spl_token::id()
+                || partitioned_instruction.program_key == bonbon::programs::bubblegum::id() {
+            mint_keys.push(partitioned_instruction.partition_key);
         }
+        // TODO: soft error?
+        store.insert_partition(*slot, *block_index, signature, &partitioned_instruction)?;
+    }
 
-        let account_keys = transaction.account_keys()
-            .iter().map(|k| k.as_ref().to_vec()).collect::<Vec<_>>();
-
-        match partition_transaction(transaction, &partitioners) {
-            Ok((partitioned, token_metas)) => {
-                if partitioned.len() != 0 {
-                    insert_client.query(
-                        &insert_account_keys_statement,
-                        &[
-                            &signature.as_slice(),
-                            &account_keys,
-                            &token_metas.into_iter()
-                                .map(|m| convert::TransactionTokenMeta::from(m))
-                                .collect::<Vec<_>>(),
-                        ],
-                    )?;
-                }
-                for PartitionedInstruction {
-                    instruction,
-                    partition_key,
-                    program_key,
-                    outer_index,
-                    inner_index,
-                } in partitioned {
-                    // TODO: soft error?
-                    let serialized = bincode::serialize(&instruction)?;
-                    insert_client.query(
-                        &insert_transaction_statement,
-                        &[
-                            &partition_key.as_ref(),
-                            &program_key.as_ref(),
-                            &slot,
-                            &block_index,
-                            &outer_index,
-                            &inner_index,
-                            &signature.as_slice(),
-                            &serialized,
-                        ],
-                    )?;
-                }
-            }
-            Err(err) => {
-                warn!("failed to partition {}.{:04x} [{}]: {:?}",
-                      slot, block_index, bs58::encode(signature).into_string(), err);
+    // only for mints the partitioners kept (in this transaction or before), and owner changes
+    // of their accounts. fungible mints' accounts are only ever needed for their own balances
+    for change in token_accounts {
+        let kept = match &change.mint_key {
+            Some(mint_key) => store.has_partitions(mint_key)?,
+            None => store.has_token_account(&change.account_key)?,
+        };
+        if kept {
+            store.insert_token_account_change(*slot, *block_index, &change)?;
+        }
+    }
+
+    Ok(Ok(mint_keys))
+}
+
+fn partition(
+    config: &Config,
+    semi_fungible: bool,
+    log_check: bonbon::partition::LogCheck,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = open_store(config)?;
+    partition_transactions(store.as_mut(), &partitioners(semi_fungible), log_check)
+}
+
+fn partition_transactions(
+    store: &mut dyn Store,
+    partitioners: &[bonbon::partition::InstructionPartitioner],
+    log_check: bonbon::partition::LogCheck,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let layouts = bonbon::layouts::default_layouts();
+
+    let loop_start = std::time::Instant::now();
+    let mut after = None;
+    loop {
+        let rows = store.transactions_after(after, PAGE_SIZE)?;
+        if rows.is_empty() { break; }
+        after = rows.last().map(|r| (r.slot, r.block_index));
+
+        for row in rows {
+            if let Err(failure) = partition_row(store, partitioners, &layouts, log_check, &row)? {
+                store.insert_failure(&failure)?;
             }
         }
     }
@@ -219,165 +265,301 @@ fn partition(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn reassemble(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+// Applies the partitioned instructions in order. Failures name the mint since that's what
+// gets retried. When lenient, failing instructions are flagged on the bonbon instead
+fn assemble_rows(
+    mint_key: &Pubkey,
+    rows: Vec<store::PartitionRow>,
+    token_accounts: &bonbon::registry::TokenAccountRegistry,
+    layouts: &bonbon::layouts::LayoutRegistry,
+    updaters: &[bonbon::assemble::BonbonUpdater],
+    lenient: bool,
+) -> Result<bonbon::assemble::Bonbon, FailureRow> {
     use bonbon::assemble::*;
-    let mut psql_client = postgres::Client::connect(
-        config.psql_config.as_str(), postgres::NoTls)?;
-
-    let mut partition_client = postgres::Client::connect(
-        config.psql_config.as_str(), postgres::NoTls)?;
-
-    let select_all_token_mints_statement = partition_client.prepare(
-        "SELECT DISTINCT partition_key
-         FROM partitions
-         WHERE program_key = decode($1, 'base64')
-        ",
-    )?;
-
-    let select_partition_key = psql_client.prepare(
-        "SELECT p.signature, p.instruction, a.keys, a.metas,
-                p.slot, p.block_index, p.outer_index, p.inner_index
-         FROM partitions p JOIN account_keys a ON p.signature = a.signature
-         WHERE partition_key = decode($1, 'base64')
-            OR partition_key = decode($2, 'base64')
-         ORDER BY (slot, block_index, outer_index, inner_index)
-        ",
-    )?;
-
-    let insert_bonbon_statement = psql_client.prepare(
-        "INSERT INTO bonbons VALUES ($1, $2, $3, $4, $5, $6)"
-    )?;
-
-    let insert_glazing_statement = psql_client.prepare(
-        "INSERT INTO glazings VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"
-    )?;
-
-    let spl_token_id_encoded = base64::encode(spl_token::id());
-    let params: &[&str] = &[&spl_token_id_encoded];
-    let query_start = std::time::Instant::now();
-    let mut it = partition_client.query_raw(
-        &select_all_token_mints_statement,
-        params,
-    )?;
-    log::info!("initial query took {:?}", query_start.elapsed());
-
-    let updaters = [
-        BonbonUpdater {
-            update: update_token_instruction,
-            program_id: spl_token::id(),
-        },
-        BonbonUpdater {
-            update: update_metadata_instruction,
-            program_id: mpl_token_metadata::id(),
-        },
-    ];
+    let mut bonbon = Bonbon::default();
+    for row in rows {
+        let instruction_context = InstructionContext {
+            signature: &row.signature,
+            account_keys: &row.keys,
+            instruction: &row.instruction,
+            owners: &row.metas,
+            pre_balances: &row.pre_balances,
+            post_balances: &row.post_balances,
+            token_accounts,
+            layouts,
+            instruction_index: row.instruction_index,
+        };
+
+        if lenient {
+            bonbon.update_lenient(instruction_context, updaters);
+        } else if let Err(err) = bonbon.update(instruction_context, updaters) {
+            warn!("failed to make bonbon {}: {}", mint_key, err);
+            return Err(FailureRow {
+                phase: FailurePhase::Assemble,
+                key: mint_key.as_ref().to_vec(),
+                slot: None,
+                block_index: None,
+                error_kind: format!("{:?}", err.code),
+                error_details: err.to_string(),
+                code_version: CODE_VERSION.to_string(),
+            });
+        }
+    }
+
+    Ok(bonbon)
+}
+
+fn token_account_registry(
+    store: &mut dyn Store,
+    mint_key: &Pubkey,
+) -> Result<bonbon::registry::TokenAccountRegistry, Box<dyn std::error::Error>> {
+    let mut registry = bonbon::registry::TokenAccountRegistry::default();
+    for store::TokenAccountRow { slot, block_index, change } in
+            store.token_account_changes(mint_key)? {
+        let instruction_index = bonbon::assemble::InstructionIndex {
+            slot,
+            block_index,
+            outer_index: change.outer_index,
+            inner_index: change.inner_index,
+        };
+        registry.insert(instruction_index, change);
+    }
+    Ok(registry)
+}
+
+fn reassemble(
+    config: &Config,
+    lenient: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = open_store(config)?;
+    reassemble_bonbons(store.as_mut(), lenient)
+}
+
+fn reassemble_bonbons(
+    store: &mut dyn Store,
+    lenient: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let updaters = bonbon::pipeline::default_updaters();
+    let layouts = bonbon::layouts::default_layouts();
 
     let loop_start = std::time::Instant::now();
     let mut partition_queries = std::time::Duration::ZERO;
     let mut update_queries = std::time::Duration::ZERO;
-    let mut deserialization_duration = std::time::Duration::ZERO;
-    while let Some(row) = it.next()? {
-        let mint_key = Pubkey::new(row.get(0));
-        let metadata_key = mpl_token_metadata::pda::find_metadata_account(&mint_key).0;
+    // mints, then compressed NFTs by asset id
+    for program_key in [spl_token::id(), bonbon::programs::bubblegum::id()] {
+        let mut after = None;
+        loop {
+            let mint_keys = store.partition_keys_after(&program_key, after, PAGE_SIZE)?;
+            if mint_keys.is_empty() { break; }
+            after = mint_keys.last().cloned();
 
-        let mint_key_encoded = base64::encode(&mint_key);
-        let metadata_key_encoded = base64::encode(&metadata_key);
-        let query_start = std::time::Instant::now();
-        let instructions = psql_client.query(
-            &select_partition_key,
-            &[&mint_key_encoded, &metadata_key_encoded],
-        )?;
-        partition_queries += query_start.elapsed();
-
-        let mut bonbon = Bonbon::default();
-        let mut update_err = None;
-        for row in instructions {
-            let deserialization_start = std::time::Instant::now();
-            let instruction = bincode::deserialize
-                ::<CompiledInstruction>(&row.get::<_, Vec<u8>>(1))?;
-
-            let keys: Vec<convert::SqlPubkey> = row.get(2);
-            let keys = keys.into_iter().map(|k| k.0).collect::<Vec<_>>();
-
-            let metas: Vec<convert::TransactionTokenMeta> = row.get(3);
-            let metas = metas.into_iter().map(|m| TransactionTokenOwnerMeta {
-                account_index: m.account_index as u8, // TODO: check?
-                owner_key: m.owner_key.0,
-            }).collect::<Vec<_>>();
-            deserialization_duration += deserialization_start.elapsed();
-
-            let slot: i64 = row.get(4);
-            let block_index: i64 = row.get(5);
-            let outer_index: i64 = row.get(6);
-            let inner_index: Option<i64> = row.get(7);
-
-            let instruction_context = InstructionContext {
-                account_keys: &keys,
-                instruction: &instruction,
-                owners: &metas,
-                instruction_index: InstructionIndex { slot, block_index, outer_index, inner_index },
-            };
+            for mint_key in mint_keys {
+                let metadata_key = mpl_token_metadata::pda::find_metadata_account(&mint_key).0;
+
+                let query_start = std::time::Instant::now();
+                let instructions = store.partition_instructions(&mint_key, &metadata_key)?;
+                let token_accounts = token_account_registry(store, &mint_key)?;
+                partition_queries += query_start.elapsed();
 
-            match bonbon.update(instruction_context, &updaters) {
-                Ok(_) => {}
-                Err(err) => {
-                    update_err = Some(err);
-                    break;
+                let bonbon = match assemble_rows(
+                        &mint_key, instructions, &token_accounts, &layouts, &updaters, lenient) {
+                    Ok(bonbon) => bonbon,
+                    Err(failure) => {
+                        store.insert_failure(&failure)?;
+                        continue;
+                    }
+                };
+
+                if bonbon.metadata_key == Pubkey::default() {
+                    continue;
                 }
+
+                // decompressed assets have a mint and were assembled with the rest
+                if program_key != spl_token::id() && bonbon.compressed_leaf.is_none() {
+                    continue;
+                }
+
+                // TODO: more verification on partition_keys?
+                let query_start = std::time::Instant::now();
+                // replaces whatever an earlier run assembled
+                store.delete_bonbon(&bonbon.metadata_key)?;
+                store.insert_bonbon(&bonbon)?;
+                update_queries += query_start.elapsed();
             }
         }
+    }
+    log::info!("reassembled in {:?}", loop_start.elapsed());
+    log::info!("partition queries took {:?}", partition_queries);
+    log::info!("update queries took {:?}", update_queries);
 
-        if let Some(err) = update_err {
-            warn!("failed to make bonbon {}: {:?}",
-                  mint_key, err);
-            continue;
-        }
+    Ok(())
+}
+
+// Reprocesses everything in the failures table with the current code. Transactions are
+// partitioned again first and the mints they touch are reassembled along with the mints that
+// failed to assemble. Anything that goes through is removed from the table
+fn retry_failures(
+    config: &Config,
+    semi_fungible: bool,
+    log_check: bonbon::partition::LogCheck,
+    lenient: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = open_store(config)?;
+    retry_failed(store.as_mut(), &partitioners(semi_fungible), log_check, lenient)
+}
+
+fn retry_failed(
+    store: &mut dyn Store,
+    partitioners: &[bonbon::partition::InstructionPartitioner],
+    log_check: bonbon::partition::LogCheck,
+    lenient: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let updaters = bonbon::pipeline::default_updaters();
+    let layouts = bonbon::layouts::default_layouts();
+
+    let mut mint_keys = std::collections::BTreeSet::new();
+    let mut after = None;
+    loop {
+        let failures = store.failures_after(FailurePhase::Partition, after, PAGE_SIZE)?;
+        if failures.is_empty() { break; }
+        after = failures.last().map(|f| f.key.clone());
 
-        if bonbon.metadata_key == Pubkey::default() {
-            continue;
+        for failure in failures {
+            let (slot, block_index) = match (failure.slot, failure.block_index) {
+                (Some(slot), Some(block_index)) => (slot, block_index),
+                _ => continue,
+            };
+            let row = match store.transaction_at(slot, block_index)? {
+                Some(row) => row,
+                None => {
+                    warn!("missing transaction {}.{:04x} for failure", slot, block_index);
+                    continue;
+                }
+            };
+            match partition_row(store, partitioners, &layouts, log_check, &row)? {
+                Ok(partitioned_mint_keys) => {
+                    mint_keys.extend(partitioned_mint_keys);
+                    store.delete_failure(FailurePhase::Partition, &failure.key)?;
+                }
+                Err(failure) => {
+                    store.insert_failure(&failure)?;
+                }
+            }
         }
+    }
 
-        // TODO: more verification on partition_keys?
-        let query_start = std::time::Instant::now();
-        psql_client.query(
-            &insert_bonbon_statement,
-            &[
-                &bonbon.metadata_key.as_ref(),
-                &bonbon.mint_key.as_ref(),
-                &bonbon.current_owner.map(|k| convert::SqlPubkey(k)),
-                &bonbon.current_account.map(|k| convert::SqlPubkey(k)),
-                &convert::EditionStatus::from(bonbon.edition_status),
-                &bonbon.limited_edition.map(convert::LimitedEdition::from),
-            ],
-        )?;
+    let mut after = None;
+    loop {
+        let failures = store.failures_after(FailurePhase::Assemble, after, PAGE_SIZE)?;
+        if failures.is_empty() { break; }
+        after = failures.last().map(|f| f.key.clone());
 
-        for glazing in bonbon.glazings {
-            psql_client.query(
-                &insert_glazing_statement,
-                &[
-                    &bonbon.metadata_key.as_ref(),
-                    &glazing.uri,
-                    &glazing.collection.as_ref().map(|c| convert::SqlPubkey(c.address)),
-                    &glazing.collection.as_ref().map(|c| c.verified),
-                    &glazing.creators.get(0).map(convert::Creator::from),
-                    &glazing.creators.get(1).map(convert::Creator::from),
-                    &glazing.creators.get(2).map(convert::Creator::from),
-                    &glazing.creators.get(3).map(convert::Creator::from),
-                    &glazing.creators.get(4).map(convert::Creator::from),
-                    &glazing.instruction_index.slot,
-                    &glazing.instruction_index.block_index,
-                    &glazing.instruction_index.outer_index,
-                    &glazing.instruction_index.inner_index,
-                ],
-            )?;
+        for failure in failures {
+            let fixed: [u8; 32] = failure.key.as_slice().try_into()?;
+            mint_keys.insert(Pubkey::new_from_array(fixed));
         }
+    }
 
-        update_queries += query_start.elapsed();
+    for mint_key in mint_keys {
+        let metadata_key = mpl_token_metadata::pda::find_metadata_account(&mint_key).0;
+        let instructions = store.partition_instructions(&mint_key, &metadata_key)?;
+        let token_accounts = token_account_registry(store, &mint_key)?;
+        match assemble_rows(
+                &mint_key, instructions, &token_accounts, &layouts, &updaters, lenient) {
+            Ok(bonbon) => {
+                store.delete_failure(FailurePhase::Assemble, mint_key.as_ref())?;
+                if bonbon.metadata_key == Pubkey::default() {
+                    continue;
+                }
+                // may have been assembled before the partition retry found more instructions
+                store.delete_bonbon(&bonbon.metadata_key)?;
+                store.insert_bonbon(&bonbon)?;
+            }
+            Err(failure) => {
+                store.insert_failure(&failure)?;
+            }
+        }
     }
-    log::info!("reassembled in {:?}", loop_start.elapsed());
-    log::info!("partition queries took {:?}", partition_queries);
-    log::info!("update queries took {:?}", update_queries);
-    log::info!("deserialization marshalling took {:?}", deserialization_duration);
+    info!("finished retrying failures");
+
+    Ok(())
+}
+
+// token metadata instructions `reprocess` can pick up a fix to, by the discriminant their data
+// starts with. the unverifies used to leave the collection verified
+const REPROCESSABLE_INSTRUCTIONS: [(&str, u8); 3] = [
+    ("UnverifyCollection", 22),
+    ("UnverifySizedCollectionItem", 31),
+    ("Unverify", 53),
+];
+
+// Reassembles and replaces just the stored bonbons with a partitioned instruction named
+// `instruction_name`, for picking up a fix to one instruction without a full reassemble
+fn reprocess(
+    config: &Config,
+    instruction_name: &str,
+    lenient: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = open_store(config)?;
+    reprocess_bonbons(store.as_mut(), instruction_name, lenient)
+}
+
+fn reprocess_bonbons(
+    store: &mut dyn Store,
+    instruction_name: &str,
+    lenient: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let discriminant = REPROCESSABLE_INSTRUCTIONS.iter()
+        .find(|(name, _)| *name == instruction_name)
+        .map(|(_, discriminant)| *discriminant)
+        .ok_or_else(|| format!("Can't reprocess {}", instruction_name))?;
+
+    let updaters = bonbon::pipeline::default_updaters();
+    let layouts = bonbon::layouts::default_layouts();
+
+    // the store only matches on the discriminant so check the name the same way as in errors
+    let program_key = mpl_token_metadata::id();
+    let is_affected = |row: &store::PartitionRow| -> bool {
+        row.keys.get(usize::from(row.instruction.program_id_index)) == Some(&program_key)
+            && bonbon::partition::metadata_instruction_name(&row.instruction.data)
+                .map_or(false, |name| name == instruction_name)
+    };
+
+    let mut reprocessed = 0;
+    let mut after = None;
+    loop {
+        let mint_keys = store.bonbon_mints_with_instruction(
+            &program_key, discriminant, after, PAGE_SIZE)?;
+        if mint_keys.is_empty() { break; }
+        after = mint_keys.last().cloned();
+
+        for mint_key in mint_keys {
+            let metadata_key = mpl_token_metadata::pda::find_metadata_account(&mint_key).0;
+            let instructions = store.partition_instructions(&mint_key, &metadata_key)?;
+            if !instructions.iter().any(is_affected) {
+                continue;
+            }
+
+            let token_accounts = token_account_registry(store, &mint_key)?;
+            let bonbon = match assemble_rows(
+                    &mint_key, instructions, &token_accounts, &layouts, &updaters, lenient) {
+                Ok(bonbon) => bonbon,
+                Err(failure) => {
+                    store.insert_failure(&failure)?;
+                    continue;
+                }
+            };
+
+            if bonbon.metadata_key == Pubkey::default() {
+                continue;
+            }
+
+            store.delete_bonbon(&bonbon.metadata_key)?;
+            store.insert_bonbon(&bonbon)?;
+            reprocessed += 1;
+        }
+    }
+    info!("reprocessed {} bonbons with {}", reprocessed, instruction_name);
 
     Ok(())
 }
@@ -405,6 +587,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .global(true)
                 .help("Transaction DB connection configuration")
         )
+        .arg(
+            clap::Arg::new("sqlite_path")
+                .long("sqlite_path")
+                .value_name("PATH")
+                .takes_value(true)
+                .global(true)
+                .help("Use a local sqlite DB instead of postgres")
+        )
         .subcommand(
             clap::Command::new("fetch")
             .about("Fetch transactions into DB")
@@ -428,18 +618,87 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .subcommand(
             clap::Command::new("partition")
             .about("Partition all transactions found in the DB")
+            .arg(
+                clap::Arg::new("semi_fungible")
+                    .long("semi_fungible")
+                    .takes_value(false)
+                    .help("Also partition decimals-0 mints with supply above 1 (SFTs)")
+            )
+            .arg(
+                clap::Arg::new("log_check")
+                    .long("log_check")
+                    .value_name("MODE")
+                    .takes_value(true)
+                    .possible_values(["off", "cross_check", "fallback"])
+                    .default_value("off")
+                    .help("Check decoded instructions against the names programs log, \
+                           optionally skipping undecodable ones the logs name")
+            )
         )
         .subcommand(
             clap::Command::new("reassemble")
             .about("Reassemble all partitioned keys found in the DB")
+            .arg(
+                clap::Arg::new("lenient")
+                    .long("lenient")
+                    .takes_value(false)
+                    .help("Skip instructions that fail to apply and flag them on the bonbon")
+            )
+        )
+        .subcommand(
+            clap::Command::new("retry-failures")
+            .about("Partition and reassemble everything in the failures table again")
+            .arg(
+                clap::Arg::new("semi_fungible")
+                    .long("semi_fungible")
+                    .takes_value(false)
+                    .help("Also partition decimals-0 mints with supply above 1 (SFTs)")
+            )
+            .arg(
+                clap::Arg::new("log_check")
+                    .long("log_check")
+                    .value_name("MODE")
+                    .takes_value(true)
+                    .possible_values(["off", "cross_check", "fallback"])
+                    .default_value("off")
+                    .help("Check decoded instructions against the names programs log, \
+                           optionally skipping undecodable ones the logs name")
+            )
+            .arg(
+                clap::Arg::new("lenient")
+                    .long("lenient")
+                    .takes_value(false)
+                    .help("Skip instructions that fail to apply and flag them on the bonbon")
+            )
+        )
+        .subcommand(
+            clap::Command::new("reprocess")
+            .about("Reassemble and replace the stored bonbons with a given instruction")
+            .arg(
+                clap::Arg::new("instruction")
+                    .long("instruction")
+                    .value_name("NAME")
+                    .takes_value(true)
+                    .required(true)
+                    .possible_values(REPROCESSABLE_INSTRUCTIONS.iter().map(|(name, _)| *name))
+                    .help("Instruction name as decoded")
+            )
+            .arg(
+                clap::Arg::new("lenient")
+                    .long("lenient")
+                    .takes_value(false)
+                    .help("Skip instructions that fail to apply and flag them on the bonbon")
+            )
         )
         .get_matches();
 
     let config = Config {
         psql_config: matches
             .value_of("psql_config")
-            .ok_or("Missing --psql_config")?
-            .to_string(),
+            .map(|v| v.to_string()),
+        sqlite_path: matches
+            .value_of("sqlite_path")
+            .map(|v| v.to_string()),
         log_file: matches
             .value_of("log_file")
             .unwrap()
@@ -473,25 +732,39 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     match matches.subcommand() {
         Some(("fetch", sub_m)) => {
-            tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap()
-                .block_on(async {
-                    fetch(
-                        &config,
-                        sub_m.value_of("bigtable_path")
-                            .ok_or("Missing --bigtable_path")?.to_string(),
-                        sub_m.value_of("block_range")
-                            .ok_or("Missing --block_range")?.to_string(),
-                    ).await
-                })?
+            fetch(
+                &config,
+                sub_m.value_of("bigtable_path")
+                    .ok_or("Missing --bigtable_path")?.to_string(),
+                sub_m.value_of("block_range")
+                    .ok_or("Missing --block_range")?.to_string(),
+            )?;
+        }
+        Some(("partition", sub_m)) => {
+            partition(
+                &config,
+                sub_m.is_present("semi_fungible"),
+                log_check(sub_m.value_of("log_check")),
+            )?;
+        }
+        Some(("reassemble", sub_m)) => {
+            reassemble(&config, sub_m.is_present("lenient"))?;
         }
-        Some(("partition", _)) => {
-            partition(&config)?;
+        Some(("retry-failures", sub_m)) => {
+            retry_failures(
+                &config,
+                sub_m.is_present("semi_fungible"),
+                log_check(sub_m.value_of("log_check")),
+                sub_m.is_present("lenient"),
+            )?;
         }
-        Some(("reassemble", _)) => {
-            reassemble(&config)?;
+        Some(("reprocess", sub_m)) => {
+            reprocess(
+                &config,
+                sub_m.value_of("instruction")
+                    .ok_or("Missing --instruction")?,
+                sub_m.is_present("lenient"),
+            )?;
         }
         o => {
             warn!("No matching subcommand found {:?}", o);
@@ -500,3 +773,690 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        mpl_token_metadata::{
+            instruction::{
+                create_master_edition_v3, create_metadata_accounts_v2, update_metadata_accounts,
+            },
+            pda::{find_master_edition_account, find_metadata_account},
+            state::{Collection, Creator},
+        },
+        solana_account_decoder::parse_token::UiTokenAmount,
+        solana_sdk::{
+            hash::Hash,
+            instruction::{AccountMeta, CompiledInstruction, Instruction},
+            signature::{Keypair, Signer},
+            system_instruction,
+            transaction::{Transaction, VersionedTransaction},
+        },
+        solana_transaction_status::{
+            InnerInstructions, TransactionStatusMeta, TransactionTokenBalance,
+            VersionedTransactionWithStatusMeta,
+        },
+        spl_token::instruction::AuthorityType,
+        store::sqlite::SqliteStore,
+    };
+
+    // a token account, its mint and owner and what it held before and after the transaction
+    // (None if it didn't exist)
+    type TokenBalance = (Pubkey, Pubkey, Pubkey, Option<u64>, Option<u64>);
+
+    // The transaction as `fetch` stores it: signed by `signers` (the first pays) and protobuf
+    // encoded with a status meta. `inner` are the instructions invoked by the outer instruction
+    // at that index
+    fn store_transaction(
+        store: &mut dyn Store,
+        slot: i64,
+        block_index: i64,
+        instructions: &[Instruction],
+        inner: &[(u8, Vec<Instruction>)],
+        signers: &[&Keypair],
+        balances: &[TokenBalance],
+    ) {
+        let transaction = Transaction::new_signed_with_payer(
+            instructions, Some(&signers[0].pubkey()), signers, Hash::default());
+        let keys = transaction.message.account_keys.clone();
+        let index_of = |key: &Pubkey| keys.iter().position(|k| k == key)
+            .expect("key is in the transaction") as u8;
+
+        let compile = |instruction: &Instruction| CompiledInstruction {
+            program_id_index: index_of(&instruction.program_id),
+            accounts: instruction.accounts.iter().map(|a| index_of(&a.pubkey)).collect(),
+            data: instruction.data.clone(),
+        };
+        let token_balance = |account: &Pubkey, mint: &Pubkey, owner: &Pubkey, amount: u64| {
+            TransactionTokenBalance {
+                account_index: index_of(account),
+                mint: mint.to_string(),
+                ui_token_amount: UiTokenAmount {
+                    ui_amount: Some(amount as f64),
+                    decimals: 0,
+                    amount: amount.to_string(),
+                    ui_amount_string: amount.to_string(),
+                },
+                owner: owner.to_string(),
+            }
+        };
+
+        let meta = TransactionStatusMeta {
+            pre_balances: vec![0; keys.len()],
+            post_balances: vec![0; keys.len()],
+            inner_instructions: Some(inner.iter()
+                .map(|(index, instructions)| InnerInstructions {
+                    index: *index,
+                    instructions: instructions.iter().map(compile).collect(),
+                })
+                .collect()),
+            pre_token_balances: Some(balances.iter()
+                .filter_map(|(account, mint, owner, pre, _)| {
+                    pre.map(|amount| token_balance(account, mint, owner, amount))
+                })
+                .collect()),
+            post_token_balances: Some(balances.iter()
+                .filter_map(|(account, mint, owner, _, post)| {
+                    post.map(|amount| token_balance(account, mint, owner, amount))
+                })
+                .collect()),
+            ..TransactionStatusMeta::default()
+        };
+
+        let signature = transaction.signatures[0];
+        let transaction = TransactionWithStatusMeta::Complete(VersionedTransactionWithStatusMeta {
+            transaction: VersionedTransaction::from(transaction),
+            meta,
+        });
+        let protobuf_tx = generated::ConfirmedTransaction::from(transaction);
+        store.insert_transaction(
+            slot, block_index, signature.as_ref(), &protobuf_tx.encode_to_vec(),
+        ).expect("transaction inserts");
+    }
+
+    fn create_account(payer: &Keypair, account: &Keypair, space: u64) -> Instruction {
+        system_instruction::create_account(
+            &payer.pubkey(), &account.pubkey(), 1_000_000, space, &spl_token::id())
+    }
+
+    // Mints a master edition NFT to its creator and then sells it on to a buyer (as a plain
+    // transfer) and checks what lands in the store
+    #[test]
+    fn partition_and_reassemble() {
+        let mut store = SqliteStore::open(":memory:").expect("schema creates");
+
+        let creator = Keypair::new();
+        let mint = Keypair::new();
+        let creator_account = Keypair::new();
+        let buyer = Keypair::new();
+        let buyer_account = Keypair::new();
+        let mint_key = mint.pubkey();
+        let metadata_key = find_metadata_account(&mint_key).0;
+        let edition_key = find_master_edition_account(&mint_key).0;
+
+        store_transaction(
+            &mut store, 130_000_000, 5,
+            &[
+                create_account(&creator, &mint, 82),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint_key, &creator.pubkey(), Some(&creator.pubkey()), 0,
+                ).unwrap(),
+                create_account(&creator, &creator_account, 165),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &creator_account.pubkey(), &mint_key, &creator.pubkey(),
+                ).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint_key, &creator_account.pubkey(), &creator.pubkey(),
+                    &[], 1,
+                ).unwrap(),
+                create_metadata_accounts_v2(
+                    mpl_token_metadata::id(), metadata_key, mint_key, creator.pubkey(),
+                    creator.pubkey(), creator.pubkey(), "Bonbon #1".to_string(),
+                    "BON".to_string(), "https://example.com/1.json".to_string(),
+                    Some(vec![Creator { address: creator.pubkey(), verified: true, share: 100 }]),
+                    500, true, true, None, None,
+                ),
+                create_master_edition_v3(
+                    mpl_token_metadata::id(), edition_key, mint_key, creator.pubkey(),
+                    creator.pubkey(), metadata_key, creator.pubkey(), Some(0),
+                ),
+            ],
+            // the master edition takes over the mint and freeze authorities
+            &[(6, vec![
+                spl_token::instruction::set_authority(
+                    &spl_token::id(), &mint_key, Some(&edition_key), AuthorityType::MintTokens,
+                    &creator.pubkey(), &[],
+                ).unwrap(),
+                spl_token::instruction::set_authority(
+                    &spl_token::id(), &mint_key, Some(&edition_key),
+                    AuthorityType::FreezeAccount, &creator.pubkey(), &[],
+                ).unwrap(),
+            ])],
+            &[&creator, &mint, &creator_account],
+            &[(creator_account.pubkey(), mint_key, creator.pubkey(), None, Some(1))],
+        );
+
+        store_transaction(
+            &mut store, 130_000_100, 2,
+            &[
+                create_account(&buyer, &buyer_account, 165),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &buyer_account.pubkey(), &mint_key, &buyer.pubkey(),
+                ).unwrap(),
+                spl_token::instruction::transfer(
+                    &spl_token::id(), &creator_account.pubkey(), &buyer_account.pubkey(),
+                    &creator.pubkey(), &[], 1,
+                ).unwrap(),
+            ],
+            &[],
+            &[&buyer, &buyer_account, &creator],
+            &[
+                (creator_account.pubkey(), mint_key, creator.pubkey(), Some(1), Some(0)),
+                (buyer_account.pubkey(), mint_key, buyer.pubkey(), None, Some(1)),
+            ],
+        );
+
+        partition_transactions(&mut store, &partitioners(false), log_check(None))
+            .expect("partitions");
+        reassemble_bonbons(&mut store, false).expect("reassembles");
+
+        let connection = store.connection();
+        let failures: i64 = connection
+            .query_row("SELECT COUNT(*) FROM failures", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(failures, 0);
+
+        type BonbonRow = (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, String, String, String, i64);
+        let bonbon: BonbonRow = connection
+            .query_row(
+                "SELECT metadata_key, mint_key, current_owner, current_account, edition_status,
+                        supply_status, token_standard, supply
+                 FROM bonbons",
+                [],
+                |row| Ok((
+                    row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?,
+                    row.get(5)?, row.get(6)?, row.get(7)?,
+                )),
+            )
+            .expect("one bonbon");
+        assert_eq!(bonbon, (
+            metadata_key.to_bytes().to_vec(),
+            mint_key.to_bytes().to_vec(),
+            buyer.pubkey().to_bytes().to_vec(),
+            buyer_account.pubkey().to_bytes().to_vec(),
+            "master".to_string(),
+            "fixed".to_string(),
+            "non_fungible".to_string(),
+            1,
+        ));
+
+        let glazing: (Vec<u8>, Vec<u8>, i64) = connection
+            .query_row(
+                "SELECT name, uri, seller_fee_basis_points FROM glazings",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .expect("one glazing");
+        assert_eq!(glazing, (b"Bonbon #1".to_vec(), b"https://example.com/1.json".to_vec(), 500));
+
+        let holders: Vec<(Vec<u8>, i64)> = connection
+            .prepare("SELECT account_key, amount FROM holders").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(holders, vec![(buyer_account.pubkey().to_bytes().to_vec(), 1)]);
+
+        let mint_authority: Option<Vec<u8>> = connection
+            .query_row(
+                "SELECT authority FROM authorities
+                 WHERE authority_type = 'mint'
+                 ORDER BY slot DESC, block_index DESC, outer_index DESC, inner_index DESC
+                 LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(mint_authority, Some(edition_key.to_bytes().to_vec()));
+
+        let update_authority_changed: bool = connection
+            .query_row("SELECT update_authority_changed FROM bonbons", [], |row| row.get(0))
+            .unwrap();
+        assert!(!update_authority_changed);
+
+        // a second run replaces the bonbon rather than adding to it
+        reassemble_bonbons(&mut store, false).expect("reassembles again");
+        let connection = store.connection();
+        for table in ["bonbons", "glazings", "holders"] {
+            let rows: i64 = connection
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(rows, 1, "{}", table);
+        }
+    }
+
+    // Leniently reassembled, an instruction that fails is recorded as an anomaly and the transfer
+    // after it in the same transaction still lands
+    #[test]
+    fn lenient_reassembly_after_a_failing_instruction() {
+        let mut store = SqliteStore::open(":memory:").expect("schema creates");
+
+        let creator = Keypair::new();
+        let mint = Keypair::new();
+        let creator_account = Keypair::new();
+        let buyer = Keypair::new();
+        let buyer_account = Keypair::new();
+        let mint_key = mint.pubkey();
+        let metadata_key = find_metadata_account(&mint_key).0;
+        let edition_key = find_master_edition_account(&mint_key).0;
+        let create_master_edition = create_master_edition_v3(
+            mpl_token_metadata::id(), edition_key, mint_key, creator.pubkey(),
+            creator.pubkey(), metadata_key, creator.pubkey(), Some(0),
+        );
+
+        store_transaction(
+            &mut store, 160_000_000, 0,
+            &[
+                create_account(&creator, &mint, 82),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint_key, &creator.pubkey(), None, 0,
+                ).unwrap(),
+                create_account(&creator, &creator_account, 165),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &creator_account.pubkey(), &mint_key, &creator.pubkey(),
+                ).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint_key, &creator_account.pubkey(), &creator.pubkey(),
+                    &[], 1,
+                ).unwrap(),
+                create_metadata_accounts_v2(
+                    mpl_token_metadata::id(), metadata_key, mint_key, creator.pubkey(),
+                    creator.pubkey(), creator.pubkey(), "Bonbon #3".to_string(),
+                    "BON".to_string(), "https://example.com/3.json".to_string(), None, 500,
+                    true, true, None, None,
+                ),
+                create_master_edition.clone(),
+            ],
+            &[],
+            &[&creator, &mint, &creator_account],
+            &[(creator_account.pubkey(), mint_key, creator.pubkey(), None, Some(1))],
+        );
+
+        // the master edition again, which the bonbon already has, and then the sale
+        store_transaction(
+            &mut store, 160_000_010, 0,
+            &[
+                create_master_edition,
+                create_account(&buyer, &buyer_account, 165),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &buyer_account.pubkey(), &mint_key, &buyer.pubkey(),
+                ).unwrap(),
+                spl_token::instruction::transfer(
+                    &spl_token::id(), &creator_account.pubkey(), &buyer_account.pubkey(),
+                    &creator.pubkey(), &[], 1,
+                ).unwrap(),
+            ],
+            &[],
+            &[&buyer, &creator, &buyer_account],
+            &[
+                (creator_account.pubkey(), mint_key, creator.pubkey(), Some(1), Some(0)),
+                (buyer_account.pubkey(), mint_key, buyer.pubkey(), None, Some(1)),
+            ],
+        );
+
+        partition_transactions(&mut store, &partitioners(false), log_check(None))
+            .expect("partitions");
+        reassemble_bonbons(&mut store, true).expect("reassembles");
+
+        let connection = store.connection();
+        let failures: i64 = connection
+            .query_row("SELECT COUNT(*) FROM failures", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(failures, 0);
+
+        let anomalies: Vec<(String, i64, i64)> = connection
+            .prepare("SELECT error_kind, slot, outer_index FROM anomalies").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(anomalies, vec![("InvalidMasterEditionCreate".to_string(), 160_000_010, 0)]);
+
+        let owner: (Vec<u8>, String) = connection
+            .query_row(
+                "SELECT current_owner, edition_status FROM bonbons", [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .expect("one bonbon");
+        assert_eq!(owner, (buyer.pubkey().to_bytes().to_vec(), "master".to_string()));
+
+        let holders: Vec<(Vec<u8>, i64)> = connection
+            .prepare("SELECT account_key, amount FROM holders").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(holders, vec![(buyer_account.pubkey().to_bytes().to_vec(), 1)]);
+    }
+
+    // A mint that fails to assemble and a transaction that fails to partition are dead-lettered.
+    // Retrying (leniently, as a stand-in for a fix) assembles the mint and clears its failure.
+    // The transaction still doesn't partition so its failure stays, once
+    #[test]
+    fn retry_dead_lettered_failures() {
+        let mut store = SqliteStore::open(":memory:").expect("schema creates");
+
+        let creator = Keypair::new();
+        let mint = Keypair::new();
+        let creator_account = Keypair::new();
+        let mint_key = mint.pubkey();
+        let metadata_key = find_metadata_account(&mint_key).0;
+        let create_master_edition = create_master_edition_v3(
+            mpl_token_metadata::id(), find_master_edition_account(&mint_key).0, mint_key,
+            creator.pubkey(), creator.pubkey(), metadata_key, creator.pubkey(), Some(0),
+        );
+
+        store_transaction(
+            &mut store, 170_000_000, 0,
+            &[
+                create_account(&creator, &mint, 82),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint_key, &creator.pubkey(), None, 0,
+                ).unwrap(),
+                create_account(&creator, &creator_account, 165),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &creator_account.pubkey(), &mint_key, &creator.pubkey(),
+                ).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint_key, &creator_account.pubkey(), &creator.pubkey(),
+                    &[], 1,
+                ).unwrap(),
+                create_metadata_accounts_v2(
+                    mpl_token_metadata::id(), metadata_key, mint_key, creator.pubkey(),
+                    creator.pubkey(), creator.pubkey(), "Bonbon #4".to_string(),
+                    "BON".to_string(), "https://example.com/4.json".to_string(), None, 500,
+                    true, true, None, None,
+                ),
+                create_master_edition.clone(),
+            ],
+            &[],
+            &[&creator, &mint, &creator_account],
+            &[(creator_account.pubkey(), mint_key, creator.pubkey(), None, Some(1))],
+        );
+        // the master edition again, which fails the strict assembly
+        store_transaction(
+            &mut store, 170_000_010, 0, &[create_master_edition], &[], &[&creator], &[]);
+        // a metadata update without its accounts
+        let mut update = update_metadata_accounts(
+            mpl_token_metadata::id(), metadata_key, creator.pubkey(), None, None, Some(true));
+        update.accounts.clear();
+        store_transaction(&mut store, 170_000_020, 0, &[update], &[], &[&creator], &[]);
+
+        // assembly failures are by mint, without a slot
+        let failures = |store: &SqliteStore| -> Vec<(String, Option<i64>)> {
+            store.connection()
+                .prepare("SELECT phase, slot FROM failures ORDER BY phase").unwrap()
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+                .collect::<Result<_, _>>().unwrap()
+        };
+        let bonbons = |store: &SqliteStore| -> i64 {
+            store.connection()
+                .query_row("SELECT COUNT(*) FROM bonbons", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        partition_transactions(&mut store, &partitioners(false), log_check(None))
+            .expect("partitions");
+        reassemble_bonbons(&mut store, false).expect("reassembles");
+        assert_eq!(failures(&store), vec![
+            ("assemble".to_string(), None),
+            ("partition".to_string(), Some(170_000_020)),
+        ]);
+        assert_eq!(bonbons(&store), 0);
+
+        for _ in 0..2 {
+            retry_failed(&mut store, &partitioners(false), log_check(None), true)
+                .expect("retries");
+            assert_eq!(failures(&store), vec![("partition".to_string(), Some(170_000_020))]);
+            assert_eq!(bonbons(&store), 1);
+        }
+        let anomalies: i64 = store.connection()
+            .query_row("SELECT COUNT(*) FROM anomalies", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(anomalies, 1);
+    }
+
+    // Token accounts of a fungible mint, which nothing is partitioned for, aren't kept. The
+    // NFT's are, along with the later change of their owner
+    #[test]
+    fn token_accounts_only_for_kept_mints() {
+        let mut store = SqliteStore::open(":memory:").expect("schema creates");
+
+        let authority = Keypair::new();
+        let new_owner = Pubkey::new_unique();
+        let mut accounts = vec![];
+        for (slot, decimals, amount) in [(150_000_000, 0, 1), (150_000_001, 6, 1_000_000)] {
+            let mint = Keypair::new();
+            let account = Keypair::new();
+            store_transaction(
+                &mut store, slot, 0,
+                &[
+                    create_account(&authority, &mint, 82),
+                    spl_token::instruction::initialize_mint(
+                        &spl_token::id(), &mint.pubkey(), &authority.pubkey(), None, decimals,
+                    ).unwrap(),
+                    create_account(&authority, &account, 165),
+                    spl_token::instruction::initialize_account(
+                        &spl_token::id(), &account.pubkey(), &mint.pubkey(), &authority.pubkey(),
+                    ).unwrap(),
+                    spl_token::instruction::mint_to(
+                        &spl_token::id(), &mint.pubkey(), &account.pubkey(), &authority.pubkey(),
+                        &[], amount,
+                    ).unwrap(),
+                ],
+                &[],
+                &[&authority, &mint, &account],
+                &[(account.pubkey(), mint.pubkey(), authority.pubkey(), None, Some(amount))],
+            );
+            store_transaction(
+                &mut store, slot + 10, 0,
+                &[spl_token::instruction::set_authority(
+                    &spl_token::id(), &account.pubkey(), Some(&new_owner),
+                    AuthorityType::AccountOwner, &authority.pubkey(), &[],
+                ).unwrap()],
+                &[],
+                &[&authority],
+                &[(account.pubkey(), mint.pubkey(), new_owner, Some(amount), Some(amount))],
+            );
+            accounts.push(account.pubkey());
+        }
+
+        partition_transactions(&mut store, &partitioners(false), log_check(None))
+            .expect("partitions");
+
+        let changes: Vec<(Vec<u8>, Vec<u8>, i64)> = store.connection()
+            .prepare("SELECT account_key, owner_key, slot FROM token_accounts ORDER BY slot")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        let nft_account = accounts[0].to_bytes().to_vec();
+        assert_eq!(changes, vec![
+            (nft_account.clone(), authority.pubkey().to_bytes().to_vec(), 150_000_000),
+            (nft_account, new_owner.to_bytes().to_vec(), 150_000_010),
+        ]);
+    }
+
+    // A mint's SetAuthority doesn't say whether it's fungible. It's only partitioned for mints
+    // partitioned some other way, here the decimals-0 InitializeMint
+    #[test]
+    fn set_authority_only_for_mints_with_history() {
+        let mut store = SqliteStore::open(":memory:").expect("schema creates");
+
+        let authority = Keypair::new();
+        let new_authority = Pubkey::new_unique();
+        let mut mint_keys = vec![];
+        for (slot, decimals) in [(150_000_000, 0), (150_000_001, 6)] {
+            let mint = Keypair::new();
+            let mint_key = mint.pubkey();
+            store_transaction(
+                &mut store, slot, 0,
+                &[
+                    create_account(&authority, &mint, 82),
+                    spl_token::instruction::initialize_mint(
+                        &spl_token::id(), &mint_key, &authority.pubkey(), None, decimals,
+                    ).unwrap(),
+                    create_metadata_accounts_v2(
+                        mpl_token_metadata::id(), find_metadata_account(&mint_key).0, mint_key,
+                        authority.pubkey(), authority.pubkey(), authority.pubkey(),
+                        "Bonbon".to_string(), "BON".to_string(),
+                        "https://example.com/bonbon.json".to_string(), None, 0, true, true,
+                        None, None,
+                    ),
+                ],
+                &[],
+                &[&authority, &mint],
+                &[],
+            );
+            store_transaction(
+                &mut store, slot + 10, 0,
+                &[spl_token::instruction::set_authority(
+                    &spl_token::id(), &mint_key, Some(&new_authority),
+                    AuthorityType::MintTokens, &authority.pubkey(), &[],
+                ).unwrap()],
+                &[],
+                &[&authority],
+                &[],
+            );
+            mint_keys.push(mint_key);
+        }
+
+        partition_transactions(&mut store, &partitioners(false), log_check(None))
+            .expect("partitions");
+        assert!(store.has_partitions(&mint_keys[0]).unwrap());
+        assert!(!store.has_partitions(&mint_keys[1]).unwrap());
+
+        reassemble_bonbons(&mut store, false).expect("reassembles");
+        let bonbons: Vec<Vec<u8>> = store.connection()
+            .prepare("SELECT mint_key FROM bonbons").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(bonbons, vec![mint_keys[0].to_bytes().to_vec()]);
+    }
+
+    // mints an NFT into an unverified collection, verifies it and, if `unverify`, unverifies it
+    // again. returns the metadata key
+    fn store_collection_item(
+        store: &mut dyn Store,
+        slot: i64,
+        collection_mint: &Pubkey,
+        unverify: bool,
+    ) -> Pubkey {
+        let authority = Keypair::new();
+        let mint = Keypair::new();
+        let account = Keypair::new();
+        let mint_key = mint.pubkey();
+        let metadata_key = find_metadata_account(&mint_key).0;
+        store_transaction(
+            store, slot, 0,
+            &[
+                create_account(&authority, &mint, 82),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(), &mint_key, &authority.pubkey(), None, 0,
+                ).unwrap(),
+                create_account(&authority, &account, 165),
+                spl_token::instruction::initialize_account(
+                    &spl_token::id(), &account.pubkey(), &mint_key, &authority.pubkey(),
+                ).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(), &mint_key, &account.pubkey(), &authority.pubkey(), &[], 1,
+                ).unwrap(),
+                create_metadata_accounts_v2(
+                    mpl_token_metadata::id(), metadata_key, mint_key, authority.pubkey(),
+                    authority.pubkey(), authority.pubkey(), "Bonbon #2".to_string(),
+                    "BON".to_string(), "https://example.com/2.json".to_string(), None, 500,
+                    true, true, Some(Collection { verified: false, key: *collection_mint }),
+                    None,
+                ),
+            ],
+            &[],
+            &[&authority, &mint, &account],
+            &[(account.pubkey(), mint_key, authority.pubkey(), None, Some(1))],
+        );
+
+        // `VerifyCollection` and `UnverifyCollection` have no arguments. the verify has a payer
+        // after the collection authority, the unverify doesn't
+        let collection_accounts = [
+            AccountMeta::new_readonly(*collection_mint, false),
+            AccountMeta::new_readonly(find_metadata_account(collection_mint).0, false),
+            AccountMeta::new_readonly(find_master_edition_account(collection_mint).0, false),
+        ];
+        let collection_instruction = |discriminant: u8, payer: bool| {
+            let mut accounts = vec![
+                AccountMeta::new(metadata_key, false),
+                AccountMeta::new(authority.pubkey(), true),
+            ];
+            if payer {
+                accounts.push(AccountMeta::new(authority.pubkey(), true));
+            }
+            accounts.extend_from_slice(&collection_accounts);
+            Instruction { program_id: mpl_token_metadata::id(), accounts, data: vec![discriminant] }
+        };
+
+        store_transaction(
+            store, slot + 1, 0, &[collection_instruction(18, true)], &[], &[&authority], &[]);
+        if unverify {
+            store_transaction(
+                store, slot + 2, 0, &[collection_instruction(22, false)], &[], &[&authority], &[]);
+        }
+        metadata_key
+    }
+
+    // As if the bonbons were assembled before the unverify fix, then reprocessed. Only the bonbon
+    // that was unverified is rewritten
+    #[test]
+    fn reprocess_unverified_collection() {
+        let mut store = SqliteStore::open(":memory:").expect("schema creates");
+        let collection_mint = Pubkey::new_unique();
+        let unverified = store_collection_item(&mut store, 140_000_000, &collection_mint, true);
+        let verified = store_collection_item(&mut store, 140_000_010, &collection_mint, false);
+
+        partition_transactions(&mut store, &partitioners(false), log_check(None))
+            .expect("partitions");
+        reassemble_bonbons(&mut store, false).expect("reassembles");
+
+        // what the unverify used to leave behind. the uri marks what was there before
+        store.connection()
+            .execute_batch(
+                "UPDATE glazings SET collection_verified = 1, uri = 'stale';
+                 DELETE FROM collection_members WHERE verified = 0;",
+            )
+            .unwrap();
+
+        reprocess_bonbons(&mut store, "UnverifyCollection", false).expect("reprocesses");
+
+        let connection = store.connection();
+        let latest_glazing = |metadata_key: &Pubkey| -> (Vec<u8>, bool) {
+            connection
+                .query_row(
+                    "SELECT uri, collection_verified FROM glazings
+                     WHERE metadata_key = ?1
+                     ORDER BY slot DESC, block_index DESC, outer_index DESC
+                     LIMIT 1",
+                    [metadata_key.to_bytes().to_vec()],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap()
+        };
+        assert_eq!(latest_glazing(&unverified), (b"https://example.com/2.json".to_vec(), false));
+        assert_eq!(latest_glazing(&verified), (b"stale".to_vec(), true));
+
+        let memberships: Vec<(bool, i64)> = connection
+            .prepare(
+                "SELECT verified, slot FROM collection_members
+                 WHERE metadata_key = ?1
+                 ORDER BY slot",
+            ).unwrap()
+            .query_map(
+                [unverified.to_bytes().to_vec()], |row| Ok((row.get(0)?, row.get(1)?)),
+            ).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(memberships, vec![(true, 140_000_001), (false, 140_000_002)]);
+
+        assert!(reprocess_bonbons(&mut store, "VerifyCollection", false).is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use {
+    bonbon::{
+        assemble::{Bonbon, InstructionIndex, TransactionTokenOwnerMeta},
+        partition::{PartitionedInstruction, TokenAccountChange, TransactionTokenMeta},
+    },
+    solana_sdk::{
+        instruction::CompiledInstruction,
+        pubkey::Pubkey,
+    },
+};
+
+pub mod psql;
+pub mod sqlite;
+
+pub type StoreResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+pub struct TransactionRow {
+    pub slot: i64,
+
+    pub block_index: i64,
+
+    pub signature: Vec<u8>,
+
+    // protobuf encoded generated::ConfirmedTransaction
+    pub transaction: Vec<u8>,
+}
+
+pub struct PartitionRow {
+    pub signature: Vec<u8>,
+
+    pub instruction: CompiledInstruction,
+
+    pub keys: Vec<Pubkey>,
+
+    pub metas: Vec<TransactionTokenOwnerMeta>,
+
+    // lamports per key before and after the transaction. empty for rows stored without them
+    pub pre_balances: Vec<u64>,
+
+    pub post_balances: Vec<u64>,
+
+    pub instruction_index: InstructionIndex,
+}
+
+pub struct TokenAccountRow {
+    pub slot: i64,
+
+    pub block_index: i64,
+
+    pub change: TokenAccountChange,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailurePhase {
+    Partition,
+
+    Assemble,
+}
+
+pub struct FailureRow {
+    pub phase: FailurePhase,
+
+    // transaction signature for partition failures, mint key for assemble failures
+    pub key: Vec<u8>,
+
+    // where the transaction is for partition failures
+    pub slot: Option<i64>,
+
+    pub block_index: Option<i64>,
+
+    pub error_kind: String,
+
+    pub error_details: String,
+
+    pub code_version: String,
+}
+
+// Everything the pipeline persists goes through here so that the same fetch / partition /
+// reassemble steps can run against a postgres server or a local sqlite file.
+//
+// Reads are paged by key (rather than streamed) so that callers are free to write back to the
+// same store while walking the results.
+pub trait Store {
+    fn insert_transaction(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        signature: &[u8],
+        transaction: &[u8],
+    ) -> StoreResult<()>;
+
+    fn transaction_at(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+    ) -> StoreResult<Option<TransactionRow>>;
+
+    // transactions strictly after `after` (slot, block_index) in block order
+    fn transactions_after(
+        &mut self,
+        after: Option<(i64, i64)>,
+        limit: i64,
+    ) -> StoreResult<Vec<TransactionRow>>;
+
+    fn insert_account_keys(
+        &mut self,
+        signature: &[u8],
+        keys: &[Pubkey],
+        metas: &[TransactionTokenMeta],
+        pre_balances: &[u64],
+        post_balances: &[u64],
+    ) -> StoreResult<()>;
+
+    fn insert_partition(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        signature: &[u8],
+        partitioned: &PartitionedInstruction,
+    ) -> StoreResult<()>;
+
+    fn insert_token_account_change(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        change: &TokenAccountChange,
+    ) -> StoreResult<()>;
+
+    // whether any change has been inserted for the token account
+    fn has_token_account(
+        &mut self,
+        account_key: &Pubkey,
+    ) -> StoreResult<bool>;
+
+    // every change to the token accounts that were ever initialized for the mint
+    fn token_account_changes(
+        &mut self,
+        mint_key: &Pubkey,
+    ) -> StoreResult<Vec<TokenAccountRow>>;
+
+    // distinct partition keys produced by `program_key` strictly after `after`, in key order
+    fn partition_keys_after(
+        &mut self,
+        program_key: &Pubkey,
+        after: Option<Pubkey>,
+        limit: i64,
+    ) -> StoreResult<Vec<Pubkey>>;
+
+    // whether any instruction has been partitioned under the key
+    fn has_partitions(
+        &mut self,
+        partition_key: &Pubkey,
+    ) -> StoreResult<bool>;
+
+    // all partitioned instructions for the mint and metadata keys, in execution order
+    fn partition_instructions(
+        &mut self,
+        mint_key: &Pubkey,
+        metadata_key: &Pubkey,
+    ) -> StoreResult<Vec<PartitionRow>>;
+
+    // mints of the stored bonbons with a `program_key` instruction partitioned on their metadata
+    // key (as token metadata instructions are) and data starting with `discriminant`, strictly
+    // after `after` in key order. can include some without one, never leaves one out
+    fn bonbon_mints_with_instruction(
+        &mut self,
+        program_key: &Pubkey,
+        discriminant: u8,
+        after: Option<Pubkey>,
+        limit: i64,
+    ) -> StoreResult<Vec<Pubkey>>;
+
+    // the bonbon and all of its glazings
+    fn insert_bonbon(
+        &mut self,
+        bonbon: &Bonbon,
+    ) -> StoreResult<()>;
+
+    // everything `insert_bonbon` wrote for the metadata key, so the mint can be reassembled
+    fn delete_bonbon(
+        &mut self,
+        metadata_key: &Pubkey,
+    ) -> StoreResult<()>;
+
+    // replaces any earlier failure for the same phase and key
+    fn insert_failure(
+        &mut self,
+        failure: &FailureRow,
+    ) -> StoreResult<()>;
+
+    // failures of `phase` with key strictly after `after`, in key order
+    fn failures_after(
+        &mut self,
+        phase: FailurePhase,
+        after: Option<Vec<u8>>,
+        limit: i64,
+    ) -> StoreResult<Vec<FailureRow>>;
+
+    fn delete_failure(
+        &mut self,
+        phase: FailurePhase,
+        key: &[u8],
+    ) -> StoreResult<()>;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        bonbon::assemble::{
+            Anomaly, AuthorityChange, CandyMachineMint, CandyMachineVersion, Collection,
+            CollectionAuthorityChange, CompressedLeaf, Creator, EditionStatus, ErrorCode, Glazing,
+            Holding, LeafOwnerChange, LimitedEdition, OpenOrder, OrderSide, RoyaltyPayment, Sale,
+            StorefrontSale, StorefrontSaleKind, TokenDelegateRole, TokenRecord, TokenState,
+            UseMethod, Uses, VaultCustody,
+        },
+    };
+
+    fn index(outer_index: i64, inner_index: Option<i64>) -> InstructionIndex {
+        InstructionIndex {
+            slot: 150_000_000,
+            block_index: 7,
+            outer_index,
+            inner_index,
+        }
+    }
+
+    // writes at least one row to every table `insert_bonbon` touches, with the nullable columns
+    // filled in
+    pub(crate) fn bonbon_in_every_table() -> Bonbon {
+        let key = Pubkey::new_unique;
+        let glazing = Glazing {
+            name: b"bonbon #1".to_vec(),
+            symbol: b"BON".to_vec(),
+            uri: b"https://example.com/1.json".to_vec(),
+            seller_fee_basis_points: 500,
+            creators: vec![
+                Creator { address: key(), verified: true, share: 60 },
+                Creator { address: key(), verified: false, share: 40 },
+            ],
+            collection: Some(Collection { address: key(), verified: true }),
+            uses: Some(Uses { use_method: UseMethod::Multiple, remaining: 2, total: 3 }),
+            primary_sale_happened: true,
+            is_mutable: true,
+            update_authority: Some(key()),
+            instruction_index: index(0, Some(2)),
+        };
+        let creator = glazing.creators[0].address;
+
+        Bonbon {
+            mint_key: key(),
+            metadata_key: key(),
+            current_owner: Some(key()),
+            current_account: Some(key()),
+            edition_status: EditionStatus::Limited,
+            limited_edition: Some(LimitedEdition { master_key: key(), edition_num: Some(3) }),
+            glazings: vec![glazing],
+            update_authorities: vec![AuthorityChange {
+                authority: Some(key()),
+                instruction_index: index(0, Some(2)),
+            }],
+            mint_authorities: vec![AuthorityChange {
+                authority: None,
+                instruction_index: index(0, None),
+            }],
+            freeze_authorities: vec![AuthorityChange {
+                authority: Some(key()),
+                instruction_index: index(0, None),
+            }],
+            decimals: Some(0),
+            recorded_token_standard: None,
+            rule_set: Some(key()),
+            token_record: Some(TokenRecord {
+                state: TokenState::Listed,
+                delegate: Some(key()),
+                delegate_role: Some(TokenDelegateRole::Sale),
+                instruction_index: index(1, None),
+            }),
+            supply: 1,
+            peak_supply: 1,
+            holdings: vec![Holding {
+                account: key(),
+                owner: Some(key()),
+                amount: 1,
+                frozen: true,
+                instruction_index: index(1, None),
+            }],
+            collection_size: Some(10),
+            collection_authorities: vec![CollectionAuthorityChange {
+                authority: key(),
+                record: key(),
+                approved: true,
+                instruction_index: index(2, None),
+            }],
+            compressed_leaf: Some(CompressedLeaf { tree: key(), nonce: 42, redeemed: false }),
+            leaf_owners: vec![LeafOwnerChange {
+                owner: key(),
+                delegate: key(),
+                instruction_index: index(3, Some(0)),
+            }],
+            candy_machine_mint: Some(CandyMachineMint {
+                version: CandyMachineVersion::V1,
+                candy_machine: key(),
+                config: Some(key()),
+                payer: key(),
+                price: Some(1_000_000_000),
+                instruction_index: index(0, None),
+            }),
+            sales: vec![Sale {
+                program_key: key(),
+                marketplace: Some(key()),
+                buyer: key(),
+                seller: key(),
+                price: 2_000_000_000,
+                currency_mint: spl_token::native_mint::id(),
+                amount: 1,
+                royalties: vec![RoyaltyPayment {
+                    creator,
+                    share: 60,
+                    seller_fee_basis_points: 500,
+                    expected: 60_000_000,
+                    paid: Some(60_000_000),
+                    shortfall: Some(0),
+                    ambiguous: false,
+                }],
+                instruction_index: index(4, None),
+            }],
+            open_orders: vec![OpenOrder {
+                side: OrderSide::Bid,
+                program_key: key(),
+                marketplace: Some(key()),
+                wallet: key(),
+                token_account: Some(key()),
+                delegate: Some(key()),
+                price: Some(3_000_000_000),
+                currency_mint: Some(spl_token::native_mint::id()),
+                amount: 1,
+                instruction_index: index(5, None),
+            }],
+            storefront_sales: vec![StorefrontSale {
+                kind: StorefrontSaleKind::Auction,
+                program_key: key(),
+                manager: key(),
+                auction: Some(key()),
+                vault: Some(key()),
+                buyer: key(),
+                bidder_metadata: Some(key()),
+                price: Some(4_000_000_000),
+                currency_mint: Some(spl_token::native_mint::id()),
+                instruction_index: index(6, None),
+            }],
+            vault_custody: vec![VaultCustody {
+                vault: key(),
+                safety_deposit_box: key(),
+                deposit: true,
+                amount: 1,
+                instruction_index: index(6, Some(0)),
+            }],
+            anomalies: vec![Anomaly {
+                instruction_index: index(7, None),
+                error_kind: ErrorCode::InvalidMetadataUpdate,
+                details: "skipped".to_string(),
+            }],
+        }
+    }
+
+    // the bonbon goes in, comes out with `delete_bonbon` and can then go in again
+    pub(crate) fn insert_and_delete_bonbon(store: &mut impl Store) {
+        let bonbon = bonbon_in_every_table();
+        store.insert_bonbon(&bonbon).expect("bonbon inserts");
+        store.delete_bonbon(&bonbon.metadata_key).expect("bonbon deletes");
+        store.insert_bonbon(&bonbon).expect("bonbon inserts again");
+        store.delete_bonbon(&bonbon.metadata_key).expect("bonbon deletes again");
+    }
+}
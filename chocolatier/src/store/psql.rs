@@ -0,0 +1,963 @@
+use {
+    super::{
+        FailurePhase, FailureRow, PartitionRow, Store, StoreResult, TokenAccountRow,
+        TransactionRow,
+    },
+    crate::convert,
+    bonbon::{
+        assemble::{Bonbon, InstructionIndex, TransactionTokenOwnerMeta},
+        partition::{PartitionedInstruction, TokenAccountChange, TransactionTokenMeta},
+    },
+    solana_sdk::{
+        instruction::CompiledInstruction,
+        pubkey::Pubkey,
+    },
+};
+
+pub struct PsqlStore {
+    client: postgres::Client,
+
+    insert_transaction_statement: postgres::Statement,
+
+    select_transaction_statement: postgres::Statement,
+
+    select_transactions_statement: postgres::Statement,
+
+    insert_account_keys_statement: postgres::Statement,
+
+    insert_partition_statement: postgres::Statement,
+
+    insert_token_account_statement: postgres::Statement,
+
+    select_token_accounts_statement: postgres::Statement,
+
+    select_token_account_exists_statement: postgres::Statement,
+
+    select_partition_keys_statement: postgres::Statement,
+
+    select_partition_exists_statement: postgres::Statement,
+
+    select_partition_instructions_statement: postgres::Statement,
+
+    select_bonbon_mints_statement: postgres::Statement,
+
+    insert_bonbon_statement: postgres::Statement,
+
+    insert_glazing_statement: postgres::Statement,
+
+    insert_authority_statement: postgres::Statement,
+
+    insert_holder_statement: postgres::Statement,
+
+    insert_leaf_owner_statement: postgres::Statement,
+
+    insert_collection_statement: postgres::Statement,
+
+    insert_collection_authority_statement: postgres::Statement,
+
+    insert_collection_member_statement: postgres::Statement,
+
+    insert_candy_machine_mint_statement: postgres::Statement,
+
+    insert_sale_statement: postgres::Statement,
+
+    insert_royalty_payment_statement: postgres::Statement,
+
+    insert_open_order_statement: postgres::Statement,
+
+    insert_storefront_sale_statement: postgres::Statement,
+
+    insert_vault_custody_statement: postgres::Statement,
+
+    insert_anomaly_statement: postgres::Statement,
+
+    // one per table written by `insert_bonbon`
+    delete_bonbon_statements: Vec<postgres::Statement>,
+
+    insert_failure_statement: postgres::Statement,
+
+    select_failures_statement: postgres::Statement,
+
+    delete_failure_statement: postgres::Statement,
+}
+
+impl PsqlStore {
+    pub fn connect(psql_config: &str) -> StoreResult<Self> {
+        let mut client = postgres::Client::connect(psql_config, postgres::NoTls)?;
+
+        let insert_transaction_statement = client.prepare(
+            "INSERT INTO transactions VALUES ($1, $2, $3, $4)"
+        )?;
+
+        let select_transaction_statement = client.prepare(
+            "SELECT *
+             FROM transactions
+             WHERE slot = $1
+               AND block_index = $2
+            ",
+        )?;
+
+        let select_transactions_statement = client.prepare(
+            "SELECT *
+             FROM transactions
+             WHERE (slot, block_index) > ($1, $2)
+             ORDER BY (slot, block_index)
+             LIMIT $3
+            ",
+        )?;
+
+        let insert_account_keys_statement = client.prepare(
+            "INSERT INTO account_keys VALUES ($1, $2, $3, $4, $5)"
+        )?;
+
+        let insert_partition_statement = client.prepare(
+            "INSERT INTO partitions VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"
+        )?;
+
+        let insert_token_account_statement = client.prepare(
+            "INSERT INTO token_accounts VALUES ($1, $2, $3, $4, $5, $6, $7)"
+        )?;
+
+        let select_token_accounts_statement = client.prepare(
+            "SELECT *
+             FROM token_accounts
+             WHERE account_key IN (
+               SELECT account_key FROM token_accounts WHERE mint_key = $1
+             )
+            ",
+        )?;
+
+        let select_token_account_exists_statement = client.prepare(
+            "SELECT EXISTS (SELECT 1 FROM token_accounts WHERE account_key = $1)"
+        )?;
+
+        let select_partition_keys_statement = client.prepare(
+            "SELECT DISTINCT partition_key
+             FROM partitions
+             WHERE program_key = $1
+               AND partition_key > $2
+             ORDER BY partition_key
+             LIMIT $3
+            ",
+        )?;
+
+        let select_partition_exists_statement = client.prepare(
+            "SELECT EXISTS (SELECT 1 FROM partitions WHERE partition_key = $1)"
+        )?;
+
+        let select_partition_instructions_statement = client.prepare(
+            "SELECT p.signature, p.instruction, a.keys, a.metas,
+                    p.slot, p.block_index, p.outer_index, p.inner_index,
+                    a.pre_balances, a.post_balances
+             FROM partitions p JOIN account_keys a ON p.signature = a.signature
+             WHERE partition_key = $1
+                OR partition_key = $2
+             ORDER BY slot, block_index, outer_index, inner_index NULLS FIRST
+            ",
+        )?;
+
+        // bonbons, glazings and royalty_payments name their columns since migrate_schema.sql
+        // adds columns at the end that create_schema.sql has in the middle
+        // the instruction column is a bincode CompiledInstruction: the program index, then the
+        // account indices and the data, each after its length. the lengths are one byte below
+        // 128, which the instructions we look for always are. anything longer gets its
+        // discriminant misread and is only a false match for the caller to weed out
+        let select_bonbon_mints_statement = client.prepare(
+            "SELECT DISTINCT b.mint_key
+             FROM partitions p JOIN bonbons b ON p.partition_key = b.metadata_key
+             WHERE p.program_key = $1
+               AND substring(p.instruction from 4 + get_byte(p.instruction, 1) for 1) = $2
+               AND b.mint_key > $3
+             ORDER BY b.mint_key
+             LIMIT $4
+            ",
+        )?;
+
+        let insert_bonbon_statement = client.prepare(
+            "INSERT INTO bonbons (
+               metadata_key, mint_key, current_owner, current_account, edition_status,
+               limited_edition, update_authority, update_authority_changed, mint_authority,
+               freeze_authority, supply_status, token_standard, supply, rule_set, token_state,
+               token_delegate, token_delegate_role, tree_key, leaf_nonce, leaf_redeemed
+             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16,
+                       $17, $18, $19, $20)"
+        )?;
+
+        let insert_glazing_statement = client.prepare(
+            "INSERT INTO glazings (
+               metadata_key, name, symbol, uri, seller_fee_basis_points, collection_key,
+               collection_verified, creator0, creator1, creator2, creator3, creator4, uses,
+               primary_sale_happened, is_mutable, update_authority, slot, block_index,
+               outer_index, inner_index
+             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16,
+                       $17, $18, $19, $20)"
+        )?;
+
+        let insert_authority_statement = client.prepare(
+            "INSERT INTO authorities VALUES ($1, $2, $3, $4, $5, $6, $7)"
+        )?;
+
+        let insert_holder_statement = client.prepare(
+            "INSERT INTO holders VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"
+        )?;
+
+        let insert_leaf_owner_statement = client.prepare(
+            "INSERT INTO leaf_owners VALUES ($1, $2, $3, $4, $5, $6, $7)"
+        )?;
+
+        let insert_collection_statement = client.prepare(
+            "INSERT INTO collections VALUES ($1, $2, $3)"
+        )?;
+
+        let insert_collection_authority_statement = client.prepare(
+            "INSERT INTO collection_authorities VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+        )?;
+
+        let insert_collection_member_statement = client.prepare(
+            "INSERT INTO collection_members VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+        )?;
+
+        let insert_candy_machine_mint_statement = client.prepare(
+            "INSERT INTO candy_machine_mints VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
+                                                     $11)"
+        )?;
+
+        let insert_sale_statement = client.prepare(
+            "INSERT INTO sales VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"
+        )?;
+
+        let insert_royalty_payment_statement = client.prepare(
+            "INSERT INTO royalty_payments (
+               metadata_key, mint_key, creator_key, share, seller_fee_basis_points, expected,
+               paid, shortfall, ambiguous, slot, block_index, outer_index, inner_index
+             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"
+        )?;
+
+        let insert_open_order_statement = client.prepare(
+            "INSERT INTO open_orders VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13,
+                                             $14, $15)"
+        )?;
+
+        let insert_storefront_sale_statement = client.prepare(
+            "INSERT INTO storefront_sales VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11,
+                                                  $12, $13, $14, $15)"
+        )?;
+
+        let insert_vault_custody_statement = client.prepare(
+            "INSERT INTO vault_custody VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"
+        )?;
+
+        let insert_anomaly_statement = client.prepare(
+            "INSERT INTO anomalies VALUES ($1, $2, $3, $4, $5, $6, $7)"
+        )?;
+
+        let delete_bonbon_statements = [
+            "bonbons", "glazings", "authorities", "holders", "leaf_owners", "collections",
+            "collection_authorities", "collection_members", "candy_machine_mints", "sales",
+            "royalty_payments", "open_orders", "storefront_sales", "vault_custody", "anomalies",
+        ].iter()
+            .map(|table| client.prepare(
+                &format!("DELETE FROM {} WHERE metadata_key = $1", table)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let insert_failure_statement = client.prepare(
+            "INSERT INTO failures VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (phase, failure_key) DO UPDATE
+             SET slot = excluded.slot,
+                 block_index = excluded.block_index,
+                 error_kind = excluded.error_kind,
+                 error_details = excluded.error_details,
+                 code_version = excluded.code_version
+            ",
+        )?;
+
+        let select_failures_statement = client.prepare(
+            "SELECT *
+             FROM failures
+             WHERE phase = $1
+               AND failure_key > $2
+             ORDER BY failure_key
+             LIMIT $3
+            ",
+        )?;
+
+        let delete_failure_statement = client.prepare(
+            "DELETE FROM failures WHERE phase = $1 AND failure_key = $2"
+        )?;
+
+        Ok(Self {
+            client,
+            insert_transaction_statement,
+            select_transaction_statement,
+            select_transactions_statement,
+            insert_account_keys_statement,
+            insert_partition_statement,
+            insert_token_account_statement,
+            select_token_accounts_statement,
+            select_token_account_exists_statement,
+            select_partition_keys_statement,
+            select_partition_exists_statement,
+            select_partition_instructions_statement,
+            select_bonbon_mints_statement,
+            insert_bonbon_statement,
+            insert_glazing_statement,
+            insert_authority_statement,
+            insert_holder_statement,
+            insert_leaf_owner_statement,
+            insert_collection_statement,
+            insert_collection_authority_statement,
+            insert_collection_member_statement,
+            insert_candy_machine_mint_statement,
+            insert_sale_statement,
+            insert_royalty_payment_statement,
+            insert_open_order_statement,
+            insert_storefront_sale_statement,
+            insert_vault_custody_statement,
+            insert_anomaly_statement,
+            delete_bonbon_statements,
+            insert_failure_statement,
+            select_failures_statement,
+            delete_failure_statement,
+        })
+    }
+}
+
+impl Store for PsqlStore {
+    fn insert_transaction(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        signature: &[u8],
+        transaction: &[u8],
+    ) -> StoreResult<()> {
+        self.client.execute(
+            &self.insert_transaction_statement,
+            &[&slot, &block_index, &signature, &transaction],
+        )?;
+        Ok(())
+    }
+
+    fn transaction_at(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+    ) -> StoreResult<Option<TransactionRow>> {
+        let row = self.client.query_opt(
+            &self.select_transaction_statement,
+            &[&slot, &block_index],
+        )?;
+
+        Ok(row.map(|row| TransactionRow {
+            slot: row.get(0),
+            block_index: row.get(1),
+            signature: row.get(2),
+            transaction: row.get(3),
+        }))
+    }
+
+    fn transactions_after(
+        &mut self,
+        after: Option<(i64, i64)>,
+        limit: i64,
+    ) -> StoreResult<Vec<TransactionRow>> {
+        let (slot, block_index) = after.unwrap_or((-1, -1));
+        let rows = self.client.query(
+            &self.select_transactions_statement,
+            &[&slot, &block_index, &limit],
+        )?;
+
+        Ok(rows.into_iter().map(|row| TransactionRow {
+            slot: row.get(0),
+            block_index: row.get(1),
+            signature: row.get(2),
+            transaction: row.get(3),
+        }).collect())
+    }
+
+    fn insert_account_keys(
+        &mut self,
+        signature: &[u8],
+        keys: &[Pubkey],
+        metas: &[TransactionTokenMeta],
+        pre_balances: &[u64],
+        post_balances: &[u64],
+    ) -> StoreResult<()> {
+        self.client.execute(
+            &self.insert_account_keys_statement,
+            &[
+                &signature,
+                &keys.iter().map(|k| convert::SqlPubkey(*k)).collect::<Vec<_>>(),
+                &metas.iter().map(convert::TransactionTokenMeta::from).collect::<Vec<_>>(),
+                &pre_balances.iter().map(|b| *b as i64).collect::<Vec<_>>(),
+                &post_balances.iter().map(|b| *b as i64).collect::<Vec<_>>(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn insert_partition(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        signature: &[u8],
+        partitioned: &PartitionedInstruction,
+    ) -> StoreResult<()> {
+        let serialized = bincode::serialize(&partitioned.instruction)?;
+        self.client.execute(
+            &self.insert_partition_statement,
+            &[
+                &partitioned.partition_key.as_ref(),
+                &partitioned.program_key.as_ref(),
+                &slot,
+                &block_index,
+                &partitioned.outer_index,
+                &partitioned.inner_index,
+                &signature,
+                &serialized,
+                &partitioned.outer_program_key.as_ref(),
+                &partitioned.stack_height,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn insert_token_account_change(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        change: &TokenAccountChange,
+    ) -> StoreResult<()> {
+        self.client.execute(
+            &self.insert_token_account_statement,
+            &[
+                &change.account_key.as_ref(),
+                &change.mint_key.map(convert::SqlPubkey),
+                &change.owner_key.as_ref(),
+                &slot,
+                &block_index,
+                &change.outer_index,
+                &change.inner_index,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn has_token_account(
+        &mut self,
+        account_key: &Pubkey,
+    ) -> StoreResult<bool> {
+        let row = self.client.query_one(
+            &self.select_token_account_exists_statement,
+            &[&account_key.as_ref()],
+        )?;
+        Ok(row.get(0))
+    }
+
+    fn token_account_changes(
+        &mut self,
+        mint_key: &Pubkey,
+    ) -> StoreResult<Vec<TokenAccountRow>> {
+        let rows = self.client.query(
+            &self.select_token_accounts_statement,
+            &[&mint_key.as_ref()],
+        )?;
+
+        Ok(rows.into_iter().map(|row| TokenAccountRow {
+            slot: row.get(3),
+            block_index: row.get(4),
+            change: TokenAccountChange {
+                account_key: row.get::<_, convert::SqlPubkey>(0).0,
+                mint_key: row.get::<_, Option<convert::SqlPubkey>>(1).map(|k| k.0),
+                owner_key: row.get::<_, convert::SqlPubkey>(2).0,
+                outer_index: row.get(5),
+                inner_index: row.get(6),
+            },
+        }).collect())
+    }
+
+    fn partition_keys_after(
+        &mut self,
+        program_key: &Pubkey,
+        after: Option<Pubkey>,
+        limit: i64,
+    ) -> StoreResult<Vec<Pubkey>> {
+        // the empty bytea sorts before every key
+        let after = after.map(|k| k.as_ref().to_vec()).unwrap_or_default();
+        let rows = self.client.query(
+            &self.select_partition_keys_statement,
+            &[&program_key.as_ref(), &after, &limit],
+        )?;
+
+        Ok(rows.into_iter().map(|row| row.get::<_, convert::SqlPubkey>(0).0).collect())
+    }
+
+    fn has_partitions(
+        &mut self,
+        partition_key: &Pubkey,
+    ) -> StoreResult<bool> {
+        let row = self.client.query_one(
+            &self.select_partition_exists_statement,
+            &[&partition_key.as_ref()],
+        )?;
+        Ok(row.get(0))
+    }
+
+    fn partition_instructions(
+        &mut self,
+        mint_key: &Pubkey,
+        metadata_key: &Pubkey,
+    ) -> StoreResult<Vec<PartitionRow>> {
+        let rows = self.client.query(
+            &self.select_partition_instructions_statement,
+            &[&mint_key.as_ref(), &metadata_key.as_ref()],
+        )?;
+
+        let mut partitions = Vec::with_capacity(rows.len());
+        for row in rows {
+            let instruction = bincode::deserialize
+                ::<CompiledInstruction>(&row.get::<_, Vec<u8>>(1))?;
+
+            let keys: Vec<convert::SqlPubkey> = row.get(2);
+            let keys = keys.into_iter().map(|k| k.0).collect::<Vec<_>>();
+
+            let metas: Vec<convert::TransactionTokenMeta> = row.get(3);
+            let metas = metas.into_iter().map(|m| TransactionTokenOwnerMeta {
+                account_index: m.account_index as u8, // TODO: check?
+                mint_key: m.mint_key.0,
+                owner_key: m.owner_key.0,
+                pre_amount: m.pre_amount.map(|a| a as u64),
+                post_amount: m.post_amount.map(|a| a as u64),
+            }).collect::<Vec<_>>();
+
+            // NULL for rows partitioned before we stored balances
+            let balances = |index: usize| row.get::<_, Option<Vec<i64>>>(index)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|b| b as u64)
+                .collect::<Vec<_>>();
+
+            partitions.push(PartitionRow {
+                signature: row.get(0),
+                instruction,
+                keys,
+                metas,
+                pre_balances: balances(8),
+                post_balances: balances(9),
+                instruction_index: InstructionIndex {
+                    slot: row.get(4),
+                    block_index: row.get(5),
+                    outer_index: row.get(6),
+                    inner_index: row.get(7),
+                },
+            });
+        }
+
+        Ok(partitions)
+    }
+
+    fn bonbon_mints_with_instruction(
+        &mut self,
+        program_key: &Pubkey,
+        discriminant: u8,
+        after: Option<Pubkey>,
+        limit: i64,
+    ) -> StoreResult<Vec<Pubkey>> {
+        let after = after.map(|k| k.as_ref().to_vec()).unwrap_or_default();
+        let rows = self.client.query(
+            &self.select_bonbon_mints_statement,
+            &[&program_key.as_ref(), &&[discriminant][..], &after, &limit],
+        )?;
+
+        Ok(rows.into_iter().map(|row| row.get::<_, convert::SqlPubkey>(0).0).collect())
+    }
+
+    fn insert_bonbon(
+        &mut self,
+        bonbon: &Bonbon,
+    ) -> StoreResult<()> {
+        let mut transaction = self.client.transaction()?;
+
+        transaction.execute(
+            &self.insert_bonbon_statement,
+            &[
+                &bonbon.metadata_key.as_ref(),
+                &bonbon.mint_key.as_ref(),
+                &bonbon.current_owner.map(convert::SqlPubkey),
+                &bonbon.current_account.map(convert::SqlPubkey),
+                &convert::EditionStatus::from(bonbon.edition_status.clone()),
+                &bonbon.limited_edition.as_ref().map(convert::LimitedEdition::from),
+                &bonbon.update_authority().map(convert::SqlPubkey),
+                &bonbon.update_authority_changed(),
+                &bonbon.mint_authority().map(convert::SqlPubkey),
+                &bonbon.freeze_authority().map(convert::SqlPubkey),
+                &convert::SupplyStatus::from(bonbon.supply_status()),
+                &convert::TokenStandard::from(bonbon.token_standard()),
+                &(bonbon.supply as i64),
+                &bonbon.rule_set.map(convert::SqlPubkey),
+                &bonbon.token_record.as_ref().map(|r| convert::TokenState::from(r.state)),
+                &bonbon.token_record.as_ref()
+                    .and_then(|r| r.delegate).map(convert::SqlPubkey),
+                &bonbon.token_record.as_ref()
+                    .and_then(|r| r.delegate_role).map(convert::TokenDelegateRole::from),
+                &bonbon.compressed_leaf.as_ref().map(|l| convert::SqlPubkey(l.tree)),
+                &bonbon.compressed_leaf.as_ref().map(|l| l.nonce as i64),
+                &bonbon.compressed_leaf.as_ref().map(|l| l.redeemed),
+            ],
+        )?;
+
+        for glazing in &bonbon.glazings {
+            transaction.execute(
+                &self.insert_glazing_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &glazing.name,
+                    &glazing.symbol,
+                    &glazing.uri,
+                    &i32::from(glazing.seller_fee_basis_points),
+                    &glazing.collection.as_ref().map(|c| convert::SqlPubkey(c.address)),
+                    &glazing.collection.as_ref().map(|c| c.verified),
+                    &glazing.creators.get(0).map(convert::Creator::from),
+                    &glazing.creators.get(1).map(convert::Creator::from),
+                    &glazing.creators.get(2).map(convert::Creator::from),
+                    &glazing.creators.get(3).map(convert::Creator::from),
+                    &glazing.creators.get(4).map(convert::Creator::from),
+                    &glazing.uses.as_ref().map(convert::Uses::from),
+                    &glazing.primary_sale_happened,
+                    &glazing.is_mutable,
+                    &glazing.update_authority.map(convert::SqlPubkey),
+                    &glazing.instruction_index.slot,
+                    &glazing.instruction_index.block_index,
+                    &glazing.instruction_index.outer_index,
+                    &glazing.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        let authorities = [
+            (convert::AuthorityType::Update, &bonbon.update_authorities),
+            (convert::AuthorityType::Mint, &bonbon.mint_authorities),
+            (convert::AuthorityType::Freeze, &bonbon.freeze_authorities),
+        ];
+        for (authority_type, change) in authorities.iter()
+                .flat_map(|(t, changes)| changes.iter().map(move |c| (t, c))) {
+            transaction.execute(
+                &self.insert_authority_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    authority_type,
+                    &change.authority.map(convert::SqlPubkey),
+                    &change.instruction_index.slot,
+                    &change.instruction_index.block_index,
+                    &change.instruction_index.outer_index,
+                    &change.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        for holding in &bonbon.holdings {
+            transaction.execute(
+                &self.insert_holder_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &bonbon.mint_key.as_ref(),
+                    &holding.account.as_ref(),
+                    &holding.owner.map(convert::SqlPubkey),
+                    &(holding.amount as i64),
+                    &holding.frozen,
+                    &holding.instruction_index.slot,
+                    &holding.instruction_index.block_index,
+                    &holding.instruction_index.outer_index,
+                    &holding.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        for change in &bonbon.leaf_owners {
+            transaction.execute(
+                &self.insert_leaf_owner_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &change.owner.as_ref(),
+                    &change.delegate.as_ref(),
+                    &change.instruction_index.slot,
+                    &change.instruction_index.block_index,
+                    &change.instruction_index.outer_index,
+                    &change.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        if bonbon.is_collection() {
+            transaction.execute(
+                &self.insert_collection_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &bonbon.mint_key.as_ref(),
+                    &bonbon.collection_size.map(|s| s as i64),
+                ],
+            )?;
+        }
+
+        for change in &bonbon.collection_authorities {
+            transaction.execute(
+                &self.insert_collection_authority_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &change.authority.as_ref(),
+                    &change.record.as_ref(),
+                    &change.approved,
+                    &change.instruction_index.slot,
+                    &change.instruction_index.block_index,
+                    &change.instruction_index.outer_index,
+                    &change.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        for membership in bonbon.collection_memberships() {
+            transaction.execute(
+                &self.insert_collection_member_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &bonbon.mint_key.as_ref(),
+                    &membership.collection.as_ref(),
+                    &membership.verified,
+                    &membership.instruction_index.slot,
+                    &membership.instruction_index.block_index,
+                    &membership.instruction_index.outer_index,
+                    &membership.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        if let Some(candy_machine_mint) = &bonbon.candy_machine_mint {
+            transaction.execute(
+                &self.insert_candy_machine_mint_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &bonbon.mint_key.as_ref(),
+                    &convert::CandyMachineVersion::from(candy_machine_mint.version),
+                    &candy_machine_mint.candy_machine.as_ref(),
+                    &candy_machine_mint.config.map(convert::SqlPubkey),
+                    &candy_machine_mint.payer.as_ref(),
+                    &candy_machine_mint.price.map(|p| p as i64),
+                    &candy_machine_mint.instruction_index.slot,
+                    &candy_machine_mint.instruction_index.block_index,
+                    &candy_machine_mint.instruction_index.outer_index,
+                    &candy_machine_mint.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        for sale in &bonbon.sales {
+            transaction.execute(
+                &self.insert_sale_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &bonbon.mint_key.as_ref(),
+                    &sale.program_key.as_ref(),
+                    &sale.marketplace.map(convert::SqlPubkey),
+                    &sale.buyer.as_ref(),
+                    &sale.seller.as_ref(),
+                    &(sale.price as i64),
+                    &sale.currency_mint.as_ref(),
+                    &(sale.amount as i64),
+                    &sale.instruction_index.slot,
+                    &sale.instruction_index.block_index,
+                    &sale.instruction_index.outer_index,
+                    &sale.instruction_index.inner_index,
+                ],
+            )?;
+
+            for royalty in &sale.royalties {
+                transaction.execute(
+                    &self.insert_royalty_payment_statement,
+                    &[
+                        &bonbon.metadata_key.as_ref(),
+                        &bonbon.mint_key.as_ref(),
+                        &royalty.creator.as_ref(),
+                        &royalty.share,
+                        &i32::from(royalty.seller_fee_basis_points),
+                        &(royalty.expected as i64),
+                        &royalty.paid,
+                        &royalty.shortfall.map(|shortfall| shortfall as i64),
+                        &royalty.ambiguous,
+                        &sale.instruction_index.slot,
+                        &sale.instruction_index.block_index,
+                        &sale.instruction_index.outer_index,
+                        &sale.instruction_index.inner_index,
+                    ],
+                )?;
+            }
+        }
+
+        for order in &bonbon.open_orders {
+            transaction.execute(
+                &self.insert_open_order_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &bonbon.mint_key.as_ref(),
+                    &convert::OrderSide::from(order.side),
+                    &order.program_key.as_ref(),
+                    &order.marketplace.map(convert::SqlPubkey),
+                    &order.wallet.as_ref(),
+                    &order.token_account.map(convert::SqlPubkey),
+                    &order.delegate.map(convert::SqlPubkey),
+                    &order.price.map(|p| p as i64),
+                    &order.currency_mint.map(convert::SqlPubkey),
+                    &(order.amount as i64),
+                    &order.instruction_index.slot,
+                    &order.instruction_index.block_index,
+                    &order.instruction_index.outer_index,
+                    &order.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        for sale in &bonbon.storefront_sales {
+            transaction.execute(
+                &self.insert_storefront_sale_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &bonbon.mint_key.as_ref(),
+                    &convert::StorefrontSaleKind::from(sale.kind),
+                    &sale.program_key.as_ref(),
+                    &sale.manager.as_ref(),
+                    &sale.auction.map(convert::SqlPubkey),
+                    &sale.vault.map(convert::SqlPubkey),
+                    &sale.buyer.as_ref(),
+                    &sale.bidder_metadata.map(convert::SqlPubkey),
+                    &sale.price.map(|p| p as i64),
+                    &sale.currency_mint.map(convert::SqlPubkey),
+                    &sale.instruction_index.slot,
+                    &sale.instruction_index.block_index,
+                    &sale.instruction_index.outer_index,
+                    &sale.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        for custody in &bonbon.vault_custody {
+            transaction.execute(
+                &self.insert_vault_custody_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &bonbon.mint_key.as_ref(),
+                    &custody.vault.as_ref(),
+                    &custody.safety_deposit_box.as_ref(),
+                    &custody.deposit,
+                    &(custody.amount as i64),
+                    &custody.instruction_index.slot,
+                    &custody.instruction_index.block_index,
+                    &custody.instruction_index.outer_index,
+                    &custody.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        for anomaly in &bonbon.anomalies {
+            transaction.execute(
+                &self.insert_anomaly_statement,
+                &[
+                    &bonbon.metadata_key.as_ref(),
+                    &format!("{:?}", anomaly.error_kind),
+                    &anomaly.details,
+                    &anomaly.instruction_index.slot,
+                    &anomaly.instruction_index.block_index,
+                    &anomaly.instruction_index.outer_index,
+                    &anomaly.instruction_index.inner_index,
+                ],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn delete_bonbon(
+        &mut self,
+        metadata_key: &Pubkey,
+    ) -> StoreResult<()> {
+        let mut transaction = self.client.transaction()?;
+        for statement in &self.delete_bonbon_statements {
+            transaction.execute(statement, &[&metadata_key.as_ref()])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn insert_failure(
+        &mut self,
+        failure: &FailureRow,
+    ) -> StoreResult<()> {
+        self.client.execute(
+            &self.insert_failure_statement,
+            &[
+                &convert::FailurePhase::from(failure.phase),
+                &failure.key,
+                &failure.slot,
+                &failure.block_index,
+                &failure.error_kind,
+                &failure.error_details,
+                &failure.code_version,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn failures_after(
+        &mut self,
+        phase: FailurePhase,
+        after: Option<Vec<u8>>,
+        limit: i64,
+    ) -> StoreResult<Vec<FailureRow>> {
+        // the empty bytea sorts before every key
+        let after = after.unwrap_or_default();
+        let rows = self.client.query(
+            &self.select_failures_statement,
+            &[&convert::FailurePhase::from(phase), &after, &limit],
+        )?;
+
+        Ok(rows.into_iter().map(|row| FailureRow {
+            phase: row.get::<_, convert::FailurePhase>(0).into(),
+            key: row.get(1),
+            slot: row.get(2),
+            block_index: row.get(3),
+            error_kind: row.get(4),
+            error_details: row.get(5),
+            code_version: row.get(6),
+        }).collect())
+    }
+
+    fn delete_failure(
+        &mut self,
+        phase: FailurePhase,
+        key: &[u8],
+    ) -> StoreResult<()> {
+        self.client.execute(
+            &self.delete_failure_statement,
+            &[&convert::FailurePhase::from(phase), &key],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::store::tests::insert_and_delete_bonbon};
+
+    // Needs a postgres server with scripts/create_schema.sql loaded, e.g
+    // CHOCOLATIER_TEST_PSQL_CONFIG="host=/tmp user=postgres". Skipped when that isn't set
+    #[test]
+    fn insert_bonbon() {
+        let psql_config = match std::env::var("CHOCOLATIER_TEST_PSQL_CONFIG") {
+            Ok(psql_config) => psql_config,
+            Err(_) => {
+                eprintln!("CHOCOLATIER_TEST_PSQL_CONFIG not set, skipping");
+                return;
+            }
+        };
+        let mut store = PsqlStore::connect(&psql_config).expect("connects");
+        insert_and_delete_bonbon(&mut store);
+    }
+}
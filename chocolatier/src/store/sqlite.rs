@@ -0,0 +1,883 @@
+use {
+    super::{
+        FailurePhase, FailureRow, PartitionRow, Store, StoreResult, TokenAccountRow,
+        TransactionRow,
+    },
+    bonbon::{
+        assemble::{
+            Bonbon, CandyMachineVersion, EditionStatus, InstructionIndex, OrderSide,
+            StorefrontSaleKind, SupplyStatus, TokenDelegateRole, TokenStandard, TokenState,
+            TransactionTokenOwnerMeta, UseMethod,
+        },
+        partition::{PartitionedInstruction, TokenAccountChange, TransactionTokenMeta},
+    },
+    rusqlite::{params, OptionalExtension},
+    solana_sdk::{
+        instruction::CompiledInstruction,
+        pubkey::Pubkey,
+    },
+};
+
+// sqlite has no composite or array types so the columns that are `token_meta[]`, `creator`,
+// etc. in postgres are stored as bincode blobs of plain tuples instead
+type SqliteTokenMeta = (u8, Pubkey, Pubkey, Option<u64>, Option<u64>);
+
+type SqliteCreator = (Pubkey, bool, i16);
+
+pub struct SqliteStore {
+    connection: rusqlite::Connection,
+}
+
+impl SqliteStore {
+    // creates the schema if it doesn't exist yet
+    pub fn open(path: &str) -> StoreResult<Self> {
+        let connection = rusqlite::Connection::open(path)?;
+        connection.execute_batch(include_str!("../../../scripts/create_schema_sqlite.sql"))?;
+        Ok(Self { connection })
+    }
+
+    // for tests to check what was written
+    #[cfg(test)]
+    pub(crate) fn connection(&self) -> &rusqlite::Connection {
+        &self.connection
+    }
+}
+
+fn edition_status_str(edition_status: &EditionStatus) -> &'static str {
+    match edition_status {
+        EditionStatus::None => "none",
+        EditionStatus::Master => "master",
+        EditionStatus::Limited => "limited",
+    }
+}
+
+fn supply_status_str(supply_status: &SupplyStatus) -> &'static str {
+    match supply_status {
+        SupplyStatus::Unknown => "unknown",
+        SupplyStatus::Fixed => "fixed",
+        SupplyStatus::Mintable => "mintable",
+    }
+}
+
+fn token_standard_str(token_standard: &TokenStandard) -> &'static str {
+    match token_standard {
+        TokenStandard::NonFungible => "non_fungible",
+        TokenStandard::FungibleAsset => "fungible_asset",
+        TokenStandard::Fungible => "fungible",
+        TokenStandard::Edition => "edition",
+        TokenStandard::ProgrammableNonFungible => "programmable_non_fungible",
+        TokenStandard::ProgrammableEdition => "programmable_edition",
+    }
+}
+
+fn token_state_str(token_state: &TokenState) -> &'static str {
+    match token_state {
+        TokenState::Unlocked => "unlocked",
+        TokenState::Locked => "locked",
+        TokenState::Listed => "listed",
+    }
+}
+
+fn token_delegate_role_str(role: &TokenDelegateRole) -> &'static str {
+    match role {
+        TokenDelegateRole::Sale => "sale",
+        TokenDelegateRole::Transfer => "transfer",
+        TokenDelegateRole::Utility => "utility",
+        TokenDelegateRole::Staking => "staking",
+        TokenDelegateRole::Standard => "standard",
+        TokenDelegateRole::LockedTransfer => "locked_transfer",
+        TokenDelegateRole::Migration => "migration",
+    }
+}
+
+fn candy_machine_version_str(version: &CandyMachineVersion) -> &'static str {
+    match version {
+        CandyMachineVersion::V1 => "v1",
+        CandyMachineVersion::V2 => "v2",
+        CandyMachineVersion::Guard => "guard",
+    }
+}
+
+fn order_side_str(side: &OrderSide) -> &'static str {
+    match side {
+        OrderSide::Listing => "listing",
+        OrderSide::Bid => "bid",
+    }
+}
+
+fn storefront_sale_kind_str(kind: &StorefrontSaleKind) -> &'static str {
+    match kind {
+        StorefrontSaleKind::Auction => "auction",
+        StorefrontSaleKind::FullRightsTransfer => "full_rights_transfer",
+        StorefrontSaleKind::AuctionPrint => "auction_print",
+        StorefrontSaleKind::Participation => "participation",
+        StorefrontSaleKind::FixedPrice => "fixed_price",
+    }
+}
+
+fn use_method_str(use_method: &UseMethod) -> &'static str {
+    match use_method {
+        UseMethod::Burn => "burn",
+        UseMethod::Multiple => "multiple",
+        UseMethod::Single => "single",
+    }
+}
+
+fn failure_phase_str(phase: FailurePhase) -> &'static str {
+    match phase {
+        FailurePhase::Partition => "partition",
+        FailurePhase::Assemble => "assemble",
+    }
+}
+
+fn failure_phase_from_str(phase: &str) -> rusqlite::Result<FailurePhase> {
+    match phase {
+        "partition" => Ok(FailurePhase::Partition),
+        "assemble" => Ok(FailurePhase::Assemble),
+        _ => Err(rusqlite::Error::InvalidColumnType(
+            0, "phase".to_string(), rusqlite::types::Type::Text)),
+    }
+}
+
+fn pubkey_from_blob(blob: Vec<u8>) -> StoreResult<Pubkey> {
+    let fixed: [u8; 32] = blob.as_slice().try_into()?;
+    Ok(Pubkey::new_from_array(fixed))
+}
+
+impl Store for SqliteStore {
+    fn insert_transaction(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        signature: &[u8],
+        transaction: &[u8],
+    ) -> StoreResult<()> {
+        self.connection.prepare_cached(
+            "INSERT INTO transactions VALUES (?1, ?2, ?3, ?4)"
+        )?.execute(params![slot, block_index, signature, transaction])?;
+        Ok(())
+    }
+
+    fn transaction_at(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+    ) -> StoreResult<Option<TransactionRow>> {
+        let row = self.connection.prepare_cached(
+            "SELECT *
+             FROM transactions
+             WHERE slot = ?1
+               AND block_index = ?2
+            ",
+        )?.query_row(params![slot, block_index], |row| {
+            Ok(TransactionRow {
+                slot: row.get(0)?,
+                block_index: row.get(1)?,
+                signature: row.get(2)?,
+                transaction: row.get(3)?,
+            })
+        }).optional()?;
+
+        Ok(row)
+    }
+
+    fn transactions_after(
+        &mut self,
+        after: Option<(i64, i64)>,
+        limit: i64,
+    ) -> StoreResult<Vec<TransactionRow>> {
+        let (slot, block_index) = after.unwrap_or((-1, -1));
+        let mut statement = self.connection.prepare_cached(
+            "SELECT *
+             FROM transactions
+             WHERE (slot, block_index) > (?1, ?2)
+             ORDER BY slot, block_index
+             LIMIT ?3
+            ",
+        )?;
+
+        let rows = statement.query_map(params![slot, block_index, limit], |row| {
+            Ok(TransactionRow {
+                slot: row.get(0)?,
+                block_index: row.get(1)?,
+                signature: row.get(2)?,
+                transaction: row.get(3)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn insert_account_keys(
+        &mut self,
+        signature: &[u8],
+        keys: &[Pubkey],
+        metas: &[TransactionTokenMeta],
+        pre_balances: &[u64],
+        post_balances: &[u64],
+    ) -> StoreResult<()> {
+        let metas = metas.iter()
+            .map(|m| {
+                let owner_meta = TransactionTokenOwnerMeta::from(m);
+                (m.account_index, m.mint_key, m.owner_key,
+                 owner_meta.pre_amount, owner_meta.post_amount)
+            })
+            .collect::<Vec<SqliteTokenMeta>>();
+        self.connection.prepare_cached(
+            "INSERT INTO account_keys VALUES (?1, ?2, ?3, ?4, ?5)"
+        )?.execute(params![
+            signature,
+            bincode::serialize(keys)?,
+            bincode::serialize(&metas)?,
+            bincode::serialize(pre_balances)?,
+            bincode::serialize(post_balances)?,
+        ])?;
+        Ok(())
+    }
+
+    fn insert_partition(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        signature: &[u8],
+        partitioned: &PartitionedInstruction,
+    ) -> StoreResult<()> {
+        let serialized = bincode::serialize(&partitioned.instruction)?;
+        self.connection.prepare_cached(
+            "INSERT INTO partitions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
+        )?.execute(params![
+            partitioned.partition_key.as_ref(),
+            partitioned.program_key.as_ref(),
+            slot,
+            block_index,
+            partitioned.outer_index,
+            partitioned.inner_index,
+            signature,
+            serialized,
+            partitioned.outer_program_key.as_ref(),
+            partitioned.stack_height,
+        ])?;
+        Ok(())
+    }
+
+    fn insert_token_account_change(
+        &mut self,
+        slot: i64,
+        block_index: i64,
+        change: &TokenAccountChange,
+    ) -> StoreResult<()> {
+        self.connection.prepare_cached(
+            "INSERT INTO token_accounts VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+        )?.execute(params![
+            change.account_key.as_ref(),
+            change.mint_key.as_ref().map(|k| k.as_ref()),
+            change.owner_key.as_ref(),
+            slot,
+            block_index,
+            change.outer_index,
+            change.inner_index,
+        ])?;
+        Ok(())
+    }
+
+    fn has_token_account(
+        &mut self,
+        account_key: &Pubkey,
+    ) -> StoreResult<bool> {
+        Ok(self.connection.prepare_cached(
+            "SELECT EXISTS (SELECT 1 FROM token_accounts WHERE account_key = ?1)"
+        )?.query_row(params![account_key.as_ref()], |row| row.get(0))?)
+    }
+
+    fn token_account_changes(
+        &mut self,
+        mint_key: &Pubkey,
+    ) -> StoreResult<Vec<TokenAccountRow>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT *
+             FROM token_accounts
+             WHERE account_key IN (
+               SELECT account_key FROM token_accounts WHERE mint_key = ?1
+             )
+            ",
+        )?;
+
+        let rows = statement.query_map(
+            params![mint_key.as_ref()],
+            |row| Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, Option<Vec<u8>>>(1)?,
+                row.get::<_, Vec<u8>>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+            )),
+        )?;
+
+        let mut changes = vec![];
+        for row in rows {
+            let (account_key, change_mint_key, owner_key, slot, block_index,
+                 outer_index, inner_index) = row?;
+            changes.push(TokenAccountRow {
+                slot,
+                block_index,
+                change: TokenAccountChange {
+                    account_key: pubkey_from_blob(account_key)?,
+                    mint_key: change_mint_key.map(pubkey_from_blob).transpose()?,
+                    owner_key: pubkey_from_blob(owner_key)?,
+                    outer_index,
+                    inner_index,
+                },
+            });
+        }
+        Ok(changes)
+    }
+
+    fn partition_keys_after(
+        &mut self,
+        program_key: &Pubkey,
+        after: Option<Pubkey>,
+        limit: i64,
+    ) -> StoreResult<Vec<Pubkey>> {
+        // the empty blob sorts before every key
+        let after = after.map(|k| k.as_ref().to_vec()).unwrap_or_default();
+        let mut statement = self.connection.prepare_cached(
+            "SELECT DISTINCT partition_key
+             FROM partitions
+             WHERE program_key = ?1
+               AND partition_key > ?2
+             ORDER BY partition_key
+             LIMIT ?3
+            ",
+        )?;
+
+        let rows = statement.query_map(
+            params![program_key.as_ref(), after, limit],
+            |row| row.get::<_, Vec<u8>>(0),
+        )?;
+
+        let mut keys = vec![];
+        for row in rows {
+            keys.push(pubkey_from_blob(row?)?);
+        }
+        Ok(keys)
+    }
+
+    fn has_partitions(
+        &mut self,
+        partition_key: &Pubkey,
+    ) -> StoreResult<bool> {
+        Ok(self.connection.prepare_cached(
+            "SELECT EXISTS (SELECT 1 FROM partitions WHERE partition_key = ?1)"
+        )?.query_row(params![partition_key.as_ref()], |row| row.get(0))?)
+    }
+
+    fn partition_instructions(
+        &mut self,
+        mint_key: &Pubkey,
+        metadata_key: &Pubkey,
+    ) -> StoreResult<Vec<PartitionRow>> {
+        // outer instructions (NULL inner_index) come before their inner instructions, same as
+        // the postgres ordering
+        let mut statement = self.connection.prepare_cached(
+            "SELECT p.signature, p.instruction, a.keys, a.metas,
+                    p.slot, p.block_index, p.outer_index, p.inner_index,
+                    a.pre_balances, a.post_balances
+             FROM partitions p JOIN account_keys a ON p.signature = a.signature
+             WHERE partition_key = ?1
+                OR partition_key = ?2
+             ORDER BY slot, block_index, outer_index, inner_index NULLS FIRST
+            ",
+        )?;
+
+        let rows = statement.query_map(
+            params![mint_key.as_ref(), metadata_key.as_ref()],
+            |row| Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get::<_, Vec<u8>>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+                InstructionIndex {
+                    slot: row.get(4)?,
+                    block_index: row.get(5)?,
+                    outer_index: row.get(6)?,
+                    inner_index: row.get(7)?,
+                },
+                row.get::<_, Option<Vec<u8>>>(8)?,
+                row.get::<_, Option<Vec<u8>>>(9)?,
+            )),
+        )?;
+
+        let mut partitions = vec![];
+        for row in rows {
+            let (
+                signature, instruction, keys, metas, instruction_index,
+                pre_balances, post_balances,
+            ) = row?;
+            let instruction = bincode::deserialize::<CompiledInstruction>(&instruction)?;
+            let keys = bincode::deserialize::<Vec<Pubkey>>(&keys)?;
+            let metas = bincode::deserialize::<Vec<SqliteTokenMeta>>(&metas)?
+                .into_iter()
+                .map(|(account_index, mint_key, owner_key, pre_amount, post_amount)| {
+                    TransactionTokenOwnerMeta {
+                        account_index,
+                        mint_key,
+                        owner_key,
+                        pre_amount,
+                        post_amount,
+                    }
+                })
+                .collect();
+            // NULL for rows partitioned before we stored balances
+            let balances = |balances: Option<Vec<u8>>| -> StoreResult<Vec<u64>> {
+                match balances {
+                    Some(balances) => Ok(bincode::deserialize::<Vec<u64>>(&balances)?),
+                    None => Ok(vec![]),
+                }
+            };
+
+            partitions.push(PartitionRow {
+                signature,
+                instruction,
+                keys,
+                metas,
+                pre_balances: balances(pre_balances)?,
+                post_balances: balances(post_balances)?,
+                instruction_index,
+            });
+        }
+
+        Ok(partitions)
+    }
+
+    fn bonbon_mints_with_instruction(
+        &mut self,
+        program_key: &Pubkey,
+        discriminant: u8,
+        after: Option<Pubkey>,
+        limit: i64,
+    ) -> StoreResult<Vec<Pubkey>> {
+        let after = after.map(|k| k.as_ref().to_vec()).unwrap_or_default();
+        // the same offset as in postgres. sqlite has no get_byte but a byte below 128 cast to
+        // text is the character with that code
+        let mut statement = self.connection.prepare_cached(
+            "SELECT DISTINCT b.mint_key
+             FROM partitions p JOIN bonbons b ON p.partition_key = b.metadata_key
+             WHERE p.program_key = ?1
+               AND substr(
+                 p.instruction, 4 + unicode(CAST(substr(p.instruction, 2, 1) AS TEXT)), 1
+               ) = ?2
+               AND b.mint_key > ?3
+             ORDER BY b.mint_key
+             LIMIT ?4
+            ",
+        )?;
+
+        let rows = statement.query_map(
+            params![program_key.as_ref(), [discriminant].as_slice(), after, limit],
+            |row| row.get::<_, Vec<u8>>(0),
+        )?;
+
+        let mut keys = vec![];
+        for row in rows {
+            keys.push(pubkey_from_blob(row?)?);
+        }
+        Ok(keys)
+    }
+
+    fn insert_bonbon(
+        &mut self,
+        bonbon: &Bonbon,
+    ) -> StoreResult<()> {
+        let transaction = self.connection.transaction()?;
+
+        transaction.prepare_cached(
+            "INSERT INTO bonbons VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13,
+                                         ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)"
+        )?.execute(params![
+            bonbon.metadata_key.as_ref(),
+            bonbon.mint_key.as_ref(),
+            bonbon.current_owner.as_ref().map(|k| k.as_ref()),
+            bonbon.current_account.as_ref().map(|k| k.as_ref()),
+            edition_status_str(&bonbon.edition_status),
+            bonbon.limited_edition.as_ref().map(|e| e.master_key.as_ref()),
+            bonbon.limited_edition.as_ref().and_then(|e| e.edition_num),
+            bonbon.update_authority().as_ref().map(|k| k.as_ref()),
+            bonbon.update_authority_changed(),
+            bonbon.mint_authority().as_ref().map(|k| k.as_ref()),
+            bonbon.freeze_authority().as_ref().map(|k| k.as_ref()),
+            supply_status_str(&bonbon.supply_status()),
+            token_standard_str(&bonbon.token_standard()),
+            bonbon.supply as i64,
+            bonbon.rule_set.as_ref().map(|k| k.as_ref()),
+            bonbon.token_record.as_ref().map(|r| token_state_str(&r.state)),
+            bonbon.token_record.as_ref().and_then(|r| r.delegate.as_ref()).map(|k| k.as_ref()),
+            bonbon.token_record.as_ref()
+                .and_then(|r| r.delegate_role.as_ref()).map(token_delegate_role_str),
+            bonbon.compressed_leaf.as_ref().map(|l| l.tree.as_ref()),
+            bonbon.compressed_leaf.as_ref().map(|l| l.nonce as i64),
+            bonbon.compressed_leaf.as_ref().map(|l| l.redeemed),
+        ])?;
+
+        for glazing in &bonbon.glazings {
+            let creators = glazing.creators.iter()
+                .map(|c| (c.address, c.verified, c.share))
+                .collect::<Vec<SqliteCreator>>();
+            transaction.prepare_cached(
+                "INSERT INTO glazings VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13,
+                                              ?14, ?15, ?16, ?17, ?18)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                glazing.name,
+                glazing.symbol,
+                glazing.uri,
+                glazing.seller_fee_basis_points,
+                glazing.collection.as_ref().map(|c| c.address.as_ref()),
+                glazing.collection.as_ref().map(|c| c.verified),
+                bincode::serialize(&creators)?,
+                glazing.uses.as_ref().map(|u| use_method_str(&u.use_method)),
+                glazing.uses.as_ref().map(|u| u.remaining),
+                glazing.uses.as_ref().map(|u| u.total),
+                glazing.primary_sale_happened,
+                glazing.is_mutable,
+                glazing.update_authority.as_ref().map(|k| k.as_ref()),
+                glazing.instruction_index.slot,
+                glazing.instruction_index.block_index,
+                glazing.instruction_index.outer_index,
+                glazing.instruction_index.inner_index,
+            ])?;
+        }
+
+        let authorities = [
+            ("update", &bonbon.update_authorities),
+            ("mint", &bonbon.mint_authorities),
+            ("freeze", &bonbon.freeze_authorities),
+        ];
+        for (authority_type, change) in authorities.iter()
+                .flat_map(|(t, changes)| changes.iter().map(move |c| (t, c))) {
+            transaction.prepare_cached(
+                "INSERT INTO authorities VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                authority_type,
+                change.authority.as_ref().map(|k| k.as_ref()),
+                change.instruction_index.slot,
+                change.instruction_index.block_index,
+                change.instruction_index.outer_index,
+                change.instruction_index.inner_index,
+            ])?;
+        }
+
+        for holding in &bonbon.holdings {
+            transaction.prepare_cached(
+                "INSERT INTO holders VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                bonbon.mint_key.as_ref(),
+                holding.account.as_ref(),
+                holding.owner.as_ref().map(|k| k.as_ref()),
+                holding.amount as i64,
+                holding.frozen,
+                holding.instruction_index.slot,
+                holding.instruction_index.block_index,
+                holding.instruction_index.outer_index,
+                holding.instruction_index.inner_index,
+            ])?;
+        }
+
+        for change in &bonbon.leaf_owners {
+            transaction.prepare_cached(
+                "INSERT INTO leaf_owners VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                change.owner.as_ref(),
+                change.delegate.as_ref(),
+                change.instruction_index.slot,
+                change.instruction_index.block_index,
+                change.instruction_index.outer_index,
+                change.instruction_index.inner_index,
+            ])?;
+        }
+
+        if bonbon.is_collection() {
+            transaction.prepare_cached(
+                "INSERT INTO collections VALUES (?1, ?2, ?3)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                bonbon.mint_key.as_ref(),
+                bonbon.collection_size.map(|s| s as i64),
+            ])?;
+        }
+
+        for change in &bonbon.collection_authorities {
+            transaction.prepare_cached(
+                "INSERT INTO collection_authorities VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                change.authority.as_ref(),
+                change.record.as_ref(),
+                change.approved,
+                change.instruction_index.slot,
+                change.instruction_index.block_index,
+                change.instruction_index.outer_index,
+                change.instruction_index.inner_index,
+            ])?;
+        }
+
+        for membership in bonbon.collection_memberships() {
+            transaction.prepare_cached(
+                "INSERT INTO collection_members VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                bonbon.mint_key.as_ref(),
+                membership.collection.as_ref(),
+                membership.verified,
+                membership.instruction_index.slot,
+                membership.instruction_index.block_index,
+                membership.instruction_index.outer_index,
+                membership.instruction_index.inner_index,
+            ])?;
+        }
+
+        if let Some(candy_machine_mint) = &bonbon.candy_machine_mint {
+            transaction.prepare_cached(
+                "INSERT INTO candy_machine_mints VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10,
+                                                         ?11)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                bonbon.mint_key.as_ref(),
+                candy_machine_version_str(&candy_machine_mint.version),
+                candy_machine_mint.candy_machine.as_ref(),
+                candy_machine_mint.config.as_ref().map(|k| k.as_ref()),
+                candy_machine_mint.payer.as_ref(),
+                candy_machine_mint.price.map(|p| p as i64),
+                candy_machine_mint.instruction_index.slot,
+                candy_machine_mint.instruction_index.block_index,
+                candy_machine_mint.instruction_index.outer_index,
+                candy_machine_mint.instruction_index.inner_index,
+            ])?;
+        }
+
+        for sale in &bonbon.sales {
+            transaction.prepare_cached(
+                "INSERT INTO sales VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                bonbon.mint_key.as_ref(),
+                sale.program_key.as_ref(),
+                sale.marketplace.as_ref().map(|k| k.as_ref()),
+                sale.buyer.as_ref(),
+                sale.seller.as_ref(),
+                sale.price as i64,
+                sale.currency_mint.as_ref(),
+                sale.amount as i64,
+                sale.instruction_index.slot,
+                sale.instruction_index.block_index,
+                sale.instruction_index.outer_index,
+                sale.instruction_index.inner_index,
+            ])?;
+
+            for royalty in &sale.royalties {
+                transaction.prepare_cached(
+                    "INSERT INTO royalty_payments VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10,
+                                                          ?11, ?12, ?13)"
+                )?.execute(params![
+                    bonbon.metadata_key.as_ref(),
+                    bonbon.mint_key.as_ref(),
+                    royalty.creator.as_ref(),
+                    royalty.share,
+                    royalty.seller_fee_basis_points,
+                    royalty.expected as i64,
+                    royalty.paid,
+                    royalty.shortfall.map(|shortfall| shortfall as i64),
+                    royalty.ambiguous,
+                    sale.instruction_index.slot,
+                    sale.instruction_index.block_index,
+                    sale.instruction_index.outer_index,
+                    sale.instruction_index.inner_index,
+                ])?;
+            }
+        }
+
+        for order in &bonbon.open_orders {
+            transaction.prepare_cached(
+                "INSERT INTO open_orders VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+                                                 ?13, ?14, ?15)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                bonbon.mint_key.as_ref(),
+                order_side_str(&order.side),
+                order.program_key.as_ref(),
+                order.marketplace.as_ref().map(|k| k.as_ref()),
+                order.wallet.as_ref(),
+                order.token_account.as_ref().map(|k| k.as_ref()),
+                order.delegate.as_ref().map(|k| k.as_ref()),
+                order.price.map(|p| p as i64),
+                order.currency_mint.as_ref().map(|k| k.as_ref()),
+                order.amount as i64,
+                order.instruction_index.slot,
+                order.instruction_index.block_index,
+                order.instruction_index.outer_index,
+                order.instruction_index.inner_index,
+            ])?;
+        }
+
+        for sale in &bonbon.storefront_sales {
+            transaction.prepare_cached(
+                "INSERT INTO storefront_sales VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11,
+                                                      ?12, ?13, ?14, ?15)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                bonbon.mint_key.as_ref(),
+                storefront_sale_kind_str(&sale.kind),
+                sale.program_key.as_ref(),
+                sale.manager.as_ref(),
+                sale.auction.as_ref().map(|k| k.as_ref()),
+                sale.vault.as_ref().map(|k| k.as_ref()),
+                sale.buyer.as_ref(),
+                sale.bidder_metadata.as_ref().map(|k| k.as_ref()),
+                sale.price.map(|p| p as i64),
+                sale.currency_mint.as_ref().map(|k| k.as_ref()),
+                sale.instruction_index.slot,
+                sale.instruction_index.block_index,
+                sale.instruction_index.outer_index,
+                sale.instruction_index.inner_index,
+            ])?;
+        }
+
+        for custody in &bonbon.vault_custody {
+            transaction.prepare_cached(
+                "INSERT INTO vault_custody VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                bonbon.mint_key.as_ref(),
+                custody.vault.as_ref(),
+                custody.safety_deposit_box.as_ref(),
+                custody.deposit,
+                custody.amount as i64,
+                custody.instruction_index.slot,
+                custody.instruction_index.block_index,
+                custody.instruction_index.outer_index,
+                custody.instruction_index.inner_index,
+            ])?;
+        }
+
+        for anomaly in &bonbon.anomalies {
+            transaction.prepare_cached(
+                "INSERT INTO anomalies VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            )?.execute(params![
+                bonbon.metadata_key.as_ref(),
+                format!("{:?}", anomaly.error_kind),
+                anomaly.details,
+                anomaly.instruction_index.slot,
+                anomaly.instruction_index.block_index,
+                anomaly.instruction_index.outer_index,
+                anomaly.instruction_index.inner_index,
+            ])?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn delete_bonbon(
+        &mut self,
+        metadata_key: &Pubkey,
+    ) -> StoreResult<()> {
+        let transaction = self.connection.transaction()?;
+        // every table written by `insert_bonbon`
+        for table in [
+            "bonbons", "glazings", "authorities", "holders", "leaf_owners", "collections",
+            "collection_authorities", "collection_members", "candy_machine_mints", "sales",
+            "royalty_payments", "open_orders", "storefront_sales", "vault_custody", "anomalies",
+        ] {
+            transaction.prepare_cached(
+                &format!("DELETE FROM {} WHERE metadata_key = ?1", table)
+            )?.execute(params![metadata_key.as_ref()])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn insert_failure(
+        &mut self,
+        failure: &FailureRow,
+    ) -> StoreResult<()> {
+        self.connection.prepare_cached(
+            "INSERT INTO failures VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (phase, failure_key) DO UPDATE
+             SET slot = excluded.slot,
+                 block_index = excluded.block_index,
+                 error_kind = excluded.error_kind,
+                 error_details = excluded.error_details,
+                 code_version = excluded.code_version
+            "
+        )?.execute(params![
+            failure_phase_str(failure.phase),
+            failure.key,
+            failure.slot,
+            failure.block_index,
+            failure.error_kind,
+            failure.error_details,
+            failure.code_version,
+        ])?;
+        Ok(())
+    }
+
+    fn failures_after(
+        &mut self,
+        phase: FailurePhase,
+        after: Option<Vec<u8>>,
+        limit: i64,
+    ) -> StoreResult<Vec<FailureRow>> {
+        // the empty blob sorts before every key
+        let after = after.unwrap_or_default();
+        let mut statement = self.connection.prepare_cached(
+            "SELECT *
+             FROM failures
+             WHERE phase = ?1
+               AND failure_key > ?2
+             ORDER BY failure_key
+             LIMIT ?3
+            ",
+        )?;
+
+        let rows = statement.query_map(
+            params![failure_phase_str(phase), after, limit],
+            |row| Ok(FailureRow {
+                phase: failure_phase_from_str(&row.get::<_, String>(0)?)?,
+                key: row.get(1)?,
+                slot: row.get(2)?,
+                block_index: row.get(3)?,
+                error_kind: row.get(4)?,
+                error_details: row.get(5)?,
+                code_version: row.get(6)?,
+            }),
+        )?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn delete_failure(
+        &mut self,
+        phase: FailurePhase,
+        key: &[u8],
+    ) -> StoreResult<()> {
+        self.connection.prepare_cached(
+            "DELETE FROM failures WHERE phase = ?1 AND failure_key = ?2"
+        )?.execute(params![failure_phase_str(phase), key])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::store::tests::insert_and_delete_bonbon};
+
+    #[test]
+    fn insert_bonbon() {
+        let mut store = SqliteStore::open(":memory:").expect("schema creates");
+        insert_and_delete_bonbon(&mut store);
+    }
+}
@@ -6,6 +6,8 @@ CREATE TABLE transactions (
   transaction BYTEA
 );
 
+CREATE INDEX by_slot ON transactions (slot, block_index) ;
+
 CREATE TABLE partitions (
   partition_key BYTEA NOT NULL,
   program_key BYTEA NOT NULL,
@@ -14,7 +16,10 @@ CREATE TABLE partitions (
   outer_index BIGINT NOT NULL,
   inner_index BIGINT,
   signature BYTEA NOT NULL,
-  instruction BYTEA
+  instruction BYTEA,
+  outer_program_key BYTEA NOT NULL,
+  -- 1 for outer instructions. NULL if it couldn't be read from the logs
+  stack_height BIGINT
 );
 
 CREATE INDEX by_partition_key ON partitions (partition_key) ;
@@ -22,13 +27,19 @@ CREATE INDEX by_partition_key ON partitions (partition_key) ;
 CREATE TYPE token_meta AS (
   account_index SMALLINT,
   mint_key BYTEA,
-  owner_key BYTEA
+  owner_key BYTEA,
+  -- u64 token amounts before and after the transaction
+  pre_amount BIGINT,
+  post_amount BIGINT
 );
 
 CREATE TABLE account_keys (
   signature BYTEA PRIMARY KEY,
   keys BYTEA[],
-  metas token_meta[]
+  metas token_meta[],
+  -- u64 lamports per key before and after the transaction
+  pre_balances BIGINT[],
+  post_balances BIGINT[]
 );
 
 
@@ -44,13 +55,83 @@ CREATE TYPE limited_edition AS (
   edition_num BIGINT
 );
 
+CREATE TYPE supply_status AS enum (
+  'unknown',
+  'fixed',
+  'mintable'
+);
+
+CREATE TYPE token_standard AS enum (
+  'non_fungible',
+  'fungible_asset',
+  'fungible',
+  'edition',
+  'programmable_non_fungible',
+  'programmable_edition'
+);
+
+CREATE TYPE token_state AS enum (
+  'unlocked',
+  'locked',
+  'listed'
+);
+
+CREATE TYPE token_delegate_role AS enum (
+  'sale',
+  'transfer',
+  'utility',
+  'staking',
+  'standard',
+  'locked_transfer',
+  'migration'
+);
+
 CREATE TABLE bonbons (
   metadata_key BYTEA NOT NULL,
   mint_key BYTEA NOT NULL,
   current_owner BYTEA,
   current_account BYTEA,
   edition_status edition_status NOT NULL,
-  limited_edition limited_edition
+  limited_edition limited_edition,
+  update_authority BYTEA,
+  -- whether the update authority changed hands after the metadata was created. the history is
+  -- in authorities
+  update_authority_changed BOOLEAN NOT NULL,
+  mint_authority BYTEA,
+  freeze_authority BYTEA,
+  supply_status supply_status NOT NULL,
+  token_standard token_standard NOT NULL,
+  -- u64 but close enough...
+  supply BIGINT NOT NULL,
+  -- programmable assets only. the token_* columns are the current account's token record and
+  -- are NULL until we see one
+  rule_set BYTEA,
+  token_state token_state,
+  token_delegate BYTEA,
+  token_delegate_role token_delegate_role,
+  -- compressed NFTs only (metadata_key and mint_key are the asset id). NULL once decompressed
+  tree_key BYTEA,
+  -- u64 but close enough...
+  leaf_nonce BIGINT,
+  leaf_redeemed BOOLEAN
+);
+
+CREATE TYPE authority_type AS enum (
+  'update',
+  'mint',
+  'freeze'
+);
+
+-- authority history per bonbon. the latest row per type is the current authority
+CREATE TABLE authorities (
+  metadata_key BYTEA NOT NULL,
+  authority_type authority_type NOT NULL,
+  -- NULL if revoked
+  authority BYTEA,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
 );
 
 CREATE TYPE creator AS (
@@ -59,9 +140,25 @@ CREATE TYPE creator AS (
   share SMALLINT
 );
 
+CREATE TYPE use_method AS enum (
+  'burn',
+  'multiple',
+  'single'
+);
+
+CREATE TYPE uses AS (
+  use_method use_method,
+  -- u64 but close enough...
+  remaining BIGINT,
+  total BIGINT
+);
+
 CREATE TABLE glazings (
   metadata_key BYTEA NOT NULL,
+  name BYTEA,
+  symbol BYTEA,
   uri BYTEA,
+  seller_fee_basis_points INTEGER NOT NULL,
   collection_key BYTEA,
   collection_verified BOOLEAN,
   creator0 creator,
@@ -69,9 +166,269 @@ CREATE TABLE glazings (
   creator2 creator,
   creator3 creator,
   creator4 creator,
+  uses uses,
+  primary_sale_happened BOOLEAN NOT NULL,
+  is_mutable BOOLEAN NOT NULL,
+  update_authority BYTEA,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+
+-- token accounts holding a non-zero balance of the bonbon's mint once assembled. the index is
+-- the last instruction that changed the account's balance or owner
+CREATE TABLE holders (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  account_key BYTEA NOT NULL,
+  owner_key BYTEA,
+  amount BIGINT NOT NULL,
+  frozen BOOLEAN NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+-- owner and delegate history of compressed NFTs, which have no token accounts to hold them.
+-- the latest row is the current owner
+CREATE TABLE leaf_owners (
+  metadata_key BYTEA NOT NULL,
+  owner_key BYTEA NOT NULL,
+  -- the owner if there's no delegate
+  delegate_key BYTEA NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+-- collection NFTs we've seen sized or delegating authority. size is as created or last set, NULL
+-- for unsized collections
+CREATE TABLE collections (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  -- u64 but close enough...
+  size BIGINT
+);
+
+-- collection authority records and collection delegates approved (approved = true) or revoked
+CREATE TABLE collection_authorities (
+  metadata_key BYTEA NOT NULL,
+  authority_key BYTEA NOT NULL,
+  record_key BYTEA NOT NULL,
+  approved BOOLEAN NOT NULL,
   slot BIGINT NOT NULL,
   block_index BIGINT NOT NULL,
   outer_index BIGINT NOT NULL,
   inner_index BIGINT
 );
 
+-- bonbons joining (verified = true) and leaving verified collections. the latest row per
+-- metadata_key and collection_key is the current membership
+CREATE TABLE collection_members (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  collection_key BYTEA NOT NULL,
+  verified BOOLEAN NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE INDEX by_collection ON collection_members (collection_key);
+
+CREATE TYPE candy_machine_version AS enum (
+  'v1',
+  'v2',
+  'guard'
+);
+
+-- the candy machine that minted the bonbon, if any
+CREATE TABLE candy_machine_mints (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  version candy_machine_version NOT NULL,
+  candy_machine_key BYTEA NOT NULL,
+  -- the config account for v1, the candy guard for guard. NULL for v2
+  config_key BYTEA,
+  payer_key BYTEA NOT NULL,
+  -- u64 lamports paid to the candy machine's wallet. NULL for guard
+  price BIGINT,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE INDEX by_candy_machine ON candy_machine_mints (candy_machine_key);
+
+-- marketplace sales per bonbon
+CREATE TABLE sales (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  -- the marketplace program, and its account for the marketplace if it has one (e.g the
+  -- auction house)
+  program_key BYTEA NOT NULL,
+  marketplace_key BYTEA,
+  buyer_key BYTEA NOT NULL,
+  seller_key BYTEA NOT NULL,
+  -- u64 total paid in the smallest unit of currency_mint (the native mint for SOL)
+  price BIGINT NOT NULL,
+  currency_mint BYTEA NOT NULL,
+  amount BIGINT NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+-- royalties owed to each verified creator on a sale (joined on the sale's index) against their
+-- balance change in the sale's currency over the transaction
+CREATE TABLE royalty_payments (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  creator_key BYTEA NOT NULL,
+  -- the creator's share and the seller fee as of the sale
+  share SMALLINT NOT NULL,
+  seller_fee_basis_points INTEGER NOT NULL,
+  -- u64 in the smallest unit of the sale's currency_mint
+  expected BIGINT NOT NULL,
+  -- the creator's balance change over the transaction. NULL if the transaction had no status
+  -- meta, the creator's balance isn't in it or the payment is ambiguous
+  paid BIGINT,
+  -- how much of expected the creator didn't get. NULL when paid is
+  shortfall BIGINT,
+  -- the balance change would count more than this royalty: the creator was the buyer or the
+  -- seller, or the transaction has more than one sale
+  ambiguous BOOLEAN NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE TYPE order_side AS enum (
+  'listing',
+  'bid'
+);
+
+-- listings and bids still open after the bonbon's last transaction. join with collections for
+-- what's currently listed in a collection
+CREATE TABLE open_orders (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  side order_side NOT NULL,
+  -- as in sales. the token program for escrowless listings, which we only see as a delegate
+  -- approval on the seller's token account
+  program_key BYTEA NOT NULL,
+  marketplace_key BYTEA,
+  -- the seller for listings, the buyer for bids
+  wallet_key BYTEA NOT NULL,
+  token_account_key BYTEA,
+  delegate_key BYTEA,
+  -- u64 total asked or offered. NULL for escrowless listings
+  price BIGINT,
+  -- NULL if the instruction doesn't pass it
+  currency_mint BYTEA,
+  amount BIGINT NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE TYPE storefront_sale_kind AS enum (
+  'auction',
+  'full_rights_transfer',
+  'auction_print',
+  'participation',
+  'fixed_price'
+);
+
+-- how the bonbon left a legacy Metaplex storefront (2021-era auctions and fixed price sales)
+CREATE TABLE storefront_sales (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  kind storefront_sale_kind NOT NULL,
+  program_key BYTEA NOT NULL,
+  -- the auction manager, or the fixed price sale's market
+  manager_key BYTEA NOT NULL,
+  -- NULL for fixed price sales
+  auction_key BYTEA,
+  vault_key BYTEA,
+  buyer_key BYTEA NOT NULL,
+  -- holds the winning bid, which the redemption doesn't pass. NULL for fixed price sales
+  bidder_metadata_key BYTEA,
+  -- u64 paid for fixed price sales. NULL for auctions
+  price BIGINT,
+  currency_mint BYTEA,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+-- token vault deposits (deposit = true) and withdrawals
+CREATE TABLE vault_custody (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  vault_key BYTEA NOT NULL,
+  safety_deposit_box_key BYTEA NOT NULL,
+  deposit BOOLEAN NOT NULL,
+  amount BIGINT NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE TYPE failure_phase AS enum (
+  'partition',
+  'assemble'
+);
+
+-- dead letters from partition / reassemble, kept until `retry-failures` gets them through
+CREATE TABLE failures (
+  phase failure_phase NOT NULL,
+  -- transaction signature for partition failures, mint key for assemble failures
+  failure_key BYTEA NOT NULL,
+  -- only for partition failures
+  slot BIGINT,
+  block_index BIGINT,
+  error_kind TEXT NOT NULL,
+  error_details TEXT NOT NULL,
+  -- chocolatier version that last failed on this
+  code_version TEXT NOT NULL,
+  PRIMARY KEY (phase, failure_key)
+);
+
+-- instructions skipped while assembling a bonbon in lenient mode
+CREATE TABLE anomalies (
+  metadata_key BYTEA NOT NULL,
+  error_kind TEXT NOT NULL,
+  error_details TEXT NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+-- token account initializations (with mint_key) and owner changes (without) for the mints that
+-- have partitioned instructions. lets reassembly find owners for accounts missing token balances
+CREATE TABLE token_accounts (
+  account_key BYTEA NOT NULL,
+  mint_key BYTEA,
+  owner_key BYTEA NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE INDEX by_token_account ON token_accounts (account_key);
+
+CREATE INDEX by_token_account_mint ON token_accounts (mint_key);
@@ -0,0 +1,287 @@
+-- sqlite mirror of create_schema.sql. composite and array columns are bincode blobs
+
+CREATE TABLE IF NOT EXISTS transactions (
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  signature BLOB NOT NULL,
+  "transaction" BLOB
+);
+
+CREATE INDEX IF NOT EXISTS by_slot ON transactions (slot, block_index);
+
+CREATE TABLE IF NOT EXISTS partitions (
+  partition_key BLOB NOT NULL,
+  program_key BLOB NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER,
+  signature BLOB NOT NULL,
+  instruction BLOB,
+  outer_program_key BLOB NOT NULL,
+  stack_height INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS by_partition_key ON partitions (partition_key);
+
+CREATE TABLE IF NOT EXISTS account_keys (
+  signature BLOB PRIMARY KEY,
+  -- Vec<Pubkey>
+  keys BLOB,
+  -- Vec<(account_index, mint_key, owner_key, pre_amount, post_amount)>
+  metas BLOB,
+  -- Vec<u64> lamports per key before and after the transaction
+  pre_balances BLOB,
+  post_balances BLOB
+);
+
+CREATE TABLE IF NOT EXISTS bonbons (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  current_owner BLOB,
+  current_account BLOB,
+  edition_status TEXT NOT NULL CHECK (edition_status IN ('none', 'master', 'limited')),
+  limited_edition_master_key BLOB,
+  limited_edition_num INTEGER,
+  update_authority BLOB,
+  -- whether the update authority changed hands after the metadata was created. the history is
+  -- in authorities
+  update_authority_changed INTEGER NOT NULL,
+  mint_authority BLOB,
+  freeze_authority BLOB,
+  supply_status TEXT NOT NULL CHECK (supply_status IN ('unknown', 'fixed', 'mintable')),
+  token_standard TEXT NOT NULL
+    CHECK (token_standard IN (
+      'non_fungible', 'fungible_asset', 'fungible', 'edition',
+      'programmable_non_fungible', 'programmable_edition')),
+  supply INTEGER NOT NULL,
+  rule_set BLOB,
+  token_state TEXT CHECK (token_state IN ('unlocked', 'locked', 'listed')),
+  token_delegate BLOB,
+  token_delegate_role TEXT
+    CHECK (token_delegate_role IN (
+      'sale', 'transfer', 'utility', 'staking', 'standard', 'locked_transfer', 'migration')),
+  tree_key BLOB,
+  leaf_nonce INTEGER,
+  leaf_redeemed INTEGER
+);
+
+-- authority history per bonbon. the latest row per type is the current authority
+CREATE TABLE IF NOT EXISTS authorities (
+  metadata_key BLOB NOT NULL,
+  authority_type TEXT NOT NULL CHECK (authority_type IN ('update', 'mint', 'freeze')),
+  -- NULL if revoked
+  authority BLOB,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS glazings (
+  metadata_key BLOB NOT NULL,
+  name BLOB,
+  symbol BLOB,
+  uri BLOB,
+  seller_fee_basis_points INTEGER NOT NULL,
+  collection_key BLOB,
+  collection_verified BOOLEAN,
+  -- Vec<(creator_key, verified, share)>
+  creators BLOB,
+  use_method TEXT CHECK (use_method IN ('burn', 'multiple', 'single')),
+  uses_remaining INTEGER,
+  uses_total INTEGER,
+  primary_sale_happened BOOLEAN NOT NULL,
+  is_mutable BOOLEAN NOT NULL,
+  update_authority BLOB,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS holders (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  account_key BLOB NOT NULL,
+  owner_key BLOB,
+  amount INTEGER NOT NULL,
+  frozen INTEGER NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS leaf_owners (
+  metadata_key BLOB NOT NULL,
+  owner_key BLOB NOT NULL,
+  delegate_key BLOB NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS collections (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  size INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS collection_authorities (
+  metadata_key BLOB NOT NULL,
+  authority_key BLOB NOT NULL,
+  record_key BLOB NOT NULL,
+  approved INTEGER NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS collection_members (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  collection_key BLOB NOT NULL,
+  verified INTEGER NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS by_collection ON collection_members (collection_key);
+
+CREATE TABLE IF NOT EXISTS candy_machine_mints (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  version TEXT NOT NULL CHECK (version IN ('v1', 'v2', 'guard')),
+  candy_machine_key BLOB NOT NULL,
+  config_key BLOB,
+  payer_key BLOB NOT NULL,
+  price INTEGER,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS by_candy_machine ON candy_machine_mints (candy_machine_key);
+
+CREATE TABLE IF NOT EXISTS sales (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  program_key BLOB NOT NULL,
+  marketplace_key BLOB,
+  buyer_key BLOB NOT NULL,
+  seller_key BLOB NOT NULL,
+  price INTEGER NOT NULL,
+  currency_mint BLOB NOT NULL,
+  amount INTEGER NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS royalty_payments (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  creator_key BLOB NOT NULL,
+  share INTEGER NOT NULL,
+  seller_fee_basis_points INTEGER NOT NULL,
+  expected INTEGER NOT NULL,
+  paid INTEGER,
+  shortfall INTEGER,
+  ambiguous INTEGER NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS open_orders (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  side TEXT NOT NULL CHECK (side IN ('listing', 'bid')),
+  program_key BLOB NOT NULL,
+  marketplace_key BLOB,
+  wallet_key BLOB NOT NULL,
+  token_account_key BLOB,
+  delegate_key BLOB,
+  price INTEGER,
+  currency_mint BLOB,
+  amount INTEGER NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS storefront_sales (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN (
+    'auction', 'full_rights_transfer', 'auction_print', 'participation', 'fixed_price')),
+  program_key BLOB NOT NULL,
+  manager_key BLOB NOT NULL,
+  auction_key BLOB,
+  vault_key BLOB,
+  buyer_key BLOB NOT NULL,
+  bidder_metadata_key BLOB,
+  price INTEGER,
+  currency_mint BLOB,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS vault_custody (
+  metadata_key BLOB NOT NULL,
+  mint_key BLOB NOT NULL,
+  vault_key BLOB NOT NULL,
+  safety_deposit_box_key BLOB NOT NULL,
+  deposit INTEGER NOT NULL,
+  amount INTEGER NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS failures (
+  phase TEXT NOT NULL CHECK (phase IN ('partition', 'assemble')),
+  failure_key BLOB NOT NULL,
+  slot INTEGER,
+  block_index INTEGER,
+  error_kind TEXT NOT NULL,
+  error_details TEXT NOT NULL,
+  code_version TEXT NOT NULL,
+  PRIMARY KEY (phase, failure_key)
+);
+
+CREATE TABLE IF NOT EXISTS anomalies (
+  metadata_key BLOB NOT NULL,
+  error_kind TEXT NOT NULL,
+  error_details TEXT NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS token_accounts (
+  account_key BLOB NOT NULL,
+  mint_key BLOB,
+  owner_key BLOB NOT NULL,
+  slot INTEGER NOT NULL,
+  block_index INTEGER NOT NULL,
+  outer_index INTEGER NOT NULL,
+  inner_index INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS by_token_account ON token_accounts (account_key);
+
+CREATE INDEX IF NOT EXISTS by_token_account_mint ON token_accounts (mint_key);
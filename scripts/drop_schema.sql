@@ -1,9 +1,36 @@
+DROP TABLE IF EXISTS failures;
+DROP TYPE IF EXISTS failure_phase;
+DROP TABLE IF EXISTS anomalies;
+DROP TABLE IF EXISTS vault_custody;
+DROP TABLE IF EXISTS storefront_sales;
+DROP TYPE IF EXISTS storefront_sale_kind;
+DROP TABLE IF EXISTS open_orders;
+DROP TABLE IF EXISTS royalty_payments;
+DROP TYPE IF EXISTS order_side;
+DROP TABLE IF EXISTS sales;
+DROP TABLE IF EXISTS candy_machine_mints;
+DROP TYPE IF EXISTS candy_machine_version;
+DROP TABLE IF EXISTS leaf_owners;
+DROP TABLE IF EXISTS holders;
+DROP TABLE IF EXISTS collection_members;
+DROP TABLE IF EXISTS collection_authorities;
 DROP TABLE IF EXISTS collections;
-DROP TABLE IF EXISTS creators;
+DROP TABLE IF EXISTS authorities;
+DROP TYPE IF EXISTS authority_type;
+DROP TABLE IF EXISTS glazings;
+DROP TYPE IF EXISTS creator;
+DROP TYPE IF EXISTS uses;
+DROP TYPE IF EXISTS use_method;
 DROP TABLE IF EXISTS bonbons;
+DROP TYPE IF EXISTS token_delegate_role;
+DROP TYPE IF EXISTS token_state;
+DROP TYPE IF EXISTS supply_status;
+DROP TYPE IF EXISTS token_standard;
 DROP TYPE IF EXISTS limited_edition;
 DROP TYPE IF EXISTS edition_status;
 
+DROP TABLE IF EXISTS token_accounts;
 DROP TABLE IF EXISTS account_keys;
+DROP TYPE IF EXISTS token_meta;
 DROP TABLE IF EXISTS partitions ;
 DROP TABLE IF EXISTS transactions ;
@@ -0,0 +1,410 @@
+-- Brings a postgres DB created by an earlier create_schema.sql up to the current one without
+-- refetching or repartitioning. Safe to run more than once.
+--
+-- bonbons and everything else `reassemble` writes only get the new columns: rows assembled
+-- before the migration hold the defaults below until their mints are reassembled (or picked up
+-- by `reprocess`)
+
+CREATE INDEX IF NOT EXISTS by_slot ON transactions (slot, block_index) ;
+
+-- the outer program of an inner instruction is only known here if the outer instruction was
+-- partitioned too. the column stays nullable until the rest are repartitioned
+ALTER TABLE partitions ADD COLUMN IF NOT EXISTS outer_program_key BYTEA;
+ALTER TABLE partitions ADD COLUMN IF NOT EXISTS stack_height BIGINT;
+
+UPDATE partitions SET outer_program_key = program_key
+WHERE outer_program_key IS NULL AND inner_index IS NULL;
+
+UPDATE partitions p SET outer_program_key = o.program_key
+FROM partitions o
+WHERE p.outer_program_key IS NULL
+  AND o.signature = p.signature
+  AND o.outer_index = p.outer_index
+  AND o.inner_index IS NULL;
+
+DO $$
+BEGIN
+  IF EXISTS (SELECT 1 FROM partitions WHERE outer_program_key IS NULL) THEN
+    RAISE NOTICE 'partitions.outer_program_key left nullable, repartition to fill it';
+  ELSE
+    ALTER TABLE partitions ALTER COLUMN outer_program_key SET NOT NULL;
+  END IF;
+END $$;
+
+-- NULL amounts and balances read as unknown, as for transactions without them
+DO $$
+BEGIN
+  IF NOT EXISTS (
+    SELECT 1 FROM pg_attribute
+    WHERE attrelid = 'token_meta'::regclass AND attname = 'pre_amount'
+  ) THEN
+    ALTER TYPE token_meta ADD ATTRIBUTE pre_amount BIGINT, ADD ATTRIBUTE post_amount BIGINT;
+  END IF;
+END $$;
+
+ALTER TABLE account_keys ADD COLUMN IF NOT EXISTS pre_balances BIGINT[];
+ALTER TABLE account_keys ADD COLUMN IF NOT EXISTS post_balances BIGINT[];
+
+
+DO $$
+BEGIN
+  CREATE TYPE supply_status AS enum (
+    'unknown',
+    'fixed',
+    'mintable'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$
+BEGIN
+  CREATE TYPE token_standard AS enum (
+    'non_fungible',
+    'fungible_asset',
+    'fungible',
+    'edition',
+    'programmable_non_fungible',
+    'programmable_edition'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$
+BEGIN
+  CREATE TYPE token_state AS enum (
+    'unlocked',
+    'locked',
+    'listed'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$
+BEGIN
+  CREATE TYPE token_delegate_role AS enum (
+    'sale',
+    'transfer',
+    'utility',
+    'staking',
+    'standard',
+    'locked_transfer',
+    'migration'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+ALTER TABLE bonbons
+  ADD COLUMN IF NOT EXISTS update_authority BYTEA,
+  ADD COLUMN IF NOT EXISTS update_authority_changed BOOLEAN NOT NULL DEFAULT false,
+  ADD COLUMN IF NOT EXISTS mint_authority BYTEA,
+  ADD COLUMN IF NOT EXISTS freeze_authority BYTEA,
+  ADD COLUMN IF NOT EXISTS supply_status supply_status NOT NULL DEFAULT 'unknown',
+  ADD COLUMN IF NOT EXISTS token_standard token_standard NOT NULL DEFAULT 'non_fungible',
+  ADD COLUMN IF NOT EXISTS supply BIGINT NOT NULL DEFAULT 0,
+  ADD COLUMN IF NOT EXISTS rule_set BYTEA,
+  ADD COLUMN IF NOT EXISTS token_state token_state,
+  ADD COLUMN IF NOT EXISTS token_delegate BYTEA,
+  ADD COLUMN IF NOT EXISTS token_delegate_role token_delegate_role,
+  ADD COLUMN IF NOT EXISTS tree_key BYTEA,
+  ADD COLUMN IF NOT EXISTS leaf_nonce BIGINT,
+  ADD COLUMN IF NOT EXISTS leaf_redeemed BOOLEAN;
+
+-- the defaults are only for the rows already there
+ALTER TABLE bonbons
+  ALTER COLUMN update_authority_changed DROP DEFAULT,
+  ALTER COLUMN supply_status DROP DEFAULT,
+  ALTER COLUMN token_standard DROP DEFAULT,
+  ALTER COLUMN supply DROP DEFAULT;
+
+DO $$
+BEGIN
+  CREATE TYPE authority_type AS enum (
+    'update',
+    'mint',
+    'freeze'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+CREATE TABLE IF NOT EXISTS authorities (
+  metadata_key BYTEA NOT NULL,
+  authority_type authority_type NOT NULL,
+  authority BYTEA,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+DO $$
+BEGIN
+  CREATE TYPE use_method AS enum (
+    'burn',
+    'multiple',
+    'single'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$
+BEGIN
+  CREATE TYPE uses AS (
+    use_method use_method,
+    remaining BIGINT,
+    total BIGINT
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+ALTER TABLE glazings
+  ADD COLUMN IF NOT EXISTS name BYTEA,
+  ADD COLUMN IF NOT EXISTS symbol BYTEA,
+  ADD COLUMN IF NOT EXISTS seller_fee_basis_points INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN IF NOT EXISTS uses uses,
+  ADD COLUMN IF NOT EXISTS primary_sale_happened BOOLEAN NOT NULL DEFAULT false,
+  ADD COLUMN IF NOT EXISTS is_mutable BOOLEAN NOT NULL DEFAULT true,
+  ADD COLUMN IF NOT EXISTS update_authority BYTEA;
+
+ALTER TABLE glazings
+  ALTER COLUMN seller_fee_basis_points DROP DEFAULT,
+  ALTER COLUMN primary_sale_happened DROP DEFAULT,
+  ALTER COLUMN is_mutable DROP DEFAULT;
+
+
+CREATE TABLE IF NOT EXISTS holders (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  account_key BYTEA NOT NULL,
+  owner_key BYTEA,
+  amount BIGINT NOT NULL,
+  frozen BOOLEAN NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS leaf_owners (
+  metadata_key BYTEA NOT NULL,
+  owner_key BYTEA NOT NULL,
+  delegate_key BYTEA NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS collections (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  size BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS collection_authorities (
+  metadata_key BYTEA NOT NULL,
+  authority_key BYTEA NOT NULL,
+  record_key BYTEA NOT NULL,
+  approved BOOLEAN NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS collection_members (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  collection_key BYTEA NOT NULL,
+  verified BOOLEAN NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS by_collection ON collection_members (collection_key);
+
+DO $$
+BEGIN
+  CREATE TYPE candy_machine_version AS enum (
+    'v1',
+    'v2',
+    'guard'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+CREATE TABLE IF NOT EXISTS candy_machine_mints (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  version candy_machine_version NOT NULL,
+  candy_machine_key BYTEA NOT NULL,
+  config_key BYTEA,
+  payer_key BYTEA NOT NULL,
+  price BIGINT,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS by_candy_machine ON candy_machine_mints (candy_machine_key);
+
+CREATE TABLE IF NOT EXISTS sales (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  program_key BYTEA NOT NULL,
+  marketplace_key BYTEA,
+  buyer_key BYTEA NOT NULL,
+  seller_key BYTEA NOT NULL,
+  price BIGINT NOT NULL,
+  currency_mint BYTEA NOT NULL,
+  amount BIGINT NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS royalty_payments (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  creator_key BYTEA NOT NULL,
+  share SMALLINT NOT NULL,
+  seller_fee_basis_points INTEGER NOT NULL,
+  expected BIGINT NOT NULL,
+  paid BIGINT,
+  shortfall BIGINT,
+  ambiguous BOOLEAN NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+-- royalty_payments made before shortfall and ambiguous. the shortfall follows from what was
+-- paid but whether it was ambiguous needs the transaction, so those stay unflagged
+ALTER TABLE royalty_payments
+  ADD COLUMN IF NOT EXISTS shortfall BIGINT,
+  ADD COLUMN IF NOT EXISTS ambiguous BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE royalty_payments ALTER COLUMN ambiguous DROP DEFAULT;
+
+UPDATE royalty_payments SET shortfall = GREATEST(expected - GREATEST(paid, 0), 0)
+WHERE shortfall IS NULL AND paid IS NOT NULL;
+
+DO $$
+BEGIN
+  CREATE TYPE order_side AS enum (
+    'listing',
+    'bid'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+CREATE TABLE IF NOT EXISTS open_orders (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  side order_side NOT NULL,
+  program_key BYTEA NOT NULL,
+  marketplace_key BYTEA,
+  wallet_key BYTEA NOT NULL,
+  token_account_key BYTEA,
+  delegate_key BYTEA,
+  price BIGINT,
+  currency_mint BYTEA,
+  amount BIGINT NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+DO $$
+BEGIN
+  CREATE TYPE storefront_sale_kind AS enum (
+    'auction',
+    'full_rights_transfer',
+    'auction_print',
+    'participation',
+    'fixed_price'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+CREATE TABLE IF NOT EXISTS storefront_sales (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  kind storefront_sale_kind NOT NULL,
+  program_key BYTEA NOT NULL,
+  manager_key BYTEA NOT NULL,
+  auction_key BYTEA,
+  vault_key BYTEA,
+  buyer_key BYTEA NOT NULL,
+  bidder_metadata_key BYTEA,
+  price BIGINT,
+  currency_mint BYTEA,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS vault_custody (
+  metadata_key BYTEA NOT NULL,
+  mint_key BYTEA NOT NULL,
+  vault_key BYTEA NOT NULL,
+  safety_deposit_box_key BYTEA NOT NULL,
+  deposit BOOLEAN NOT NULL,
+  amount BIGINT NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+DO $$
+BEGIN
+  CREATE TYPE failure_phase AS enum (
+    'partition',
+    'assemble'
+  );
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+CREATE TABLE IF NOT EXISTS failures (
+  phase failure_phase NOT NULL,
+  failure_key BYTEA NOT NULL,
+  slot BIGINT,
+  block_index BIGINT,
+  error_kind TEXT NOT NULL,
+  error_details TEXT NOT NULL,
+  code_version TEXT NOT NULL,
+  PRIMARY KEY (phase, failure_key)
+);
+
+CREATE TABLE IF NOT EXISTS anomalies (
+  metadata_key BYTEA NOT NULL,
+  error_kind TEXT NOT NULL,
+  error_details TEXT NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+-- only filled by partitioning, so older transactions need repartitioning before reassembly can
+-- use it to find owners
+CREATE TABLE IF NOT EXISTS token_accounts (
+  account_key BYTEA NOT NULL,
+  mint_key BYTEA,
+  owner_key BYTEA NOT NULL,
+  slot BIGINT NOT NULL,
+  block_index BIGINT NOT NULL,
+  outer_index BIGINT NOT NULL,
+  inner_index BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS by_token_account ON token_accounts (account_key);
+
+CREATE INDEX IF NOT EXISTS by_token_account_mint ON token_accounts (mint_key);